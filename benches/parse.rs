@@ -0,0 +1,108 @@
+//! Throughput of the parsers and of the line framing
+//!
+//! Run with `cargo bench`, or `cargo bench -- <filter>` for the benchmarks whose name matches
+//! the filter. Criterion reports the time per iteration and the throughput, and compares them
+//! with the previous run, so that runs before and after a change can be compared.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use instant_smtp::{
+    data::DotStuffDecoder, decoder::Decoder, Command, Response, ResponseKind, SyntaxOptions,
+};
+
+const COMMANDS: &[&[u8]] = &[
+    b"EHLO client.example.org\r\n",
+    b"MAIL FROM:<sender@example.org> SIZE=12345 BODY=8BITMIME\r\n",
+    b"RCPT TO:<recipient@example.com> NOTIFY=SUCCESS,FAILURE ORCPT=rfc822;recipient@example.com\r\n",
+    b"DATA\r\n",
+    b"QUIT\r\n",
+];
+
+const EHLO_REPLY: &[u8] = b"250-mail.example.com Hello\r\n250-SIZE 35882577\r\n250-8BITMIME\r\n\
+250-PIPELINING\r\n250-AUTH LOGIN PLAIN XOAUTH2\r\n250-ENHANCEDSTATUSCODES\r\n250-CHUNKING\r\n\
+250 SMTPUTF8\r\n";
+
+fn parsers(c: &mut Criterion) {
+    let commands = COMMANDS.concat();
+    let mut group = c.benchmark_group("parse");
+
+    group.throughput(Throughput::Bytes(commands.len() as u64));
+    group.bench_function("command", |b| {
+        b.iter(|| {
+            for line in COMMANDS {
+                black_box(Command::from_bytes(black_box(line)).unwrap());
+            }
+        })
+    });
+
+    group.bench_function("command_lenient", |b| {
+        b.iter(|| {
+            for line in COMMANDS {
+                black_box(
+                    Command::from_bytes_lenient(black_box(line), SyntaxOptions::default()).unwrap(),
+                );
+            }
+        })
+    });
+
+    group.throughput(Throughput::Bytes(EHLO_REPLY.len() as u64));
+    group.bench_function("ehlo_reply", |b| {
+        b.iter(|| black_box(Response::parse(ResponseKind::Ehlo, black_box(EHLO_REPLY)).unwrap()))
+    });
+
+    group.finish();
+}
+
+fn decoders(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+
+    // A pipelined group arriving in small reads, which the decoder scans for CRLF.
+    let pipelined = COMMANDS.concat().repeat(20);
+    group.throughput(Throughput::Bytes(pipelined.len() as u64));
+    group.bench_function("decoder", |b| {
+        b.iter(|| {
+            let mut decoder = Decoder::new();
+            for read in pipelined.chunks(64) {
+                decoder.push(read);
+                while let Some(command) = decoder.decode_command().unwrap() {
+                    black_box(command);
+                }
+            }
+        })
+    });
+
+    // A line that is received in many small reads, e.g., AUTH with a long initial response.
+    let long = [b"AUTH PLAIN ".as_ref(), &[b'A'; 4000], b"\r\n"].concat();
+    group.throughput(Throughput::Bytes(long.len() as u64));
+    group.bench_function("decoder_long_line", |b| {
+        b.iter(|| {
+            let mut decoder = Decoder::new();
+            for read in long.chunks(16) {
+                decoder.push(read);
+                black_box(decoder.decode_command().unwrap());
+            }
+        })
+    });
+
+    let content = [
+        &b"Subject: Test\r\n\r\n"[..],
+        &b"A line of text of some length.\r\n".repeat(32 * 1024),
+        b".\r\n",
+    ]
+    .concat();
+    let mut output = Vec::with_capacity(content.len());
+    group.throughput(Throughput::Bytes(content.len() as u64));
+    group.bench_function("dot_stuff_decoder", |b| {
+        b.iter(|| {
+            output.clear();
+            let mut decoder = DotStuffDecoder::new();
+            black_box(decoder.decode(black_box(&content), &mut output).unwrap());
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, parsers, decoders);
+criterion_main!(benches);
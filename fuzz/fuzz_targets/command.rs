@@ -1,8 +1,11 @@
 #![no_main]
 use libfuzzer_sys::fuzz_target;
-use instant_smtp::Command;
+use instant_smtp::{Command, SyntaxOptions};
 
 fuzz_target!(|data: &[u8]| {
+    // Locating errors must not panic.
+    let _ = Command::diagnose(data, SyntaxOptions::default());
+
     if let Ok((_, cmd)) = Command::from_bytes(data) {
         // Fuzzer created a valid SMTP command.
         // dbg!(&cmd);
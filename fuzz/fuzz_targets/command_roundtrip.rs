@@ -0,0 +1,16 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use instant_smtp::Command;
+
+fuzz_target!(|cmd: Command| {
+    // Serialize the generated command ...
+    let mut buf = Vec::new();
+    cmd.serialize(&mut buf).unwrap();
+
+    // ... parse it again ...
+    let (rem, cmd2) = Command::from_bytes(&buf).unwrap();
+    assert!(rem.is_empty());
+
+    // ... and verify that we got the same command.
+    assert_eq!(cmd, cmd2);
+});
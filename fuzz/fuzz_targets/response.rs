@@ -0,0 +1,18 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use instant_smtp::{Response, ResponseKind};
+
+fuzz_target!(|data: &[u8]| {
+    for kind in [ResponseKind::Greeting, ResponseKind::Ehlo, ResponseKind::Other] {
+        if let Ok((_, rsp)) = Response::parse(kind, data) {
+            // Fuzzer created a valid SMTP reply.
+
+            // Replies with invalid codes are parsed, but not serialized.
+            if let Ok(buf) = rsp.to_vec() {
+                // The serialized reply must be accepted again.
+                let (rem, _) = Response::parse(kind, &buf).unwrap();
+                assert!(rem.is_empty());
+            }
+        }
+    }
+});
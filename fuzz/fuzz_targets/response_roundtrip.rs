@@ -0,0 +1,21 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use instant_smtp::{Response, ResponseKind};
+
+fuzz_target!(|rsp: Response| {
+    let kind = match rsp {
+        Response::Greeting { .. } => ResponseKind::Greeting,
+        Response::Ehlo { .. } => ResponseKind::Ehlo,
+        _ => ResponseKind::Other,
+    };
+
+    // Serialize the generated reply ...
+    let buf = rsp.to_vec().unwrap();
+
+    // ... parse it again ...
+    let (rem, rsp2) = Response::parse(kind, &buf).unwrap();
+    assert!(rem.is_empty());
+
+    // ... and verify that we got the same reply.
+    assert_eq!(rsp, rsp2);
+});
@@ -0,0 +1,375 @@
+//! [`Arbitrary`] implementations for round-trip fuzzing
+//!
+//! The generated values are valid according to the grammar, i.e., serializing and parsing them
+//! again results in the same value.
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::{
+    AddressLiteral, AtomOrQuoted, AuthMechanism, Body, ByMode, Capability, Command, DeliverBy,
+    EsmtpParam, ForwardPath, HeloIdentity, Notify, Path, Priority, ReplyCode, Response, Ret,
+    ReversePath, Secret, TextString,
+};
+
+const ALPHA_DIGIT: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+const UPPER_DIGIT: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+const ATEXT: &[u8] =
+    b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!#$%&'*+-/=?^_`{|}~";
+
+fn string(u: &mut Unstructured, alphabet: &[u8], min: usize, max: usize) -> Result<String> {
+    let len = u.int_in_range(min..=max)?;
+    (0..len)
+        .map(|_| u.choose(alphabet).map(|&byte| byte as char))
+        .collect()
+}
+
+/// A string of bytes in the given (inclusive) ranges
+fn string_in(u: &mut Unstructured, ranges: &[(u8, u8)], min: usize, max: usize) -> Result<String> {
+    let alphabet = ranges
+        .iter()
+        .flat_map(|&(from, to)| from..=to)
+        .collect::<Vec<_>>();
+    string(u, &alphabet, min, max)
+}
+
+fn vec<'a, T>(
+    u: &mut Unstructured<'a>,
+    min: usize,
+    max: usize,
+    mut f: impl FnMut(&mut Unstructured<'a>) -> Result<T>,
+) -> Result<Vec<T>> {
+    let len = u.int_in_range(min..=max)?;
+    (0..len).map(|_| f(u)).collect()
+}
+
+/// Domain = sub-domain *("." sub-domain)
+fn domain(u: &mut Unstructured) -> Result<String> {
+    let labels = vec(u, 1, 3, |u| {
+        let mut label = string(u, ALPHA_DIGIT, 1, 8)?;
+        if u.arbitrary()? {
+            label.push('-');
+            label.push_str(&string(u, ALPHA_DIGIT, 1, 8)?);
+        }
+        Ok(label)
+    })?;
+
+    Ok(labels.join("."))
+}
+
+/// Mailbox = Dot-string "@" Domain
+fn mailbox(u: &mut Unstructured) -> Result<String> {
+    let local_part = vec(u, 1, 3, |u| string(u, ATEXT, 1, 8))?.join(".");
+
+    let domain = match u.ratio(1, 8)? {
+        true => AddressLiteral::arbitrary(u)?.to_string(),
+        false => domain(u)?,
+    };
+
+    Ok(format!("{}@{}", local_part, domain))
+}
+
+/// Keyword that does not start with any known keyword
+fn extension_keyword(u: &mut Unstructured) -> Result<String> {
+    Ok(format!("X-{}", string(u, UPPER_DIGIT, 1, 10)?))
+}
+
+impl<'a> Arbitrary<'a> for Path {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Path {
+            route: match u.ratio(1, 8)? {
+                true => Some(vec(u, 1, 2, domain)?),
+                false => None,
+            },
+            mailbox: mailbox(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for ForwardPath {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.ratio(1, 8)? {
+            true => ForwardPath::Postmaster(match u.arbitrary()? {
+                true => Some(domain(u)?),
+                false => None,
+            }),
+            false => ForwardPath::Path(u.arbitrary()?),
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for AddressLiteral {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        match u.arbitrary()? {
+            true => Ok(AddressLiteral::Ip(u.arbitrary()?)),
+            false => Ok(AddressLiteral::General {
+                tag: extension_keyword(u)?,
+                // dcontent = %d33-90 / %d94-126
+                content: string_in(u, &[(33, 90), (94, 126)], 1, 16)?,
+            }),
+        }
+    }
+}
+
+impl<'a> Arbitrary<'a> for HeloIdentity {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        match u.arbitrary()? {
+            true => Ok(HeloIdentity::Domain(domain(u)?)),
+            false => Ok(HeloIdentity::AddressLiteral(u.arbitrary()?)),
+        }
+    }
+}
+
+impl<'a> Arbitrary<'a> for AtomOrQuoted {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        match u.arbitrary()? {
+            true => Ok(AtomOrQuoted::Atom(string(u, ATEXT, 1, 16)?)),
+            false => Ok(AtomOrQuoted::Quoted(string_in(u, &[(32, 126)], 0, 16)?)),
+        }
+    }
+}
+
+impl<'a> Arbitrary<'a> for AuthMechanism {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=9)? {
+            0 => AuthMechanism::Plain,
+            1 => AuthMechanism::Login,
+            2 => AuthMechanism::GssApi,
+            3 => AuthMechanism::CramMd5,
+            4 => AuthMechanism::CramSha1,
+            5 => AuthMechanism::ScramMd5,
+            6 => AuthMechanism::ScramSha256,
+            7 => AuthMechanism::DigestMd5,
+            8 => AuthMechanism::Ntlm,
+            _ => AuthMechanism::Other(extension_keyword(u)?),
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for EsmtpParam {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        // esmtp-value = 1*(%d33-60 / %d62-126)
+        const VALUE: &[(u8, u8)] = &[(33, 60), (62, 126)];
+
+        Ok(match u.int_in_range(0..=11)? {
+            0 => EsmtpParam::Size(u.arbitrary()?),
+            1 => EsmtpParam::Body(*u.choose(&[
+                Body::SevenBit,
+                Body::EightBitMime,
+                Body::BinaryMime,
+            ])?),
+            2 => EsmtpParam::Ret(*u.choose(&[Ret::Full, Ret::Hdrs])?),
+            3 => EsmtpParam::Envid(string_in(u, &[(32, 126)], 1, 16)?),
+            4 => EsmtpParam::Notify(Notify(u.int_in_range(0..=7)?)),
+            5 => EsmtpParam::Orcpt {
+                addr_type: string(u, ALPHA_DIGIT, 1, 8)?,
+                address: string_in(u, &[(32, 126)], 1, 16)?,
+            },
+            6 => EsmtpParam::Auth(string_in(u, VALUE, 1, 16)?),
+            7 => EsmtpParam::SmtpUtf8,
+            8 => {
+                let mode = *u.choose(&[ByMode::Notify, ByMode::Return])?;
+                EsmtpParam::By(DeliverBy {
+                    time: match mode {
+                        ByMode::Notify => u.int_in_range(-999_999_999..=999_999_999)?,
+                        ByMode::Return => u.int_in_range(1..=999_999_999)?,
+                    },
+                    mode,
+                    trace: u.arbitrary()?,
+                })
+            }
+            9 => EsmtpParam::MtPriority(Priority::new(u.int_in_range(-9..=9)?).unwrap()),
+            10 => EsmtpParam::RequireTls,
+            _ => EsmtpParam::Other {
+                keyword: extension_keyword(u)?,
+                value: match u.arbitrary()? {
+                    true => Some(string_in(u, VALUE, 1, 16)?),
+                    false => None,
+                },
+            },
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Command {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let parameters = |u: &mut Unstructured<'a>| vec(u, 0, 3, EsmtpParam::arbitrary);
+
+        Ok(match u.int_in_range(0..=15)? {
+            0 => Command::Ehlo {
+                domain_or_address: u.arbitrary()?,
+            },
+            1 => Command::Helo {
+                domain_or_address: u.arbitrary()?,
+            },
+            2 => Command::Mail {
+                reverse_path: match u.ratio(1, 8)? {
+                    true => ReversePath::Null,
+                    false => ReversePath::Path(u.arbitrary()?),
+                },
+                parameters: parameters(u)?,
+            },
+            3 => Command::Rcpt {
+                forward_path: u.arbitrary()?,
+                parameters: parameters(u)?,
+            },
+            4 => Command::Data,
+            5 => Command::Rset,
+            6 => Command::Vrfy {
+                user_or_mailbox: u.arbitrary()?,
+            },
+            7 => Command::Expn {
+                mailing_list: u.arbitrary()?,
+            },
+            8 => Command::Help {
+                argument: u.arbitrary()?,
+            },
+            9 => Command::Noop {
+                argument: u.arbitrary()?,
+            },
+            10 => Command::Quit,
+            11 => Command::StartTls,
+            12 => Command::Auth {
+                mechanism: u.arbitrary()?,
+                initial_response: match u.arbitrary()? {
+                    true => Some(Secret::new(vec(u, 0, 64, u8::arbitrary)?)),
+                    false => None,
+                },
+            },
+            13 => Command::Bdat {
+                size: u.arbitrary()?,
+                last: u.arbitrary()?,
+            },
+            _ => Command::Lhlo {
+                domain_or_address: u.arbitrary()?,
+            },
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Capability {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=22)? {
+            0 => Capability::Expn,
+            1 => Capability::Help,
+            2 => Capability::EightBitMime,
+            3 => Capability::Size(u.arbitrary()?),
+            4 => Capability::Chunking,
+            5 => Capability::BinaryMime,
+            6 => Capability::Checkpoint,
+            7 => Capability::DeliverBy(u.arbitrary()?),
+            8 => Capability::Pipelining,
+            9 => Capability::Dsn,
+            10 => Capability::Etrn,
+            11 => Capability::EnhancedStatusCodes,
+            12 => Capability::StartTls,
+            13 => Capability::Mtrk,
+            14 => Capability::Atrn,
+            15 => Capability::Auth(vec(u, 1, 3, AuthMechanism::arbitrary)?),
+            16 => Capability::Burl,
+            17 => Capability::SmtpUtf8,
+            18 => Capability::Rrvs,
+            19 => Capability::RequireTls,
+            20 => Capability::MtPriority(match u.arbitrary()? {
+                true => Some(string(u, UPPER_DIGIT, 1, 8)?),
+                false => None,
+            }),
+            _ => Capability::Other {
+                keyword: extension_keyword(u)?,
+                // ehlo-param = 1*(%d33-126)
+                params: vec(u, 0, 2, |u| string_in(u, &[(33, 126)], 1, 8))?,
+            },
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Response {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        // textstring = 1*(%d09 / %d32-126)
+        let text = |u: &mut Unstructured<'a>| string_in(u, &[(9, 9), (32, 126)], 1, 40);
+
+        Ok(match u.int_in_range(0..=2)? {
+            0 => Response::Greeting {
+                domain: domain(u)?,
+                text: text(u)?,
+            },
+            1 => Response::Ehlo {
+                domain: domain(u)?,
+                greet: match u.arbitrary()? {
+                    true => Some(string_in(u, &[(32, 126)], 1, 40)?),
+                    false => None,
+                },
+                capabilities: vec(u, 0, 4, Capability::arbitrary)?,
+            },
+            _ => {
+                // Reply-code = %x32-35 %x30-35 %x30-39
+                let code = 100 * u.int_in_range(2..=5)?
+                    + 10 * u.int_in_range(0..=5)?
+                    + u.int_in_range(0..=9)?;
+
+                Response::Other {
+                    code: ReplyCode::from(code),
+                    lines: vec(u, 0, 3, |u| {
+                        // Only contains valid characters.
+                        Ok(TextString::new(&text(u)?).unwrap().into_owned())
+                    })?,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{CommandRef, ResponseKind};
+
+    /// Deterministic input for `Unstructured` (xorshift)
+    fn inputs() -> impl Iterator<Item = Vec<u8>> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+
+        (0..500).map(move |_| {
+            (0..256)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect()
+        })
+    }
+
+    #[test]
+    fn test_command_round_trip() {
+        for input in inputs() {
+            let command = Command::arbitrary(&mut Unstructured::new(&input)).unwrap();
+
+            let mut serialized = Vec::new();
+            command.serialize(&mut serialized).unwrap();
+            let (rem, parsed) = Command::from_bytes(&serialized).unwrap();
+            assert!(rem.is_empty());
+            assert_eq!(parsed, command, "{}", String::from_utf8_lossy(&serialized));
+
+            let (rem, borrowed) = CommandRef::from_bytes(&serialized).unwrap();
+            assert!(rem.is_empty());
+            assert_eq!(borrowed.to_owned(), command);
+        }
+    }
+
+    #[test]
+    fn test_response_round_trip() {
+        for input in inputs() {
+            let response = Response::arbitrary(&mut Unstructured::new(&input)).unwrap();
+            let kind = match response {
+                Response::Greeting { .. } => ResponseKind::Greeting,
+                Response::Ehlo { .. } => ResponseKind::Ehlo,
+                Response::Other { .. } => ResponseKind::Other,
+            };
+
+            let serialized = response.to_vec().unwrap();
+            let (rem, parsed) = Response::parse(kind, &serialized).unwrap();
+            assert!(rem.is_empty());
+            assert_eq!(parsed, response, "{}", String::from_utf8_lossy(&serialized));
+        }
+    }
+}
@@ -0,0 +1,361 @@
+//! Blocking submission of a message over `std::io`.
+//!
+//! [`SmtpClient`] drives a [`ClientMachine`] over a [`TcpStream`] (or any other [`Read`] +
+//! [`Write`] transport) until the message was submitted, e.g., in scripts and tests that don't
+//! use an async runtime:
+//!
+//! ```no_run
+//! # use instant_smtp::{blocking::SmtpClient, client::*, sasl, HeloIdentity, Limits};
+//! # fn main() -> Result<(), instant_smtp::blocking::BlockingError> {
+//! let config = ClientConfig {
+//!     identity: HeloIdentity::Domain("client.example.org".into()),
+//!     tls: TlsMode::None,
+//!     credentials: None,
+//!     mechanisms: sasl::password_mechanisms(),
+//!     limits: Limits::default(),
+//!     timeouts: Timeouts::default(),
+//! };
+//! let message = Message {
+//!     reverse_path: "a@example.org".into(),
+//!     mail_parameters: vec![],
+//!     recipients: vec!["b@example.org".into()],
+//!     rcpt_parameters: vec![],
+//!     content: b"Subject: Test\r\n\r\nHello\r\n".to_vec(),
+//! };
+//!
+//! SmtpClient::connect("mx.example.org:25")?.send(config, message)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! STARTTLS requires an implementation of [`StartTls`], e.g., with rustls or native-tls.
+//!
+//! A client of [`SmtpClient::connect`] applies the [`Timeouts`](crate::client::Timeouts) of the
+//! config as read and write timeouts of the socket, and fails with [`ClientError::Timeout`] when
+//! one expires.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+};
+
+use crate::{
+    client::{ClientConfig, ClientError, ClientMachine, Message, TimeoutEvent},
+    decoder::{DecodeError, Decoder},
+    SyntaxOptions,
+};
+
+/// A transport the client can read from and write to.
+pub trait Transport: Read + Write {}
+
+impl<T: Read + Write> Transport for T {}
+
+/// The TLS handshake following STARTTLS.
+pub trait StartTls {
+    /// Perform the handshake over `stream` and return the protected stream.
+    fn upgrade(&self, stream: Box<dyn Transport>) -> io::Result<Box<dyn Transport>>;
+}
+
+pub struct SmtpClient {
+    stream: Box<dyn Transport>,
+    decoder: Decoder,
+    tls: Option<Box<dyn StartTls>>,
+    /// The socket underneath `stream`, to set the timeouts of
+    socket: Option<TcpStream>,
+}
+
+impl fmt::Debug for SmtpClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SmtpClient")
+            .field("decoder", &self.decoder)
+            .field("tls", &self.tls.is_some())
+            .field("socket", &self.socket)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SmtpClient {
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        let socket = stream.try_clone()?;
+
+        Ok(Self {
+            socket: Some(socket),
+            ..Self::new(stream)
+        })
+    }
+
+    /// Use an established connection, e.g., a [`TcpStream`] with timeouts.
+    ///
+    /// The [`Timeouts`](crate::client::Timeouts) of the config are not applied to `stream`.
+    pub fn new(stream: impl Transport + 'static) -> Self {
+        Self {
+            stream: Box::new(stream),
+            decoder: Decoder::new(),
+            tls: None,
+            socket: None,
+        }
+    }
+
+    /// Perform STARTTLS with the given implementation, see [`ClientConfig::tls`].
+    pub fn with_tls(mut self, tls: impl StartTls + 'static) -> Self {
+        self.tls = Some(Box::new(tls));
+        self
+    }
+
+    /// Submit a message, from the greeting to QUIT.
+    ///
+    /// When the server rejects the message, the client still sends QUIT and waits for the reply,
+    /// then returns the rejection.
+    pub fn send(mut self, config: ClientConfig, message: Message) -> Result<(), BlockingError> {
+        // Servers may send UTF-8 text, e.g., in the greeting, before SMTPUTF8 was negotiated.
+        let options = SyntaxOptions {
+            smtputf8: true,
+            limits: config.limits,
+            ..SyntaxOptions::default()
+        };
+        self.decoder = Decoder::with_options(options);
+        let mut machine = ClientMachine::new(config, message);
+        let mut result = Ok(());
+
+        loop {
+            while let Some(timeout) = machine.poll_timeout() {
+                if let Some(socket) = &self.socket {
+                    match timeout {
+                        TimeoutEvent::Reply { duration, .. } => {
+                            socket.set_read_timeout(Some(duration))?
+                        }
+                        TimeoutEvent::DataBlock { duration } => {
+                            socket.set_write_timeout(Some(duration))?
+                        }
+                    }
+                }
+            }
+
+            if let Some(outgoing) = machine.poll_transmit() {
+                self.stream
+                    .write_all(&outgoing)
+                    .and_then(|()| self.stream.flush())
+                    .map_err(|error| io_error(&mut machine, error))?;
+            }
+
+            if machine.needs_tls_upgrade() {
+                let tls = self.tls.as_ref().ok_or(BlockingError::TlsUnavailable)?;
+                let stream = std::mem::replace(&mut self.stream, Box::new(io::empty()));
+                self.stream = tls.upgrade(stream)?;
+                // Input received before the handshake was not protected by TLS.
+                self.decoder = Decoder::with_options(options);
+                machine.tls_established();
+                continue;
+            }
+
+            let Some(kind) = machine.expected() else {
+                break;
+            };
+            let response = loop {
+                if let Some(response) = self.decoder.decode_response(kind)? {
+                    break response;
+                }
+
+                let mut buffer = [0; 4096];
+                let read = self
+                    .stream
+                    .read(&mut buffer)
+                    .map_err(|error| io_error(&mut machine, error))?;
+                match read {
+                    0 => return Err(BlockingError::ConnectionClosed),
+                    read => self.decoder.push(&buffer[..read]),
+                }
+            };
+
+            if let Err(error) = machine.handle(response) {
+                // Keep the first error, the machine continues with QUIT.
+                if result.is_ok() {
+                    result = Err(BlockingError::Client(error));
+                }
+            }
+        }
+
+        result
+    }
+}
+
+/// An error of the transport, or [`ClientError::Timeout`] for an expired timeout of the socket.
+fn io_error(machine: &mut ClientMachine, error: io::Error) -> BlockingError {
+    match error.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            BlockingError::Client(machine.handle_timeout())
+        }
+        _ => BlockingError::Io(error),
+    }
+}
+
+#[derive(Debug)]
+pub enum BlockingError {
+    Io(io::Error),
+    /// A reply could not be decoded.
+    Decode(DecodeError),
+    /// The submission failed, e.g., because the server rejected the message.
+    Client(ClientError),
+    /// The server accepted STARTTLS, but no [`StartTls`] implementation was set.
+    TlsUnavailable,
+    /// The server closed the connection before the session was finished.
+    ConnectionClosed,
+}
+
+impl From<io::Error> for BlockingError {
+    fn from(error: io::Error) -> Self {
+        BlockingError::Io(error)
+    }
+}
+
+impl From<DecodeError> for BlockingError {
+    fn from(error: DecodeError) -> Self {
+        BlockingError::Decode(error)
+    }
+}
+
+impl fmt::Display for BlockingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockingError::Io(error) => write!(f, "{}", error),
+            BlockingError::Decode(error) => write!(f, "{}", error),
+            BlockingError::Client(error) => write!(f, "{}", error),
+            BlockingError::TlsUnavailable => write!(f, "no TLS implementation for STARTTLS"),
+            BlockingError::ConnectionClosed => write!(f, "connection closed by server"),
+        }
+    }
+}
+
+impl std::error::Error for BlockingError {}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{
+        client::{Timeouts, TlsMode},
+        HeloIdentity, Limits,
+    };
+
+    /// Replies to read, and a shared buffer of what was written
+    struct Mock {
+        input: io::Cursor<Vec<u8>>,
+        output: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Read for Mock {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Mock {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn mock(input: &[u8]) -> (Mock, Rc<RefCell<Vec<u8>>>) {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mock = Mock {
+            input: io::Cursor::new(input.to_vec()),
+            output: output.clone(),
+        };
+        (mock, output)
+    }
+
+    fn config(tls: TlsMode) -> ClientConfig {
+        ClientConfig {
+            identity: HeloIdentity::Domain("client.example.org".into()),
+            tls,
+            credentials: None,
+            mechanisms: crate::sasl::password_mechanisms(),
+            limits: Limits::default(),
+            timeouts: Timeouts::default(),
+        }
+    }
+
+    fn message() -> Message {
+        Message {
+            reverse_path: "a@example.org".into(),
+            mail_parameters: vec![],
+            recipients: vec!["b@example.org".into()],
+            rcpt_parameters: vec![],
+            content: b"Hello\r\n".to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_send() {
+        let (stream, output) = mock(
+            b"220 example.org ESMTP\r\n250 example.org\r\n250 OK\r\n250 OK\r\n\
+354 Go ahead\r\n250 OK\r\n221 Bye\r\n",
+        );
+
+        SmtpClient::new(stream)
+            .send(config(TlsMode::Opportunistic), message())
+            .unwrap();
+        assert_eq!(
+            &output.borrow()[..],
+            &b"EHLO client.example.org\r\nMAIL FROM:<a@example.org>\r\n\
+RCPT TO:<b@example.org>\r\nDATA\r\nHello\r\n.\r\nQUIT\r\n"[..]
+        );
+    }
+
+    #[test]
+    fn test_utf8_reply() {
+        let (stream, _) = mock(
+            "220 example.org ESMTP Gr\u{fc}\u{df}e\r\n250 example.org\r\n250 OK\r\n250 OK\r\n\
+354 Go ahead\r\n250 OK\r\n221 Bye\r\n"
+                .as_bytes(),
+        );
+
+        SmtpClient::new(stream)
+            .send(config(TlsMode::None), message())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_timeout() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = SmtpClient::connect(listener.local_addr().unwrap()).unwrap();
+        // The server accepts the connection, but never sends the greeting.
+        let _server = listener.accept().unwrap();
+
+        let mut config = config(TlsMode::None);
+        config.timeouts.greeting = std::time::Duration::from_millis(50);
+        assert!(matches!(
+            client.send(config, message()),
+            Err(BlockingError::Client(ClientError::Timeout {
+                state: crate::client::State::Greeting
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_rejected() {
+        let (stream, output) =
+            mock(b"220 example.org ESMTP\r\n250 example.org\r\n550 No\r\n221 Bye\r\n");
+
+        assert!(matches!(
+            SmtpClient::new(stream).send(config(TlsMode::None), message()),
+            Err(BlockingError::Client(ClientError::Rejected { .. }))
+        ));
+        assert!(output.borrow().ends_with(b"QUIT\r\n"));
+
+        let (stream, _) =
+            mock(b"220 example.org ESMTP\r\n250-example.org\r\n250 STARTTLS\r\n220 Go\r\n");
+        assert!(matches!(
+            SmtpClient::new(stream).send(config(TlsMode::Required), message()),
+            Err(BlockingError::TlsUnavailable)
+        ));
+    }
+}
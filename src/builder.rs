@@ -0,0 +1,725 @@
+//! Building MAIL and RCPT commands, and replies
+//!
+//! The builders check the path and the parameters when the command is built, so that a client
+//! does not need to format a command line and parse it again to find out whether it is valid:
+//!
+//! ```
+//! use instant_smtp::{builder::MailBuilder, Command};
+//!
+//! let command = MailBuilder::new()
+//!     .reverse_path("a@example.org")
+//!     .size(12345)
+//!     .body_8bitmime()
+//!     .build()
+//!     .unwrap();
+//!
+//! let mut serialized = Vec::new();
+//! command.serialize(&mut serialized).unwrap();
+//! assert_eq!(serialized, b"MAIL FROM:<a@example.org> SIZE=12345 BODY=8BITMIME\r\n");
+//! ```
+//!
+//! Likewise, [`ResponseBuilder`] checks the reply code against the enhanced status code:
+//!
+//! ```
+//! use instant_smtp::Response;
+//!
+//! let response = Response::builder(250)
+//!     .enhanced("2.1.0")
+//!     .text("Sender OK")
+//!     .build()
+//!     .unwrap();
+//! assert_eq!(response.to_vec().unwrap(), b"250 2.1.0 Sender OK\r\n");
+//! ```
+
+use alloc::{borrow::ToOwned, format, string::String, vec::Vec};
+use core::fmt;
+
+use nom::IResult;
+
+use crate::{
+    has_line_break_or_nul,
+    parse::{
+        atom,
+        command::{esmtp_keyword, esmtp_value, parse_esmtp_param, rcpt_path, reverse_path},
+        encode_xtext,
+    },
+    validate, AuthMechanism, Body, ByMode, Capabilities, Capability, Command, DeliverBy,
+    EnhancedStatusCode, EsmtpParam, ForwardPath, Notify, Priority, ReplyCode, Response, Ret,
+    ReversePath, TextString,
+};
+
+/// Reply lines should fit a terminal of 80 columns.
+const REPLY_LINE_LENGTH: usize = 80;
+
+/// Builder of a MAIL command
+///
+/// Parameters are serialized in the order they were added.
+#[derive(Clone, Debug, Default)]
+pub struct MailBuilder {
+    reverse_path: String,
+    parameters: Vec<EsmtpParam>,
+    error: Option<BuildError>,
+}
+
+impl MailBuilder {
+    /// A builder with the null reverse-path "<>" and no parameters
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The path without the angle brackets, e.g., "a@example.org". The empty string is the null
+    /// reverse-path.
+    pub fn reverse_path(mut self, reverse_path: impl Into<String>) -> Self {
+        self.reverse_path = reverse_path.into();
+        self
+    }
+
+    pub fn size(self, size: u32) -> Self {
+        self.parameter(EsmtpParam::Size(size))
+    }
+
+    pub fn body(self, body: Body) -> Self {
+        self.parameter(EsmtpParam::Body(body))
+    }
+
+    pub fn body_8bitmime(self) -> Self {
+        self.body(Body::EightBitMime)
+    }
+
+    pub fn ret(self, ret: Ret) -> Self {
+        self.parameter(EsmtpParam::Ret(ret))
+    }
+
+    pub fn envid(self, envid: impl Into<String>) -> Self {
+        self.parameter(EsmtpParam::Envid(envid.into()))
+    }
+
+    /// The identity that submitted the message, or `None` for "AUTH=<>".
+    pub fn auth(self, mailbox: Option<&str>) -> Self {
+        let value = mailbox.map_or("<>".into(), |mailbox| encode_xtext(mailbox).into_owned());
+        self.parameter(EsmtpParam::Auth(value))
+    }
+
+    /// Request SMTPUTF8, which is required for a non-ASCII reverse-path.
+    pub fn smtputf8(self) -> Self {
+        self.parameter(EsmtpParam::SmtpUtf8)
+    }
+
+    pub fn by(self, by: DeliverBy) -> Self {
+        self.parameter(EsmtpParam::By(by))
+    }
+
+    pub fn mt_priority(self, priority: Priority) -> Self {
+        self.parameter(EsmtpParam::MtPriority(priority))
+    }
+
+    pub fn require_tls(self) -> Self {
+        self.parameter(EsmtpParam::RequireTls)
+    }
+
+    /// Add a parameter, e.g., of an extension without a method.
+    pub fn parameter(mut self, parameter: EsmtpParam) -> Self {
+        self.parameters.push(parameter);
+        self
+    }
+
+    /// Add a parameter from its keyword and value, which must match the esmtp-param grammar.
+    pub fn keyword(mut self, keyword: &str, value: Option<&str>) -> Self {
+        match other_parameter(keyword, value) {
+            Ok(parameter) => self.parameters.push(parameter),
+            Err(error) => {
+                self.error.get_or_insert(error);
+            }
+        }
+        self
+    }
+
+    pub fn build(self) -> Result<Command, BuildError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        if has_line_break_or_nul(self.reverse_path.as_bytes()) {
+            return Err(BuildError::InjectionAttempt);
+        }
+
+        let path = format!("<{}>", self.reverse_path);
+        if !self.reverse_path.is_empty() && !matches_fully(reverse_path, &path) {
+            return Err(BuildError::InvalidPath(self.reverse_path));
+        }
+        if !self.reverse_path.is_ascii() && !self.parameters.contains(&EsmtpParam::SmtpUtf8) {
+            return Err(BuildError::NonAscii);
+        }
+
+        let reverse_path = ReversePath::from(self.reverse_path.as_str());
+        if let Some(violation) = validate::reverse_path(&reverse_path).into_iter().next() {
+            return Err(BuildError::Violation(violation));
+        }
+        check_parameters(&self.parameters)?;
+
+        Ok(Command::Mail {
+            reverse_path,
+            parameters: self.parameters,
+        })
+    }
+}
+
+/// Builder of a RCPT command
+///
+/// Parameters are serialized in the order they were added.
+#[derive(Clone, Debug, Default)]
+pub struct RcptBuilder {
+    forward_path: String,
+    parameters: Vec<EsmtpParam>,
+    error: Option<BuildError>,
+}
+
+impl RcptBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The path without the angle brackets, e.g., "b@example.org" or "Postmaster"
+    pub fn forward_path(mut self, forward_path: impl Into<String>) -> Self {
+        self.forward_path = forward_path.into();
+        self
+    }
+
+    pub fn notify(self, notify: Notify) -> Self {
+        self.parameter(EsmtpParam::Notify(notify))
+    }
+
+    /// The original recipient, e.g., "rfc822" and "b@example.org"
+    pub fn orcpt(self, addr_type: impl Into<String>, address: impl Into<String>) -> Self {
+        self.parameter(EsmtpParam::Orcpt {
+            addr_type: addr_type.into(),
+            address: address.into(),
+        })
+    }
+
+    /// Add a parameter, e.g., of an extension without a method.
+    pub fn parameter(mut self, parameter: EsmtpParam) -> Self {
+        self.parameters.push(parameter);
+        self
+    }
+
+    /// Add a parameter from its keyword and value, which must match the esmtp-param grammar.
+    pub fn keyword(mut self, keyword: &str, value: Option<&str>) -> Self {
+        match other_parameter(keyword, value) {
+            Ok(parameter) => self.parameters.push(parameter),
+            Err(error) => {
+                self.error.get_or_insert(error);
+            }
+        }
+        self
+    }
+
+    pub fn build(self) -> Result<Command, BuildError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        if has_line_break_or_nul(self.forward_path.as_bytes()) {
+            return Err(BuildError::InjectionAttempt);
+        }
+
+        let path = format!("<{}>", self.forward_path);
+        if !matches_fully(rcpt_path, &path) {
+            return Err(BuildError::InvalidPath(self.forward_path));
+        }
+
+        let forward_path = ForwardPath::from(self.forward_path.as_str());
+        if let Some(violation) = validate::forward_path(&forward_path).into_iter().next() {
+            return Err(BuildError::Violation(violation));
+        }
+        check_parameters(&self.parameters)?;
+
+        Ok(Command::Rcpt {
+            forward_path,
+            parameters: self.parameters,
+        })
+    }
+}
+
+/// Builder of a reply, see [`Response::builder`]
+///
+/// The text is wrapped at spaces into lines of at most 80 columns, including the reply code and
+/// the enhanced status code, which prefixes every line. Longer words are kept whole.
+#[derive(Clone, Debug)]
+pub struct ResponseBuilder {
+    code: ReplyCode,
+    enhanced: Option<String>,
+    paragraphs: Vec<String>,
+}
+
+impl ResponseBuilder {
+    pub fn new(code: impl Into<ReplyCode>) -> Self {
+        Self {
+            code: code.into(),
+            enhanced: None,
+            paragraphs: Vec::new(),
+        }
+    }
+
+    /// The enhanced status code, e.g., "2.1.0", whose class must match the reply code (RFC 2034)
+    pub fn enhanced(mut self, code: impl Into<String>) -> Self {
+        self.enhanced = Some(code.into());
+        self
+    }
+
+    /// Add text, which starts on a new line.
+    pub fn text(mut self, text: impl Into<String>) -> Self {
+        self.paragraphs.push(text.into());
+        self
+    }
+
+    pub fn build(self) -> Result<Response, BuildError> {
+        let code = u16::from(self.code);
+        if !self.code.is_valid() {
+            return Err(BuildError::InvalidReplyCode(code));
+        }
+        if let Some(ref enhanced) = self.enhanced {
+            match EnhancedStatusCode::parse(enhanced) {
+                Some((parsed, "")) if u16::from(parsed.class) == code / 100 => {}
+                _ => return Err(BuildError::InvalidEnhancedCode(enhanced.clone())),
+            }
+        }
+
+        let prefix = self
+            .enhanced
+            .as_ref()
+            .map_or(0, |enhanced| enhanced.len() + 1);
+        let width = REPLY_LINE_LENGTH - "250-".len() - prefix;
+        let mut lines = Vec::new();
+        for paragraph in &self.paragraphs {
+            if has_line_break_or_nul(paragraph.as_bytes()) {
+                return Err(BuildError::InjectionAttempt);
+            }
+            if TextString::new(paragraph).is_err() {
+                return Err(BuildError::InvalidText(paragraph.clone()));
+            }
+
+            for line in fill(paragraph, width) {
+                let line = match self.enhanced {
+                    Some(ref enhanced) if line.is_empty() => enhanced.clone(),
+                    Some(ref enhanced) => format!("{} {}", enhanced, line),
+                    None => line,
+                };
+                lines.push(TextString(line.into()));
+            }
+        }
+        if let (Some(enhanced), true) = (self.enhanced, lines.is_empty()) {
+            lines.push(TextString(enhanced.into()));
+        }
+
+        Ok(Response::Other {
+            code: self.code,
+            lines,
+        })
+    }
+}
+
+/// Builder of the capabilities a server advertises, see [`Capabilities::builder`]
+///
+/// Capabilities are advertised in the order of [`Capability`], see [`Capabilities::to_vec`].
+#[derive(Clone, Debug, Default)]
+pub struct CapabilitiesBuilder {
+    capabilities: Capabilities,
+}
+
+impl CapabilitiesBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The fixed maximum message size, or 0 to declare none (RFC 1870, 4.)
+    pub fn size(self, size: u32) -> Self {
+        self.capability(Capability::Size(size))
+    }
+
+    pub fn auth(self, mechanisms: impl IntoIterator<Item = AuthMechanism>) -> Self {
+        self.capability(Capability::Auth(mechanisms.into_iter().collect()))
+    }
+
+    pub fn pipelining(self) -> Self {
+        self.capability(Capability::Pipelining)
+    }
+
+    pub fn eight_bit_mime(self) -> Self {
+        self.capability(Capability::EightBitMime)
+    }
+
+    pub fn chunking(self) -> Self {
+        self.capability(Capability::Chunking)
+    }
+
+    /// BINARYMIME, which also advertises CHUNKING, as it requires BDAT (RFC 3030, 3.)
+    pub fn binary_mime(self) -> Self {
+        self.chunking().capability(Capability::BinaryMime)
+    }
+
+    pub fn smtp_utf8(self) -> Self {
+        self.capability(Capability::SmtpUtf8)
+    }
+
+    pub fn start_tls(self) -> Self {
+        self.capability(Capability::StartTls)
+    }
+
+    pub fn enhanced_status_codes(self) -> Self {
+        self.capability(Capability::EnhancedStatusCodes)
+    }
+
+    pub fn dsn(self) -> Self {
+        self.capability(Capability::Dsn)
+    }
+
+    pub fn require_tls(self) -> Self {
+        self.capability(Capability::RequireTls)
+    }
+
+    /// Add a capability, e.g., of an extension without a method.
+    pub fn capability(mut self, capability: Capability) -> Self {
+        self.capabilities.extend([capability]);
+        self
+    }
+
+    pub fn build(self) -> Capabilities {
+        self.capabilities
+    }
+}
+
+/// Wrap text at spaces into lines of at most `width` octets, or a single longer word.
+fn fill(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+
+    for word in text.split(' ') {
+        if !line.is_empty() && line.len() + 1 + word.len() > width {
+            lines.push(core::mem::take(&mut line));
+        } else if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    lines.push(line);
+
+    lines
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BuildError {
+    /// The path does not match the grammar of the command.
+    InvalidPath(String),
+    /// The reverse-path contains non-ASCII characters, but SMTPUTF8 was not requested.
+    NonAscii,
+    /// The path violates a constraint beyond the grammar, see [`validate`].
+    Violation(validate::Violation),
+    /// The parameter with this keyword does not match the esmtp-param grammar, or its value is
+    /// out of range.
+    InvalidParameter(String),
+    /// A parameter was added more than once.
+    DuplicateParameter(String),
+    /// The path, a parameter, or the text contains CR, LF, or NUL, which would end the line
+    /// early.
+    InjectionAttempt,
+    /// The reply code is out of range, see [`ReplyCode::is_valid`].
+    InvalidReplyCode(u16),
+    /// The enhanced status code is malformed, or its class does not match the reply code.
+    InvalidEnhancedCode(String),
+    /// The text of a reply contains characters other than tab and printable ASCII.
+    InvalidText(String),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::InvalidPath(path) => write!(f, "invalid path {:?}", path),
+            BuildError::NonAscii => write!(f, "non-ASCII reverse-path requires SMTPUTF8"),
+            BuildError::Violation(violation) => write!(f, "{}", violation),
+            BuildError::InvalidParameter(keyword) => write!(f, "invalid parameter {}", keyword),
+            BuildError::DuplicateParameter(keyword) => {
+                write!(f, "parameter {} added more than once", keyword)
+            }
+            BuildError::InjectionAttempt => write!(f, "field contains CR, LF, or NUL"),
+            BuildError::InvalidReplyCode(code) => write!(f, "invalid reply code {}", code),
+            BuildError::InvalidEnhancedCode(code) => {
+                write!(f, "invalid enhanced status code {:?}", code)
+            }
+            BuildError::InvalidText(text) => write!(f, "invalid reply text {:?}", text),
+        }
+    }
+}
+
+impl core::error::Error for BuildError {}
+
+/// Whether the parser consumes all of `input`
+fn matches_fully(parser: fn(&[u8]) -> IResult<&[u8], &str>, input: &str) -> bool {
+    // The streaming parsers need a terminator to know that, e.g., a domain is complete.
+    let mut terminated = input.as_bytes().to_vec();
+    terminated.extend_from_slice(b"\r\n");
+
+    matches!(parser(&terminated), Ok((b"\r\n", _)))
+}
+
+fn other_parameter(keyword: &str, value: Option<&str>) -> Result<EsmtpParam, BuildError> {
+    if has_line_break_or_nul(keyword.as_bytes())
+        || value.is_some_and(|value| has_line_break_or_nul(value.as_bytes()))
+    {
+        return Err(BuildError::InjectionAttempt);
+    }
+
+    let valid = matches_fully(esmtp_keyword, keyword)
+        && value.is_none_or(|value| matches_fully(esmtp_value, value));
+
+    match (valid, value) {
+        (true, Some(value)) => Ok(parse_esmtp_param(&format!("{}={}", keyword, value))),
+        (true, None) => Ok(parse_esmtp_param(keyword)),
+        (false, _) => Err(BuildError::InvalidParameter(keyword.to_owned())),
+    }
+}
+
+fn check_parameters(parameters: &[EsmtpParam]) -> Result<(), BuildError> {
+    let mut keywords = Vec::new();
+
+    for parameter in parameters {
+        let mut serialized = Vec::new();
+        // Writing to a `Vec` does not fail.
+        let _ = parameter.serialize(&mut serialized);
+        if has_line_break_or_nul(&serialized) {
+            return Err(BuildError::InjectionAttempt);
+        }
+        let serialized = String::from_utf8_lossy(&serialized);
+        let keyword = match serialized.split_once('=') {
+            Some((keyword, _)) => keyword,
+            None => &serialized,
+        }
+        .to_ascii_uppercase();
+
+        let valid = match parameter {
+            // The time of a new message must be positive in either mode (RFC 2852, 4.).
+            EsmtpParam::By(DeliverBy { time, mode, .. }) => {
+                *time > 0 || (*mode == ByMode::Notify && *time == 0)
+            }
+            EsmtpParam::Orcpt { addr_type, .. } => matches_fully(atom, addr_type),
+            EsmtpParam::Envid(envid) => !envid.is_empty(),
+            EsmtpParam::Other { keyword, value } => {
+                matches_fully(esmtp_keyword, keyword)
+                    && value
+                        .as_deref()
+                        .is_none_or(|value| matches_fully(esmtp_value, value))
+            }
+            _ => true,
+        };
+        if !valid {
+            return Err(BuildError::InvalidParameter(keyword));
+        }
+        if keywords.contains(&keyword) {
+            return Err(BuildError::DuplicateParameter(keyword));
+        }
+        keywords.push(keyword);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mail() {
+        let command = MailBuilder::new()
+            .reverse_path("a@example.org")
+            .ret(Ret::Hdrs)
+            .envid("QQ314159")
+            .auth(Some("a+b@example.org"))
+            .keyword("X-TEST", None)
+            .build()
+            .unwrap();
+        let mut serialized = Vec::new();
+        command.serialize(&mut serialized).unwrap();
+        assert_eq!(
+            serialized,
+            b"MAIL FROM:<a@example.org> RET=HDRS ENVID=QQ314159 AUTH=a+2Bb@example.org X-TEST\r\n"
+        );
+
+        assert_eq!(
+            MailBuilder::new().build().unwrap(),
+            Command::Mail {
+                reverse_path: ReversePath::Null,
+                parameters: vec![],
+            }
+        );
+        assert_eq!(
+            MailBuilder::new().reverse_path("a example.org").build(),
+            Err(BuildError::InvalidPath("a example.org".into()))
+        );
+        assert_eq!(
+            MailBuilder::new().reverse_path("ä@example.org").build(),
+            Err(BuildError::NonAscii)
+        );
+        assert!(MailBuilder::new()
+            .reverse_path("ä@example.org")
+            .smtputf8()
+            .build()
+            .is_ok());
+        assert_eq!(
+            MailBuilder::new()
+                .size(1)
+                .keyword("size", Some("2"))
+                .build(),
+            Err(BuildError::DuplicateParameter("SIZE".into()))
+        );
+        assert_eq!(
+            MailBuilder::new().keyword("X=Y", None).build(),
+            Err(BuildError::InvalidParameter("X=Y".into()))
+        );
+    }
+
+    #[test]
+    fn test_rcpt() {
+        let command = RcptBuilder::new()
+            .forward_path("b@example.org")
+            .notify(Notify::SUCCESS | Notify::FAILURE)
+            .orcpt("rfc822", "b@example.org")
+            .build()
+            .unwrap();
+        let mut serialized = Vec::new();
+        command.serialize(&mut serialized).unwrap();
+        assert_eq!(
+            serialized,
+            b"RCPT TO:<b@example.org> NOTIFY=SUCCESS,FAILURE ORCPT=rfc822;b@example.org\r\n"
+        );
+
+        assert!(matches!(
+            RcptBuilder::new().forward_path("Postmaster").build(),
+            Ok(Command::Rcpt {
+                forward_path: ForwardPath::Postmaster(None),
+                ..
+            })
+        ));
+        assert_eq!(
+            RcptBuilder::new().build(),
+            Err(BuildError::InvalidPath("".into()))
+        );
+        assert_eq!(
+            RcptBuilder::new().forward_path("b@example.123").build(),
+            Err(BuildError::Violation(validate::Violation::NumericTld(
+                "123".into()
+            )))
+        );
+    }
+
+    #[test]
+    fn test_injection_attempt() {
+        assert_eq!(
+            MailBuilder::new()
+                .reverse_path("a@example.org>\r\nRCPT TO:<b@example.org")
+                .build(),
+            Err(BuildError::InjectionAttempt)
+        );
+        assert_eq!(
+            MailBuilder::new()
+                .reverse_path("a@example.org")
+                .keyword("X-TEST", Some("1\r\nRSET"))
+                .build(),
+            Err(BuildError::InjectionAttempt)
+        );
+        assert_eq!(
+            MailBuilder::new()
+                .parameter(EsmtpParam::Auth("a@example.org\0".into()))
+                .build(),
+            Err(BuildError::InjectionAttempt)
+        );
+        assert_eq!(
+            RcptBuilder::new().forward_path("b@example.org\n").build(),
+            Err(BuildError::InjectionAttempt)
+        );
+        // ENVID is xtext-encoded.
+        assert!(MailBuilder::new().envid("a\r\nb").build().is_ok());
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let capabilities = Capabilities::builder()
+            .size(10_000_000)
+            .auth([AuthMechanism::Plain, AuthMechanism::Login])
+            .pipelining()
+            .binary_mime()
+            .enhanced_status_codes()
+            .capability(Capability::Other {
+                keyword: "X-TEST".into(),
+                params: vec!["1".into()],
+            })
+            .build();
+        assert!(capabilities.chunking);
+
+        let response = capabilities.to_response("mx.example.org", Some("Hello"));
+        let serialized = response.to_vec().unwrap();
+        assert_eq!(
+            String::from_utf8(serialized.clone()).unwrap(),
+            "250-mx.example.org Hello\r\n\
+             250-SIZE 10000000\r\n\
+             250-CHUNKING\r\n\
+             250-BINARYMIME\r\n\
+             250-PIPELINING\r\n\
+             250-ENHANCEDSTATUSCODES\r\n\
+             250-AUTH PLAIN LOGIN\r\n\
+             250 X-TEST 1\r\n"
+        );
+
+        let (_, parsed) = crate::EhloResponse::parse(&serialized).unwrap();
+        assert_eq!(parsed.capabilities, capabilities);
+    }
+
+    #[test]
+    fn test_response() {
+        let text = "The recipient's mailbox is full and can't accept messages now, please try \
+                    again later or contact the recipient otherwise";
+        let response = Response::builder(452)
+            .enhanced("4.2.2")
+            .text(text)
+            .text("Thank you")
+            .build()
+            .unwrap();
+        let serialized = response.to_vec().unwrap();
+        assert_eq!(
+            String::from_utf8(serialized).unwrap(),
+            "452-4.2.2 The recipient's mailbox is full and can't accept messages now, please\r\n\
+             452-4.2.2 try again later or contact the recipient otherwise\r\n\
+             452 4.2.2 Thank you\r\n"
+        );
+        assert_eq!(
+            response.enhanced_code(),
+            Some(EnhancedStatusCode::new(4, 2, 2))
+        );
+
+        let response = Response::builder(ReplyCode::StartMailInput)
+            .text("Go ahead")
+            .build()
+            .unwrap();
+        assert_eq!(response.to_vec().unwrap(), b"354 Go ahead\r\n");
+        let response = Response::builder(250).enhanced("2.0.0").build().unwrap();
+        assert_eq!(response.to_vec().unwrap(), b"250 2.0.0\r\n");
+
+        assert_eq!(
+            Response::builder(250).enhanced("5.0.0").build(),
+            Err(BuildError::InvalidEnhancedCode("5.0.0".into()))
+        );
+        assert_eq!(
+            Response::builder(354).enhanced("3.0.0").build(),
+            Err(BuildError::InvalidEnhancedCode("3.0.0".into()))
+        );
+        assert_eq!(
+            Response::builder(199).text("OK").build(),
+            Err(BuildError::InvalidReplyCode(199))
+        );
+        assert_eq!(
+            Response::builder(250).text("OK\r\n250 Hi").build(),
+            Err(BuildError::InjectionAttempt)
+        );
+        assert_eq!(
+            Response::builder(250).text("Grüße").build(),
+            Err(BuildError::InvalidText("Grüße".into()))
+        );
+    }
+}
@@ -0,0 +1,1273 @@
+//! Client-side submission of a single message.
+//!
+//! A [`ClientMachine`] does not perform any I/O. The caller writes the bytes returned by
+//! [`ClientMachine::poll_transmit`] to the connection, parses replies of the kind returned by
+//! [`ClientMachine::expected`], and passes them to [`ClientMachine::handle`] until the machine
+//! is finished.
+//!
+//! The machine does not keep time either. After every transition, it announces how long to wait
+//! for the next reply as a [`TimeoutEvent`], see [`ClientMachine::poll_timeout`].
+
+use alloc::{
+    collections::VecDeque,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{fmt, time::Duration};
+
+#[cfg(feature = "cram-md5")]
+use crate::sasl::CramMd5;
+use crate::{
+    data::{check_body, dot_stuff, DataError},
+    sasl::{
+        choose_mechanism, password_mechanisms, Authenticator, Continuation, Login, Plain, Step,
+    },
+    AuthMechanism, Body, Capabilities, Command, EncodeError, EsmtpParam, ForwardPath, HeloIdentity,
+    Limits, Path, ReplyCode, Response, ResponseKind, ReversePath, Secret, SyntaxOptions,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsMode {
+    /// Never use STARTTLS.
+    None,
+    /// Use STARTTLS when the server advertises it, and continue without TLS when the server
+    /// refuses it.
+    Opportunistic,
+    /// Abort when the server does not advertise STARTTLS, or refuses it.
+    Required,
+    /// The connection uses TLS from the start, e.g., to port 465 (RFC 8314, 3.3.), so STARTTLS is
+    /// never sent.
+    Implicit,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientConfig {
+    /// Identity sent with EHLO (or HELO)
+    pub identity: HeloIdentity,
+    pub tls: TlsMode,
+    /// Authenticate when set, with the first mechanism of `mechanisms` that the server supports.
+    /// PLAIN and LOGIN require TLS.
+    pub credentials: Option<Credentials>,
+    /// The mechanisms to authenticate with, in order of preference, e.g., the strongest first as
+    /// in [`password_mechanisms`]. Mechanisms not in [`password_mechanisms`] are skipped.
+    pub mechanisms: Vec<AuthMechanism>,
+    /// Maximum lengths of command lines; longer commands fail with
+    /// [`ClientError::InvalidCommand`].
+    pub limits: Limits,
+    pub timeouts: Timeouts,
+}
+
+impl ClientConfig {
+    /// Relay to the MX host of a domain on port 25, with STARTTLS when advertised and without
+    /// authentication (RFC 5321)
+    ///
+    /// MX hosts rarely have certificates that a client could verify, so TLS is opportunistic.
+    pub fn relay_port_25(identity: HeloIdentity) -> Self {
+        Self {
+            identity,
+            tls: TlsMode::Opportunistic,
+            credentials: None,
+            mechanisms: password_mechanisms(),
+            limits: Limits::default(),
+            timeouts: Timeouts::default(),
+        }
+    }
+
+    /// Submit to a mail submission agent on port 587 with STARTTLS and authentication
+    /// (RFC 6409)
+    pub fn submission_587(identity: HeloIdentity, credentials: Credentials) -> Self {
+        Self {
+            tls: TlsMode::Required,
+            credentials: Some(credentials),
+            ..Self::relay_port_25(identity)
+        }
+    }
+
+    /// Submit over implicit TLS on port 465 with authentication (RFC 8314, 3.3.), which RFC
+    /// 8314 recommends over port 587
+    pub fn smtps_465(identity: HeloIdentity, credentials: Credentials) -> Self {
+        Self {
+            tls: TlsMode::Implicit,
+            ..Self::submission_587(identity, credentials)
+        }
+    }
+}
+
+/// Time to wait for the replies of the server (RFC 5321, 4.5.3.2.)
+///
+/// The defaults are the minimums of the RFC. A client should not give up earlier, as servers
+/// may be slow to reply, e.g., while checking a recipient.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Initial 220 message
+    pub greeting: Duration,
+    /// MAIL command
+    pub mail: Duration,
+    /// RCPT command
+    pub rcpt: Duration,
+    /// DATA initiation, i.e., the "354" reply
+    pub data_init: Duration,
+    /// Data block, i.e., each write of the message content
+    pub data_block: Duration,
+    /// DATA termination, i.e., the reply to the final "."
+    pub data_termination: Duration,
+    /// Other commands, e.g., EHLO and QUIT, for which the RFC gives no value
+    pub other: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        const MINUTE: Duration = Duration::from_secs(60);
+
+        Self {
+            greeting: 5 * MINUTE,
+            mail: 5 * MINUTE,
+            rcpt: 5 * MINUTE,
+            data_init: 2 * MINUTE,
+            data_block: 3 * MINUTE,
+            data_termination: 10 * MINUTE,
+            other: 5 * MINUTE,
+        }
+    }
+}
+
+impl Timeouts {
+    /// Time to wait for the reply expected in the given state
+    pub fn reply(&self, state: State) -> Option<Duration> {
+        match state {
+            State::Greeting => Some(self.greeting),
+            State::Mail => Some(self.mail),
+            State::Rcpt => Some(self.rcpt),
+            State::Data => Some(self.data_init),
+            State::Content => Some(self.data_termination),
+            State::TlsHandshake | State::Finished => None,
+            _ => Some(self.other),
+        }
+    }
+}
+
+/// A timer the transport should arm, see [`ClientMachine::poll_timeout`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeoutEvent {
+    /// Wait at most `duration` for the reply expected in `state`, then call
+    /// [`ClientMachine::handle_timeout`].
+    Reply { state: State, duration: Duration },
+    /// Each write of the message content must complete within `duration`, otherwise call
+    /// [`ClientMachine::handle_timeout`].
+    DataBlock { duration: Duration },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub reverse_path: ReversePath,
+    /// Parameters sent with MAIL, e.g., [`EsmtpParam::Ret`] and [`EsmtpParam::Envid`]
+    pub mail_parameters: Vec<EsmtpParam>,
+    pub recipients: Vec<String>,
+    /// Parameters sent with every RCPT, e.g., [`EsmtpParam::Notify`]
+    pub rcpt_parameters: Vec<EsmtpParam>,
+    /// Message content without dot-stuffing
+    pub content: Vec<u8>,
+}
+
+/// The replies to the transaction, e.g., to return the message to the sender for each rejected
+/// recipient
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TransactionResult {
+    /// The replies to RCPT, in the order of [`Message::recipients`]
+    pub recipients: Vec<RecipientResult>,
+    pub message: MessageOutcome,
+}
+
+impl TransactionResult {
+    pub fn accepted(&self) -> impl Iterator<Item = &RecipientResult> {
+        self.recipients.iter().filter(|result| result.is_accepted())
+    }
+
+    pub fn rejected(&self) -> impl Iterator<Item = &RecipientResult> {
+        self.recipients
+            .iter()
+            .filter(|result| !result.is_accepted())
+    }
+
+    /// Whether the message was accepted for some recipients, but others were rejected.
+    pub fn is_partial(&self) -> bool {
+        matches!(self.message, MessageOutcome::Accepted(_)) && self.rejected().next().is_some()
+    }
+}
+
+/// The reply to RCPT for a recipient
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecipientResult {
+    pub recipient: String,
+    pub response: Response,
+}
+
+impl RecipientResult {
+    pub fn is_accepted(&self) -> bool {
+        self.response.code().is_completed()
+    }
+}
+
+/// What became of the message content
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum MessageOutcome {
+    /// The content was not sent, e.g., because MAIL or all recipients were rejected.
+    #[default]
+    NotSent,
+    /// The reply to the end of the content
+    Accepted(Response),
+    /// The reply that rejected DATA or the content
+    Rejected(Response),
+}
+
+/// A mail transaction that the machine abandoned after MAIL was accepted, by sending QUIT or
+/// because the server did not reply in time
+///
+/// The message was not delivered to any recipient.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransactionAborted {
+    /// The state in which the transaction was abandoned
+    pub state: State,
+}
+
+/// The reply the machine is waiting for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Greeting,
+    Ehlo,
+    Helo,
+    StartTls,
+    /// The server accepted STARTTLS. The caller must perform the TLS handshake and then call
+    /// [`ClientMachine::tls_established`].
+    TlsHandshake,
+    /// AUTH was sent, the SASL exchange is in progress.
+    Auth,
+    Mail,
+    Rcpt,
+    Data,
+    Content,
+    Quit,
+    /// QUIT was answered; the caller should close the connection.
+    Finished,
+}
+
+pub struct ClientMachine {
+    config: ClientConfig,
+    message: Message,
+    state: State,
+    capabilities: Capabilities,
+    /// The domain announced in the greeting
+    server_domain: Option<String>,
+    tls: bool,
+    sasl: Option<Authenticator>,
+    outgoing: Vec<u8>,
+    /// Timers to announce, see [`ClientMachine::poll_timeout`]
+    timers: VecDeque<TimeoutEvent>,
+    /// Index of the recipient whose RCPT reply is expected next
+    recipient: usize,
+    result: TransactionResult,
+    aborted: Option<TransactionAborted>,
+}
+
+/// The output that was not transmitted yet is omitted, as it may contain the credentials of AUTH.
+impl fmt::Debug for ClientMachine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientMachine")
+            .field("config", &self.config)
+            .field("message", &self.message)
+            .field("state", &self.state)
+            .field("capabilities", &self.capabilities)
+            .field("server_domain", &self.server_domain)
+            .field("tls", &self.tls)
+            .field("sasl", &self.sasl)
+            .field(
+                "outgoing",
+                &format_args!("[{} octets]", self.outgoing.len()),
+            )
+            .field("timers", &self.timers)
+            .field("recipient", &self.recipient)
+            .field("result", &self.result)
+            .field("aborted", &self.aborted)
+            .finish()
+    }
+}
+
+impl ClientMachine {
+    pub fn new(config: ClientConfig, message: Message) -> Self {
+        let tls = config.tls == TlsMode::Implicit;
+        let mut machine = Self {
+            config,
+            message,
+            state: State::Greeting,
+            capabilities: Capabilities::default(),
+            server_domain: None,
+            tls,
+            sasl: None,
+            outgoing: Vec::new(),
+            timers: VecDeque::new(),
+            recipient: 0,
+            result: TransactionResult::default(),
+            aborted: None,
+        };
+        machine.arm_reply();
+        machine
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// The reply to parse next, or `None` when no reply is expected.
+    pub fn expected(&self) -> Option<ResponseKind> {
+        match self.state {
+            State::Greeting => Some(ResponseKind::Greeting),
+            State::Ehlo => Some(ResponseKind::Ehlo),
+            State::TlsHandshake | State::Finished => None,
+            _ => Some(ResponseKind::Other),
+        }
+    }
+
+    /// Whether the caller must perform the TLS handshake and then call
+    /// [`ClientMachine::tls_established`].
+    ///
+    /// Any bytes that were received after the "220" reply to STARTTLS must be discarded, as they
+    /// were not protected by TLS.
+    pub fn needs_tls_upgrade(&self) -> bool {
+        self.state == State::TlsHandshake
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state == State::Finished
+    }
+
+    /// The domain the server announced in its greeting, e.g., to compare it with the name that was
+    /// connected to.
+    pub fn server_domain(&self) -> Option<&str> {
+        self.server_domain.as_deref()
+    }
+
+    /// The ESMTP extensions advertised in the last EHLO reply.
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// The replies to the recipients and to the content, as far as they were received.
+    pub fn result(&self) -> &TransactionResult {
+        &self.result
+    }
+
+    /// Take the transaction that was abandoned, if any.
+    pub fn poll_aborted(&mut self) -> Option<TransactionAborted> {
+        self.aborted.take()
+    }
+
+    /// Take the bytes that must be sent to the server.
+    pub fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+        match self.outgoing.is_empty() {
+            true => None,
+            false => Some(core::mem::take(&mut self.outgoing)),
+        }
+    }
+
+    /// Take the next timer to arm, in the order in which they apply.
+    ///
+    /// A [`TimeoutEvent::Reply`] replaces the timer of the previous reply. It starts once the
+    /// bytes of [`ClientMachine::poll_transmit`] were written.
+    pub fn poll_timeout(&mut self) -> Option<TimeoutEvent> {
+        self.timers.pop_front()
+    }
+
+    /// Signal that a timer of [`ClientMachine::poll_timeout`] expired.
+    ///
+    /// The server is considered unresponsive, so the machine is finished without QUIT, and the
+    /// caller should close the connection.
+    pub fn handle_timeout(&mut self) -> ClientError {
+        let state = self.state;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(?state, "timed out");
+
+        self.abort();
+        self.state = State::Finished;
+        self.outgoing.clear();
+        self.timers.clear();
+        ClientError::Timeout { state }
+    }
+
+    /// Advance the machine with a reply from the server.
+    ///
+    /// An error means that the message was not (fully) submitted. The machine continues by
+    /// sending QUIT and should be driven until it is finished.
+    pub fn handle(&mut self, response: Response) -> Result<(), ClientError> {
+        #[cfg(feature = "tracing")]
+        let (from, code) = (self.state, u16::from(response.code()));
+
+        let result = self.transition(response);
+
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok(()) => tracing::trace!(code, ?from, to = ?self.state, "handled reply"),
+            Err(error) => tracing::debug!(code, state = ?from, %error, "submission failed"),
+        }
+
+        result
+    }
+
+    fn transition(&mut self, response: Response) -> Result<(), ClientError> {
+        let code = response.code();
+
+        match self.state {
+            State::Greeting if code == ReplyCode::SERVICE_READY_220 => {
+                if let Response::Greeting { domain, .. } = response {
+                    self.server_domain = Some(domain);
+                }
+                self.send_ehlo()
+            }
+            State::Ehlo if code.is_completed() => {
+                self.capabilities = match response {
+                    Response::Ehlo { capabilities, .. } => capabilities.into(),
+                    _ => Capabilities::default(),
+                };
+                self.after_ehlo()
+            }
+            // Fall back to HELO for servers that do not implement EHLO.
+            State::Ehlo if code.is_permanent_error() => self.send(
+                State::Helo,
+                &Command::Helo {
+                    domain_or_address: self.config.identity.clone(),
+                },
+            ),
+            State::Helo if code.is_completed() => {
+                self.capabilities = Capabilities::default();
+                self.after_ehlo()
+            }
+            // Forget what was learned before the upgrade (RFC 3207, 4.2.)
+            State::StartTls if code == ReplyCode::SERVICE_READY_220 => {
+                self.state = State::TlsHandshake;
+                self.capabilities = Capabilities::default();
+                Ok(())
+            }
+            State::StartTls
+                if self.config.tls == TlsMode::Opportunistic && !code.is_completed() =>
+            {
+                self.capabilities.start_tls = false;
+                self.after_ehlo()
+            }
+            State::Auth => {
+                let step = match self.sasl.as_mut() {
+                    Some(sasl) => sasl.handle(&response),
+                    None => Ok(Step::Rejected),
+                };
+
+                match step {
+                    Ok(Step::Continue(continuation)) => {
+                        continuation.serialize(&mut self.outgoing).unwrap();
+                        self.arm_reply();
+                        Ok(())
+                    }
+                    Ok(Step::Authenticated) => {
+                        self.sasl = None;
+                        self.send_mail()
+                    }
+                    Ok(Step::Rejected) => self.fail(ClientError::Rejected {
+                        state: State::Auth,
+                        response,
+                    }),
+                    // The server answers the cancellation with an error.
+                    Err(_) => {
+                        Continuation::Cancel.serialize(&mut self.outgoing).unwrap();
+                        self.arm_reply();
+                        Ok(())
+                    }
+                }
+            }
+            State::Mail if code.is_completed() => self.send_rcpt(),
+            State::Rcpt => {
+                self.result.recipients.push(RecipientResult {
+                    recipient: self.message.recipients[self.recipient].clone(),
+                    response: response.clone(),
+                });
+                self.recipient += 1;
+
+                if self.recipient < self.message.recipients.len() {
+                    self.send_rcpt()
+                } else if self.result.accepted().next().is_some() {
+                    self.send(State::Data, &Command::Data)
+                } else {
+                    self.fail(ClientError::Rejected {
+                        state: State::Rcpt,
+                        response,
+                    })
+                }
+            }
+            State::Data if code.is_accepted() => {
+                self.outgoing
+                    .extend_from_slice(&dot_stuff(&self.message.content));
+                self.state = State::Content;
+                self.timers.push_back(TimeoutEvent::DataBlock {
+                    duration: self.config.timeouts.data_block,
+                });
+                self.arm_reply();
+                Ok(())
+            }
+            State::Content if code.is_completed() => {
+                self.result.message = MessageOutcome::Accepted(response);
+                self.send(State::Quit, &Command::Quit)
+            }
+            State::Data | State::Content => {
+                self.result.message = MessageOutcome::Rejected(response.clone());
+                self.fail(ClientError::Rejected {
+                    state: self.state,
+                    response,
+                })
+            }
+            State::Quit => {
+                self.state = State::Finished;
+                Ok(())
+            }
+            State::TlsHandshake | State::Finished => Err(ClientError::UnexpectedResponse),
+            state => self.fail(ClientError::Rejected { state, response }),
+        }
+    }
+
+    /// Signal that the TLS handshake following STARTTLS completed.
+    ///
+    /// The machine repeats EHLO, as the capabilities may differ after the upgrade. Has no effect
+    /// unless the machine is in [`State::TlsHandshake`].
+    pub fn tls_established(&mut self) {
+        if self.state == State::TlsHandshake {
+            self.tls = true;
+            // EHLO fits the limit, as the identity already passed with the first EHLO.
+            let _ = self.send_ehlo();
+        }
+    }
+
+    fn after_ehlo(&mut self) -> Result<(), ClientError> {
+        if !self.tls && self.config.tls != TlsMode::None {
+            if self.capabilities.start_tls {
+                return self.send(State::StartTls, &Command::StartTls);
+            } else if self.config.tls == TlsMode::Required {
+                return self.fail(ClientError::TlsUnavailable);
+            }
+        }
+
+        let credentials = match self.config.credentials {
+            Some(ref credentials) => credentials.clone(),
+            None => return self.send_mail(),
+        };
+
+        let supported = self.capabilities.auth.as_deref().unwrap_or_default();
+        let usable = password_mechanisms();
+        let preference: Vec<_> = (self.config.mechanisms.iter())
+            .filter(|mechanism| usable.contains(mechanism))
+            .cloned()
+            .collect();
+        let mechanism = choose_mechanism(supported, &preference, self.tls);
+        let (username, password) = (credentials.username, credentials.password.into_inner());
+        let mut sasl = match mechanism {
+            #[cfg(feature = "cram-md5")]
+            Some(AuthMechanism::CramMd5) => Authenticator::new(CramMd5::new(username, password)),
+            Some(AuthMechanism::Plain) => Authenticator::new(Plain::new(username, password)),
+            Some(AuthMechanism::Login) => Authenticator::new(Login::new(username, password)),
+            _ => return self.fail(ClientError::AuthUnavailable),
+        };
+
+        self.send(State::Auth, &sasl.command())?;
+        self.sasl = Some(sasl);
+        Ok(())
+    }
+
+    fn send_ehlo(&mut self) -> Result<(), ClientError> {
+        self.send(
+            State::Ehlo,
+            &Command::Ehlo {
+                domain_or_address: self.config.identity.clone(),
+            },
+        )
+    }
+
+    fn send_mail(&mut self) -> Result<(), ClientError> {
+        let mut parameters = self.message.mail_parameters.clone();
+
+        // The message must not be sent without TLS, not even in plain text (RFC 8689, 4.2.1.)
+        if parameters.contains(&EsmtpParam::RequireTls)
+            && !(self.tls && self.capabilities.require_tls)
+        {
+            return self.fail(ClientError::RequireTlsUnavailable);
+        }
+
+        // Declare the size, so that the server can reject the message before it is sent.
+        if self.capabilities.size.is_some() {
+            let size = self.message.content.len();
+            if let Some(limit) = self.capabilities.max_size() {
+                if size > limit as usize {
+                    return self.fail(ClientError::MessageTooLarge { size, limit });
+                }
+            }
+            if !parameters
+                .iter()
+                .any(|parameter| matches!(parameter, EsmtpParam::Size(_)))
+            {
+                parameters.push(EsmtpParam::Size(u32::try_from(size).unwrap_or(u32::MAX)));
+            }
+        }
+
+        // Declare 8-bit content as 8BITMIME, unless the caller chose a body type.
+        let body = parameters.iter().find_map(|parameter| match parameter {
+            EsmtpParam::Body(body) => Some(*body),
+            _ => None,
+        });
+        let body = match body {
+            Some(body) => body,
+            None if !self.message.content.is_ascii() && self.capabilities.eight_bit_mime => {
+                parameters.push(EsmtpParam::Body(Body::EightBitMime));
+                Body::EightBitMime
+            }
+            None => Body::SevenBit,
+        };
+        if let Err(error) = check_body(&self.message.content, body) {
+            return self.fail(ClientError::InvalidContent(error));
+        }
+
+        let ascii = self.message.reverse_path.to_string().is_ascii()
+            && self.message.recipients.iter().all(|rcpt| rcpt.is_ascii());
+        if !ascii {
+            if !self.capabilities.smtp_utf8 {
+                return self.fail(ClientError::SmtpUtf8Unavailable);
+            }
+            if !parameters.contains(&EsmtpParam::SmtpUtf8) {
+                parameters.push(EsmtpParam::SmtpUtf8);
+            }
+        }
+
+        self.send(
+            State::Mail,
+            &Command::Mail {
+                reverse_path: self.message.reverse_path.clone(),
+                parameters,
+            },
+        )
+    }
+
+    fn send_rcpt(&mut self) -> Result<(), ClientError> {
+        match self.message.recipients.get(self.recipient) {
+            Some(forward_path) => {
+                let command = Command::Rcpt {
+                    forward_path: ForwardPath::Path(Path::new(forward_path.clone())),
+                    parameters: self.message.rcpt_parameters.clone(),
+                };
+                self.send(State::Rcpt, &command)
+            }
+            // Nothing to deliver.
+            None => self.send(State::Quit, &Command::Quit),
+        }
+    }
+
+    fn send(&mut self, state: State, command: &Command) -> Result<(), ClientError> {
+        let options = SyntaxOptions {
+            smtputf8: self.capabilities.smtp_utf8,
+            limits: self.config.limits,
+            ..SyntaxOptions::default()
+        };
+
+        match command.encode(options) {
+            Ok(line) => {
+                self.outgoing.extend_from_slice(&line);
+                self.state = state;
+                self.arm_reply();
+                Ok(())
+            }
+            Err(error) => self.fail(ClientError::InvalidCommand(error)),
+        }
+    }
+
+    fn arm_reply(&mut self) {
+        if let Some(duration) = self.config.timeouts.reply(self.state) {
+            self.timers.push_back(TimeoutEvent::Reply {
+                state: self.state,
+                duration,
+            });
+        }
+    }
+
+    /// Record the transaction in progress as aborted, if any.
+    fn abort(&mut self) {
+        let in_progress = match self.state {
+            State::Rcpt | State::Data => true,
+            // Unless the server rejected the content, which ends the transaction.
+            State::Content => self.result.message == MessageOutcome::NotSent,
+            _ => false,
+        };
+        if in_progress {
+            self.aborted = Some(TransactionAborted { state: self.state });
+        }
+    }
+
+    fn fail(&mut self, error: ClientError) -> Result<(), ClientError> {
+        self.abort();
+        // QUIT is always valid.
+        let _ = self.send(State::Quit, &Command::Quit);
+        Err(error)
+    }
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The server replied with an unexpected code in the given state.
+    Rejected { state: State, response: Response },
+    /// STARTTLS is required, but the server does not advertise it.
+    TlsUnavailable,
+    /// The server does not advertise any supported authentication mechanism, or only ones that
+    /// send the password in plain text while the connection does not use TLS.
+    AuthUnavailable,
+    /// The message has non-ASCII addresses, but the server does not advertise SMTPUTF8.
+    SmtpUtf8Unavailable,
+    /// The message is flagged with REQUIRETLS, but the connection is not protected by TLS or the
+    /// server does not advertise REQUIRETLS.
+    RequireTlsUnavailable,
+    /// The message exceeds the maximum size declared by the server (RFC 1870).
+    MessageTooLarge { size: usize, limit: u32 },
+    /// The content cannot be sent, e.g., because it has 8-bit octets, but the server does not
+    /// advertise 8BITMIME.
+    InvalidContent(DataError),
+    /// A command could not be encoded, e.g., because an address or parameter makes it too long.
+    InvalidCommand(EncodeError),
+    /// A reply was passed although none was expected.
+    UnexpectedResponse,
+    /// The server did not reply in time in the given state, see [`Timeouts`].
+    Timeout { state: State },
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Rejected { state, response } => write!(
+                f,
+                "server replied {} in state {:?}",
+                u16::from(response.code()),
+                state
+            ),
+            ClientError::TlsUnavailable => write!(f, "server does not support STARTTLS"),
+            ClientError::AuthUnavailable => {
+                write!(f, "server does not support any authentication mechanism")
+            }
+            ClientError::SmtpUtf8Unavailable => write!(f, "server does not support SMTPUTF8"),
+            ClientError::RequireTlsUnavailable => {
+                write!(f, "REQUIRETLS is not available on this connection")
+            }
+            ClientError::MessageTooLarge { size, limit } => write!(
+                f,
+                "message of {} octets exceeds maximum size of {}",
+                size, limit
+            ),
+            ClientError::InvalidContent(error) => write!(f, "invalid content: {}", error),
+            ClientError::InvalidCommand(error) => write!(f, "invalid command: {}", error),
+            ClientError::UnexpectedResponse => write!(f, "no reply was expected"),
+            ClientError::Timeout { state } => write!(f, "timed out in state {:?}", state),
+        }
+    }
+}
+
+impl core::error::Error for ClientError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Notify;
+
+    fn config(tls: TlsMode, credentials: Option<Credentials>) -> ClientConfig {
+        ClientConfig {
+            tls,
+            credentials,
+            ..ClientConfig::relay_port_25(HeloIdentity::Domain("client.example.org".into()))
+        }
+    }
+
+    fn message() -> Message {
+        Message {
+            reverse_path: "a@example.org".into(),
+            mail_parameters: vec![EsmtpParam::Envid("QQ 314159".into())],
+            recipients: vec!["b@example.org".into(), "c@example.org".into()],
+            rcpt_parameters: vec![EsmtpParam::Notify(Notify::FAILURE | Notify::DELAY)],
+            content: b"Subject: Test\r\n\r\n.Hello\r\n".to_vec(),
+        }
+    }
+
+    /// Feed a server reply and return what the client sends next.
+    fn step(machine: &mut ClientMachine, reply: &[u8]) -> Vec<u8> {
+        let kind = machine.expected().unwrap();
+        let (rem, response) = Response::parse(kind, reply).unwrap();
+        assert!(rem.is_empty());
+        machine.handle(response).unwrap();
+        machine.poll_transmit().unwrap_or_default()
+    }
+
+    #[test]
+    fn test_submission() {
+        let credentials = Credentials {
+            username: "user".into(),
+            password: "pass".into(),
+        };
+        let mut machine =
+            ClientMachine::new(config(TlsMode::Required, Some(credentials)), message());
+
+        assert_eq!(
+            step(&mut machine, b"220 example.org ESMTP\r\n"),
+            b"EHLO client.example.org\r\n"
+        );
+        assert_eq!(machine.server_domain(), Some("example.org"));
+        assert_eq!(
+            step(&mut machine, b"250-example.org\r\n250 STARTTLS\r\n"),
+            b"STARTTLS\r\n"
+        );
+        assert!(step(&mut machine, b"220 Go ahead\r\n").is_empty());
+        assert_eq!(machine.state(), State::TlsHandshake);
+        assert!(machine.needs_tls_upgrade());
+        assert_eq!(machine.expected(), None);
+        assert!(!machine.capabilities().start_tls);
+
+        machine.tls_established();
+        assert_eq!(
+            machine.poll_transmit().unwrap(),
+            b"EHLO client.example.org\r\n"
+        );
+        assert_eq!(
+            step(&mut machine, b"250-example.org\r\n250 AUTH LOGIN PLAIN\r\n"),
+            b"AUTH PLAIN AHVzZXIAcGFzcw==\r\n"
+        );
+        assert_eq!(
+            machine.capabilities().auth,
+            Some(vec![AuthMechanism::Login, AuthMechanism::Plain])
+        );
+        assert_eq!(
+            step(&mut machine, b"235 OK\r\n"),
+            b"MAIL FROM:<a@example.org> ENVID=QQ+20314159\r\n"
+        );
+        assert_eq!(
+            step(&mut machine, b"250 OK\r\n"),
+            b"RCPT TO:<b@example.org> NOTIFY=FAILURE,DELAY\r\n"
+        );
+        assert_eq!(
+            step(&mut machine, b"550 No such user\r\n"),
+            b"RCPT TO:<c@example.org> NOTIFY=FAILURE,DELAY\r\n"
+        );
+        assert_eq!(step(&mut machine, b"250 OK\r\n"), b"DATA\r\n");
+        assert_eq!(
+            step(&mut machine, b"354 Go ahead\r\n"),
+            b"Subject: Test\r\n\r\n..Hello\r\n.\r\n"
+        );
+        assert_eq!(step(&mut machine, b"250 OK\r\n"), b"QUIT\r\n");
+        assert!(step(&mut machine, b"221 Bye\r\n").is_empty());
+        assert!(machine.is_finished());
+    }
+
+    #[test]
+    fn test_rejected() {
+        let mut machine = ClientMachine::new(config(TlsMode::None, None), message());
+
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        assert_eq!(
+            step(&mut machine, b"250 example.org\r\n"),
+            b"MAIL FROM:<a@example.org> ENVID=QQ+20314159\r\n"
+        );
+
+        let (_, response) = Response::parse_other(b"553 Not allowed\r\n").unwrap();
+        match machine.handle(response) {
+            Err(ClientError::Rejected { state, response }) => {
+                assert_eq!(state, State::Mail);
+                assert_eq!(response.code(), ReplyCode::MailboxNameNotAllowed);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(machine.poll_transmit().unwrap(), b"QUIT\r\n");
+    }
+
+    #[test]
+    fn test_tls_unavailable() {
+        let mut machine = ClientMachine::new(config(TlsMode::Required, None), message());
+
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        let (_, response) = Response::parse_ehlo(b"250 example.org\r\n").unwrap();
+        assert!(matches!(
+            machine.handle(response),
+            Err(ClientError::TlsUnavailable)
+        ));
+        assert_eq!(machine.poll_transmit().unwrap(), b"QUIT\r\n");
+    }
+
+    #[test]
+    fn test_starttls_refused() {
+        let mut machine = ClientMachine::new(config(TlsMode::Opportunistic, None), message());
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        step(&mut machine, b"250-example.org\r\n250 STARTTLS\r\n");
+        assert!(step(&mut machine, b"454 4.7.0 TLS not available\r\n")
+            .starts_with(b"MAIL FROM:<a@example.org>"));
+        assert!(!machine.tls);
+
+        let mut machine = ClientMachine::new(config(TlsMode::Required, None), message());
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        step(&mut machine, b"250-example.org\r\n250 STARTTLS\r\n");
+        let (_, response) =
+            Response::parse(ResponseKind::Other, b"454 4.7.0 TLS not available\r\n").unwrap();
+        assert!(matches!(
+            machine.handle(response),
+            Err(ClientError::Rejected {
+                state: State::StartTls,
+                ..
+            })
+        ));
+        assert_eq!(machine.poll_transmit().unwrap(), b"QUIT\r\n");
+    }
+
+    #[test]
+    fn test_debug_redacts_credentials() {
+        let credentials = Credentials {
+            username: "user".into(),
+            password: "s3cret".into(),
+        };
+        let mut machine =
+            ClientMachine::new(config(TlsMode::Implicit, Some(credentials)), message());
+
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        let (_, response) = Response::parse_ehlo(b"250-example.org\r\n250 AUTH PLAIN\r\n").unwrap();
+        machine.handle(response).unwrap();
+
+        // The pending AUTH command, and the mechanism
+        let debug = format!("{:?}", machine);
+        assert!(!debug.contains("s3cret") && !debug.contains("AHVzZXIAczNjcmV0"));
+        assert!(debug.contains("Plain"));
+        assert_eq!(
+            machine.poll_transmit().unwrap(),
+            b"AUTH PLAIN AHVzZXIAczNjcmV0\r\n"
+        );
+    }
+
+    #[test]
+    fn test_auth_without_tls() {
+        let credentials = Credentials {
+            username: "user".into(),
+            password: "pass".into(),
+        };
+        let mut machine =
+            ClientMachine::new(config(TlsMode::None, Some(credentials.clone())), message());
+
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        let (_, response) =
+            Response::parse_ehlo(b"250-example.org\r\n250 AUTH PLAIN LOGIN\r\n").unwrap();
+        assert!(matches!(
+            machine.handle(response),
+            Err(ClientError::AuthUnavailable)
+        ));
+
+        #[cfg(feature = "cram-md5")]
+        {
+            let mut machine =
+                ClientMachine::new(config(TlsMode::None, Some(credentials)), message());
+
+            step(&mut machine, b"220 example.org ESMTP\r\n");
+            assert_eq!(
+                step(
+                    &mut machine,
+                    b"250-example.org\r\n250 AUTH PLAIN CRAM-MD5\r\n"
+                ),
+                b"AUTH CRAM-MD5\r\n"
+            );
+        }
+    }
+
+    #[test]
+    fn test_auth_preference() {
+        let credentials = Credentials {
+            username: "user".into(),
+            password: "pass".into(),
+        };
+        let mut config = config(TlsMode::Implicit, Some(credentials));
+        config.mechanisms = vec![
+            AuthMechanism::Other("XOAUTH2".into()),
+            AuthMechanism::Login,
+            AuthMechanism::Plain,
+        ];
+        let mut machine = ClientMachine::new(config, message());
+
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        assert_eq!(
+            step(
+                &mut machine,
+                b"250-example.org\r\n250 AUTH XOAUTH2 PLAIN LOGIN\r\n"
+            ),
+            b"AUTH LOGIN dXNlcg==\r\n"
+        );
+    }
+
+    #[test]
+    fn test_smtputf8() {
+        let mut message = message();
+        message.recipients = vec!["jörg@bücher.example".into()];
+
+        let mut machine = ClientMachine::new(config(TlsMode::None, None), message.clone());
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        let (_, response) = Response::parse_ehlo(b"250 example.org\r\n").unwrap();
+        assert!(matches!(
+            machine.handle(response),
+            Err(ClientError::SmtpUtf8Unavailable)
+        ));
+        assert_eq!(machine.poll_transmit().unwrap(), b"QUIT\r\n");
+
+        let mut machine = ClientMachine::new(config(TlsMode::None, None), message);
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        assert_eq!(
+            step(&mut machine, b"250-example.org\r\n250 SMTPUTF8\r\n"),
+            b"MAIL FROM:<a@example.org> ENVID=QQ+20314159 SMTPUTF8\r\n"
+        );
+        assert_eq!(
+            step(&mut machine, b"250 OK\r\n"),
+            "RCPT TO:<jörg@bücher.example> NOTIFY=FAILURE,DELAY\r\n".as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_line_too_long() {
+        let mut message = message();
+        message.reverse_path = Path::new(format!("{}@example.org", "a".repeat(600))).into();
+
+        let mut machine = ClientMachine::new(config(TlsMode::None, None), message);
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        let (_, response) = Response::parse_ehlo(b"250 example.org\r\n").unwrap();
+        assert!(matches!(
+            machine.handle(response),
+            Err(ClientError::InvalidCommand(EncodeError::LineTooLong {
+                limit: 612,
+                ..
+            }))
+        ));
+        assert_eq!(machine.poll_transmit().unwrap(), b"QUIT\r\n");
+    }
+
+    #[test]
+    fn test_size() {
+        let mut machine = ClientMachine::new(config(TlsMode::None, None), message());
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        assert_eq!(
+            step(&mut machine, b"250-example.org\r\n250 SIZE 1000\r\n"),
+            b"MAIL FROM:<a@example.org> ENVID=QQ+20314159 SIZE=25\r\n"
+        );
+
+        let mut machine = ClientMachine::new(config(TlsMode::None, None), message());
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        let (_, response) = Response::parse_ehlo(b"250-example.org\r\n250 SIZE 10\r\n").unwrap();
+        assert!(matches!(
+            machine.handle(response),
+            Err(ClientError::MessageTooLarge {
+                size: 25,
+                limit: 10
+            })
+        ));
+        assert_eq!(machine.poll_transmit().unwrap(), b"QUIT\r\n");
+    }
+
+    #[test]
+    fn test_eight_bit_mime() {
+        let mut message = message();
+        message.content = "Subject: Grüße\r\n\r\nHello\r\n".into();
+
+        let mut machine = ClientMachine::new(config(TlsMode::None, None), message.clone());
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        assert_eq!(
+            step(&mut machine, b"250-example.org\r\n250 8BITMIME\r\n"),
+            b"MAIL FROM:<a@example.org> ENVID=QQ+20314159 BODY=8BITMIME\r\n"
+        );
+
+        let mut machine = ClientMachine::new(config(TlsMode::None, None), message);
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        let (_, response) = Response::parse_ehlo(b"250 example.org\r\n").unwrap();
+        assert!(matches!(
+            machine.handle(response),
+            Err(ClientError::InvalidContent(DataError::EightBitContent))
+        ));
+        assert_eq!(machine.poll_transmit().unwrap(), b"QUIT\r\n");
+    }
+
+    #[test]
+    fn test_require_tls() {
+        let mut message = message();
+        message.mail_parameters.push(EsmtpParam::RequireTls);
+
+        let mut machine = ClientMachine::new(config(TlsMode::Opportunistic, None), message.clone());
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        let (_, response) = Response::parse_ehlo(b"250-example.org\r\n250 REQUIRETLS\r\n").unwrap();
+        assert!(matches!(
+            machine.handle(response),
+            Err(ClientError::RequireTlsUnavailable)
+        ));
+        assert_eq!(machine.poll_transmit().unwrap(), b"QUIT\r\n");
+
+        let mut machine = ClientMachine::new(config(TlsMode::Opportunistic, None), message);
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        step(&mut machine, b"250-example.org\r\n250 STARTTLS\r\n");
+        step(&mut machine, b"220 Go ahead\r\n");
+        machine.tls_established();
+        machine.poll_transmit();
+        assert_eq!(
+            step(&mut machine, b"250-example.org\r\n250 REQUIRETLS\r\n"),
+            b"MAIL FROM:<a@example.org> ENVID=QQ+20314159 REQUIRETLS\r\n"
+        );
+    }
+
+    #[test]
+    fn test_implicit_tls() {
+        let mut message = message();
+        message.mail_parameters.push(EsmtpParam::RequireTls);
+
+        let mut machine = ClientMachine::new(config(TlsMode::Implicit, None), message);
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        assert_eq!(
+            step(
+                &mut machine,
+                b"250-example.org\r\n250-STARTTLS\r\n250 REQUIRETLS\r\n"
+            ),
+            b"MAIL FROM:<a@example.org> ENVID=QQ+20314159 REQUIRETLS\r\n"
+        );
+    }
+
+    #[test]
+    fn test_presets() {
+        let identity = HeloIdentity::Domain("client.example.org".into());
+        let credentials = Credentials {
+            username: "user".into(),
+            password: "pass".into(),
+        };
+
+        let relay = ClientConfig::relay_port_25(identity.clone());
+        assert_eq!(
+            (relay.tls, relay.credentials),
+            (TlsMode::Opportunistic, None)
+        );
+        let submission = ClientConfig::submission_587(identity.clone(), credentials.clone());
+        assert_eq!(submission.tls, TlsMode::Required);
+        assert_eq!(submission.timeouts, Timeouts::default());
+
+        // PLAIN is sent at once, as the connection uses TLS.
+        let config = ClientConfig::smtps_465(identity, credentials);
+        let mut machine = ClientMachine::new(config, message());
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        assert_eq!(
+            step(&mut machine, b"250-example.org\r\n250 AUTH PLAIN\r\n"),
+            b"AUTH PLAIN AHVzZXIAcGFzcw==\r\n"
+        );
+    }
+
+    #[test]
+    fn test_transaction_result() {
+        let mut machine = ClientMachine::new(config(TlsMode::None, None), message());
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        step(&mut machine, b"250 example.org\r\n");
+        step(&mut machine, b"250 OK\r\n");
+        step(&mut machine, b"550 5.1.1 No such user\r\n");
+        step(&mut machine, b"250 OK\r\n");
+        step(&mut machine, b"354 Go ahead\r\n");
+        assert_eq!(machine.result().message, MessageOutcome::NotSent);
+        step(&mut machine, b"250 Queued\r\n");
+
+        let result = machine.result();
+        let rejected: Vec<_> = result.rejected().collect();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].recipient, "b@example.org");
+        assert_eq!(
+            rejected[0].response.code(),
+            ReplyCode::MailboxPermanentlyUnavailable
+        );
+        assert_eq!(result.accepted().next().unwrap().recipient, "c@example.org");
+        assert!(matches!(result.message, MessageOutcome::Accepted(_)));
+        assert!(result.is_partial());
+
+        let mut machine = ClientMachine::new(config(TlsMode::None, None), message());
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        step(&mut machine, b"250 example.org\r\n");
+        step(&mut machine, b"250 OK\r\n");
+        step(&mut machine, b"250 OK\r\n");
+        step(&mut machine, b"250 OK\r\n");
+        step(&mut machine, b"354 Go ahead\r\n");
+        let (_, response) = Response::parse_other(b"554 5.6.0 Rejected\r\n").unwrap();
+        assert!(machine.handle(response).is_err());
+
+        let result = machine.result();
+        assert_eq!(result.accepted().count(), 2);
+        assert!(matches!(result.message, MessageOutcome::Rejected(_)));
+        assert!(!result.is_partial());
+        assert_eq!(machine.poll_aborted(), None);
+    }
+
+    #[test]
+    fn test_aborted() {
+        let mut message = message();
+        message.recipients.truncate(1);
+        let mut machine = ClientMachine::new(config(TlsMode::None, None), message);
+        step(&mut machine, b"220 example.org ESMTP\r\n");
+        step(&mut machine, b"250 example.org\r\n");
+        step(&mut machine, b"250 OK\r\n");
+        assert_eq!(machine.poll_aborted(), None);
+
+        let (_, response) = Response::parse_other(b"550 No such user\r\n").unwrap();
+        assert!(machine.handle(response).is_err());
+        assert_eq!(machine.poll_transmit().unwrap(), b"QUIT\r\n");
+        assert_eq!(
+            machine.poll_aborted(),
+            Some(TransactionAborted { state: State::Rcpt })
+        );
+        assert_eq!(machine.poll_aborted(), None);
+        assert_eq!(machine.result().message, MessageOutcome::NotSent);
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let minutes = |minutes: u64| Duration::from_secs(60 * minutes);
+        let mut message = message();
+        message.recipients.truncate(1);
+
+        let mut machine = ClientMachine::new(config(TlsMode::None, None), message);
+        let mut timers = vec![];
+        for reply in [
+            &b"220 example.org ESMTP\r\n"[..],
+            b"250 example.org\r\n",
+            b"250 OK\r\n",
+            b"250 OK\r\n",
+            b"354 Go ahead\r\n",
+        ] {
+            timers.extend(core::iter::from_fn(|| machine.poll_timeout()));
+            step(&mut machine, reply);
+        }
+        timers.extend(core::iter::from_fn(|| machine.poll_timeout()));
+
+        let reply = |state, duration| TimeoutEvent::Reply { state, duration };
+        assert_eq!(
+            timers,
+            [
+                reply(State::Greeting, minutes(5)),
+                reply(State::Ehlo, minutes(5)),
+                reply(State::Mail, minutes(5)),
+                reply(State::Rcpt, minutes(5)),
+                reply(State::Data, minutes(2)),
+                TimeoutEvent::DataBlock {
+                    duration: minutes(3)
+                },
+                reply(State::Content, minutes(10)),
+            ]
+        );
+
+        assert!(matches!(
+            machine.handle_timeout(),
+            ClientError::Timeout {
+                state: State::Content
+            }
+        ));
+        assert!(machine.is_finished());
+        assert_eq!(machine.poll_transmit(), None);
+        assert_eq!(machine.poll_timeout(), None);
+    }
+}
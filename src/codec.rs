@@ -0,0 +1,656 @@
+//! Framing of SMTP commands and replies for `tokio_util::codec`.
+//!
+//! [`SmtpCodec<Command>`] is meant for servers: it decodes commands and encodes replies.
+//! [`SmtpCodec<Response>`] is meant for clients: it decodes replies and encodes commands.
+//!
+//! Both use the streaming parsers of this crate, i.e., incomplete input is left in the buffer
+//! until more data is available.
+//!
+//! After a BDAT command, the server codec passes through the declared number of octets as
+//! [`Frame::Chunk`]s without parsing them. After encoding a "334" challenge, it decodes the next
+//! line as [`Frame::Continuation`]. After encoding a "354" reply, it decodes the message content
+//! up to the end-of-data indicator as [`Frame::Data`], without dot-stuffing.
+
+use std::{fmt, io, marker::PhantomData};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{
+    data::{DataError, DotStuffDecoder, Status},
+    sasl::Continuation,
+    Command, ParseResult, ReplyCode, Response, ResponseKind, SmtpParseError, SyntaxOptions,
+};
+
+#[derive(Debug)]
+pub struct SmtpCodec<T> {
+    expected: ResponseKind,
+    /// Octets of the current BDAT chunk that were not decoded yet
+    chunk: u64,
+    /// A "334" challenge was sent, the next line is the answer of the client
+    continuation: bool,
+    /// A "354" reply was sent, the message content follows up to the end of data.
+    data: Option<DotStuffDecoder>,
+    /// A line exceeded the limit, and is dropped up to the next CRLF.
+    discarding: bool,
+    options: SyntaxOptions,
+    item: PhantomData<fn() -> T>,
+}
+
+/// Item decoded by [`SmtpCodec<Command>`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Frame {
+    Command(Command),
+    /// Message content following BDAT, possibly split into several frames.
+    ///
+    /// `remaining` is the number of octets of the chunk that are still to come.
+    Chunk {
+        data: Bytes,
+        remaining: u64,
+    },
+    /// Answer to a "334" challenge during AUTH
+    Continuation(Continuation),
+    /// Message content following DATA, possibly split into several frames.
+    ///
+    /// `last` is set on the frame that ends with the end-of-data indicator, which is not part of
+    /// `data`.
+    Data {
+        data: Bytes,
+        last: bool,
+    },
+}
+
+impl<T> SmtpCodec<T> {
+    pub fn new() -> Self {
+        Self::with_options(SyntaxOptions::default())
+    }
+
+    /// Parse with the given options, e.g., to accept SMTPUTF8, and apply their limits to the
+    /// lines.
+    pub fn with_options(options: SyntaxOptions) -> Self {
+        Self {
+            expected: ResponseKind::Greeting,
+            chunk: 0,
+            continuation: false,
+            data: None,
+            discarding: false,
+            options,
+            item: PhantomData,
+        }
+    }
+}
+
+impl<T> Default for SmtpCodec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SmtpCodec<Response> {
+    /// Kind of reply the next call to `decode` will parse.
+    ///
+    /// A new codec expects a greeting.
+    pub fn expected(&self) -> ResponseKind {
+        self.expected
+    }
+
+    /// Set the kind of reply to parse next, e.g., [`ResponseKind::Ehlo`] after sending EHLO.
+    pub fn expect(&mut self, kind: ResponseKind) {
+        self.expected = kind;
+    }
+}
+
+impl Decoder for SmtpCodec<Command> {
+    type Item = Frame;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if self.discarding {
+            if !discard_line(src) {
+                return Ok(None);
+            }
+            self.discarding = false;
+        }
+
+        if let Some(decoder) = &mut self.data {
+            if src.is_empty() {
+                return Ok(None);
+            }
+
+            let mut data = Vec::new();
+            let last = match decoder.decode(src, &mut data).map_err(CodecError::Data)? {
+                Status::Incomplete => {
+                    src.clear();
+                    false
+                }
+                Status::Complete { consumed } => {
+                    src.advance(consumed);
+                    self.data = None;
+                    true
+                }
+            };
+            if data.is_empty() && !last {
+                return Ok(None);
+            }
+
+            return Ok(Some(Frame::Data {
+                data: data.into(),
+                last,
+            }));
+        }
+
+        if self.chunk > 0 {
+            if src.is_empty() {
+                return Ok(None);
+            }
+
+            let length = src
+                .len()
+                .min(usize::try_from(self.chunk).unwrap_or(usize::MAX));
+            self.chunk -= length as u64;
+
+            return Ok(Some(Frame::Chunk {
+                data: src.split_to(length).freeze(),
+                remaining: self.chunk,
+            }));
+        }
+
+        if self.continuation {
+            let limit = self.options.limits.auth;
+            if src.len() > limit && crate::find_crlf(src).is_none() {
+                return Err(self.line_too_long(src, limit));
+            }
+            let continuation = decode_with(src, Continuation::from_bytes, line_end)?;
+
+            if continuation.is_some() {
+                self.continuation = false;
+            }
+
+            return Ok(continuation.map(Frame::Continuation));
+        }
+
+        let options = self.options;
+        let command = match decode_with(
+            src,
+            |input| Command::from_bytes_with(input, options),
+            line_end,
+        ) {
+            Err(CodecError::LineTooLong { limit }) => return Err(self.line_too_long(src, limit)),
+            result => result?,
+        };
+
+        // The chunk follows the command, even when the server rejects it. (See RFC 3030, 2.)
+        if let Some(Command::Bdat { size, .. }) = command {
+            self.chunk = size;
+        }
+
+        Ok(command.map(Frame::Command))
+    }
+}
+
+impl SmtpCodec<Command> {
+    /// Drop a line that exceeds the limit without waiting for its end, so that the buffer
+    /// does not grow without bound.
+    fn line_too_long(&mut self, src: &mut BytesMut, limit: usize) -> CodecError {
+        self.discarding = !discard_line(src);
+        CodecError::LineTooLong { limit }
+    }
+}
+
+impl Decoder for SmtpCodec<Response> {
+    type Item = Response;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let (expected, options) = (self.expected, self.options);
+        let response = decode_with(
+            src,
+            |input| Response::parse_with(expected, input, options),
+            reply_end,
+        )?;
+
+        // Only the first reply of a session is a greeting.
+        if response.is_some() && expected == ResponseKind::Greeting {
+            self.expected = ResponseKind::Other;
+        }
+
+        Ok(response)
+    }
+}
+
+impl Encoder<Response> for SmtpCodec<Command> {
+    type Error = CodecError;
+
+    fn encode(&mut self, item: Response, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.continuation = item.code() == ReplyCode::AUTH_CHALLENGE_334;
+        if item.code() == ReplyCode::START_MAIL_INPUT_354 {
+            self.data = Some(DotStuffDecoder::new());
+        }
+        item.serialize(&mut dst.writer())?;
+        Ok(())
+    }
+}
+
+impl Encoder<Command> for SmtpCodec<Response> {
+    type Error = CodecError;
+
+    fn encode(&mut self, item: Command, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        item.serialize(&mut dst.writer())?;
+        Ok(())
+    }
+}
+
+/// Parse an item, or drop the invalid input up to the end of the item at `end`.
+fn decode_with<O>(
+    src: &mut BytesMut,
+    parser: impl FnOnce(&[u8]) -> ParseResult<'_, O>,
+    end: fn(&[u8]) -> Option<usize>,
+) -> Result<Option<O>, CodecError> {
+    match parser(src) {
+        Ok((remaining, item)) => {
+            let consumed = src.len() - remaining.len();
+            src.advance(consumed);
+            Ok(Some(item))
+        }
+        Err(SmtpParseError::UnexpectedEof { .. }) => Ok(None),
+        Err(SmtpParseError::LineTooLong { limit }) if end(src).is_none() => {
+            Err(CodecError::LineTooLong { limit })
+        }
+        Err(_) => {
+            // Wait for the rest of the offending line or reply, then drop it, so that decoding
+            // can continue with the next one.
+            match end(src) {
+                Some(end) => Err(CodecError::Syntax(src.split_to(end).to_vec())),
+                None => Ok(None),
+            }
+        }
+    }
+}
+
+/// Drop the input up to and including the next CRLF. Returns whether it was found.
+fn discard_line(src: &mut BytesMut) -> bool {
+    match line_end(src) {
+        Some(end) => {
+            src.advance(end);
+            true
+        }
+        None => {
+            // Keep a CR that may be followed by LF.
+            let keep = usize::from(src.last() == Some(&b'\r'));
+            src.advance(src.len() - keep);
+            false
+        }
+    }
+}
+
+/// The end of the first line, including CRLF
+fn line_end(input: &[u8]) -> Option<usize> {
+    crate::find_crlf(input).map(|position| position + 2)
+}
+
+/// The end of the first reply, i.e., of the first line whose code is not followed by "-"
+fn reply_end(input: &[u8]) -> Option<usize> {
+    let mut end = 0;
+    loop {
+        let line = &input[end..end + crate::find_crlf(&input[end..])?];
+        end += line.len() + 2;
+        if line.get(3) != Some(&b'-') {
+            return Some(end);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+    Io(io::Error),
+    /// A line could not be parsed.
+    ///
+    /// The line (including CRLF) was removed from the buffer, or all lines of a reply that
+    /// could not be parsed.
+    Syntax(Vec<u8>),
+    /// A line exceeds the limit of [`Limits`](crate::Limits), see [`SmtpParseError::LineTooLong`].
+    ///
+    /// The line is dropped up to the next CRLF, and decoding continues with the line after it.
+    LineTooLong {
+        limit: usize,
+    },
+    /// The message content following DATA was rejected.
+    ///
+    /// The error is not recoverable, see [`DotStuffDecoder::decode`].
+    Data(DataError),
+}
+
+impl From<io::Error> for CodecError {
+    fn from(error: io::Error) -> Self {
+        CodecError::Io(error)
+    }
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::Io(error) => write!(f, "{}", error),
+            CodecError::Syntax(line) => {
+                write!(f, "syntax error in {:?}", String::from_utf8_lossy(line))
+            }
+            CodecError::LineTooLong { limit } => {
+                write!(f, "line exceeds limit of {} octets", limit)
+            }
+            CodecError::Data(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CodecError::Io(error) => Some(error),
+            CodecError::Data(error) => Some(error),
+            CodecError::Syntax(_) | CodecError::LineTooLong { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HeloIdentity, Limits, TextString};
+
+    #[test]
+    fn test_decode_command() {
+        let mut codec = SmtpCodec::<Command>::new();
+        let mut buf = BytesMut::from(&b"EHLO exam"[..]);
+
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+
+        buf.extend_from_slice(b"ple.org\r\nNOOP\r\nQU");
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Command(Command::Ehlo {
+                domain_or_address: HeloIdentity::Domain("example.org".into()),
+            }))
+        );
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Command(Command::Noop { argument: None }))
+        );
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        assert_eq!(buf.as_ref(), b"QU");
+    }
+
+    #[test]
+    fn test_decode_invalid_command() {
+        let mut codec = SmtpCodec::<Command>::new();
+        let mut buf = BytesMut::from(&b"XYZ:"[..]);
+
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+
+        buf.extend_from_slice(b" abc\r\nQUIT\r\n");
+        match codec.decode(&mut buf) {
+            Err(CodecError::Syntax(line)) => assert_eq!(line, b"XYZ: abc\r\n"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Command(Command::Quit))
+        );
+    }
+
+    #[test]
+    fn test_decode_line_too_long() {
+        let mut codec = SmtpCodec::<Command>::new();
+        let limit = Limits::default().auth;
+        let mut buf = BytesMut::from(&b"NOOP "[..]);
+        buf.extend_from_slice(&vec![b'a'; limit]);
+
+        // The line is dropped without waiting for CRLF.
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(CodecError::LineTooLong { limit: 12288 })
+        ));
+        assert!(buf.is_empty());
+
+        buf.extend_from_slice(b"aaaa\r");
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        assert_eq!(buf.as_ref(), b"\r");
+        buf.extend_from_slice(b"\nQUIT\r\n");
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Command(Command::Quit))
+        );
+    }
+
+    #[test]
+    fn test_with_options() {
+        let line = "MAIL FROM:<j\u{f6}rg@b\u{fc}cher.example>\r\n";
+
+        let mut codec = SmtpCodec::<Command>::new();
+        let mut buf = BytesMut::from(line);
+        assert!(matches!(codec.decode(&mut buf), Err(CodecError::Syntax(_))));
+
+        let mut codec = SmtpCodec::<Command>::with_options(SyntaxOptions {
+            smtputf8: true,
+            limits: Limits {
+                command: 40,
+                auth: 64,
+                ..Limits::default()
+            },
+            ..SyntaxOptions::default()
+        });
+        let mut buf = BytesMut::from(line);
+        assert!(matches!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Command(Command::Mail { .. }))
+        ));
+        buf.extend_from_slice(b"NOOP ");
+        buf.extend_from_slice(&[b'a'; 64]);
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(CodecError::LineTooLong { limit: 64 })
+        ));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_decode_bdat() {
+        let mut codec = SmtpCodec::<Command>::new();
+        let mut buf = BytesMut::from(&b"BDAT 12 LAST\r\nQUIT\r\n"[..]);
+
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Command(Command::Bdat {
+                size: 12,
+                last: true
+            }))
+        );
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Chunk {
+                data: Bytes::from_static(b"QUIT\r\n"),
+                remaining: 6,
+            })
+        );
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+
+        buf.extend_from_slice(b"QUIT\r\nQUIT\r\n");
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Chunk {
+                data: Bytes::from_static(b"QUIT\r\n"),
+                remaining: 0,
+            })
+        );
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Command(Command::Quit))
+        );
+    }
+
+    #[test]
+    fn test_decode_continuation() {
+        let mut codec = SmtpCodec::<Command>::new();
+        let mut buf = BytesMut::from(&b"AUTH LOGIN\r\n"[..]);
+
+        assert!(matches!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Command(Command::Auth { .. }))
+        ));
+
+        codec
+            .encode(
+                crate::sasl::challenge_response(b"Username:"),
+                &mut BytesMut::new(),
+            )
+            .unwrap();
+        buf.extend_from_slice(b"dXNlcg==\r\nQUIT\r\n");
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Continuation(Continuation::Response(
+                b"user".to_vec().into()
+            )))
+        );
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Command(Command::Quit))
+        );
+    }
+
+    #[test]
+    fn test_decode_data() {
+        let mut codec = SmtpCodec::<Command>::new();
+        let mut buf = BytesMut::from(&b"DATA\r\n"[..]);
+
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Command(Command::Data))
+        );
+
+        codec
+            .encode(
+                Response::Other {
+                    code: ReplyCode::START_MAIL_INPUT_354,
+                    lines: vec![TextString::new("Go").unwrap()],
+                },
+                &mut BytesMut::new(),
+            )
+            .unwrap();
+        buf.extend_from_slice(b"From: fred\r\n\r\n..QUIT\r\n.");
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Data {
+                data: Bytes::from_static(b"From: fred\r\n\r\n.QUIT\r\n"),
+                last: false,
+            })
+        );
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+
+        buf.extend_from_slice(b"\r\nQUIT\r\n");
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Data {
+                data: Bytes::new(),
+                last: true,
+            })
+        );
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Command(Command::Quit))
+        );
+    }
+
+    #[test]
+    fn test_decode_data_bare_dot_line() {
+        let mut codec = SmtpCodec::<Command>::new();
+        codec
+            .encode(
+                Response::Other {
+                    code: ReplyCode::START_MAIL_INPUT_354,
+                    lines: vec![TextString::new("Go").unwrap()],
+                },
+                &mut BytesMut::new(),
+            )
+            .unwrap();
+        let mut buf = BytesMut::from(&b"Hello\n.\nQUIT\r\n"[..]);
+
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(CodecError::Data(DataError::BareDotLine))
+        ));
+    }
+
+    #[test]
+    fn test_decode_response() {
+        let mut codec = SmtpCodec::<Response>::new();
+        let mut buf = BytesMut::from(&b"220 example.org ESMTP\r\n250-example.org\r\n250-"[..]);
+
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Response::greeting("example.org", "ESMTP"))
+        );
+        assert_eq!(codec.expected(), ResponseKind::Other);
+
+        codec.expect(ResponseKind::Ehlo);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+
+        buf.extend_from_slice(b"8BITMIME\r\n250 STARTTLS\r\n354 Go ahead\r\n");
+        assert!(matches!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Response::Ehlo { .. })
+        ));
+
+        codec.expect(ResponseKind::Other);
+        match codec.decode(&mut buf).unwrap() {
+            Some(Response::Other { code, .. }) => assert_eq!(code, ReplyCode::StartMailInput),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_decode_invalid_response() {
+        let mut codec = SmtpCodec::<Response>::new();
+        codec.expect(ResponseKind::Other);
+        let mut buf = BytesMut::from(&b"250-a\r\n251 b\r\n250 ok\r\n"[..]);
+
+        // The whole reply is dropped, not only its first line.
+        match codec.decode(&mut buf) {
+            Err(CodecError::Syntax(reply)) => assert_eq!(reply, b"250-a\r\n251 b\r\n"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Response::Other {
+                code: ReplyCode::Ok,
+                lines: vec![TextString::new("ok").unwrap()],
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_non_ascii_response() {
+        let mut codec = SmtpCodec::<Response>::new();
+        codec.expect(ResponseKind::Other);
+        let mut buf = BytesMut::from(&b"250-Caf\xc3\xa9\r\n250 ok\r\n354 Go ahead\r\n"[..]);
+
+        // UTF-8 requires SMTPUTF8, and the continuation line is not a reply of its own.
+        assert!(matches!(codec.decode(&mut buf), Err(CodecError::Syntax(_))));
+        assert_eq!(
+            codec.decode(&mut buf).unwrap().unwrap().code(),
+            ReplyCode::StartMailInput
+        );
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut buf = BytesMut::new();
+
+        SmtpCodec::<Response>::new()
+            .encode(Command::Quit, &mut buf)
+            .unwrap();
+        SmtpCodec::<Command>::new()
+            .encode(Response::greeting("example.org", ""), &mut buf)
+            .unwrap();
+
+        assert_eq!(buf.as_ref(), b"QUIT\r\n220 example.org\r\n");
+    }
+}
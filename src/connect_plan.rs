@@ -0,0 +1,304 @@
+//! Order of the hosts and addresses to connect to for a domain (RFC 5321, 5.1.)
+//!
+//! The caller looks up the MX records of the domain and the addresses of each host, and
+//! [`Planner::plan`] orders the attempts:
+//!
+//! ```
+//! use std::net::IpAddr;
+//!
+//! use instant_smtp::connect_plan::{MxRecord, Planner};
+//!
+//! let address = |address: &str| address.parse::<IpAddr>().unwrap();
+//! let records = [
+//!     MxRecord::new("backup.example.org", 20, [address("192.0.2.2")]),
+//!     MxRecord::new("mx.example.org", 10, [address("2001:db8::1"), address("192.0.2.1")]),
+//! ];
+//!
+//! let plan = Planner::default()
+//!     .plan("example.org", &records, &[])
+//!     .unwrap();
+//! let order: Vec<_> = plan.iter().map(|attempt| attempt.address).collect();
+//! assert_eq!(
+//!     order,
+//!     [address("2001:db8::1"), address("192.0.2.1"), address("192.0.2.2")]
+//! );
+//! ```
+//!
+//! DNS is out of scope. Hosts of equal preference should be tried in random order, so the
+//! caller shuffles records of equal preference beforehand, which [`Planner::plan`] keeps.
+
+use alloc::{borrow::ToOwned, collections::BTreeSet, string::String, vec::Vec};
+use core::{fmt, net::IpAddr};
+
+/// An MX record with the addresses of its host
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MxRecord {
+    pub host: String,
+    pub preference: u16,
+    /// The A and AAAA records of the host
+    pub addresses: Vec<IpAddr>,
+}
+
+impl MxRecord {
+    pub fn new(
+        host: impl Into<String>,
+        preference: u16,
+        addresses: impl IntoIterator<Item = IpAddr>,
+    ) -> Self {
+        Self {
+            host: host.into(),
+            preference,
+            addresses: addresses.into_iter().collect(),
+        }
+    }
+
+    /// Whether the record is a "null MX", i.e., "0 .", which denotes that the domain does not
+    /// accept mail (RFC 7505)
+    pub fn is_null(&self) -> bool {
+        self.host.is_empty() || self.host == "."
+    }
+}
+
+/// A connection attempt
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attempt {
+    /// The host name, e.g., for the verification of its certificate
+    pub host: String,
+    pub preference: u16,
+    pub address: IpAddr,
+}
+
+/// How to order the attempts
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Planner {
+    /// The names of the sending host. When a record names one of them, the records of equal or
+    /// lower preference are skipped, as they would deliver to the sender itself (RFC 5321,
+    /// 5.1.).
+    pub local_names: Vec<String>,
+    /// Alternate between IPv6 and IPv4 addresses of the same host, starting with IPv6 (RFC
+    /// 8305, 4.), instead of keeping the order of the addresses
+    pub interleave: bool,
+    /// Maximum number of attempts, if any
+    pub max_attempts: Option<usize>,
+}
+
+impl Default for Planner {
+    fn default() -> Self {
+        Self {
+            local_names: Vec::new(),
+            interleave: true,
+            max_attempts: None,
+        }
+    }
+}
+
+impl Planner {
+    /// Order the attempts for `domain`.
+    ///
+    /// Without MX records, the domain itself is the only host, with `domain_addresses`
+    /// ("implicit MX"). The records are tried in order of preference, lowest first. An address
+    /// is only tried once, even if several hosts share it.
+    pub fn plan(
+        &self,
+        domain: &str,
+        records: &[MxRecord],
+        domain_addresses: &[IpAddr],
+    ) -> Result<Vec<Attempt>, PlanError> {
+        let implicit;
+        let records = match records {
+            [] => {
+                implicit = [MxRecord::new(domain, 0, domain_addresses.iter().copied())];
+                &implicit[..]
+            }
+            [record] if record.is_null() => return Err(PlanError::NullMx),
+            records => records,
+        };
+
+        let mut records: Vec<&MxRecord> =
+            records.iter().filter(|record| !record.is_null()).collect();
+        // The sort is stable, so records of equal preference keep their order.
+        records.sort_by_key(|record| record.preference);
+
+        if let Some(local) = records
+            .iter()
+            .find(|record| self.is_local(&record.host))
+            .map(|record| record.preference)
+        {
+            records.retain(|record| record.preference < local);
+            if records.is_empty() {
+                return Err(PlanError::Loop);
+            }
+        }
+
+        let mut seen = BTreeSet::new();
+        let mut attempts = Vec::new();
+        for record in records {
+            for address in self.order(&record.addresses) {
+                if seen.insert(address) {
+                    attempts.push(Attempt {
+                        host: record.host.trim_end_matches('.').to_owned(),
+                        preference: record.preference,
+                        address,
+                    });
+                }
+            }
+        }
+
+        if let Some(max) = self.max_attempts {
+            attempts.truncate(max);
+        }
+        match attempts.is_empty() {
+            true => Err(PlanError::NoAddresses),
+            false => Ok(attempts),
+        }
+    }
+
+    fn is_local(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.');
+        self.local_names
+            .iter()
+            .any(|name| name.trim_end_matches('.').eq_ignore_ascii_case(host))
+    }
+
+    fn order(&self, addresses: &[IpAddr]) -> Vec<IpAddr> {
+        if !self.interleave {
+            return addresses.to_vec();
+        }
+
+        let (mut v6, mut v4): (Vec<_>, Vec<_>) =
+            addresses.iter().copied().partition(IpAddr::is_ipv6);
+        v6.reverse();
+        v4.reverse();
+
+        let mut ordered = Vec::with_capacity(addresses.len());
+        while !v6.is_empty() || !v4.is_empty() {
+            ordered.extend(v6.pop());
+            ordered.extend(v4.pop());
+        }
+        ordered
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PlanError {
+    /// The domain has a "null MX" and does not accept mail.
+    NullMx,
+    /// The most preferred record names the sending host.
+    Loop,
+    /// None of the hosts has an address.
+    NoAddresses,
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::NullMx => write!(f, "domain does not accept mail (null MX)"),
+            PlanError::Loop => write!(f, "most preferred MX is the local host"),
+            PlanError::NoAddresses => write!(f, "no MX host has an address"),
+        }
+    }
+}
+
+impl core::error::Error for PlanError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn address(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    fn addresses(plan: &[Attempt]) -> Vec<IpAddr> {
+        plan.iter().map(|attempt| attempt.address).collect()
+    }
+
+    #[test]
+    fn test_plan() {
+        let records = [
+            MxRecord::new("c.example.org", 20, [address("192.0.2.3")]),
+            MxRecord::new("b.example.org.", 10, [address("192.0.2.2")]),
+            MxRecord::new(
+                "a.example.org",
+                10,
+                [address("192.0.2.1"), address("192.0.2.2")],
+            ),
+            MxRecord::new(
+                "d.example.org",
+                30,
+                [
+                    address("192.0.2.4"),
+                    address("192.0.2.5"),
+                    address("2001:db8::4"),
+                ],
+            ),
+        ];
+
+        let plan = Planner::default()
+            .plan("example.org", &records, &[address("192.0.2.9")])
+            .unwrap();
+        assert_eq!(
+            addresses(&plan),
+            [
+                address("192.0.2.2"),
+                address("192.0.2.1"),
+                address("192.0.2.3"),
+                address("2001:db8::4"),
+                address("192.0.2.4"),
+                address("192.0.2.5"),
+            ]
+        );
+        assert_eq!(plan[0].host, "b.example.org");
+        assert_eq!(plan[1].host, "a.example.org");
+
+        let planner = Planner {
+            local_names: vec!["C.example.org".into()],
+            interleave: false,
+            max_attempts: Some(1),
+        };
+        let plan = planner.plan("example.org", &records, &[]).unwrap();
+        assert_eq!(addresses(&plan), [address("192.0.2.2")]);
+        assert_eq!(
+            planner.plan("example.org", &records[..1], &[]),
+            Err(PlanError::Loop)
+        );
+    }
+
+    #[test]
+    fn test_plan_implicit_mx() {
+        let planner = Planner::default();
+
+        let plan = planner
+            .plan("example.org", &[], &[address("192.0.2.1")])
+            .unwrap();
+        assert_eq!(
+            plan,
+            [Attempt {
+                host: "example.org".into(),
+                preference: 0,
+                address: address("192.0.2.1"),
+            }]
+        );
+        assert_eq!(
+            planner.plan("example.org", &[], &[]),
+            Err(PlanError::NoAddresses)
+        );
+        // MX records without addresses do not fall back to the domain.
+        assert_eq!(
+            planner.plan(
+                "example.org",
+                &[MxRecord::new("mx.example.org", 10, [])],
+                &[address("192.0.2.1")]
+            ),
+            Err(PlanError::NoAddresses)
+        );
+        assert_eq!(
+            planner.plan(
+                "example.org",
+                &[MxRecord::new(".", 0, [])],
+                &[address("192.0.2.1")]
+            ),
+            Err(PlanError::NullMx)
+        );
+    }
+}
@@ -0,0 +1,309 @@
+//! Replies of deployed servers, to test clients against
+//!
+//! The samples are greetings, EHLO replies, and other replies as sent by common MTAs and mail
+//! providers, including their quirks, e.g., the "AUTH=" keyword of old Exchange versions, or
+//! the two spaces Gmail puts after enhanced status codes. Client addresses and the names of
+//! self-hosted servers are replaced with reserved ones:
+//!
+//! ```
+//! use instant_smtp::{corpus, Response};
+//!
+//! for sample in corpus::by_server("Postfix") {
+//!     let (rest, _) = Response::parse(sample.kind, sample.bytes).unwrap();
+//!     assert!(rest.is_empty());
+//! }
+//! ```
+
+use crate::ResponseKind::{self, Ehlo, Greeting, Other};
+
+/// A reply as sent by a server
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sample {
+    /// The software or provider, e.g., "Postfix" or "Gmail"
+    pub server: &'static str,
+    /// What the sample shows
+    pub description: &'static str,
+    pub kind: ResponseKind,
+    /// The reply including the final CRLF
+    pub bytes: &'static [u8],
+}
+
+impl Sample {
+    const fn new(
+        server: &'static str,
+        description: &'static str,
+        kind: ResponseKind,
+        bytes: &'static [u8],
+    ) -> Self {
+        Self {
+            server,
+            description,
+            kind,
+            bytes,
+        }
+    }
+}
+
+/// All samples, grouped by server
+pub static SAMPLES: &[Sample] = &[
+    // Postfix
+    Sample::new(
+        "Postfix",
+        "greeting",
+        Greeting,
+        b"220 mx.example.org ESMTP Postfix (Debian/GNU)\r\n",
+    ),
+    Sample::new(
+        "Postfix",
+        "EHLO",
+        Ehlo,
+        b"250-mx.example.org\r\n250-PIPELINING\r\n250-SIZE 10240000\r\n250-VRFY\r\n\
+250-ETRN\r\n250-STARTTLS\r\n250-ENHANCEDSTATUSCODES\r\n250-8BITMIME\r\n250-DSN\r\n\
+250-SMTPUTF8\r\n250 CHUNKING\r\n",
+    ),
+    Sample::new(
+        "Postfix",
+        "relay denied",
+        Other,
+        b"554 5.7.1 <fred@example.com>: Relay access denied\r\n",
+    ),
+    Sample::new(
+        "Postfix",
+        "postscreen rejection",
+        Other,
+        b"421 4.7.0 mx.example.org Error: too many connections from 192.0.2.1\r\n",
+    ),
+    Sample::new(
+        "Postfix",
+        "queued",
+        Other,
+        b"250 2.0.0 Ok: queued as 4XyZ1b2C3dz9vK\r\n",
+    ),
+    // Exim
+    Sample::new(
+        "Exim",
+        "greeting with date",
+        Greeting,
+        b"220 mail.example.org ESMTP Exim 4.96 Wed, 14 Oct 2026 12:34:56 +0000\r\n",
+    ),
+    Sample::new(
+        "Exim",
+        "EHLO",
+        Ehlo,
+        b"250-mail.example.org Hello client.example.com [192.0.2.1]\r\n250-SIZE 52428800\r\n\
+250-8BITMIME\r\n250-PIPELINING\r\n250-PIPE_CONNECT\r\n250-AUTH PLAIN LOGIN\r\n\
+250-CHUNKING\r\n250-STARTTLS\r\n250-PRDR\r\n250 HELP\r\n",
+    ),
+    Sample::new(
+        "Exim",
+        "multiline sender verification failure",
+        Other,
+        b"550-Verification failed for <fred@example.com>\r\n550-Unrouteable address\r\n\
+550 Sender verify failed\r\n",
+    ),
+    Sample::new(
+        "Exim",
+        "queued",
+        Other,
+        b"250 OK id=1tA2b3-000ABC-4d\r\n",
+    ),
+    // Sendmail
+    Sample::new(
+        "Sendmail",
+        "greeting with version and date",
+        Greeting,
+        b"220 mail.example.org ESMTP Sendmail 8.17.1/8.17.1; Wed, 14 Oct 2026 12:34:56 GMT\r\n",
+    ),
+    Sample::new(
+        "Sendmail",
+        "EHLO with a SIZE without limit",
+        Ehlo,
+        b"250-mail.example.org Hello client.example.com [192.0.2.1], pleased to meet you\r\n\
+250-ENHANCEDSTATUSCODES\r\n250-PIPELINING\r\n250-8BITMIME\r\n250-SIZE\r\n250-DSN\r\n\
+250-ETRN\r\n250-AUTH DIGEST-MD5 CRAM-MD5\r\n250-DELIVERBY\r\n250 HELP\r\n",
+    ),
+    Sample::new(
+        "Sendmail",
+        "start mail input",
+        Other,
+        b"354 Enter mail, end with \".\" on a line by itself\r\n",
+    ),
+    // Exchange
+    Sample::new(
+        "Exchange",
+        "greeting",
+        Greeting,
+        b"220 mail.example.org Microsoft ESMTP MAIL Service ready at Wed, 14 Oct 2026 12:34:56 +0000\r\n",
+    ),
+    Sample::new(
+        "Exchange",
+        "EHLO",
+        Ehlo,
+        b"250-mail.example.org Hello [192.0.2.1]\r\n250-SIZE 37748736\r\n250-PIPELINING\r\n\
+250-DSN\r\n250-ENHANCEDSTATUSCODES\r\n250-STARTTLS\r\n250-X-ANONYMOUSTLS\r\n\
+250-AUTH NTLM\r\n250-X-EXPS GSSAPI NTLM\r\n250-8BITMIME\r\n250-BINARYMIME\r\n\
+250-CHUNKING\r\n250 XRDST\r\n",
+    ),
+    Sample::new(
+        "Exchange",
+        "EHLO of old versions, with both AUTH keywords",
+        Ehlo,
+        b"250-mail.example.org Hello [192.0.2.1]\r\n250-AUTH LOGIN NTLM\r\n\
+250-AUTH=LOGIN\r\n250 OK\r\n",
+    ),
+    Sample::new(
+        "Exchange",
+        "blocklist rejection",
+        Other,
+        b"550 5.7.1 Service unavailable; Client host [192.0.2.1] blocked using Spamhaus. \
+To request removal from this list see https://www.spamhaus.org/query/ip/192.0.2.1 (AS3130). \
+[DB8EUR01FT012.eop-EUR01.prod.protection.outlook.com 2026-10-14T12:34:56.000Z 08DC0123456789AB]\r\n",
+    ),
+    Sample::new(
+        "Exchange",
+        "queued",
+        Other,
+        b"250 2.6.0 <1@example.org> [InternalId=123456789, Hostname=mail.example.org] \
+12345 bytes in 0.123, 98.765 KB/sec Queued mail for delivery\r\n",
+    ),
+    // Gmail
+    Sample::new(
+        "Gmail",
+        "greeting",
+        Greeting,
+        b"220 mx.google.com ESMTP d2e1a72fcca58-6b1234567890si123456b3a.123 - gsmtp\r\n",
+    ),
+    Sample::new(
+        "Gmail",
+        "EHLO",
+        Ehlo,
+        b"250-mx.google.com at your service, [192.0.2.1]\r\n250-SIZE 157286400\r\n\
+250-8BITMIME\r\n250-STARTTLS\r\n250-ENHANCEDSTATUSCODES\r\n250-PIPELINING\r\n\
+250-CHUNKING\r\n250 SMTPUTF8\r\n",
+    ),
+    Sample::new(
+        "Gmail",
+        "EHLO of submission",
+        Ehlo,
+        b"250-smtp.gmail.com at your service, [192.0.2.1]\r\n250-SIZE 35882577\r\n\
+250-8BITMIME\r\n250-AUTH LOGIN PLAIN XOAUTH2 PLAIN-CLIENTTOKEN OAUTHBEARER XOAUTH\r\n\
+250-ENHANCEDSTATUSCODES\r\n250-PIPELINING\r\n250-CHUNKING\r\n250 SMTPUTF8\r\n",
+    ),
+    Sample::new(
+        "Gmail",
+        "unknown user, with two spaces in the last line",
+        Other,
+        b"550-5.1.1 The email account that you tried to reach does not exist. Please try\r\n\
+550-5.1.1 double-checking the recipient's email address for typos or\r\n\
+550-5.1.1 unnecessary spaces. For more information, go to\r\n\
+550 5.1.1  https://support.google.com/mail/?p=NoSuchUser a1b2c3d4e5f6-6b1234567890si123456b3a.123 - gsmtp\r\n",
+    ),
+    Sample::new(
+        "Gmail",
+        "rate limit",
+        Other,
+        b"421-4.7.0 Try again later, closing connection. (EHLO)\r\n\
+421-4.7.0 For more information, go to\r\n\
+421 4.7.0  https://support.google.com/a/answer/3221692 a1b2c3d4e5f6-6b1234567890si123456b3a.123 - gsmtp\r\n",
+    ),
+    Sample::new("Gmail", "AUTH LOGIN challenge", Other, b"334 VXNlcm5hbWU6\r\n"),
+    Sample::new(
+        "Gmail",
+        "queued",
+        Other,
+        b"250 2.0.0 OK  1791981296 a1b2c3d4e5f6-6b1234567890si123456b3a.123 - gsmtp\r\n",
+    ),
+    // Yahoo
+    Sample::new(
+        "Yahoo",
+        "greeting",
+        Greeting,
+        b"220 mta1234.mail.gq1.yahoo.com ESMTP ready\r\n",
+    ),
+    Sample::new(
+        "Yahoo",
+        "EHLO",
+        Ehlo,
+        b"250-mta1234.mail.gq1.yahoo.com\r\n250-PIPELINING\r\n250-SIZE 41943040\r\n\
+250-8BITMIME\r\n250 STARTTLS\r\n",
+    ),
+    Sample::new(
+        "Yahoo",
+        "deferral",
+        Other,
+        b"421 4.7.0 [TSS04] Messages from 192.0.2.1 temporarily deferred due to unexpected \
+volume or user complaints - 4.16.55.1; see https://postmaster.yahooinc.com/error-codes\r\n",
+    ),
+    Sample::new(
+        "Yahoo",
+        "policy rejection",
+        Other,
+        b"554 5.7.9 Message not accepted for policy reasons.  See https://postmaster.yahooinc.com/error-codes\r\n",
+    ),
+    // Odd formats that RFC 5321 allows
+    Sample::new(
+        "RFC 5321",
+        "greeting without text",
+        Greeting,
+        b"220 mx.example.org\r\n",
+    ),
+    Sample::new(
+        "RFC 5321",
+        "multiline greeting",
+        Greeting,
+        b"220-mx.example.org ESMTP\r\n220-No UCE\r\n220 Welcome\r\n",
+    ),
+    Sample::new(
+        "RFC 5321",
+        "EHLO without capabilities",
+        Ehlo,
+        b"250 mx.example.org\r\n",
+    ),
+    Sample::new("RFC 5321", "reply without text", Other, b"250\r\n"),
+    Sample::new(
+        "RFC 5321",
+        "multiline reply with empty lines",
+        Other,
+        b"250-\r\n250-\r\n250 OK\r\n",
+    ),
+];
+
+/// The samples of a server, e.g., "Exim"
+pub fn by_server(server: &'static str) -> impl Iterator<Item = &'static Sample> {
+    SAMPLES.iter().filter(move |sample| sample.server == server)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Capability, Response};
+
+    #[test]
+    fn test_samples_parse() {
+        for sample in SAMPLES {
+            match Response::parse(sample.kind, sample.bytes) {
+                Ok(([], _)) => {}
+                result => panic!(
+                    "{} ({}) not parsed: {:?}",
+                    sample.server, sample.description, result
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_by_server() {
+        for server in ["Postfix", "Exim", "Sendmail", "Exchange", "Gmail", "Yahoo"] {
+            assert!(by_server(server).any(|sample| sample.kind == Ehlo));
+        }
+
+        let gmail = by_server("Gmail")
+            .find(|sample| sample.description == "EHLO")
+            .unwrap();
+        let Ok((_, Response::Ehlo { capabilities, .. })) = Response::parse(Ehlo, gmail.bytes)
+        else {
+            panic!("not an EHLO reply");
+        };
+        assert!(capabilities.contains(&Capability::Size(157_286_400)));
+    }
+}
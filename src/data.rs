@@ -0,0 +1,584 @@
+//! 4.5.2.  Transparency (RFC 5321)
+//!
+//! Message content sent after DATA ends with a line containing a single period. Lines of the
+//! content that start with a period are escaped with an additional period ("dot-stuffing").
+//!
+//! A server that does not hold the content in memory passes it to a [`DataSink`] as it is
+//! received, see [`DotStuffDecoder::decode_into`]:
+//!
+//! ```
+//! use instant_smtp::data::{DotStuffDecoder, Status, WriteSink};
+//!
+//! let mut decoder = DotStuffDecoder::new();
+//! let mut spool = WriteSink(Vec::new()); // e.g., a `File`
+//!
+//! let status = decoder.decode_into(b"..Hello\r\n", &mut spool).unwrap();
+//! assert_eq!(status, Status::Incomplete);
+//! let status = decoder.decode_into(b".\r\nQUIT\r\n", &mut spool).unwrap();
+//! assert_eq!(status, Status::Complete { consumed: 3 });
+//! assert_eq!(spool.0, b".Hello\r\n");
+//! ```
+
+use alloc::vec::Vec;
+use core::{convert::Infallible, fmt};
+
+use crate::{io, Body};
+
+/// Dot-stuff message content and append the end-of-data indicator.
+///
+/// Bare CR and LF characters are replaced with CRLF, and a missing CRLF at the end is added, so
+/// that the result consists of valid SMTP lines only. It can be sent as-is after a "354" reply.
+pub fn dot_stuff(content: &[u8]) -> Vec<u8> {
+    let mut stuffed = Vec::with_capacity(content.len() + 5);
+    let mut line_start = true;
+    let mut bytes = content.iter().copied().peekable();
+
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'\r' | b'\n' => {
+                if byte == b'\r' && bytes.peek() == Some(&b'\n') {
+                    bytes.next();
+                }
+                stuffed.extend_from_slice(b"\r\n");
+                line_start = true;
+            }
+            _ => {
+                if line_start && byte == b'.' {
+                    stuffed.push(b'.');
+                }
+                stuffed.push(byte);
+                line_start = false;
+            }
+        }
+    }
+
+    if !line_start {
+        stuffed.extend_from_slice(b"\r\n");
+    }
+    stuffed.extend_from_slice(b".\r\n");
+
+    stuffed
+}
+
+/// Check that the content may be sent with the given body type (RFC 6152).
+///
+/// Without BODY=8BITMIME or BODY=BINARYMIME, the content must consist of 7-bit octets only.
+pub fn check_body(content: &[u8], body: Body) -> Result<(), DataError> {
+    match body {
+        Body::SevenBit if !content.is_ascii() => Err(DataError::EightBitContent),
+        _ => Ok(()),
+    }
+}
+
+/// Streaming decoder for dot-stuffed message content.
+///
+/// The decoder removes the escaping period from lines, and stops at the end-of-data indicator
+/// ("." CRLF at the start of a line). The CRLF preceding the indicator is part of the content.
+///
+/// A line containing only a period that is not delimited by CRLF on both sides, e.g., "\n.\n" or
+/// "\r\n.\r", is rejected. Some receivers treat these as end of data and others don't, which
+/// allows to "smuggle" additional commands or messages through a relay.
+#[derive(Clone, Debug)]
+pub struct DotStuffDecoder {
+    state: State,
+    /// Octets of the current line, including CRLF
+    line_length: usize,
+    line_limit: Option<usize>,
+    /// Octets of the decoded content
+    size: usize,
+    size_limit: Option<usize>,
+    /// The content exceeded the size limit, and is dropped up to the end of data.
+    discarding: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// After CRLF, or at the very beginning
+    LineStart,
+    Text,
+    /// After a CR that may be followed by LF
+    Cr,
+    /// After a bare LF
+    BareEol,
+    /// After a period at the start of a line
+    Dot,
+    /// After a period and CR at the start of a line
+    DotCr,
+    /// After a period following a bare CR or LF
+    BareEolDot,
+    Done,
+    Failed,
+    LineTooLong,
+}
+
+/// Result of [`DotStuffDecoder::decode`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// All input was consumed, and the end of data is yet to come.
+    Incomplete,
+    /// The end of data was found. Input beyond `consumed` bytes is not part of the content.
+    Complete { consumed: usize },
+}
+
+impl Default for DotStuffDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DotStuffDecoder {
+    pub fn new() -> Self {
+        Self {
+            state: State::LineStart,
+            line_length: 0,
+            line_limit: None,
+            size: 0,
+            size_limit: None,
+            discarding: false,
+        }
+    }
+
+    /// Like [`DotStuffDecoder::new`], but reject lines longer than `limit` octets, including
+    /// CRLF, e.g., [`Limits::text_line`](crate::Limits::text_line).
+    ///
+    /// The limit applies to the lines as transmitted, i.e., before the escaping period is removed.
+    pub fn with_line_limit(limit: usize) -> Self {
+        Self {
+            line_limit: Some(limit),
+            ..Self::new()
+        }
+    }
+
+    /// Reject content larger than `limit` octets, e.g., the maximum size of SIZE (RFC 1870).
+    ///
+    /// The limit applies to the decoded content, see [`DataError::MessageTooLarge`].
+    pub fn set_size_limit(&mut self, limit: Option<usize>) {
+        self.size_limit = limit;
+    }
+
+    /// Octets of the content decoded so far, including those dropped after the size limit was
+    /// exceeded
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Decode the next part of the content into `output`.
+    ///
+    /// Errors are not recoverable; the server should reply and close the connection. The
+    /// exception is [`DataError::MessageTooLarge`], after which decoding continues without
+    /// output up to the end of data, so that the server can reply "552" (RFC 1870, 6.3.).
+    pub fn decode(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<Status, DataError> {
+        for (index, &byte) in input.iter().enumerate() {
+            let decoded = output.len();
+            self.line_length += 1;
+            if self
+                .line_limit
+                .is_some_and(|limit| self.line_length > limit)
+                && !matches!(self.state, State::Done | State::Failed)
+            {
+                self.state = State::LineTooLong;
+            }
+
+            self.state = match (self.state, byte) {
+                (State::Done, _) => return Ok(Status::Complete { consumed: 0 }),
+                (State::Failed, _) => return Err(DataError::BareDotLine),
+                (State::LineTooLong, _) => {
+                    return Err(DataError::LineTooLong {
+                        limit: self.line_limit.unwrap_or_default(),
+                    })
+                }
+                (State::LineStart, b'.') => State::Dot,
+                (State::Dot, b'\r') => State::DotCr,
+                (State::DotCr, b'\n') => {
+                    self.state = State::Done;
+                    return Ok(Status::Complete {
+                        consumed: index + 1,
+                    });
+                }
+                (State::Cr | State::BareEol, b'.') => State::BareEolDot,
+                (State::Dot, b'\n') | (State::DotCr, _) | (State::BareEolDot, b'\r' | b'\n') => {
+                    self.state = State::Failed;
+                    return Err(DataError::BareDotLine);
+                }
+                (State::BareEolDot, byte) => {
+                    output.push(b'.');
+                    text(byte, output)
+                }
+                (State::Cr, b'\n') => {
+                    output.push(b'\n');
+                    State::LineStart
+                }
+                (_, byte) => text(byte, output),
+            };
+
+            if self.state == State::LineStart {
+                self.line_length = 0;
+            }
+
+            self.size += output.len() - decoded;
+            if self.discarding {
+                output.truncate(decoded);
+            } else if let Some(limit) = self.size_limit.filter(|limit| self.size > *limit) {
+                output.truncate(decoded);
+                self.discarding = true;
+                return Err(DataError::MessageTooLarge {
+                    limit,
+                    consumed: index + 1,
+                });
+            }
+        }
+
+        Ok(match self.state {
+            State::Done => Status::Complete { consumed: 0 },
+            _ => Status::Incomplete,
+        })
+    }
+}
+
+impl DotStuffDecoder {
+    /// Like [`DotStuffDecoder::decode`], but pass the content of every call to `sink`.
+    ///
+    /// The sink is finished at the end of data, and aborted when the content is rejected,
+    /// including [`DataError::MessageTooLarge`]. Content that is dropped after the size limit
+    /// was exceeded does not reach the sink.
+    pub fn decode_into<S: DataSink>(
+        &mut self,
+        input: &[u8],
+        sink: &mut S,
+    ) -> Result<Status, SinkError<S::Error>> {
+        let discarding = self.discarding;
+        let mut chunk = Vec::new();
+
+        let status = match self.decode(input, &mut chunk) {
+            Ok(status) => status,
+            Err(error) => {
+                if !discarding {
+                    sink.abort();
+                }
+                return Err(SinkError::Data(error));
+            }
+        };
+        if self.discarding {
+            return Ok(status);
+        }
+
+        if !chunk.is_empty() {
+            sink.write_chunk(&chunk).map_err(SinkError::Sink)?;
+        }
+        if let Status::Complete { consumed } = status {
+            // After the end of data, further calls complete with nothing consumed.
+            if consumed > 0 {
+                sink.finish().map_err(SinkError::Sink)?;
+            }
+        }
+
+        Ok(status)
+    }
+}
+
+/// A receiver of message content, e.g., a spool file or a hash
+///
+/// The content is passed without dot-stuffing, in the order it was received, followed by one
+/// call to [`DataSink::finish`] or [`DataSink::abort`].
+pub trait DataSink {
+    type Error;
+
+    fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), Self::Error>;
+
+    /// The content is complete.
+    fn finish(&mut self) -> Result<(), Self::Error>;
+
+    /// The content was rejected, and whatever was written should be dropped.
+    fn abort(&mut self);
+}
+
+impl DataSink for Vec<u8> {
+    type Error = Infallible;
+
+    fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), Self::Error> {
+        self.extend_from_slice(chunk);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn abort(&mut self) {
+        self.clear();
+    }
+}
+
+/// A sink that writes to an [`io::Write`], e.g., a `File`, and flushes it when finished
+///
+/// Aborting does not undo the writes; the caller should remove the file.
+#[derive(Debug)]
+pub struct WriteSink<W>(pub W);
+
+impl<W: io::Write> DataSink for WriteSink<W> {
+    type Error = io::Error;
+
+    fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), Self::Error> {
+        self.0.write_all(chunk)
+    }
+
+    fn finish(&mut self) -> Result<(), Self::Error> {
+        self.0.flush()
+    }
+
+    fn abort(&mut self) {}
+}
+
+fn text(byte: u8, output: &mut Vec<u8>) -> State {
+    output.push(byte);
+
+    match byte {
+        b'\r' => State::Cr,
+        b'\n' => State::BareEol,
+        _ => State::Text,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DataError {
+    /// A line containing only a period was not delimited by CRLF.
+    BareDotLine,
+    /// A line exceeds the limit of [`DotStuffDecoder::with_line_limit`].
+    LineTooLong { limit: usize },
+    /// The content has 8-bit octets, but was declared as 7BIT.
+    EightBitContent,
+    /// The content exceeds the limit of [`DotStuffDecoder::set_size_limit`].
+    ///
+    /// Input following `consumed` octets was not decoded yet. It should be passed to
+    /// [`DotStuffDecoder::decode`] again, which drops the content up to the end of data.
+    MessageTooLarge { limit: usize, consumed: usize },
+}
+
+impl fmt::Display for DataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataError::BareDotLine => write!(f, "line containing only \".\" without CRLF"),
+            DataError::LineTooLong { limit } => {
+                write!(f, "line exceeds limit of {} octets", limit)
+            }
+            DataError::EightBitContent => write!(f, "8-bit octet in 7BIT content"),
+            DataError::MessageTooLarge { limit, .. } => {
+                write!(f, "message exceeds limit of {} octets", limit)
+            }
+        }
+    }
+}
+
+impl core::error::Error for DataError {}
+
+/// Error of [`DotStuffDecoder::decode_into`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SinkError<E> {
+    /// The content was rejected.
+    Data(DataError),
+    /// The sink failed.
+    Sink(E),
+}
+
+impl<E: fmt::Display> fmt::Display for SinkError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkError::Data(error) => write!(f, "{}", error),
+            SinkError::Sink(error) => write!(f, "sink failed: {}", error),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> core::error::Error for SinkError<E> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dot_stuff() {
+        let tests: &[(&[u8], &[u8])] = &[
+            (b"", b".\r\n"),
+            (b"A", b"A\r\n.\r\n"),
+            (b"A\r\n", b"A\r\n.\r\n"),
+            (b".A\r\n.\r\n..\r\n", b"..A\r\n..\r\n...\r\n.\r\n"),
+            (b"A\n.\r\nB\rC", b"A\r\n..\r\nB\r\nC\r\n.\r\n"),
+        ];
+
+        for (content, expected) in tests {
+            assert_eq!(dot_stuff(content), *expected);
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut decoder = DotStuffDecoder::new();
+        let mut content = Vec::new();
+
+        assert_eq!(
+            decoder.decode(b"..A\r\nB\r", &mut content),
+            Ok(Status::Incomplete)
+        );
+        assert_eq!(decoder.decode(b"\n.", &mut content), Ok(Status::Incomplete));
+        assert_eq!(
+            decoder.decode(b"\r\nQUIT\r\n", &mut content),
+            Ok(Status::Complete { consumed: 2 })
+        );
+        assert!(decoder.is_complete());
+        assert_eq!(content, b".A\r\nB\r\n");
+    }
+
+    #[test]
+    fn test_decode_roundtrip() {
+        let content = b"From: a@example.org\r\n\r\n.\r\n..\r\n.A\r\nA.\r\n";
+        let mut decoded = Vec::new();
+
+        let status = DotStuffDecoder::new()
+            .decode(&dot_stuff(content), &mut decoded)
+            .unwrap();
+        assert!(matches!(status, Status::Complete { .. }));
+        assert_eq!(decoded, content);
+    }
+
+    #[test]
+    fn test_decode_bare_dot_line() {
+        let tests: &[&[u8]] = &[
+            b"A\n.\n",
+            b"A\r\n.\n",
+            b"A\n.\r\n",
+            b"A\r.\r\n",
+            b"A\r\n.\rB",
+        ];
+
+        for test in tests {
+            let result = DotStuffDecoder::new().decode(test, &mut Vec::new());
+            assert_eq!(result, Err(DataError::BareDotLine), "{:?}", test);
+        }
+
+        let mut content = Vec::new();
+        let result = DotStuffDecoder::new().decode(b"A\n.B\r\n.\r\n", &mut content);
+        assert_eq!(result, Ok(Status::Complete { consumed: 9 }));
+        assert_eq!(content, b"A\n.B\r\n");
+    }
+
+    #[test]
+    fn test_decode_line_limit() {
+        let mut decoder = DotStuffDecoder::with_line_limit(6);
+        let mut content = Vec::new();
+
+        assert_eq!(
+            decoder.decode(b"ABCD\r\n..CD\r\n", &mut content),
+            Ok(Status::Incomplete)
+        );
+        assert_eq!(
+            decoder.decode(b"ABCDE\r\n", &mut content),
+            Err(DataError::LineTooLong { limit: 6 })
+        );
+        assert_eq!(
+            decoder.decode(b".\r\n", &mut content),
+            Err(DataError::LineTooLong { limit: 6 })
+        );
+    }
+
+    #[test]
+    fn test_decode_size_limit() {
+        let mut decoder = DotStuffDecoder::new();
+        decoder.set_size_limit(Some(8));
+        let mut content = Vec::new();
+
+        assert_eq!(
+            decoder.decode(b"..AB\r\n", &mut content),
+            Ok(Status::Incomplete)
+        );
+        let input = b"CDEF\r\n.\r\nQUIT\r\n";
+        assert_eq!(
+            decoder.decode(input, &mut content),
+            Err(DataError::MessageTooLarge {
+                limit: 8,
+                consumed: 4
+            })
+        );
+        assert_eq!(content, b".AB\r\nCDE");
+        assert_eq!(
+            decoder.decode(&input[4..], &mut content),
+            Ok(Status::Complete { consumed: 5 })
+        );
+        assert_eq!(content, b".AB\r\nCDE");
+        assert_eq!(decoder.size(), 11);
+    }
+
+    #[test]
+    fn test_check_body() {
+        let content = "Subject: Grüße\r\n\r\nHello\r\n".as_bytes();
+
+        assert_eq!(check_body(b"Hello\r\n", Body::SevenBit), Ok(()));
+        assert_eq!(
+            check_body(content, Body::SevenBit),
+            Err(DataError::EightBitContent)
+        );
+        assert_eq!(check_body(content, Body::EightBitMime), Ok(()));
+        assert_eq!(check_body(content, Body::BinaryMime), Ok(()));
+    }
+
+    #[derive(Default)]
+    struct Spool {
+        content: Vec<u8>,
+        finished: usize,
+        aborted: usize,
+    }
+
+    impl DataSink for Spool {
+        type Error = Infallible;
+
+        fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), Self::Error> {
+            self.content.extend_from_slice(chunk);
+            Ok(())
+        }
+
+        fn finish(&mut self) -> Result<(), Self::Error> {
+            self.finished += 1;
+            Ok(())
+        }
+
+        fn abort(&mut self) {
+            self.aborted += 1;
+        }
+    }
+
+    #[test]
+    fn test_decode_into() {
+        let mut decoder = DotStuffDecoder::new();
+        let mut spool = Spool::default();
+
+        for chunk in [&b"Hel"[..], b"lo\r\n..", b"\r\n.\r", b"\n"] {
+            decoder.decode_into(chunk, &mut spool).unwrap();
+        }
+        assert_eq!(
+            decoder.decode_into(b"QUIT\r\n", &mut spool),
+            Ok(Status::Complete { consumed: 0 })
+        );
+        assert_eq!(spool.content, b"Hello\r\n.\r\n");
+        assert_eq!((spool.finished, spool.aborted), (1, 0));
+
+        let mut decoder = DotStuffDecoder::new();
+        decoder.set_size_limit(Some(4));
+        let mut spool = Spool::default();
+
+        assert_eq!(
+            decoder.decode_into(b"Hello\r\n", &mut spool),
+            Err(SinkError::Data(DataError::MessageTooLarge {
+                limit: 4,
+                consumed: 5
+            }))
+        );
+        assert_eq!(
+            decoder.decode_into(b"World\r\n.\r\n", &mut spool),
+            Ok(Status::Complete { consumed: 10 })
+        );
+        assert_eq!((spool.finished, spool.aborted), (0, 1));
+    }
+}
@@ -0,0 +1,86 @@
+//! Dates of the "Date:" and "Received:" fields (RFC 5322, 3.3.)
+//!
+//! ```
+//! use instant_smtp::datetime;
+//! use time::{OffsetDateTime, UtcOffset};
+//!
+//! let timestamp = OffsetDateTime::from_unix_timestamp(1_791_981_296).unwrap();
+//! assert_eq!(datetime::format(timestamp), "Wed, 14 Oct 2026 12:34:56 +0000");
+//! assert_eq!(
+//!     datetime::format(timestamp.to_offset(UtcOffset::from_hms(2, 0, 0).unwrap())),
+//!     "Wed, 14 Oct 2026 14:34:56 +0200"
+//! );
+//! ```
+
+use time::OffsetDateTime;
+
+/// date-time = [ day-of-week "," ] date time, in the offset of `datetime`
+///
+/// Seconds of the offset are dropped, as the zone only has hours and minutes.
+pub fn format(datetime: OffsetDateTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let (hours, minutes, _) = datetime.offset().as_hms();
+
+    // zone = (FWS ( "+" / "-" ) 4DIGIT)
+    format!(
+        "{}, {} {} {} {:02}:{:02}:{:02} {}{:02}{:02}",
+        &datetime.weekday().to_string()[..3],
+        datetime.day(),
+        MONTHS[datetime.month() as usize - 1],
+        datetime.year(),
+        datetime.hour(),
+        datetime.minute(),
+        datetime.second(),
+        if datetime.offset().is_negative() {
+            '-'
+        } else {
+            '+'
+        },
+        hours.unsigned_abs(),
+        minutes.unsigned_abs()
+    )
+}
+
+/// The current time, in the local offset
+///
+/// Falls back to UTC when the offset cannot be determined, e.g., when other threads may modify
+/// the environment.
+pub fn now() -> String {
+    format(OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc()))
+}
+
+/// The "Date:" field with the current time, including the final CRLF
+///
+/// A submission server adds it to messages that lack one (RFC 6409, 8.1.).
+pub fn date_header() -> String {
+    format!("Date: {}\r\n", now())
+}
+
+#[cfg(test)]
+mod test {
+    use time::UtcOffset;
+
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let tests = [
+            (0, (0, 0), "Thu, 1 Jan 1970 00:00:00 +0000"),
+            (951_782_405, (0, 0), "Tue, 29 Feb 2000 00:00:05 +0000"),
+            (1_791_981_296, (0, 0), "Wed, 14 Oct 2026 12:34:56 +0000"),
+            (1_791_981_296, (-5, -30), "Wed, 14 Oct 2026 07:04:56 -0530"),
+            (1_791_981_296, (12, 0), "Thu, 15 Oct 2026 00:34:56 +1200"),
+            (-86_400, (0, 0), "Wed, 31 Dec 1969 00:00:00 +0000"),
+        ];
+
+        for (seconds, (hours, minutes), expected) in tests {
+            let offset = UtcOffset::from_hms(hours, minutes, 0).unwrap();
+            let datetime = OffsetDateTime::from_unix_timestamp(seconds).unwrap();
+            assert_eq!(format(datetime.to_offset(offset)), expected);
+        }
+        assert!(date_header().starts_with("Date: ") && date_header().ends_with("\r\n"));
+    }
+}
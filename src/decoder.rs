@@ -0,0 +1,414 @@
+//! Incremental framing of input into lines
+//!
+//! The streaming parsers of this crate report incomplete input, and the caller has to retry with
+//! more data. Retrying after every read parses the same prefix again and again, which is
+//! quadratic in the line length. A [`Decoder`] buffers the input instead, remembers how far it
+//! searched for CRLF, and only hands complete lines (or complete multiline replies) to the
+//! parsers.
+//!
+//! ```
+//! use instant_smtp::{decoder::Decoder, Command};
+//!
+//! let mut decoder = Decoder::new();
+//! decoder.push(b"NOOP\r\nQU");
+//! assert_eq!(decoder.decode_command().unwrap(), Some(Command::Noop { argument: None }));
+//! assert_eq!(decoder.decode_command().unwrap(), None);
+//! decoder.push(b"IT\r\n");
+//! assert_eq!(decoder.decode_command().unwrap(), Some(Command::Quit));
+//! ```
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+use crate::{
+    find_crlf, Command, Limits, ParseMode, Response, ResponseKind, SmtpParseError, SyntaxOptions,
+};
+
+#[derive(Clone, Debug)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+    /// Start of the input that was not consumed yet
+    start: usize,
+    /// Octets of the complete lines of a multiline reply, following `start`
+    pending: usize,
+    /// Octets of the line following the pending ones that contain no CRLF
+    scanned: usize,
+    max_line_length: usize,
+    /// The rest of a line that exceeded the limit is dropped, up to the next CRLF.
+    discarding: bool,
+    /// The line that exceeded the limit was marked with "-" as followed by more lines of a reply.
+    continued: bool,
+    /// The rest of a reply with a line that exceeded the limit is dropped, up to its last line.
+    discarding_reply: bool,
+    options: SyntaxOptions,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    /// A decoder that accepts lines up to the longest limit of [`Limits::default`], i.e., the
+    /// limit of AUTH lines.
+    pub fn new() -> Self {
+        Self::with_options(SyntaxOptions::default())
+    }
+
+    /// A decoder that parses commands with the given options, and accepts lines up to the longer
+    /// of [`Limits::command`] and [`Limits::auth`].
+    pub fn with_options(options: SyntaxOptions) -> Self {
+        let Limits { command, auth, .. } = options.limits;
+
+        Self {
+            buffer: Vec::new(),
+            start: 0,
+            pending: 0,
+            scanned: 0,
+            max_line_length: command.max(auth),
+            discarding: false,
+            continued: false,
+            discarding_reply: false,
+            options,
+        }
+    }
+
+    pub fn set_max_line_length(&mut self, max_line_length: usize) {
+        self.max_line_length = max_line_length;
+    }
+
+    /// Append input, e.g., the bytes returned by a read.
+    pub fn push(&mut self, input: &[u8]) {
+        // Drop the consumed input once it makes up the larger part of the buffer, so that every
+        // octet is moved a bounded number of times.
+        if self.start > 0 && self.start >= self.buffer.len() / 2 {
+            self.buffer.drain(..self.start);
+            self.start = 0;
+        }
+
+        self.buffer.extend_from_slice(input);
+    }
+
+    /// The input that was not consumed yet
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffer[self.start..]
+    }
+
+    /// Consume up to `length` octets without parsing them, e.g., the message content following
+    /// BDAT.
+    pub fn take(&mut self, length: usize) -> &[u8] {
+        let length = length.min(self.buffer.len() - self.start);
+        let taken = self.start..self.start + length;
+
+        self.start += length;
+        self.pending = 0;
+        self.scanned = 0;
+
+        &self.buffer[taken]
+    }
+
+    /// The next complete line including CRLF, or `None` if more input is needed.
+    ///
+    /// A line that exceeds the limit is reported once as [`DecodeError::LineTooLong`]. Its
+    /// remainder is dropped, and decoding continues with the next line.
+    pub fn next_line(&mut self) -> Result<Option<&[u8]>, DecodeError> {
+        // Lines of an incomplete reply are returned one by one.
+        self.pending = 0;
+
+        match self.next_line_end()? {
+            Some(end) => {
+                let line = self.start..self.start + end;
+                self.start += end;
+                Ok(Some(&self.buffer[line]))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// The next command, or `None` if more input is needed.
+    ///
+    /// A line that cannot be parsed is consumed, so that decoding can continue after the error.
+    pub fn decode_command(&mut self) -> Result<Option<Command>, DecodeError> {
+        let options = self.options;
+        let Some(line) = self.next_line()? else {
+            return Ok(None);
+        };
+
+        match Command::from_bytes_with(line, options) {
+            Ok((_, command)) => Ok(Some(command)),
+            Err(error) => Err(DecodeError::Syntax {
+                error,
+                line: line.to_vec(),
+            }),
+        }
+    }
+
+    /// The next reply of the given kind, or `None` if more input is needed.
+    ///
+    /// The reply is parsed once all of its lines were received. The lines that were received
+    /// before are not searched again. A reply that cannot be parsed is consumed, including a
+    /// reply with a line that exceeds the limit.
+    pub fn decode_response(&mut self, kind: ResponseKind) -> Result<Option<Response>, DecodeError> {
+        while self.discarding_reply {
+            match self.next_line_end() {
+                Ok(Some(end)) => {
+                    self.discarding_reply = self.buffer.get(self.start + 3) == Some(&b'-');
+                    self.start += end;
+                }
+                Ok(None) => return Ok(None),
+                Err(_) => self.discarding_reply = self.continued,
+            }
+        }
+
+        loop {
+            let end = match self.next_line_end() {
+                Ok(Some(end)) => end,
+                Ok(None) => return Ok(None),
+                Err(error) => {
+                    self.discarding_reply = self.continued;
+                    return Err(error);
+                }
+            };
+
+            let line = &self.buffer[self.start + self.pending..];
+            self.pending += end;
+            // Reply-code "-" marks all but the last line.
+            if line.get(3) != Some(&b'-') {
+                break;
+            }
+        }
+
+        let reply = self.start..self.start + self.pending;
+        self.start += self.pending;
+        self.pending = 0;
+
+        let reply = &self.buffer[reply];
+        match Response::parse_with(kind, reply, self.options) {
+            Ok((_, response)) => Ok(Some(response)),
+            Err(error) => Err(DecodeError::Syntax {
+                error,
+                line: reply.to_vec(),
+            }),
+        }
+    }
+
+    /// Length of the line following the pending lines, including CRLF.
+    fn next_line_end(&mut self) -> Result<Option<usize>, DecodeError> {
+        loop {
+            let from = self.start + self.pending;
+            let Some(end) = self.find_line_end(from) else {
+                if self.discarding {
+                    // Keep a final CR, which may be followed by LF.
+                    self.start = self.buffer.len().saturating_sub(1).max(self.start);
+                    self.scanned = self.buffer.len() - self.start;
+                } else if self.buffer.len() - from > self.max_line_length {
+                    return Err(self.line_too_long(from));
+                }
+                return Ok(None);
+            };
+
+            if self.discarding {
+                self.discarding = false;
+                self.start += end;
+                continue;
+            }
+            if end > self.max_line_length {
+                let error = self.line_too_long(from);
+                // The line is complete, nothing remains to be dropped.
+                self.discarding = false;
+                self.start += end;
+                return Err(error);
+            }
+
+            return Ok(Some(end));
+        }
+    }
+
+    /// Drop the pending lines, as the reply they belong to can't be parsed anymore, and the
+    /// following input up to the next CRLF.
+    fn line_too_long(&mut self, from: usize) -> DecodeError {
+        self.continued = self.buffer.get(from + 3) == Some(&b'-');
+        self.start = from;
+        self.pending = 0;
+        self.discarding = true;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(limit = self.max_line_length, "line too long");
+
+        DecodeError::LineTooLong {
+            limit: self.max_line_length,
+        }
+    }
+
+    /// Length of the line starting at `from`, including CRLF
+    ///
+    /// In [`ParseMode::Lenient`], a line ends with LF, see [`crate::Violation::BareLf`].
+    fn find_line_end(&mut self, from: usize) -> Option<usize> {
+        let line = &self.buffer[from..];
+        // A CR at the end of the searched input may be followed by LF.
+        let searched = self.scanned.saturating_sub(1);
+
+        let end = match self.options.mode {
+            ParseMode::Strict => find_crlf(&line[searched..]).map(|position| position + 2),
+            ParseMode::Lenient => {
+                memchr::memchr(b'\n', &line[searched..]).map(|position| position + 1)
+            }
+        };
+        match end {
+            Some(end) => {
+                self.scanned = 0;
+                Some(searched + end)
+            }
+            None => {
+                self.scanned = line.len();
+                None
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// A line exceeds the maximum length.
+    LineTooLong { limit: usize },
+    /// A line (or reply) could not be parsed and was consumed.
+    Syntax {
+        error: SmtpParseError,
+        line: Vec<u8>,
+    },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::LineTooLong { limit } => {
+                write!(f, "line exceeds limit of {} octets", limit)
+            }
+            DecodeError::Syntax { error, line } => {
+                write!(f, "{} in {:?}", error, String::from_utf8_lossy(line))
+            }
+        }
+    }
+}
+
+impl core::error::Error for DecodeError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_command() {
+        let mut decoder = Decoder::new();
+
+        for byte in b"MAIL FROM:<a@example.org>\r\nFOO\r\nDATA\r\n" {
+            decoder.push(&[*byte]);
+            if *byte == b'\n' {
+                break;
+            }
+            assert_eq!(decoder.decode_command(), Ok(None));
+        }
+        assert!(matches!(
+            decoder.decode_command(),
+            Ok(Some(Command::Mail { .. }))
+        ));
+
+        decoder.push(b"FOO:\r\nDATA\r\n");
+        assert!(matches!(
+            decoder.decode_command(),
+            Err(DecodeError::Syntax {
+                error: SmtpParseError::UnknownCommand,
+                ..
+            })
+        ));
+        assert_eq!(decoder.decode_command(), Ok(Some(Command::Data)));
+        assert_eq!(decoder.decode_command(), Ok(None));
+        assert!(decoder.buffered().is_empty());
+    }
+
+    #[test]
+    fn test_decode_response() {
+        let mut decoder = Decoder::new();
+
+        decoder.push(b"250-example.org\r\n250-SIZE 1000\r");
+        assert_eq!(decoder.decode_response(ResponseKind::Ehlo), Ok(None));
+        decoder.push(b"\n250 STARTTLS\r\n354 Go ahead\r\n");
+        assert!(matches!(
+            decoder.decode_response(ResponseKind::Ehlo),
+            Ok(Some(Response::Ehlo { .. }))
+        ));
+        assert_eq!(
+            decoder
+                .decode_response(ResponseKind::Other)
+                .unwrap()
+                .unwrap()
+                .code(),
+            crate::ReplyCode::StartMailInput
+        );
+    }
+
+    #[test]
+    fn test_line_too_long() {
+        let mut decoder = Decoder::new();
+        decoder.set_max_line_length(16);
+
+        decoder.push(&[b'a'; 12]);
+        assert_eq!(decoder.next_line(), Ok(None));
+        decoder.push(&[b'a'; 12]);
+        assert_eq!(
+            decoder.next_line(),
+            Err(DecodeError::LineTooLong { limit: 16 })
+        );
+        decoder.push(b"aaaa\r\nNOOP\r\n");
+        assert_eq!(decoder.next_line(), Ok(Some(&b"NOOP\r\n"[..])));
+
+        decoder.push(b"250-aaaaaaaa\r\n250-aaaaaaaaaaaaaaaa\r\n250 OK\r\n221 Bye\r\n");
+        assert_eq!(
+            decoder.decode_response(ResponseKind::Other),
+            Err(DecodeError::LineTooLong { limit: 16 })
+        );
+        // The rest of the reply is dropped as well.
+        assert_eq!(
+            decoder
+                .decode_response(ResponseKind::Other)
+                .unwrap()
+                .unwrap()
+                .code(),
+            crate::ReplyCode::ClosingChannel
+        );
+        decoder.push(b"250-aaaaaaaaaaaaaaaaaaaa");
+        assert_eq!(
+            decoder.decode_response(ResponseKind::Other),
+            Err(DecodeError::LineTooLong { limit: 16 })
+        );
+        decoder.push(b"aa\r\n250-aaaaaaaaaaaaaaaaaaaa\r\n250 OK\r\n221 Bye\r\n");
+        assert_eq!(
+            decoder
+                .decode_response(ResponseKind::Other)
+                .unwrap()
+                .unwrap()
+                .code(),
+            crate::ReplyCode::ClosingChannel
+        );
+
+        decoder.push(b"BDAT 3\r\nabc");
+        decoder.next_line().unwrap();
+        assert_eq!(decoder.take(5), b"abc");
+    }
+
+    #[test]
+    fn test_lenient() {
+        let mut decoder = Decoder::with_options(SyntaxOptions {
+            mode: ParseMode::Lenient,
+            ..SyntaxOptions::default()
+        });
+
+        decoder.push(b"MAIL FROM: a@example.org\nRSET \r\n");
+        assert!(matches!(
+            decoder.decode_command(),
+            Ok(Some(Command::Mail { .. }))
+        ));
+        assert_eq!(decoder.decode_command(), Ok(Some(Command::Rset)));
+    }
+}
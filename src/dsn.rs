@@ -0,0 +1,392 @@
+//! Delivery status notifications (RFC 3464)
+//!
+//! A server that accepted a message, but fails to deliver it to some recipients, returns a
+//! report to the reverse-path. The report is a multipart/report message (RFC 6522) with a
+//! human-readable part, a message/delivery-status part with the fields of every recipient, and
+//! the original message or its header section:
+//!
+//! ```
+//! use instant_smtp::{dsn, ReplyCode, Response, TextString};
+//!
+//! let reply = Response::Other {
+//!     code: ReplyCode::MailboxPermanentlyUnavailable,
+//!     lines: vec![TextString::new("5.1.1 No such user").unwrap()],
+//! };
+//! let report = dsn::report("mx.example.org")
+//!     .envid("QQ314159")
+//!     .recipient(dsn::RecipientStatus::from_response(
+//!         "b@example.net",
+//!         Some("mx.example.net"),
+//!         &reply,
+//!     ))
+//!     .build();
+//!
+//! let report = String::from_utf8(report).unwrap();
+//! assert!(report.contains("Action: failed\r\nStatus: 5.1.1\r\n"));
+//! assert!(report.contains("Diagnostic-Code: smtp; 550 5.1.1 No such user\r\n"));
+//! ```
+//!
+//! The report lacks the header fields of the notification itself, e.g., From, To, and Subject,
+//! which the caller prepends. It is sent with the null reverse-path "<>" (RFC 5321, 4.5.5.).
+
+use alloc::{
+    borrow::ToOwned,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt::{self, Write as _};
+
+use crate::{EnhancedStatusCode, Response, Ret, Severity};
+
+/// Start building a report of the given MTA, e.g., "mx.example.org".
+pub fn report(reporting_mta: impl Into<String>) -> ReportBuilder {
+    ReportBuilder {
+        reporting_mta: reporting_mta.into(),
+        envid: None,
+        arrival_date: None,
+        text: None,
+        recipients: Vec::new(),
+        original: None,
+    }
+}
+
+/// Builder of a report, see [`report`]
+///
+/// Recipient fields are written in the order the recipients were added.
+#[derive(Clone, Debug)]
+pub struct ReportBuilder {
+    reporting_mta: String,
+    envid: Option<String>,
+    arrival_date: Option<String>,
+    text: Option<String>,
+    recipients: Vec<RecipientStatus>,
+    original: Option<(Ret, Vec<u8>)>,
+}
+
+impl ReportBuilder {
+    /// The ENVID parameter of MAIL, as decoded from xtext
+    pub fn envid(mut self, envid: impl Into<String>) -> Self {
+        self.envid = Some(envid.into());
+        self
+    }
+
+    /// The date and time the message arrived, as in the Date header field (RFC 5322, 3.3.)
+    pub fn arrival_date(mut self, date: impl Into<String>) -> Self {
+        self.arrival_date = Some(date.into());
+        self
+    }
+
+    /// The human-readable explanation, which otherwise lists the recipients and their actions
+    pub fn text(mut self, text: impl Into<String>) -> Self {
+        self.text = Some(text.into());
+        self
+    }
+
+    pub fn recipient(mut self, recipient: RecipientStatus) -> Self {
+        self.recipients.push(recipient);
+        self
+    }
+
+    /// The original message, which is returned in full or its header section only, as
+    /// requested with the RET parameter of MAIL
+    pub fn original(mut self, ret: Ret, message: &[u8]) -> Self {
+        let returned = match ret {
+            Ret::Full => message,
+            Ret::Hdrs => header_section(message),
+        };
+        self.original = Some((ret, returned.to_vec()));
+        self
+    }
+
+    /// The MIME entity of the report, i.e., the MIME-Version and Content-Type header fields
+    /// followed by the body
+    pub fn build(self) -> Vec<u8> {
+        let mut parts = vec![(
+            "text/plain; charset=utf-8".to_owned(),
+            normalize_lines(&self.text.clone().unwrap_or_else(|| self.summary())),
+        )];
+        parts.push(("message/delivery-status".to_owned(), self.delivery_status()));
+        if let Some((ret, message)) = &self.original {
+            let content_type = match ret {
+                Ret::Full => "message/rfc822",
+                Ret::Hdrs => "text/rfc822-headers",
+            };
+            parts.push((content_type.to_owned(), message.clone()));
+        }
+
+        // The boundary must not occur in any part (RFC 2046, 5.1.1.)
+        let mut boundary = "dsn-boundary".to_owned();
+        let mut counter = 0;
+        while parts
+            .iter()
+            .any(|(_, body)| contains(body, boundary.as_bytes()))
+        {
+            counter += 1;
+            boundary = format!("dsn-boundary-{}", counter);
+        }
+
+        let mut report = format!(
+            "MIME-Version: 1.0\r\n\
+             Content-Type: multipart/report; report-type=delivery-status;\r\n\
+             \tboundary=\"{}\"\r\n\r\n",
+            boundary
+        )
+        .into_bytes();
+        for (content_type, body) in parts {
+            report.extend_from_slice(
+                format!("--{}\r\nContent-Type: {}\r\n\r\n", boundary, content_type).as_bytes(),
+            );
+            report.extend_from_slice(&body);
+            if !body.ends_with(b"\r\n") {
+                report.extend_from_slice(b"\r\n");
+            }
+        }
+        report.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+        report
+    }
+
+    fn summary(&self) -> String {
+        let mut summary = format!(
+            "This is the mail system at {}.\n\nThe delivery of your message had the following \
+             results:\n\n",
+            self.reporting_mta
+        );
+        for recipient in &self.recipients {
+            let _ = writeln!(
+                summary,
+                "<{}>: {} ({})",
+                recipient.final_recipient, recipient.action, recipient.status
+            );
+        }
+
+        summary
+    }
+
+    /// per-message-fields 1*( CRLF per-recipient-fields )
+    fn delivery_status(&self) -> Vec<u8> {
+        let mut fields = String::new();
+
+        let _ = write!(fields, "Reporting-MTA: dns; {}\r\n", self.reporting_mta);
+        if let Some(envid) = &self.envid {
+            let _ = write!(fields, "Original-Envelope-Id: {}\r\n", envid);
+        }
+        if let Some(date) = &self.arrival_date {
+            let _ = write!(fields, "Arrival-Date: {}\r\n", date);
+        }
+
+        for recipient in &self.recipients {
+            fields.push_str("\r\n");
+            if let Some(original) = &recipient.original_recipient {
+                let _ = write!(fields, "Original-Recipient: rfc822; {}\r\n", original);
+            }
+            let _ = write!(
+                fields,
+                "Final-Recipient: rfc822; {}\r\nAction: {}\r\nStatus: {}\r\n",
+                recipient.final_recipient, recipient.action, recipient.status
+            );
+            if let Some(remote_mta) = &recipient.remote_mta {
+                let _ = write!(fields, "Remote-MTA: dns; {}\r\n", remote_mta);
+            }
+            if let Some(diagnostic) = &recipient.diagnostic_code {
+                let _ = write!(fields, "Diagnostic-Code: smtp; {}\r\n", diagnostic);
+            }
+        }
+
+        fields.into_bytes()
+    }
+}
+
+/// The outcome for a recipient
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecipientStatus {
+    /// The recipient the delivery was attempted to, e.g., the forward-path of RCPT
+    pub final_recipient: String,
+    /// The ORCPT parameter of RCPT, if it was given
+    pub original_recipient: Option<String>,
+    pub action: Action,
+    pub status: EnhancedStatusCode,
+    /// The server that replied, e.g., "mx.example.net"
+    pub remote_mta: Option<String>,
+    /// The reply of the remote server, e.g., "550 5.1.1 No such user"
+    pub diagnostic_code: Option<String>,
+}
+
+impl RecipientStatus {
+    pub fn new(
+        final_recipient: impl Into<String>,
+        action: Action,
+        status: EnhancedStatusCode,
+    ) -> Self {
+        Self {
+            final_recipient: final_recipient.into(),
+            original_recipient: None,
+            action,
+            status,
+            remote_mta: None,
+            diagnostic_code: None,
+        }
+    }
+
+    /// The outcome of a reply of the remote server to RCPT, or to the message content.
+    ///
+    /// The status is the enhanced status code of the reply, or the generic "X.0.0" of its class.
+    /// Failures are "failed" for 5yz and "delayed" for 4yz replies.
+    pub fn from_response(
+        final_recipient: impl Into<String>,
+        remote_mta: Option<&str>,
+        response: &Response,
+    ) -> Self {
+        let (action, class) = match response.severity() {
+            Some(Severity::PositiveCompletion | Severity::PositiveIntermediate) => {
+                (Action::Delivered, 2)
+            }
+            Some(Severity::TransientNegative) => (Action::Delayed, 4),
+            Some(Severity::PermanentNegative) | None => (Action::Failed, 5),
+        };
+        let status = response
+            .enhanced_code()
+            .unwrap_or_else(|| EnhancedStatusCode::new(class, 0, 0));
+        let diagnostic_code = match response {
+            Response::Other { code, lines } => {
+                let text: Vec<&str> = lines.iter().map(|line| &**line).collect();
+                format!("{} {}", u16::from(*code), text.join(" "))
+                    .trim_end()
+                    .to_owned()
+            }
+            _ => response.to_string().replace("\r\n", " "),
+        };
+
+        Self {
+            remote_mta: remote_mta.map(String::from),
+            diagnostic_code: Some(diagnostic_code),
+            ..Self::new(final_recipient, action, status)
+        }
+    }
+}
+
+/// action-value (RFC 3464, 2.3.3.)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// The message could not be delivered.
+    Failed,
+    /// The delivery is retried later.
+    Delayed,
+    Delivered,
+    /// The message was relayed to a system that does not return DSNs.
+    Relayed,
+    /// The message was delivered to a mailing list or alias.
+    Expanded,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::Failed => "failed",
+            Action::Delayed => "delayed",
+            Action::Delivered => "delivered",
+            Action::Relayed => "relayed",
+            Action::Expanded => "expanded",
+        })
+    }
+}
+
+/// The header section of a message, including the empty line that ends it
+fn header_section(message: &[u8]) -> &[u8] {
+    match message.windows(4).position(|window| window == b"\r\n\r\n") {
+        Some(position) => &message[..position + 4],
+        None => message,
+    }
+}
+
+fn normalize_lines(text: &str) -> Vec<u8> {
+    text.lines()
+        .flat_map(|line| [line, "\r\n"])
+        .collect::<String>()
+        .into_bytes()
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ReplyCode, TextString};
+
+    #[test]
+    fn test_report() {
+        let reply = Response::Other {
+            code: ReplyCode::MailboxTemporarilyUnavailable,
+            lines: vec![
+                TextString::new("Greylisted,").unwrap(),
+                TextString::new("try again later").unwrap(),
+            ],
+        };
+        let mut failed = RecipientStatus::new(
+            "c@example.net",
+            Action::Failed,
+            EnhancedStatusCode::new(5, 1, 2),
+        );
+        failed.original_recipient = Some("C@example.net".into());
+
+        let report = report("mx.example.org")
+            .envid("QQ314159")
+            .arrival_date("Thu, 1 Jan 2026 00:00:00 +0000")
+            .recipient(RecipientStatus::from_response(
+                "b@example.net",
+                Some("mx.example.net"),
+                &reply,
+            ))
+            .recipient(failed)
+            .original(
+                Ret::Hdrs,
+                b"Subject: dsn-boundary\r\nFrom: a@example.org\r\n\r\nHello\r\n",
+            )
+            .build();
+
+        let expected = "MIME-Version: 1.0\r\n\
+Content-Type: multipart/report; report-type=delivery-status;\r\n\
+\tboundary=\"dsn-boundary-1\"\r\n\
+\r\n\
+--dsn-boundary-1\r\n\
+Content-Type: text/plain; charset=utf-8\r\n\
+\r\n\
+This is the mail system at mx.example.org.\r\n\
+\r\n\
+The delivery of your message had the following results:\r\n\
+\r\n\
+<b@example.net>: delayed (4.0.0)\r\n\
+<c@example.net>: failed (5.1.2)\r\n\
+--dsn-boundary-1\r\n\
+Content-Type: message/delivery-status\r\n\
+\r\n\
+Reporting-MTA: dns; mx.example.org\r\n\
+Original-Envelope-Id: QQ314159\r\n\
+Arrival-Date: Thu, 1 Jan 2026 00:00:00 +0000\r\n\
+\r\n\
+Final-Recipient: rfc822; b@example.net\r\n\
+Action: delayed\r\n\
+Status: 4.0.0\r\n\
+Remote-MTA: dns; mx.example.net\r\n\
+Diagnostic-Code: smtp; 450 Greylisted, try again later\r\n\
+\r\n\
+Original-Recipient: rfc822; C@example.net\r\n\
+Final-Recipient: rfc822; c@example.net\r\n\
+Action: failed\r\n\
+Status: 5.1.2\r\n\
+--dsn-boundary-1\r\n\
+Content-Type: text/rfc822-headers\r\n\
+\r\n\
+Subject: dsn-boundary\r\n\
+From: a@example.org\r\n\
+\r\n\
+--dsn-boundary-1--\r\n";
+        assert_eq!(String::from_utf8(report).unwrap(), expected);
+    }
+}
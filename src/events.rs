@@ -0,0 +1,529 @@
+//! Events of a server session, decoded from the raw input
+//!
+//! An [`EventDecoder`] combines a [`Decoder`], a [`Session`], and a [`DotStuffDecoder`]: the
+//! server pushes the bytes it reads, and handles the [`SessionEvent`]s, which include the
+//! message content:
+//!
+//! ```
+//! use instant_smtp::{
+//!     events::{EventDecoder, SessionEvent},
+//!     server::{Event, Session},
+//! };
+//!
+//! let mut events = EventDecoder::new(Session::new());
+//! events.push(b"EHLO client.example.com\r\nMAIL FROM:<a@example.com>\r\n");
+//! events.push(b"RCPT TO:<b@example.org>\r\nDATA\r\nHello\r\n.\r\n");
+//!
+//! let mut content = Vec::new();
+//! while let Some(event) = events.poll_event() {
+//!     match event {
+//!         SessionEvent::Command(Event::Hello { .. }) => { /* send "250" with capabilities */ }
+//!         SessionEvent::Command(Event::Data) => { /* send "354" */ }
+//!         SessionEvent::MailAccepted { .. } | SessionEvent::RecipientAdded { .. } => {
+//!             /* send "250" */
+//!         }
+//!         SessionEvent::DataChunk(chunk) => content.extend_from_slice(&chunk),
+//!         SessionEvent::MessageComplete(transaction) => {
+//!             assert_eq!(transaction.recipients.len(), 1);
+//!             /* queue the message, and send "250" */
+//!         }
+//!         SessionEvent::ProtocolError { reply, .. } => { /* send `reply` */ }
+//!         _ => {}
+//!     }
+//! }
+//! assert_eq!(content, b"Hello\r\n");
+//! ```
+//!
+//! The server replies to the events in order, and to rejected input with the reply of
+//! [`SessionEvent::ProtocolError`]. To reject an accepted MAIL or RCPT nonetheless, it calls
+//! [`Session::reject`] with [`EventDecoder::session_mut`] before polling the next event.
+
+use alloc::{collections::VecDeque, vec, vec::Vec};
+
+use crate::{
+    data::{DataError, DotStuffDecoder, Status},
+    decoder::{DecodeError, Decoder},
+    sasl::Continuation,
+    server::{size_exceeded, Event, Session, Transaction},
+    Command, EsmtpParam, ForwardPath, ReplyCode, Response, ReversePath, SyntaxOptions, TextString,
+};
+
+/// What happened in the session, see [`EventDecoder::poll_event`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// An accepted command other than MAIL and RCPT, e.g., EHLO, AUTH, DATA, or QUIT
+    ///
+    /// After [`Event::Data`] and [`Event::Bdat`], the message content follows as
+    /// [`SessionEvent::DataChunk`]s. After [`Event::NeedsTlsUpgrade`], the input received so
+    /// far is discarded, and the server calls [`Session::tls_established`] after the handshake.
+    Command(Event),
+    /// MAIL was accepted, and a mail transaction started.
+    MailAccepted {
+        reverse_path: ReversePath,
+        parameters: Vec<EsmtpParam>,
+    },
+    /// RCPT was accepted.
+    RecipientAdded {
+        forward_path: ForwardPath,
+        parameters: Vec<EsmtpParam>,
+    },
+    /// Message content without dot-stuffing, in the order it was received
+    DataChunk(Vec<u8>),
+    /// The content of a BDAT chunk without LAST was received. The server replies "250".
+    ChunkComplete,
+    /// The content following DATA, or the last BDAT chunk, was received.
+    MessageComplete(Transaction),
+    /// The answer to a "334" challenge, see [`EventDecoder::expect_continuation`]
+    Continuation(Continuation),
+    /// The input was rejected, and the server sends `reply`.
+    ///
+    /// When `fatal`, the content can't be decoded any further, and the server closes the
+    /// connection after the reply. No more events follow.
+    ProtocolError { reply: Response, fatal: bool },
+}
+
+#[derive(Clone, Debug)]
+enum Mode {
+    Commands,
+    /// Content following DATA
+    Data(DotStuffDecoder),
+    /// Content following BDAT
+    Chunk {
+        remaining: u64,
+        last: bool,
+    },
+    /// Content following a rejected BDAT, which is dropped (RFC 3030, 2.)
+    Discard {
+        remaining: u64,
+    },
+}
+
+/// Decoder of the [`SessionEvent`]s of a session
+#[derive(Clone, Debug)]
+pub struct EventDecoder {
+    decoder: Decoder,
+    session: Session,
+    mode: Mode,
+    /// A "334" challenge was sent, the next line is the answer of the client.
+    continuation: bool,
+    /// The content exceeds [`Session::max_size`], and is dropped up to the end of data.
+    too_large: bool,
+    failed: bool,
+    events: VecDeque<SessionEvent>,
+}
+
+impl EventDecoder {
+    pub fn new(session: Session) -> Self {
+        Self::with_options(session, SyntaxOptions::default())
+    }
+
+    /// Parse commands with the given options, see [`Decoder::with_options`].
+    pub fn with_options(session: Session, options: SyntaxOptions) -> Self {
+        Self {
+            decoder: Decoder::with_options(options),
+            session,
+            mode: Mode::Commands,
+            continuation: false,
+            too_large: false,
+            failed: false,
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// The session, e.g., to call [`Session::set_authenticated`] after AUTH.
+    pub fn session_mut(&mut self) -> &mut Session {
+        &mut self.session
+    }
+
+    /// Append input, e.g., the bytes returned by a read.
+    pub fn push(&mut self, input: &[u8]) {
+        self.decoder.push(input);
+    }
+
+    /// Decode the next line as the answer to a "334" challenge that was sent.
+    pub fn expect_continuation(&mut self) {
+        self.continuation = true;
+    }
+
+    /// The next event, or `None` if more input is needed.
+    pub fn poll_event(&mut self) -> Option<SessionEvent> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(event);
+            }
+            if self.failed {
+                return None;
+            }
+
+            match self.mode {
+                Mode::Commands if self.continuation => self.decode_continuation()?,
+                Mode::Commands => self.decode_command()?,
+                Mode::Data(_) => self.decode_content()?,
+                Mode::Chunk { .. } => self.decode_chunk()?,
+                Mode::Discard { .. } => self.discard_chunk()?,
+            }
+        }
+    }
+
+    /// Returns `None` if more input is needed.
+    fn decode_command(&mut self) -> Option<()> {
+        let command = match self.decoder.decode_command() {
+            Ok(command) => command?,
+            Err(error) => {
+                self.decode_error(error);
+                return Some(());
+            }
+        };
+
+        let rejected_chunk = match command {
+            Command::Bdat { size, .. } if size > 0 => Some(size),
+            _ => None,
+        };
+
+        let event = match self.session.handle(command) {
+            Ok(Event::Mail {
+                reverse_path,
+                parameters,
+            }) => SessionEvent::MailAccepted {
+                reverse_path,
+                parameters,
+            },
+            Ok(Event::Rcpt {
+                forward_path,
+                parameters,
+            }) => SessionEvent::RecipientAdded {
+                forward_path,
+                parameters,
+            },
+            Ok(event) => {
+                match event {
+                    Event::Data => {
+                        let limits = self.session.limits();
+                        let mut decoder = DotStuffDecoder::with_line_limit(limits.text_line);
+                        let max_size = self.session.max_size();
+                        decoder.set_size_limit(max_size.map(|size| size as usize));
+                        self.mode = Mode::Data(decoder);
+                    }
+                    Event::Bdat { size, last } => {
+                        self.mode = Mode::Chunk {
+                            remaining: size,
+                            last,
+                        };
+                    }
+                    Event::NeedsTlsUpgrade => {
+                        let buffered = self.decoder.buffered().len();
+                        self.decoder.take(buffered);
+                    }
+                    _ => {}
+                }
+                SessionEvent::Command(event)
+            }
+            Err(reply) => {
+                // The chunk follows the command, even when it is rejected.
+                if let Some(remaining) = rejected_chunk {
+                    self.mode = Mode::Discard { remaining };
+                }
+                SessionEvent::ProtocolError {
+                    reply,
+                    fatal: false,
+                }
+            }
+        };
+        self.events.push_back(event);
+
+        Some(())
+    }
+
+    fn decode_continuation(&mut self) -> Option<()> {
+        let line = match self.decoder.next_line() {
+            Ok(line) => line?,
+            Err(error) => {
+                self.continuation = false;
+                self.decode_error(error);
+                return Some(());
+            }
+        };
+
+        self.continuation = false;
+        let event = match Continuation::from_bytes(line) {
+            Ok((_, continuation)) => SessionEvent::Continuation(continuation),
+            // RFC 4954, 4.
+            Err(_) => SessionEvent::ProtocolError {
+                reply: reply(ReplyCode::ParameterSyntaxError, "5.5.2 Invalid response"),
+                fatal: false,
+            },
+        };
+        self.events.push_back(event);
+
+        Some(())
+    }
+
+    fn decode_content(&mut self) -> Option<()> {
+        let Mode::Data(decoder) = &mut self.mode else {
+            return Some(());
+        };
+        let input = self.decoder.buffered();
+        if input.is_empty() {
+            return None;
+        }
+
+        let mut chunk = Vec::new();
+        let result = decoder.decode(input, &mut chunk);
+        let consumed = match result {
+            Ok(Status::Incomplete) => input.len(),
+            Ok(Status::Complete { consumed }) => consumed,
+            Err(DataError::MessageTooLarge { consumed, .. }) => {
+                self.too_large = true;
+                consumed
+            }
+            Err(error) => {
+                self.failed = true;
+                let text = match error {
+                    DataError::LineTooLong { .. } => "5.5.6 Line too long",
+                    _ => "5.6.0 Line containing only \".\" without CRLF",
+                };
+                self.events.push_back(SessionEvent::ProtocolError {
+                    reply: reply(ReplyCode::TransactionFailed, text),
+                    fatal: true,
+                });
+                return Some(());
+            }
+        };
+        self.decoder.take(consumed);
+
+        if !chunk.is_empty() {
+            self.events.push_back(SessionEvent::DataChunk(chunk));
+        }
+        if let Ok(Status::Complete { .. }) = result {
+            self.end_of_message();
+        }
+
+        Some(())
+    }
+
+    fn decode_chunk(&mut self) -> Option<()> {
+        let Mode::Chunk { remaining, last } = &mut self.mode else {
+            return Some(());
+        };
+
+        if *remaining > 0 {
+            let chunk = self
+                .decoder
+                .take(usize::try_from(*remaining).unwrap_or(usize::MAX));
+            if chunk.is_empty() {
+                return None;
+            }
+            *remaining -= chunk.len() as u64;
+            self.events
+                .push_back(SessionEvent::DataChunk(chunk.to_vec()));
+        }
+
+        if *remaining == 0 {
+            match *last {
+                true => self.end_of_message(),
+                false => {
+                    self.mode = Mode::Commands;
+                    self.events.push_back(SessionEvent::ChunkComplete);
+                }
+            }
+        }
+
+        Some(())
+    }
+
+    fn discard_chunk(&mut self) -> Option<()> {
+        let Mode::Discard { remaining } = &mut self.mode else {
+            return Some(());
+        };
+
+        let discarded = self
+            .decoder
+            .take(usize::try_from(*remaining).unwrap_or(usize::MAX))
+            .len();
+        if discarded == 0 {
+            return None;
+        }
+        *remaining -= discarded as u64;
+        if *remaining == 0 {
+            self.mode = Mode::Commands;
+        }
+
+        Some(())
+    }
+
+    fn end_of_message(&mut self) {
+        self.mode = Mode::Commands;
+        let transaction = self.session.data_end();
+
+        let event = match (core::mem::take(&mut self.too_large), transaction) {
+            // RFC 1870, 6.3.
+            (true, _) => SessionEvent::ProtocolError {
+                reply: size_exceeded(),
+                fatal: false,
+            },
+            (false, Some(transaction)) => SessionEvent::MessageComplete(transaction),
+            (false, None) => return,
+        };
+        self.events.push_back(event);
+    }
+
+    fn decode_error(&mut self, error: DecodeError) {
+        let reply = match error {
+            // RFC 5321, 4.5.3.1.4.
+            DecodeError::LineTooLong { .. } => reply(ReplyCode::SyntaxError, "Line too long"),
+            DecodeError::Syntax { .. } => {
+                self.session.record_syntax_error();
+                reply(
+                    ReplyCode::ParameterSyntaxError,
+                    "Syntax error in parameters or arguments",
+                )
+            }
+        };
+        self.events.push_back(SessionEvent::ProtocolError {
+            reply,
+            fatal: false,
+        });
+    }
+}
+
+fn reply(code: ReplyCode, text: &'static str) -> Response {
+    Response::Other {
+        code,
+        lines: vec![TextString::new(text).unwrap()],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server::State;
+
+    fn events(decoder: &mut EventDecoder, input: &[u8]) -> Vec<SessionEvent> {
+        decoder.push(input);
+        core::iter::from_fn(|| decoder.poll_event()).collect()
+    }
+
+    #[test]
+    fn test_events() {
+        let mut decoder = EventDecoder::new(Session::new());
+
+        let input = b"EHLO client.example.com\r\nMAIL FROM:<a@example.com>\r\n\
+RCPT TO:<b@example.org>\r\nRCPT TO:<c@example.org>\r\nDATA\r\n..Hel";
+        let received = events(&mut decoder, input);
+        assert!(matches!(
+            received[0],
+            SessionEvent::Command(Event::Hello { .. })
+        ));
+        assert!(matches!(received[1], SessionEvent::MailAccepted { .. }));
+        assert!(matches!(received[2], SessionEvent::RecipientAdded { .. }));
+        assert_eq!(received[4], SessionEvent::Command(Event::Data));
+        assert_eq!(received[5], SessionEvent::DataChunk(b".Hel".to_vec()));
+        assert_eq!(received.len(), 6);
+
+        let received = events(&mut decoder, b"lo\r\n.\r\nNOOP\r\n");
+        assert_eq!(received[0], SessionEvent::DataChunk(b"lo\r\n".to_vec()));
+        let SessionEvent::MessageComplete(transaction) = &received[1] else {
+            panic!("not complete: {:?}", received);
+        };
+        assert_eq!(transaction.recipients.len(), 2);
+        assert!(matches!(
+            received[2],
+            SessionEvent::Command(Event::Other(crate::Command::Noop { .. }))
+        ));
+
+        // BDAT, with the content of the first chunk split
+        let received = events(
+            &mut decoder,
+            b"MAIL FROM:<>\r\nRCPT TO:<b@example.org>\r\nBDAT 7\r\nHel",
+        );
+        assert_eq!(received[3], SessionEvent::DataChunk(b"Hel".to_vec()));
+        let received = events(&mut decoder, b"lo\r\nBDAT 0 LAST\r\n");
+        assert_eq!(
+            received[..2],
+            [
+                SessionEvent::DataChunk(b"lo\r\n".to_vec()),
+                SessionEvent::ChunkComplete
+            ]
+        );
+        assert!(matches!(received[3], SessionEvent::MessageComplete(_)));
+        assert_eq!(decoder.session().state(), State::Ready);
+    }
+
+    #[test]
+    fn test_events_errors() {
+        let mut session = Session::new();
+        session.set_max_size(Some(4));
+        let mut decoder = EventDecoder::new(session);
+
+        let code = |event: &SessionEvent| match event {
+            SessionEvent::ProtocolError { reply, fatal } => (u16::from(reply.code()), *fatal),
+            event => panic!("not an error: {:?}", event),
+        };
+        let received = events(
+            &mut decoder,
+            b"DATA\r\nMAIL FROM:<a b>\r\nEHLO a.example\r\n",
+        );
+        assert_eq!(code(&received[0]), (503, false));
+        assert_eq!(code(&received[1]), (501, false));
+        assert_eq!(decoder.session().abuse_counters().syntax_errors, 1);
+
+        // The content is dropped after the size limit.
+        let received = events(
+            &mut decoder,
+            b"MAIL FROM:<>\r\nRCPT TO:<b@example.org>\r\nDATA\r\nHello\r\nWorld\r\n.\r\n",
+        );
+        assert_eq!(received[3], SessionEvent::DataChunk(b"Hell".to_vec()));
+        assert_eq!(code(&received[4]), (552, false));
+        assert_eq!(received.len(), 5);
+
+        // AUTH
+        assert!(matches!(
+            events(&mut decoder, b"AUTH LOGIN\r\n")[..],
+            [SessionEvent::Command(Event::Other(
+                crate::Command::Auth { .. }
+            ))]
+        ));
+        decoder.expect_continuation();
+        let received = events(&mut decoder, b"dXNlcg==\r\n");
+        assert_eq!(
+            received,
+            [SessionEvent::Continuation(Continuation::Response(
+                b"user".to_vec().into()
+            ))]
+        );
+
+        let received = events(
+            &mut decoder,
+            b"MAIL FROM:<>\r\nRCPT TO:<b@example.org>\r\nDATA\r\n.\n.\r\nQUIT\r\n",
+        );
+        assert_eq!(code(&received[3]), (554, true));
+        assert_eq!(received.len(), 4);
+        assert_eq!(decoder.poll_event(), None);
+    }
+
+    #[test]
+    fn test_events_rejected_bdat() {
+        let mut decoder = EventDecoder::new(Session::new());
+
+        // The chunk of a BDAT without transaction is dropped, not run as a command.
+        let received = events(
+            &mut decoder,
+            b"EHLO a.example\r\nBDAT 25 LAST\r\nRCPT TO:<x@exa",
+        );
+        assert_eq!(received.len(), 2);
+        assert!(matches!(
+            &received[1],
+            SessionEvent::ProtocolError { reply, fatal: false }
+                if reply.code() == ReplyCode::BadSequence
+        ));
+        assert!(events(&mut decoder, b"mple.org>\r\n").is_empty());
+        assert!(matches!(
+            events(&mut decoder, b"QUIT\r\n")[..],
+            [SessionEvent::Command(Event::Quit)]
+        ));
+    }
+}
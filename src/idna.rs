@@ -0,0 +1,331 @@
+//! Conversion between U-labels and A-labels of internationalized domains (RFC 5890)
+//!
+//! A client that sends a mailbox with a U-label domain, e.g., "bücher.example", to a server that
+//! does not advertise SMTPUTF8 can convert the domain to A-labels, i.e., "xn--bcher-kva.example"
+//! (RFC 6531, 3.2.). The local part can't be converted.
+//!
+//! ```
+//! use instant_smtp::idna;
+//!
+//! assert_eq!(idna::to_ascii("bücher.example").unwrap(), "xn--bcher-kva.example");
+//! assert_eq!(idna::to_unicode("xn--bcher-kva.example").unwrap(), "bücher.example");
+//! ```
+//!
+//! Labels are converted with Punycode (RFC 3492) and lowercased, but not mapped or normalized as
+//! by UTS #46. The input is expected to be in NFC already.
+
+use alloc::{borrow::ToOwned, format, string::String, vec::Vec};
+use core::fmt;
+
+use crate::{HeloIdentity, Path};
+
+/// Prefix of an A-label (RFC 5890, 2.3.2.1.)
+const ACE_PREFIX: &str = "xn--";
+/// Maximum length of a label (RFC 1035, 2.3.4.)
+const LABEL_LENGTH: usize = 63;
+
+// Parameters of Punycode (RFC 3492, 5.)
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+/// Convert the U-labels of a domain to A-labels. ASCII labels are kept as they are.
+pub fn to_ascii(domain: &str) -> Result<String, IdnaError> {
+    let labels = domain.split('.').map(|label| {
+        if label.is_ascii() {
+            return Ok(label.to_owned());
+        }
+
+        let lowercase: Vec<char> = label.chars().flat_map(char::to_lowercase).collect();
+        let a_label = match encode(&lowercase) {
+            Some(encoded) => format!("{}{}", ACE_PREFIX, encoded),
+            None => return Err(IdnaError::InvalidLabel(label.to_owned())),
+        };
+        match a_label.len() > LABEL_LENGTH {
+            true => Err(IdnaError::LabelTooLong(a_label)),
+            false => Ok(a_label),
+        }
+    });
+
+    Ok(labels.collect::<Result<Vec<_>, _>>()?.join("."))
+}
+
+/// Convert the A-labels of a domain to U-labels, e.g., for display. Other labels are kept as they
+/// are.
+///
+/// Fails when an A-label is not valid Punycode, or does not convert back to the same A-label,
+/// e.g., "xn--abc-" which only contains ASCII.
+pub fn to_unicode(domain: &str) -> Result<String, IdnaError> {
+    let labels = domain.split('.').map(|label| {
+        let encoded = match label.get(..ACE_PREFIX.len()) {
+            Some(prefix) if prefix.eq_ignore_ascii_case(ACE_PREFIX) => &label[ACE_PREFIX.len()..],
+            _ => return Ok(label.to_owned()),
+        };
+
+        let decoded = decode(encoded)
+            .filter(|decoded| !decoded.is_ascii())
+            .filter(|decoded| {
+                let chars: Vec<char> = decoded.chars().collect();
+                encode(&chars).is_some_and(|reencoded| reencoded.eq_ignore_ascii_case(encoded))
+            });
+        decoded.ok_or_else(|| IdnaError::InvalidLabel(label.to_owned()))
+    });
+
+    Ok(labels.collect::<Result<Vec<_>, _>>()?.join("."))
+}
+
+impl HeloIdentity {
+    /// The identity with the domain converted to A-labels, see [`to_ascii`].
+    pub fn to_ascii(&self) -> Result<HeloIdentity, IdnaError> {
+        match self {
+            HeloIdentity::Domain(domain) => Ok(HeloIdentity::Domain(to_ascii(domain)?)),
+            HeloIdentity::AddressLiteral(_) => Ok(self.clone()),
+        }
+    }
+
+    /// The identity with the domain converted to U-labels, see [`to_unicode`].
+    pub fn to_unicode(&self) -> Result<HeloIdentity, IdnaError> {
+        match self {
+            HeloIdentity::Domain(domain) => Ok(HeloIdentity::Domain(to_unicode(domain)?)),
+            HeloIdentity::AddressLiteral(_) => Ok(self.clone()),
+        }
+    }
+}
+
+impl Path {
+    /// The path with the domains of the mailbox and the source route converted to A-labels, see
+    /// [`to_ascii`].
+    ///
+    /// The local part is kept, so the path may still contain non-ASCII characters.
+    pub fn to_ascii(&self) -> Result<Path, IdnaError> {
+        self.map_domains(to_ascii)
+    }
+
+    /// The path with the domains of the mailbox and the source route converted to U-labels, see
+    /// [`to_unicode`].
+    pub fn to_unicode(&self) -> Result<Path, IdnaError> {
+        self.map_domains(to_unicode)
+    }
+
+    fn map_domains(
+        &self,
+        convert: impl Fn(&str) -> Result<String, IdnaError>,
+    ) -> Result<Path, IdnaError> {
+        let route = match &self.route {
+            Some(route) => Some(
+                route
+                    .iter()
+                    .map(|domain| convert(domain))
+                    .collect::<Result<_, _>>()?,
+            ),
+            None => None,
+        };
+        // The domain can't contain "@", a quoted local part can.
+        let mailbox = match self.mailbox.rsplit_once('@') {
+            Some((local_part, domain)) if !domain.starts_with('[') => {
+                format!("{}@{}", local_part, convert(domain)?)
+            }
+            _ => self.mailbox.clone(),
+        };
+
+        Ok(Path { route, mailbox })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IdnaError {
+    /// A label can't be converted.
+    InvalidLabel(String),
+    /// The A-label of a U-label exceeds 63 octets.
+    LabelTooLong(String),
+}
+
+impl fmt::Display for IdnaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdnaError::InvalidLabel(label) => write!(f, "invalid label {:?}", label),
+            IdnaError::LabelTooLong(label) => {
+                write!(f, "label {:?} exceeds {} octets", label, LABEL_LENGTH)
+            }
+        }
+    }
+}
+
+impl core::error::Error for IdnaError {}
+
+/// Bias adaptation (RFC 3492, 6.1.)
+fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / DAMP } else { delta / 2 };
+    delta += delta / points;
+
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+
+    k + (BASE - T_MIN + 1) * delta / (delta + SKEW)
+}
+
+/// Threshold of the digit at position `k`
+fn threshold(k: u32, bias: u32) -> u32 {
+    k.saturating_sub(bias).clamp(T_MIN, T_MAX)
+}
+
+fn encode_digit(digit: u32) -> char {
+    match digit {
+        0..=25 => (b'a' + digit as u8) as char,
+        _ => (b'0' + (digit - 26) as u8) as char,
+    }
+}
+
+fn decode_digit(byte: u8) -> Option<u32> {
+    match byte {
+        b'a'..=b'z' => Some(u32::from(byte - b'a')),
+        b'A'..=b'Z' => Some(u32::from(byte - b'A')),
+        b'0'..=b'9' => Some(u32::from(byte - b'0') + 26),
+        _ => None,
+    }
+}
+
+/// Punycode encoding (RFC 3492, 6.3.). Returns `None` on overflow.
+fn encode(input: &[char]) -> Option<String> {
+    let mut output: String = input.iter().filter(|c| c.is_ascii()).collect();
+    let basic = output.len() as u32;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let mut n = INITIAL_N;
+    let mut delta: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut handled = basic;
+
+    while (handled as usize) < input.len() {
+        let m = input.iter().map(|c| *c as u32).filter(|c| *c >= n).min()?;
+        delta = delta.checked_add((m - n).checked_mul(handled + 1)?)?;
+        n = m;
+
+        for c in input.iter().map(|c| *c as u32) {
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = threshold(k, bias);
+                    if q < t {
+                        break;
+                    }
+                    output.push(encode_digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(encode_digit(q));
+
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+
+        delta = delta.checked_add(1)?;
+        n += 1;
+    }
+
+    Some(output)
+}
+
+/// Punycode decoding (RFC 3492, 6.2.). Returns `None` for invalid input.
+fn decode(input: &str) -> Option<String> {
+    let (basic, extended) = match input.rfind('-') {
+        Some(position) => (&input[..position], &input[position + 1..]),
+        None => ("", input),
+    };
+    if !basic.is_ascii() {
+        return None;
+    }
+
+    let mut output: Vec<char> = basic.chars().collect();
+    let mut n = INITIAL_N;
+    let mut i: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut digits = extended.bytes();
+
+    while digits.len() > 0 {
+        let old_i = i;
+        let mut w: u32 = 1;
+        let mut k = BASE;
+        loop {
+            let digit = decode_digit(digits.next()?)?;
+            i = i.checked_add(digit.checked_mul(w)?)?;
+            let t = threshold(k, bias);
+            if digit < t {
+                break;
+            }
+            w = w.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+
+        let length = output.len() as u32 + 1;
+        bias = adapt(i - old_i, length, old_i == 0);
+        n = n.checked_add(i / length)?;
+        i %= length;
+        output.insert(i as usize, char::from_u32(n)?);
+        i += 1;
+    }
+
+    Some(output.into_iter().collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_ascii() {
+        let tests = [
+            ("bücher.example", "xn--bcher-kva.example"),
+            ("MÜNCHEN.example", "xn--mnchen-3ya.example"),
+            ("日本語.jp", "xn--wgv71a119e.jp"),
+            ("ñ.example", "xn--ida.example"),
+            ("Example.ORG", "Example.ORG"),
+        ];
+        for (domain, expected) in tests {
+            assert_eq!(to_ascii(domain).unwrap(), expected);
+        }
+
+        assert!(matches!(
+            to_ascii(&"日本語".repeat(20)),
+            Err(IdnaError::LabelTooLong(_))
+        ));
+    }
+
+    #[test]
+    fn test_to_unicode() {
+        let tests = [
+            ("xn--bcher-kva.example", "bücher.example"),
+            ("XN--WGV71A119E.jp", "日本語.jp"),
+            ("example.org", "example.org"),
+        ];
+        for (domain, expected) in tests {
+            assert_eq!(to_unicode(domain).unwrap(), expected);
+        }
+
+        for domain in ["xn--abc-", "xn--bcher-kv!", "xn--9999999999a"] {
+            assert!(to_unicode(domain).is_err(), "{}", domain);
+        }
+
+        let path = Path::from("@xn--mnchen-3ya.example:ü@xn--bcher-kva.example");
+        assert_eq!(
+            path.to_unicode().unwrap().to_string(),
+            "@münchen.example:ü@bücher.example"
+        );
+        assert_eq!(path.to_unicode().unwrap().to_ascii().unwrap(), path);
+    }
+}
@@ -0,0 +1,336 @@
+//! Inspection of message content while it is received, like milters
+//!
+//! An [`InspectingSink`] passes the content to a [`DataSink`], and to a [`ContentInspector`],
+//! e.g., a spam or virus scanner. The inspector gets the header block, then the body in
+//! chunks, and decides at each step whether to reject the message:
+//!
+//! ```
+//! use instant_smtp::{
+//!     data::{DotStuffDecoder, SinkError},
+//!     inspect::{ContentInspector, HeaderField, InspectError, InspectingSink},
+//!     ReplyCode, Response, TextString,
+//! };
+//!
+//! struct Scanner;
+//!
+//! impl ContentInspector for Scanner {
+//!     fn body_chunk(&mut self, chunk: &[u8]) -> Result<(), Response> {
+//!         match chunk.windows(5).any(|window| window == b"EICAR") {
+//!             true => Err(Response::Other {
+//!                 code: ReplyCode::TransactionFailed,
+//!                 lines: vec![TextString::new("5.7.1 Virus found").unwrap()],
+//!             }),
+//!             false => Ok(()),
+//!         }
+//!     }
+//!
+//!     fn end_of_message(&mut self, added: &mut Vec<HeaderField>) -> Result<(), Response> {
+//!         added.push(HeaderField::new("X-Virus-Scanned", "clean"));
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let mut decoder = DotStuffDecoder::new();
+//! let mut sink = InspectingSink::new(Vec::new(), Scanner);
+//! let result = decoder.decode_into(b"Subject: Test\r\n\r\nEICAR\r\n.\r\n", &mut sink);
+//!
+//! let Err(SinkError::Sink(InspectError::Rejected(reply))) = result else {
+//!     panic!()
+//! };
+//! assert_eq!(reply.code(), ReplyCode::TransactionFailed);
+//! ```
+//!
+//! A rejection takes effect at the end of the content, as the client only reads the reply
+//! after it: the inner sink is aborted, and the remaining content is dropped. With BDAT, the
+//! server can check [`InspectingSink::rejection`] after each chunk.
+//!
+//! The content is passed on as it is received, so that header fields can't be inserted into
+//! it. The fields an inspector adds are returned by [`InspectingSink::added_headers`], and the
+//! server prepends them, like the "Received:" header.
+
+use alloc::{format, string::String, vec::Vec};
+use core::fmt;
+
+use crate::{data::DataSink, Response};
+
+/// A header field to add to the message
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeaderField {
+    pub name: String,
+    /// The unfolded value
+    pub value: String,
+}
+
+impl HeaderField {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+
+    /// The header including the name and the final CRLF
+    pub fn to_header(&self) -> String {
+        format!("{}: {}\r\n", self.name, self.value)
+    }
+}
+
+/// The decisions of a content scanner, see [`InspectingSink`]
+///
+/// Returning a reply rejects the message with it. All steps continue by default.
+pub trait ContentInspector {
+    /// The header block, including the empty line that ends it. Without a body, this is all
+    /// of the content, and it is passed at the end.
+    ///
+    /// See [`split_headers`](crate::message::split_headers) for its fields.
+    fn header_block(
+        &mut self,
+        headers: &[u8],
+        added: &mut Vec<HeaderField>,
+    ) -> Result<(), Response> {
+        let _ = (headers, added);
+        Ok(())
+    }
+
+    /// A chunk of the body, in the order it was received
+    fn body_chunk(&mut self, chunk: &[u8]) -> Result<(), Response> {
+        let _ = chunk;
+        Ok(())
+    }
+
+    /// The content is complete, and the server is about to accept it.
+    fn end_of_message(&mut self, added: &mut Vec<HeaderField>) -> Result<(), Response> {
+        let _ = added;
+        Ok(())
+    }
+}
+
+/// A sink that passes the content to another sink and to a [`ContentInspector`]
+///
+/// The header block is held in memory until it is complete, which the size limit of the
+/// decoder bounds, see [`DotStuffDecoder::set_size_limit`](crate::data::DotStuffDecoder::set_size_limit).
+#[derive(Debug)]
+pub struct InspectingSink<S, I> {
+    sink: S,
+    inspector: I,
+    /// The header block received so far, until it is complete
+    headers: Option<Vec<u8>>,
+    rejection: Option<Response>,
+    added: Vec<HeaderField>,
+}
+
+impl<S: DataSink, I: ContentInspector> InspectingSink<S, I> {
+    pub fn new(sink: S, inspector: I) -> Self {
+        Self {
+            sink,
+            inspector,
+            headers: Some(Vec::new()),
+            rejection: None,
+            added: Vec::new(),
+        }
+    }
+
+    /// The reply of the inspector that rejected the message, if any
+    pub fn rejection(&self) -> Option<&Response> {
+        self.rejection.as_ref()
+    }
+
+    /// The header fields the inspector added
+    pub fn added_headers(&self) -> &[HeaderField] {
+        &self.added
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn into_parts(self) -> (S, I) {
+        (self.sink, self.inspector)
+    }
+
+    fn inspect(&mut self, chunk: &[u8]) -> Result<(), Response> {
+        let Some(headers) = self.headers.as_mut() else {
+            return self.inspector.body_chunk(chunk);
+        };
+
+        // The previous chunk may have ended within the empty line.
+        let searched = headers.len().saturating_sub(3);
+        headers.extend_from_slice(chunk);
+        let end = match headers.starts_with(b"\r\n") {
+            true => Some(2),
+            false => memchr::memmem::find(&headers[searched..], b"\r\n\r\n")
+                .map(|position| searched + position + 4),
+        };
+        let Some(end) = end else {
+            return Ok(());
+        };
+
+        let mut headers = self.headers.take().unwrap_or_default();
+        let body = headers.split_off(end);
+        self.inspector.header_block(&headers, &mut self.added)?;
+        match body.is_empty() {
+            true => Ok(()),
+            false => self.inspector.body_chunk(&body),
+        }
+    }
+
+    fn reject(&mut self, reply: Response) -> InspectError<S::Error> {
+        self.sink.abort();
+        self.rejection = Some(reply.clone());
+        InspectError::Rejected(reply)
+    }
+}
+
+impl<S: DataSink, I: ContentInspector> DataSink for InspectingSink<S, I> {
+    type Error = InspectError<S::Error>;
+
+    fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), Self::Error> {
+        if self.rejection.is_some() {
+            return Ok(());
+        }
+
+        self.sink.write_chunk(chunk).map_err(InspectError::Sink)?;
+        if let Err(reply) = self.inspect(chunk) {
+            self.reject(reply);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Self::Error> {
+        if let Some(reply) = self.rejection.clone() {
+            return Err(InspectError::Rejected(reply));
+        }
+
+        let mut result = Ok(());
+        if let Some(headers) = self.headers.take() {
+            result = self.inspector.header_block(&headers, &mut self.added);
+        }
+        if let Err(reply) = result.and_then(|()| self.inspector.end_of_message(&mut self.added)) {
+            return Err(self.reject(reply));
+        }
+
+        self.sink.finish().map_err(InspectError::Sink)
+    }
+
+    fn abort(&mut self) {
+        if self.rejection.is_none() {
+            self.sink.abort();
+        }
+    }
+}
+
+/// Error of [`InspectingSink`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InspectError<E> {
+    /// The inspector rejected the message with the reply.
+    Rejected(Response),
+    /// The inner sink failed.
+    Sink(E),
+}
+
+impl<E: fmt::Display> fmt::Display for InspectError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InspectError::Rejected(reply) => write!(f, "message rejected: {}", reply),
+            InspectError::Sink(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> core::error::Error for InspectError<E> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        data::{DotStuffDecoder, SinkError, Status},
+        ReplyCode, TextString,
+    };
+
+    #[derive(Default)]
+    struct Recorder {
+        headers: Vec<u8>,
+        body: Vec<u8>,
+        ended: bool,
+    }
+
+    impl ContentInspector for Recorder {
+        fn header_block(
+            &mut self,
+            headers: &[u8],
+            added: &mut Vec<HeaderField>,
+        ) -> Result<(), Response> {
+            self.headers = headers.to_owned();
+            added.push(HeaderField::new("X-Headers", headers.len().to_string()));
+            Ok(())
+        }
+
+        fn body_chunk(&mut self, chunk: &[u8]) -> Result<(), Response> {
+            self.body.extend_from_slice(chunk);
+            match self.body.len() > 16 {
+                true => Err(Response::Other {
+                    code: ReplyCode::TransactionFailed,
+                    lines: vec![TextString::new("5.7.1 Too long").unwrap()],
+                }),
+                false => Ok(()),
+            }
+        }
+
+        fn end_of_message(&mut self, _: &mut Vec<HeaderField>) -> Result<(), Response> {
+            self.ended = true;
+            Ok(())
+        }
+    }
+
+    fn inspect(chunks: &[&[u8]]) -> (InspectingSink<Vec<u8>, Recorder>, Result<Status, ()>) {
+        let mut decoder = DotStuffDecoder::new();
+        let mut sink = InspectingSink::new(Vec::new(), Recorder::default());
+        let mut status = Ok(Status::Incomplete);
+        for chunk in chunks {
+            status = decoder.decode_into(chunk, &mut sink).map_err(|_| ());
+        }
+        (sink, status)
+    }
+
+    #[test]
+    fn test_inspecting_sink() {
+        let (sink, status) = inspect(&[b"Subject: Test\r", b"\n\r", b"\nHello\r\n.\r\n"]);
+        assert_eq!(status, Ok(Status::Complete { consumed: 11 }));
+        let (content, recorder) = sink.into_parts();
+        assert_eq!(content, b"Subject: Test\r\n\r\nHello\r\n");
+        assert_eq!(recorder.headers, b"Subject: Test\r\n\r\n");
+        assert_eq!(recorder.body, b"Hello\r\n");
+        assert!(recorder.ended);
+
+        // Without a body, and without headers
+        let (sink, _) = inspect(&[b"Subject: Test\r\n.\r\n"]);
+        assert_eq!(sink.added_headers()[0].to_header(), "X-Headers: 15\r\n");
+        let (sink, _) = inspect(&[b"\r\nHello\r\n.\r\n"]);
+        assert_eq!(sink.into_parts().1.headers, b"\r\n");
+    }
+
+    #[test]
+    fn test_inspecting_sink_rejected() {
+        let mut decoder = DotStuffDecoder::new();
+        let mut sink = InspectingSink::new(Vec::new(), Recorder::default());
+
+        let content = b"Subject: Test\r\n\r\nA line that is too long\r\n";
+        assert_eq!(
+            decoder.decode_into(content, &mut sink),
+            Ok(Status::Incomplete)
+        );
+        assert_eq!(
+            sink.rejection().unwrap().code(),
+            ReplyCode::TransactionFailed
+        );
+        assert!(sink.sink().is_empty());
+
+        let Err(SinkError::Sink(InspectError::Rejected(reply))) =
+            decoder.decode_into(b"More\r\n.\r\n", &mut sink)
+        else {
+            panic!("not rejected");
+        };
+        assert_eq!(reply.code(), ReplyCode::TransactionFailed);
+        assert!(!sink.into_parts().1.ended);
+    }
+}
@@ -0,0 +1,136 @@
+//! The writer of the serializers
+//!
+//! With the `std` feature, these are the items of `std::io`. Without it, [`Write`] is a minimal
+//! replacement with the methods the serializers use, implemented for `Vec<u8>`.
+
+#[cfg(feature = "std")]
+pub use std::io::{Error, ErrorKind, Result, Write};
+
+#[cfg(not(feature = "std"))]
+pub use self::no_std::{Error, ErrorKind, Result, Write};
+
+#[cfg(not(feature = "std"))]
+mod no_std {
+    use alloc::vec::Vec;
+    use core::fmt;
+
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    #[non_exhaustive]
+    pub enum ErrorKind {
+        /// A value is not allowed, e.g., a reply that exceeds the line length.
+        InvalidInput,
+        Other,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Error {
+        kind: ErrorKind,
+    }
+
+    impl Error {
+        /// Create an error of the given kind. The cause is dropped, as there is no `Box<dyn
+        /// Error>` to keep it in.
+        pub fn new<E>(kind: ErrorKind, _error: E) -> Self {
+            Self { kind }
+        }
+
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Self {
+            Self { kind }
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self.kind {
+                ErrorKind::InvalidInput => write!(f, "invalid input"),
+                ErrorKind::Other => write!(f, "other error"),
+            }
+        }
+    }
+
+    impl core::error::Error for Error {}
+
+    pub trait Write {
+        fn write_all(&mut self, buf: &[u8]) -> Result<()>;
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> Result<()> {
+            struct Adapter<'a, W: ?Sized> {
+                writer: &'a mut W,
+                error: Option<Error>,
+            }
+
+            impl<W: Write + ?Sized> fmt::Write for Adapter<'_, W> {
+                fn write_str(&mut self, s: &str) -> fmt::Result {
+                    self.writer.write_all(s.as_bytes()).map_err(|error| {
+                        self.error = Some(error);
+                        fmt::Error
+                    })
+                }
+            }
+
+            let mut adapter = Adapter {
+                writer: self,
+                error: None,
+            };
+            match fmt::write(&mut adapter, args) {
+                Ok(()) => Ok(()),
+                Err(_) => Err(adapter.error.unwrap_or(ErrorKind::Other.into())),
+            }
+        }
+    }
+
+    impl Write for Vec<u8> {
+        fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+            self.extend_from_slice(buf);
+            Ok(())
+        }
+    }
+
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+            (**self).write_all(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "std")))]
+mod test {
+    use super::*;
+    use crate::{Command, HeloIdentity};
+
+    #[test]
+    fn test_write() {
+        let mut buffer = Vec::new();
+        Command::Ehlo {
+            domain_or_address: HeloIdentity::Domain("client.example.org".into()),
+        }
+        .serialize(&mut buffer)
+        .unwrap();
+        assert_eq!(buffer, b"EHLO client.example.org\r\n");
+
+        struct Full;
+        impl Write for Full {
+            fn write_all(&mut self, _: &[u8]) -> Result<()> {
+                Err(Error::new(ErrorKind::InvalidInput, "full"))
+            }
+        }
+        let error = write!(Full, "{}", 1).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+}
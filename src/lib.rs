@@ -1,28 +1,84 @@
-use std::{borrow::Cow, fmt, io::Write, ops::Deref};
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
-use nom::IResult;
+extern crate alloc;
+
+use alloc::{
+    borrow::{Cow, ToOwned},
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{fmt, net::IpAddr, ops::Deref};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "arbitrary")]
+mod arbitrary;
+#[cfg(feature = "std")]
+pub mod blocking;
+pub mod builder;
+pub mod client;
+#[cfg(feature = "tokio")]
+pub mod codec;
+pub mod connect_plan;
+pub mod corpus;
+pub mod data;
+#[cfg(feature = "time")]
+pub mod datetime;
+pub mod decoder;
+pub mod dsn;
+pub mod events;
+#[cfg(feature = "idna")]
+pub mod idna;
+pub mod inspect;
+pub mod io;
+pub mod message;
+#[cfg(feature = "std")]
+pub mod message_id;
 mod parse;
-use parse::escape_quoted;
+pub mod pipeline;
+pub mod policy;
+#[cfg(feature = "proxy-protocol")]
+pub mod proxy_protocol;
+pub mod retry;
+pub mod sasl;
+pub mod server;
+#[cfg(feature = "futures")]
+pub mod stream;
+#[cfg(feature = "proptest")]
+pub mod testing;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod transcript;
+pub mod url;
+pub mod validate;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod xclient;
+use io::Write;
 use parse::response::is_text_string_byte;
+use parse::{encode_xtext, escape_quoted, is_atext};
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Command {
     Ehlo {
-        domain_or_address: DomainOrAddress,
+        domain_or_address: HeloIdentity,
     },
     Helo {
-        domain_or_address: DomainOrAddress,
+        domain_or_address: HeloIdentity,
     },
     Mail {
-        reverse_path: String,
-        parameters: Vec<Parameter>,
+        reverse_path: ReversePath,
+        parameters: Vec<EsmtpParam>,
     },
     Rcpt {
-        forward_path: String,
-        parameters: Vec<Parameter>,
+        forward_path: ForwardPath,
+        parameters: Vec<EsmtpParam>,
     },
     Data,
     Rset,
@@ -91,74 +147,1453 @@ pub enum Command {
     Quit,
     // Extensions
     StartTls,
-    // AUTH LOGIN
-    AuthLogin(Option<String>),
-    // AUTH PLAIN
-    AuthPlain(Option<String>),
+    /// Start a SASL exchange \[RFC4954\]
+    ///
+    /// The initial response is base64-encoded when serialized. See [`sasl`] for the exchange
+    /// that follows.
+    Auth {
+        mechanism: AuthMechanism,
+        initial_response: Option<Secret<Vec<u8>>>,
+    },
+    /// Send a chunk of the message content \[RFC3030\]
+    ///
+    /// The command line is followed by exactly `size` octets of content. `last` marks the final
+    /// chunk of the message.
+    Bdat {
+        size: u64,
+        last: bool,
+    },
+    /// Identify the client to an LMTP server \[RFC2033\]
+    ///
+    /// Replaces EHLO in LMTP, see [`server::Protocol::Lmtp`].
+    Lhlo {
+        domain_or_address: HeloIdentity,
+    },
+    /// Override the client attributes of the session at a Postfix server, see [`xclient`]
+    Xclient {
+        attributes: Vec<xclient::Attribute>,
+    },
+    /// Forward the client attributes of the message to a Postfix server, see [`xclient`]
+    Xforward {
+        attributes: Vec<xclient::Attribute>,
+    },
+    /// A command with a verb that is not known, e.g., a mistyped one
+    ///
+    /// Servers reply with "500" (RFC 5321, 4.2.4.). A known verb with invalid arguments is a
+    /// syntax error instead, e.g., [`SmtpParseError::InvalidMailbox`].
+    Unknown {
+        /// The verb as sent, e.g., "FOO"
+        verb: String,
+        /// The rest of the line following the space
+        arguments: Option<String>,
+    },
+    /// A private extension, i.e., a verb starting with "X" (RFC 5321, 4.1.5.), other than
+    /// XCLIENT and XFORWARD
+    ///
+    /// The session passes it on as [`server::Event::Other`].
+    Extension {
+        /// The verb as sent, e.g., "XUSR"
+        verb: String,
+        /// The rest of the line following the space
+        arguments: Option<String>,
+    },
 }
 
 impl Command {
-    pub fn from_bytes(input: &[u8]) -> IResult<&[u8], Self> {
-        crate::parse::command::command(input)
+    pub fn from_bytes(input: &[u8]) -> ParseResult<'_, Self> {
+        Self::from_bytes_with(input, SyntaxOptions::default())
+    }
+
+    /// Like [`Command::from_bytes`], but with the given syntax extensions enabled.
+    ///
+    /// In [`ParseMode::Lenient`], the violations are accepted silently, see
+    /// [`Command::from_bytes_lenient`].
+    pub fn from_bytes_with(input: &[u8], options: SyntaxOptions) -> ParseResult<'_, Self> {
+        use crate::parse::command::{command, command_error};
+
+        if options.mode == ParseMode::Lenient {
+            let (remaining, (command, _)) = Self::from_bytes_lenient(input, options)?;
+            return Ok((remaining, command));
+        }
+
+        let result = command(input)
+            .map_err(|error| command_error(input, error, &options.limits))
+            .and_then(|(remaining, command)| {
+                command.check(input.len() - remaining.len(), &options)?;
+                Ok((remaining, command))
+            });
+
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok((_, command)) => tracing::debug!(command = %command.redacted(), "parsed command"),
+            Err(error) if !error.is_incomplete() => {
+                tracing::debug!(%error, "invalid command")
+            }
+            Err(_) => {}
+        }
+
+        result
+    }
+
+    /// Explain why [`Command::from_bytes_with`] rejects the line.
+    ///
+    /// Returns `None` when the line is parsed, or is incomplete. Locating the error parses the
+    /// line again, so this is meant for replies and logs, not for every command.
+    pub fn diagnose(input: &[u8], options: SyntaxOptions) -> Option<Diagnostic> {
+        use crate::parse::command::locate_error;
+
+        let error = match Self::from_bytes_with(input, options) {
+            Ok(_) => return None,
+            Err(error) if error.is_incomplete() => return None,
+            Err(error) => error,
+        };
+        let (offset, detail) = match locate_error(input) {
+            Some((offset, detail)) => (offset, Some(detail)),
+            None => (0, None),
+        };
+
+        Some(Diagnostic {
+            error,
+            offset,
+            detail,
+        })
+    }
+
+    /// Like [`Command::from_bytes`], but for input that is known to be complete.
+    ///
+    /// The final CRLF may be omitted, and incomplete input is an error.
+    pub fn from_bytes_complete(input: &[u8]) -> ParseResult<'_, Self> {
+        use crate::parse::command::{command, command_error};
+
+        let options = SyntaxOptions::default();
+        let (remaining, command) = crate::parse::complete(command, input)
+            .map_err(|error| command_error(input, error, &options.limits))?;
+        // The CRLF may be missing from the input.
+        command.check(input.len() - remaining.len(), &options)?;
+
+        Ok((remaining, command))
+    }
+
+    /// The checks of a parsed command line of `length` octets that the grammar leaves to
+    /// [`SyntaxOptions`]
+    fn check(&self, length: usize, options: &SyntaxOptions) -> Result<(), SmtpParseError> {
+        if !options.smtputf8 && !self.is_ascii() {
+            return Err(SmtpParseError::NonAscii);
+        }
+        self.check_limits(length, &options.limits)?;
+        if options.strict_domains {
+            check_domains(self)?;
+        }
+        if options.domain_policy.is_restricted() {
+            check_domain_policy(self, &options.domain_policy)?;
+        }
+
+        Ok(())
+    }
+
+    /// Parse a command, and repair the violations of [`ParseMode::Lenient`].
+    ///
+    /// Returns the command and the violations the line had, so that a server can decide whether
+    /// to accept it. The line is complete once it ends with LF. 8-bit octets that are not valid
+    /// UTF-8 are replaced with U+FFFD.
+    pub fn from_bytes_lenient(
+        input: &[u8],
+        options: SyntaxOptions,
+    ) -> ParseResult<'_, (Self, Vec<Violation>)> {
+        let Some(end) = memchr::memchr(b'\n', input) else {
+            let limit = options.limits.command.max(options.limits.auth);
+            return Err(match input.len() > limit {
+                true => SmtpParseError::LineTooLong { limit },
+                false => SmtpParseError::UnexpectedEof { needed: None },
+            });
+        };
+        let (line, remaining) = input.split_at(end + 1);
+
+        let (repaired, violations) = parse::lenient::repair_command(line, options.smtputf8);
+        let strict = SyntaxOptions {
+            smtputf8: options.smtputf8 || violations.contains(&Violation::EightBit),
+            mode: ParseMode::Strict,
+            ..options
+        };
+        let (_, command) = Self::from_bytes_with(&repaired, strict)?;
+
+        #[cfg(feature = "tracing")]
+        if !violations.is_empty() {
+            tracing::info!(?violations, command = command.name(), "repaired command");
+        }
+
+        Ok((remaining, (command, violations)))
+    }
+
+    /// Like [`Command::from_bytes_with`], but keep the octets of the command line.
+    ///
+    /// Proxies can then forward the line as received, or the normalized form, see
+    /// [`ParsedCommand`].
+    pub fn parse_strict(input: &[u8], options: SyntaxOptions) -> ParseResult<'_, ParsedCommand> {
+        let (remaining, command) = Self::from_bytes_with(input, options)?;
+        let original = input[..input.len() - remaining.len()].to_vec();
+
+        Ok((
+            remaining,
+            ParsedCommand {
+                command,
+                original,
+                options,
+            },
+        ))
+    }
+}
+
+/// A command and the exact line it was parsed from, see [`Command::parse_strict`]
+///
+/// Parsing normalizes the line, e.g., the case of the verb, "mail from:<a@example.org>" and
+/// "MAIL FROM:<a@example.org>" are the same command.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParsedCommand {
+    pub command: Command,
+    /// The command line including CRLF
+    original: Vec<u8>,
+    /// The options the line was parsed with
+    options: SyntaxOptions,
+}
+
+impl ParsedCommand {
+    pub fn original(&self) -> &[u8] {
+        &self.original
+    }
+
+    /// The verb as it was spelled by the client, e.g., "mail" or "MaIl" for MAIL
+    ///
+    /// Verbs are case-insensitive, [`Command::name`] returns the uppercase form.
+    pub fn verb(&self) -> &str {
+        let length = self
+            .original
+            .iter()
+            .take_while(|byte| byte.is_ascii_alphabetic())
+            .count();
+
+        // ASCII letters are valid UTF-8.
+        core::str::from_utf8(&self.original[..length]).unwrap()
+    }
+
+    /// Write the normalized line, with the options the command was parsed with.
+    pub fn serialize_canonical(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.command.serialize_with(writer, self.options)
+    }
+
+    /// Write the line as it was received.
+    pub fn serialize_original(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.original)
+    }
+
+    /// Whether the received line is the normalized one, i.e., both serializations are equal.
+    pub fn is_canonical(&self) -> bool {
+        self.command.encode(self.options).as_ref() == Ok(&self.original)
+    }
+
+    pub fn into_command(self) -> Command {
+        self.command
+    }
+}
+
+/// Syntax extensions that change which commands are accepted and emitted.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SyntaxOptions {
+    /// Allow UTF-8 in mailboxes, e.g., "jörg@bücher.example", and in parameter values (RFC 6531)
+    ///
+    /// Only enable this when the server advertised SMTPUTF8 (client), or when the client sent
+    /// the SMTPUTF8 parameter with MAIL (server).
+    pub smtputf8: bool,
+    /// Maximum lengths of command lines and their parts
+    pub limits: Limits,
+    /// Whether the common violations of [`Violation`] are accepted in commands
+    pub mode: ParseMode,
+    /// Reject domains in commands that UTS #46 rejects, see
+    /// [`validate::Violation::is_invalid_domain`].
+    ///
+    /// The grammar admits labels of any length, and U-labels that start or end with a hyphen.
+    pub strict_domains: bool,
+    /// Which domains of mailboxes in MAIL and RCPT are accepted
+    pub domain_policy: DomainPolicy,
+}
+
+/// Restrictions on the domains of mailboxes, e.g., for submission servers
+///
+/// Violations are rejected with [`SmtpParseError::ForbiddenDomain`]. "\<Postmaster>" without a
+/// domain is always accepted (RFC 5321, 4.5.1.).
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DomainPolicy {
+    /// Require a fully-qualified domain: at least two labels, and a top-level domain that is
+    /// not numeric or reserved for local use, e.g., "localhost" or "invalid" (RFC 6761)
+    pub require_fqdn: bool,
+    /// Reject address literals, e.g., "a@[192.0.2.1]"
+    pub forbid_address_literals: bool,
+    /// Reject domains of a single label, e.g., "a@localhost"
+    pub forbid_single_label: bool,
+}
+
+impl DomainPolicy {
+    /// Whether the domain of a mailbox, or an address literal with brackets, is accepted.
+    pub fn allows(&self, domain: &str) -> bool {
+        /// Top-level domains that are not delegated in the DNS (RFC 6761, RFC 6762)
+        const LOCAL_TLDS: [&str; 4] = ["invalid", "local", "localdomain", "localhost"];
+
+        if domain.starts_with('[') {
+            return !self.forbid_address_literals;
+        }
+
+        let domain = domain.strip_suffix('.').unwrap_or(domain);
+        let (rest, tld) = match domain.rsplit_once('.') {
+            Some((rest, tld)) => (Some(rest), tld),
+            None => (None, domain),
+        };
+        if rest.is_none() && (self.forbid_single_label || self.require_fqdn) {
+            return false;
+        }
+
+        !self.require_fqdn
+            || !(tld.bytes().all(|byte| byte.is_ascii_digit())
+                || LOCAL_TLDS
+                    .iter()
+                    .any(|local| local.eq_ignore_ascii_case(tld)))
+    }
+
+    fn is_restricted(&self) -> bool {
+        *self != DomainPolicy::default()
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Accept the grammar of the RFCs only.
+    #[default]
+    Strict,
+    /// Repair the violations of [`Violation`], as seen from real-world clients.
+    ///
+    /// Applies to [`Command`]; [`CommandRef`] can't be repaired without copying and is always
+    /// parsed strictly.
+    Lenient,
+}
+
+/// A violation of the grammar that [`ParseMode::Lenient`] accepts
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Violation {
+    /// The line ends with LF instead of CRLF.
+    BareLf,
+    /// Spaces or tabs precede the end of the line.
+    TrailingWhitespace,
+    /// Spaces follow the colon of "MAIL FROM:" or "RCPT TO:".
+    SpaceAfterColon,
+    /// The path of MAIL or RCPT lacks the angle brackets.
+    UnbracketedPath,
+    /// The line has 8-bit octets, but SMTPUTF8 is not enabled or they are not valid UTF-8.
+    EightBit,
+    /// The lines of a multiline reply have different codes, e.g., "250-" followed by "550 ".
+    ///
+    /// The code of the last line is used, see [`Response::parse_lenient`].
+    MismatchedReplyCodes,
+}
+
+/// Size limits of RFC 5321, 4.5.3.1., in octets. Line lengths include the final CRLF.
+///
+/// The defaults are those of the RFCs. Raise them for peers that are known to accept more,
+/// e.g., in LMTP or private deployments.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Limit for all commands except AUTH (RFC 5321, 4.5.3.1.4.)
+    ///
+    /// MAIL and RCPT get additional allowances for extension parameters.
+    pub command: usize,
+    /// Limit for AUTH and the lines of the SASL exchange (RFC 4954, 4.)
+    pub auth: usize,
+    /// Limit for the local part of a mailbox (RFC 5321, 4.5.3.1.1.)
+    pub local_part: usize,
+    /// Limit for a domain (RFC 5321, 4.5.3.1.2.)
+    pub domain: usize,
+    /// Limit for a line of the message content (RFC 5321, 4.5.3.1.6.)
+    pub text_line: usize,
+    /// Number of recipients per transaction a server accepts (RFC 5321, 4.5.3.1.8.)
+    ///
+    /// Servers must accept at least 100 recipients. Further recipients are rejected with
+    /// "452 Too many recipients" (RFC 5321, 4.5.3.1.10.)
+    pub recipients: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            command: 512,
+            auth: 12288,
+            local_part: 64,
+            domain: 255,
+            text_line: 1000,
+            recipients: 100,
+        }
     }
 }
 
+/// Error of [`Command::encode`].
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub enum DomainOrAddress {
+pub enum EncodeError {
+    /// The command contains non-ASCII characters, but SMTPUTF8 is not enabled.
+    NonAscii,
+    /// The command line exceeds the limit.
+    LineTooLong { length: usize, limit: usize },
+    /// A field contains CR, LF, or NUL, which would end the command line early, e.g., to
+    /// smuggle another command.
+    InjectionAttempt,
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::NonAscii => write!(f, "non-ASCII command requires SMTPUTF8"),
+            EncodeError::InjectionAttempt => write!(f, "command contains CR, LF, or NUL"),
+            EncodeError::LineTooLong { length, limit } => {
+                write!(
+                    f,
+                    "command line of {} octets exceeds limit of {}",
+                    length, limit
+                )
+            }
+        }
+    }
+}
+
+impl core::error::Error for EncodeError {}
+
+/// Result of the public parsers: the remaining input and the parsed value.
+pub type ParseResult<'a, T> = Result<(&'a [u8], T), SmtpParseError>;
+
+/// Error of the public parsers, e.g., [`Command::from_bytes`] and [`Response::parse`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SmtpParseError {
+    /// The input ends before the item is complete.
+    ///
+    /// `needed` is the number of missing octets, if known. Streaming callers should retry with
+    /// more input.
+    UnexpectedEof { needed: Option<usize> },
+    /// The line exceeds the limit of [`Limits`].
+    LineTooLong { limit: usize },
+    /// The local part of a mailbox exceeds [`Limits::local_part`].
+    LocalPartTooLong { limit: usize },
+    /// A domain exceeds [`Limits::domain`].
+    DomainTooLong { limit: usize },
+    /// The line does not start with a known command.
+    UnknownCommand,
+    /// The domain or address literal of HELO, EHLO, or LHLO is malformed.
+    InvalidDomain,
+    /// The path of MAIL or RCPT is malformed.
+    InvalidMailbox,
+    /// The domain of a mailbox is not accepted by [`SyntaxOptions::domain_policy`].
+    ForbiddenDomain,
+    /// The command or reply contains non-ASCII characters, but SMTPUTF8 is not enabled.
+    NonAscii,
+    /// The lines of a multiline reply have different codes, see
+    /// [`Violation::MismatchedReplyCodes`].
+    MismatchedReplyCodes,
+    /// Any other violation of the grammar.
+    Syntax,
+}
+
+impl SmtpParseError {
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self, SmtpParseError::UnexpectedEof { .. })
+    }
+}
+
+impl fmt::Display for SmtpParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SmtpParseError::UnexpectedEof {
+                needed: Some(needed),
+            } => {
+                write!(f, "unexpected end of input, {} more octets needed", needed)
+            }
+            SmtpParseError::UnexpectedEof { needed: None } => write!(f, "unexpected end of input"),
+            SmtpParseError::LineTooLong { limit } => {
+                write!(f, "line exceeds limit of {} octets", limit)
+            }
+            SmtpParseError::LocalPartTooLong { limit } => {
+                write!(f, "local part exceeds limit of {} octets", limit)
+            }
+            SmtpParseError::DomainTooLong { limit } => {
+                write!(f, "domain exceeds limit of {} octets", limit)
+            }
+            SmtpParseError::UnknownCommand => write!(f, "unknown command"),
+            SmtpParseError::InvalidDomain => write!(f, "invalid domain or address literal"),
+            SmtpParseError::InvalidMailbox => write!(f, "invalid mailbox"),
+            SmtpParseError::ForbiddenDomain => write!(f, "domain not allowed by policy"),
+            SmtpParseError::NonAscii => write!(f, "non-ASCII text requires SMTPUTF8"),
+            SmtpParseError::MismatchedReplyCodes => write!(f, "mismatched reply codes"),
+            SmtpParseError::Syntax => write!(f, "syntax error"),
+        }
+    }
+}
+
+impl core::error::Error for SmtpParseError {}
+
+impl From<nom::Err<nom::error::Error<&[u8]>>> for SmtpParseError {
+    fn from(error: nom::Err<nom::error::Error<&[u8]>>) -> Self {
+        match error {
+            nom::Err::Incomplete(nom::Needed::Size(needed)) => SmtpParseError::UnexpectedEof {
+                needed: Some(needed.get()),
+            },
+            nom::Err::Incomplete(nom::Needed::Unknown) => {
+                SmtpParseError::UnexpectedEof { needed: None }
+            }
+            nom::Err::Error(_) | nom::Err::Failure(_) => SmtpParseError::Syntax,
+        }
+    }
+}
+
+/// A [`SmtpParseError`] with the position of the offending octet, for messages to humans, see
+/// [`Command::diagnose`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub error: SmtpParseError,
+    /// The offset of the octet in the line at which the grammar is violated, or 0 when the line
+    /// as a whole is rejected, e.g., because it is too long
+    pub offset: usize,
+    /// What is wrong at the offset, e.g., "unexpected \"@\""
+    pub detail: Option<String>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.error, self.offset)?;
+        match &self.detail {
+            Some(detail) => write!(f, ": {}", detail),
+            None => Ok(()),
+        }
+    }
+}
+
+impl core::error::Error for Diagnostic {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// The identity of EHLO, HELO, and LHLO: Domain / address-literal
+///
+/// Clients without a meaningful name must send an address literal (RFC 5321, 4.1.4.). Servers
+/// may reject those by policy, see [`HeloIdentity::is_address_literal`].
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HeloIdentity {
     Domain(String),
-    Address(String),
+    AddressLiteral(AddressLiteral),
+}
+
+/// Previous name of [`HeloIdentity`]
+pub type DomainOrAddress = HeloIdentity;
+
+impl HeloIdentity {
+    pub fn serialize(&self, writer: &mut impl Write) -> io::Result<()> {
+        match self {
+            HeloIdentity::Domain(domain) => write!(writer, "{}", domain),
+            HeloIdentity::AddressLiteral(address) => write!(writer, "{}", address),
+        }
+    }
+
+    pub fn domain(&self) -> Option<&str> {
+        match self {
+            HeloIdentity::Domain(domain) => Some(domain),
+            HeloIdentity::AddressLiteral(_) => None,
+        }
+    }
+
+    pub fn is_address_literal(&self) -> bool {
+        matches!(self, HeloIdentity::AddressLiteral(_))
+    }
+}
+
+/// Parse a domain or an address literal with brackets, e.g., "example.org" or "[192.0.2.1]".
+impl core::str::FromStr for HeloIdentity {
+    type Err = SmtpParseError;
+
+    fn from_str(identity: &str) -> Result<Self, Self::Err> {
+        use crate::parse::command::helo_identity;
+
+        let mut input = identity.as_bytes().to_vec();
+        input.extend_from_slice(b"\r\n");
+
+        match helo_identity(&input) {
+            Ok((b"\r\n", identity)) => Ok(identity),
+            _ => Err(SmtpParseError::InvalidDomain),
+        }
+    }
+}
+
+impl From<AddressLiteral> for HeloIdentity {
+    fn from(address: AddressLiteral) -> Self {
+        HeloIdentity::AddressLiteral(address)
+    }
+}
+
+impl fmt::Display for HeloIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeloIdentity::Domain(domain) => f.write_str(domain),
+            HeloIdentity::AddressLiteral(address) => write!(f, "{}", address),
+        }
+    }
+}
+
+/// address-literal (RFC 5321, 4.1.3.)
+///
+/// Displayed with the enclosing brackets, e.g., "[192.0.2.1]" or "\[IPv6:2001:db8::1\]".
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AddressLiteral {
+    /// IPv4-address-literal or IPv6-address-literal
+    Ip(IpAddr),
+    /// General-address-literal = Standardized-tag ":" 1*dcontent
+    General { tag: String, content: String },
+}
+
+impl fmt::Display for AddressLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressLiteral::Ip(IpAddr::V4(ip)) => write!(f, "[{}]", ip),
+            AddressLiteral::Ip(IpAddr::V6(ip)) => write!(f, "[IPv6:{}]", ip),
+            AddressLiteral::General { tag, content } => write!(f, "[{}:{}]", tag, content),
+        }
+    }
+}
+
+impl From<IpAddr> for AddressLiteral {
+    fn from(ip: IpAddr) -> Self {
+        AddressLiteral::Ip(ip)
+    }
+}
+
+/// Path = "<" [ A-d-l ":" ] Mailbox ">"
+///
+/// The source route (A-d-l) is obsolete: it must be accepted, but should be ignored and not be
+/// generated (RFC 5321, 4.1.2. and Appendix C). Use [`Path::without_route`] to discard it.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Path {
+    /// The domains of the source route, without "@"
+    pub route: Option<Vec<String>>,
+    pub mailbox: String,
+}
+
+impl Path {
+    pub fn new<M>(mailbox: M) -> Self
+    where
+        M: Into<String>,
+    {
+        Self {
+            route: None,
+            mailbox: mailbox.into(),
+        }
+    }
+
+    pub fn without_route(self) -> Self {
+        Self::new(self.mailbox)
+    }
+
+    /// The mailbox in a canonical form for comparisons, e.g., to deduplicate recipients
+    ///
+    /// The source route is dropped, the domain is lowercased, and the quotes of a Quoted-string
+    /// local part are dropped if the content is a Dot-string, e.g., "\"john\"@Example.ORG" becomes
+    /// "john@example.org". An address literal is written in its shortest form, e.g.,
+    /// "\[IPv6:0::1\]" becomes "\[IPv6:::1\]". The local part is not lowercased, as it may be
+    /// case-sensitive (RFC 5321, 2.4.).
+    pub fn normalized(&self) -> Path {
+        let (local_part, domain) = match self.mailbox.rsplit_once('@') {
+            Some((local_part, domain)) => (local_part, Some(domain)),
+            None => (self.mailbox.as_str(), None),
+        };
+
+        let mut mailbox = normalize_local_part(local_part).into_owned();
+        if let Some(domain) = domain {
+            mailbox.push('@');
+            match parse::complete(parse::address::address_literal, domain.as_bytes()) {
+                Ok((b"", literal)) => mailbox.push_str(&literal.to_string()),
+                _ => mailbox.push_str(&domain.to_lowercase()),
+            }
+        }
+
+        Path::new(mailbox)
+    }
+
+    /// The local part of the mailbox, e.g., "first.last" of "first.last@example.org"
+    ///
+    /// Returns `None` if the mailbox was not parsed and is malformed.
+    pub fn local_part(&self) -> Option<LocalPart> {
+        let local_part = match self.mailbox.rsplit_once('@') {
+            Some((local_part, _)) => local_part,
+            None => &self.mailbox,
+        };
+
+        local_part.parse().ok()
+    }
+
+    /// Whether both paths denote the same mailbox, see [`Path::normalized`].
+    pub fn matches(&self, other: &Path) -> bool {
+        self.normalized() == other.normalized()
+    }
+}
+
+/// The path without the angle brackets, e.g., "@a.example,@b.example:user@example.org"
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(route) = &self.route {
+            for (index, domain) in route.iter().enumerate() {
+                let separator = if index == 0 { "" } else { "," };
+                write!(f, "{}@{}", separator, domain)?;
+            }
+            write!(f, ":")?;
+        }
+
+        write!(f, "{}", self.mailbox)
+    }
+}
+
+/// Split the text of a path without angle brackets into the source route and the mailbox.
+///
+/// The text is not validated.
+impl From<&str> for Path {
+    fn from(path: &str) -> Self {
+        match path.strip_prefix('@').and_then(|path| path.split_once(':')) {
+            Some((route, mailbox)) => Path {
+                route: Some(
+                    route
+                        .split(',')
+                        .map(|domain| domain.trim_start_matches('@').to_owned())
+                        .collect(),
+                ),
+                mailbox: mailbox.to_owned(),
+            },
+            None => Path::new(path),
+        }
+    }
+}
+
+/// Reverse-path = Path / "<>"
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ReversePath {
+    /// "<>", used for notifications such as DSNs, to which no notifications must be sent back
+    /// (RFC 5321, 4.5.5.)
+    #[default]
+    Null,
+    Path(Path),
+}
+
+impl ReversePath {
+    pub fn is_null(&self) -> bool {
+        *self == ReversePath::Null
+    }
+
+    /// The "Return-Path:" header that the final delivery agent prepends to the message, including
+    /// the final CRLF (RFC 5321, 4.4.)
+    ///
+    /// The source route is dropped (RFC 5321, C.). A local part that is neither a Dot-string nor a
+    /// Quoted-string is quoted, e.g., "Return-Path: <"first last"@example.org>".
+    pub fn to_return_path_header(&self) -> String {
+        match self {
+            ReversePath::Null => "Return-Path: <>\r\n".to_owned(),
+            ReversePath::Path(path) => {
+                let mailbox = match path.mailbox.rsplit_once('@') {
+                    Some((local_part, domain)) => {
+                        format!("{}@{}", quote_local_part(local_part), domain)
+                    }
+                    None => quote_local_part(&path.mailbox).into_owned(),
+                };
+                format!("Return-Path: <{}>\r\n", mailbox)
+            }
+        }
+    }
+}
+
+/// Local-part = Dot-string / Quoted-string
+fn quote_local_part(local_part: &str) -> Cow<'_, str> {
+    match local_part.parse::<LocalPart>() {
+        Ok(_) => Cow::Borrowed(local_part),
+        Err(_) => Cow::Owned(LocalPart::new(local_part).to_string()),
+    }
 }
 
-impl DomainOrAddress {
-    pub fn serialize(&self, writer: &mut impl Write) -> std::io::Result<()> {
+/// A Quoted-string local part without the quotes, if they are not needed, or with the fewest
+/// escapes.
+fn normalize_local_part(local_part: &str) -> Cow<'_, str> {
+    match local_part.parse::<LocalPart>() {
+        Ok(quoted @ LocalPart::Quoted(_)) => Cow::Owned(quoted.to_string()),
+        _ => Cow::Borrowed(local_part),
+    }
+}
+
+/// Local-part = Dot-string / Quoted-string
+///
+/// The local part of a [`Path`] is kept as it was sent. This is its interpretation: a
+/// Dot-string, e.g., "first.last", or the content of a Quoted-string without the quotes and
+/// escapes, e.g., "first last" for "\"first\\ last\"".
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LocalPart {
+    /// Dot-string = Atom *("."  Atom)
+    DotString(String),
+    Quoted(String),
+}
+
+impl LocalPart {
+    /// The maximum length of a local part in octets, as sent (RFC 5321, 4.5.3.1.1.)
+    pub const MAX_LENGTH: usize = 64;
+
+    /// The local part with the given content, quoted only if it is not a Dot-string.
+    pub fn new(content: &str) -> Self {
+        match is_dot_string(content) {
+            true => LocalPart::DotString(content.to_owned()),
+            false => LocalPart::Quoted(content.to_owned()),
+        }
+    }
+
+    /// Like [`LocalPart::new`], but fails if the local part exceeds [`LocalPart::MAX_LENGTH`]
+    /// octets once quoted.
+    pub fn try_new(content: &str) -> Result<Self, SmtpParseError> {
+        let local_part = Self::new(content);
+
+        match local_part.to_string().len() > Self::MAX_LENGTH {
+            true => Err(SmtpParseError::LocalPartTooLong {
+                limit: Self::MAX_LENGTH,
+            }),
+            false => Ok(local_part),
+        }
+    }
+
+    /// Whether the content must be sent as a Quoted-string, i.e., it is not a Dot-string
+    ///
+    /// This is `false` for a Quoted-string with no need for quotes, e.g., "\"john\"".
+    pub fn needs_quoting(&self) -> bool {
+        !is_dot_string(self.as_str())
+    }
+
+    /// The content, i.e., without quotes and escapes
+    pub fn as_str(&self) -> &str {
+        match self {
+            LocalPart::DotString(content) | LocalPart::Quoted(content) => content,
+        }
+    }
+
+    /// The atoms of a Dot-string, e.g., "first" and "last" for "first.last"
+    pub fn atoms(&self) -> Option<core::str::Split<'_, char>> {
+        match self {
+            LocalPart::DotString(dot_string) => Some(dot_string.split('.')),
+            LocalPart::Quoted(_) => None,
+        }
+    }
+
+    pub fn is_quoted(&self) -> bool {
+        matches!(self, LocalPart::Quoted(_))
+    }
+}
+
+/// Parse a local part as sent, e.g., "first.last" or "\"first last\"".
+///
+/// The length is not checked, as the limit of the parser is [`Limits::local_part`], see
+/// [`LocalPart::try_new`].
+impl core::str::FromStr for LocalPart {
+    type Err = SmtpParseError;
+
+    fn from_str(local_part: &str) -> Result<Self, Self::Err> {
+        use crate::parse::command::local_part_value;
+
+        // The parser is streaming, "@" ends the local part.
+        let mut input = local_part.as_bytes().to_vec();
+        input.push(b'@');
+
+        match local_part_value(&input) {
+            Ok((b"@", local_part)) => Ok(local_part),
+            _ => Err(SmtpParseError::InvalidMailbox),
+        }
+    }
+}
+
+/// The canonical form, with quotes and escapes only where they are needed, e.g., "john" for
+/// "\"john\""
+impl fmt::Display for LocalPart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.needs_quoting() {
+            true => write!(f, "\"{}\"", escape_quoted(self.as_str())),
+            false => write!(f, "{}", self.as_str()),
+        }
+    }
+}
+
+/// An Atom is a Dot-string, a Quoted-string is kept as such.
+impl From<AtomOrQuoted> for LocalPart {
+    fn from(value: AtomOrQuoted) -> Self {
+        match value {
+            AtomOrQuoted::Atom(atom) => LocalPart::DotString(atom),
+            AtomOrQuoted::Quoted(quoted) => LocalPart::Quoted(quoted),
+        }
+    }
+}
+
+/// Dot-string = Atom *("."  Atom)
+fn is_dot_string(content: &str) -> bool {
+    content
+        .split('.')
+        .all(|atom| !atom.is_empty() && atom.bytes().all(is_atext))
+}
+
+/// The path without the angle brackets, i.e., empty for [`ReversePath::Null`]
+impl fmt::Display for ReversePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReversePath::Null => Ok(()),
+            ReversePath::Path(path) => write!(f, "{}", path),
+        }
+    }
+}
+
+/// Like [`Path::from`], but the empty string is [`ReversePath::Null`].
+impl From<&str> for ReversePath {
+    fn from(path: &str) -> Self {
+        match path.is_empty() {
+            true => ReversePath::Null,
+            false => ReversePath::Path(Path::from(path)),
+        }
+    }
+}
+
+impl From<Path> for ReversePath {
+    fn from(path: Path) -> Self {
+        ReversePath::Path(path)
+    }
+}
+
+/// Forward-path of RCPT
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ForwardPath {
+    /// "\<Postmaster>", or "<Postmaster@" Domain ">", matched case-insensitively
+    ///
+    /// Servers must accept mail for the postmaster of their domains without the domain, too
+    /// (RFC 5321, 4.5.1.).
+    Postmaster(Option<String>),
+    Path(Path),
+}
+
+/// The path without the angle brackets
+impl fmt::Display for ForwardPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ForwardPath::Postmaster(None) => write!(f, "Postmaster"),
+            ForwardPath::Postmaster(Some(domain)) => write!(f, "Postmaster@{}", domain),
+            ForwardPath::Path(path) => write!(f, "{}", path),
+        }
+    }
+}
+
+impl ForwardPath {
+    pub fn is_postmaster(&self) -> bool {
+        matches!(self, ForwardPath::Postmaster(_))
+    }
+
+    /// The path in a canonical form for comparisons, see [`Path::normalized`]. The domain of
+    /// [`ForwardPath::Postmaster`] is lowercased.
+    pub fn normalized(&self) -> ForwardPath {
+        match self {
+            ForwardPath::Postmaster(domain) => {
+                ForwardPath::Postmaster(domain.as_deref().map(str::to_lowercase))
+            }
+            ForwardPath::Path(path) => ForwardPath::Path(path.normalized()),
+        }
+    }
+
+    /// Whether both paths denote the same recipient, see [`ForwardPath::normalized`].
+    pub fn matches(&self, other: &ForwardPath) -> bool {
+        self.normalized() == other.normalized()
+    }
+}
+
+/// Like [`Path::from`], but recognizes the Postmaster forms.
+impl From<&str> for ForwardPath {
+    fn from(path: &str) -> Self {
+        if path.eq_ignore_ascii_case("Postmaster") {
+            return ForwardPath::Postmaster(None);
+        }
+
+        match path.split_once('@') {
+            Some((local_part, domain))
+                if local_part.eq_ignore_ascii_case("Postmaster") && !domain.starts_with('[') =>
+            {
+                ForwardPath::Postmaster(Some(domain.to_owned()))
+            }
+            _ => ForwardPath::Path(Path::from(path)),
+        }
+    }
+}
+
+impl From<Path> for ForwardPath {
+    fn from(path: Path) -> Self {
+        ForwardPath::Path(path)
+    }
+}
+
+/// A MAIL or RCPT parameter
+///
+/// Parameters with a known keyword but a value that does not match its syntax are kept as
+/// [`EsmtpParam::Other`].
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EsmtpParam {
+    /// Message size declaration [RFC1870]
+    Size(u32),
+    /// Body type of the message [RFC6152, RFC3030]
+    Body(Body),
+    /// Which part of the message to return in a DSN \[RFC3461\]
+    Ret(Ret),
+    /// Envelope identifier for DSNs \[RFC3461\]
+    ///
+    /// The identifier is xtext-encoded when serialized.
+    Envid(String),
+    /// When to send a DSN \[RFC3461\]
+    Notify(Notify),
+    /// Original recipient for DSNs \[RFC3461\]
+    Orcpt {
+        /// Address type, e.g., "rfc822"
+        addr_type: String,
+        /// Address, xtext-encoded when serialized
+        address: String,
+    },
+    /// Identity that submitted the message, xtext-encoded, or "<>" \[RFC4954\]
+    Auth(String),
+    /// Internationalized email address \[RFC6531\]
+    SmtpUtf8,
+    /// Deliver the message within the given time \[RFC2852\]
+    By(DeliverBy),
+    /// Priority of the message \[RFC6710\]
+    MtPriority(Priority),
+    /// Relay the message over TLS only \[RFC8689\]
+    RequireTls,
+    Other {
+        keyword: String,
+        value: Option<String>,
+    },
+}
+
+/// Previous name of [`EsmtpParam`]
+pub type Parameter = EsmtpParam;
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Body {
+    SevenBit,
+    EightBitMime,
+    BinaryMime,
+}
+
+impl Body {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Body::SevenBit => "7BIT",
+            Body::EightBitMime => "8BITMIME",
+            Body::BinaryMime => "BINARYMIME",
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ret {
+    /// Return the full message
+    Full,
+    /// Return the headers only
+    Hdrs,
+}
+
+impl Ret {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Ret::Full => "FULL",
+            Ret::Hdrs => "HDRS",
+        }
+    }
+}
+
+/// Value of the BY parameter \[RFC2852\]
+///
+/// by-value = by-time ";" by-mode [ by-trace ]
+/// by-time  = ["-" / "+"] 1*9digit
+/// by-mode  = "N" / "R"
+/// by-trace = "T"
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeliverBy {
+    /// Seconds until the deadline, which may be negative when relayed
+    pub time: i32,
+    pub mode: ByMode,
+    /// Request a DSN with the delivery status of each relay
+    pub trace: bool,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByMode {
+    /// Notify the sender when the deadline passes, but continue the delivery.
+    Notify,
+    /// Return the message when it can't be delivered by the deadline. The time must be positive.
+    Return,
+}
+
+impl DeliverBy {
+    pub fn serialize(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mode = match self.mode {
+            ByMode::Notify => "N",
+            ByMode::Return => "R",
+        };
+        write!(writer, "{};{}", self.time, mode)?;
+        if self.trace {
+            writer.write_all(b"T")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Priority of a message \[RFC6710\]
+///
+/// priority-value = [ "-" / "+" ] DIGIT
+///
+/// Ranges from -9 (lowest) to 9 (highest), 0 is the default.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "i8", into = "i8"))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Priority(i8);
+
+impl Priority {
+    pub const MIN: Priority = Priority(-9);
+    pub const MAX: Priority = Priority(9);
+
+    /// Returns `None` if the priority is not in -9..=9.
+    pub fn new(priority: i8) -> Option<Priority> {
+        match (Self::MIN.0..=Self::MAX.0).contains(&priority) {
+            true => Some(Priority(priority)),
+            false => None,
+        }
+    }
+
+    pub fn get(&self) -> i8 {
+        self.0
+    }
+}
+
+impl TryFrom<i8> for Priority {
+    type Error = InvalidPriority;
+
+    fn try_from(priority: i8) -> Result<Self, Self::Error> {
+        Priority::new(priority).ok_or(InvalidPriority(()))
+    }
+}
+
+impl From<Priority> for i8 {
+    fn from(priority: Priority) -> Self {
+        priority.0
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidPriority(());
+
+impl fmt::Display for InvalidPriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "priority is not in -9..=9")
+    }
+}
+
+impl core::error::Error for InvalidPriority {}
+
+/// Conditions for sending a DSN \[RFC3461\]
+///
+/// notify-esmtp-value  = "NEVER" / 1#notify-list-element
+/// notify-list-element = "SUCCESS" / "FAILURE" / "DELAY"
+///
+/// "NEVER" is the empty set, and can't be combined with other conditions.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "u8", into = "u8"))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Notify(u8);
+
+impl Notify {
+    pub const NEVER: Notify = Notify(0);
+    pub const SUCCESS: Notify = Notify(1 << 0);
+    pub const FAILURE: Notify = Notify(1 << 1);
+    pub const DELAY: Notify = Notify(1 << 2);
+
+    const KEYWORDS: [(Notify, &'static str); 3] = [
+        (Notify::SUCCESS, "SUCCESS"),
+        (Notify::FAILURE, "FAILURE"),
+        (Notify::DELAY, "DELAY"),
+    ];
+
+    pub fn is_never(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(&self, other: Notify) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Notify) {
+        self.0 |= other.0;
+    }
+
+    pub fn serialize(&self, writer: &mut impl Write) -> io::Result<()> {
+        if self.is_never() {
+            return writer.write_all(b"NEVER");
+        }
+
+        let mut keywords = Self::KEYWORDS
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, keyword)| keyword);
+
+        if let Some(keyword) = keywords.next() {
+            writer.write_all(keyword.as_bytes())?;
+        }
+        for keyword in keywords {
+            write!(writer, ",{}", keyword)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Accepts the bits of [`Notify::SUCCESS`], [`Notify::FAILURE`], and [`Notify::DELAY`].
+impl TryFrom<u8> for Notify {
+    type Error = InvalidNotify;
+
+    fn try_from(bits: u8) -> Result<Self, Self::Error> {
+        let all = Notify::SUCCESS | Notify::FAILURE | Notify::DELAY;
+
+        match bits & !all.0 {
+            0 => Ok(Notify(bits)),
+            _ => Err(InvalidNotify(())),
+        }
+    }
+}
+
+impl From<Notify> for u8 {
+    fn from(notify: Notify) -> Self {
+        notify.0
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidNotify(());
+
+impl fmt::Display for InvalidNotify {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown NOTIFY condition")
+    }
+}
+
+impl core::error::Error for InvalidNotify {}
+
+impl core::ops::BitOr for Notify {
+    type Output = Notify;
+
+    fn bitor(mut self, rhs: Notify) -> Notify {
+        self.insert(rhs);
+        self
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AtomOrQuoted {
+    Atom(String),
+    Quoted(String),
+}
+
+impl Command {
+    /// The verb, e.g., "MAIL", or the verb as sent for [`Command::Unknown`] and
+    /// [`Command::Extension`]
+    pub fn name(&self) -> &str {
+        match self {
+            Command::Ehlo { .. } => "EHLO",
+            Command::Helo { .. } => "HELO",
+            Command::Mail { .. } => "MAIL",
+            Command::Rcpt { .. } => "RCPT",
+            Command::Data => "DATA",
+            Command::Rset => "RSET",
+            Command::Vrfy { .. } => "VRFY",
+            Command::Expn { .. } => "EXPN",
+            Command::Help { .. } => "HELP",
+            Command::Noop { .. } => "NOOP",
+            Command::Quit => "QUIT",
+            // Extensions
+            Command::StartTls => "STARTTLS",
+            Command::Auth { .. } => "AUTH",
+            Command::Bdat { .. } => "BDAT",
+            Command::Lhlo { .. } => "LHLO",
+            Command::Xclient { .. } => "XCLIENT",
+            Command::Xforward { .. } => "XFORWARD",
+            Command::Unknown { verb, .. } | Command::Extension { verb, .. } => verb,
+        }
+    }
+
+    /// Whether the command may be followed by other commands in a group (RFC 2920, 3.1.)
+    ///
+    /// Only RSET, MAIL, and RCPT may appear anywhere in a group. All other commands, e.g., EHLO,
+    /// DATA, QUIT, or STARTTLS, may only appear as the last command of a group.
+    pub fn is_pipelinable(&self) -> bool {
+        matches!(
+            self,
+            Command::Rset | Command::Mail { .. } | Command::Rcpt { .. }
+        )
+    }
+
+    /// The command line without CRLF, with the initial response of AUTH replaced by
+    /// "[REDACTED]", for logging
+    #[cfg(feature = "tracing")]
+    pub(crate) fn redacted(&self) -> String {
+        let mut line = Vec::new();
+
+        match self {
+            Command::Auth {
+                mechanism,
+                initial_response: Some(_),
+            } => {
+                line.extend_from_slice(b"AUTH ");
+                let _ = mechanism.serialize(&mut line);
+                line.extend_from_slice(b" [REDACTED]");
+            }
+            // Writing to a `Vec` does not fail.
+            _ => {
+                let _ = self.write(&mut line);
+                line.truncate(line.len() - 2);
+            }
+        }
+
+        String::from_utf8_lossy(&line).into_owned()
+    }
+
+    /// Serialize the command.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] when the command contains non-ASCII
+    /// characters. Use [`Command::serialize_with`] to allow them.
+    pub fn serialize(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.serialize_with(writer, SyntaxOptions::default())
+    }
+
+    /// Serialize the command with the given syntax extensions enabled.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] when [`Command::encode`] fails.
+    pub fn serialize_with(
+        &self,
+        writer: &mut impl Write,
+        options: SyntaxOptions,
+    ) -> io::Result<()> {
+        let line = self
+            .encode(options)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+
+        writer.write_all(&line)
+    }
+
+    /// Write the command line as on the wire, i.e., the [`Display`](fmt::Display) form
+    /// followed by CRLF.
+    pub fn write_wire(&self, writer: &mut impl fmt::Write) -> fmt::Result {
+        write!(writer, "{}\r\n", self)
+    }
+
+    /// Encode the command line, including the final CRLF.
+    ///
+    /// Fails when a field contains CR, LF, or NUL, when the command contains non-ASCII
+    /// characters but SMTPUTF8 is not enabled, or when the line exceeds the limit of
+    /// [`SyntaxOptions::limits`] for this command.
+    pub fn encode(&self, options: SyntaxOptions) -> Result<Vec<u8>, EncodeError> {
+        let mut line = Vec::new();
+        // Writing to a `Vec` does not fail.
+        self.write(&mut line).unwrap();
+
+        if has_line_break_or_nul(&line[..line.len() - 2]) {
+            return Err(EncodeError::InjectionAttempt);
+        }
+
+        if !options.smtputf8 && !line.is_ascii() {
+            return Err(EncodeError::NonAscii);
+        }
+
+        let limit = self.line_limit(&options.limits);
+        if line.len() > limit {
+            return Err(EncodeError::LineTooLong {
+                length: line.len(),
+                limit,
+            });
+        }
+
+        Ok(line)
+    }
+
+    /// Maximum length of the command line, see [`Limits`].
+    ///
+    /// MAIL and RCPT may exceed [`Limits::command`] by the allowances of the extensions
+    /// whose parameters they carry.
+    fn line_limit(&self, limits: &Limits) -> usize {
         match self {
-            DomainOrAddress::Domain(domain) => write!(writer, "{}", domain),
-            DomainOrAddress::Address(address) => write!(writer, "[{}]", address),
+            Command::Mail { parameters, .. } => limits
+                .command
+                .saturating_add(parameter_allowance(parameters, 100)),
+            Command::Rcpt { parameters, .. } => limits
+                .command
+                .saturating_add(parameter_allowance(parameters, 500)),
+            Command::Auth { .. } => limits.auth,
+            _ => limits.command,
         }
     }
-}
-
-#[derive(Clone, Debug, PartialEq, Eq)]
-#[non_exhaustive]
-pub enum Parameter {
-    /// Message size declaration [RFC1870]
-    Size(u32),
-    Other {
-        keyword: String,
-        value: Option<String>,
-    },
-}
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum AtomOrQuoted {
-    Atom(String),
-    Quoted(String),
-}
+    /// Check the lengths of the parsed command against the limits.
+    fn check_limits(&self, length: usize, limits: &Limits) -> Result<(), SmtpParseError> {
+        let limit = self.line_limit(limits);
+        if length > limit {
+            return Err(SmtpParseError::LineTooLong { limit });
+        }
 
-impl Command {
-    pub fn name(&self) -> &'static str {
         match self {
-            Command::Ehlo { .. } => "EHLO",
-            Command::Helo { .. } => "HELO",
-            Command::Mail { .. } => "MAIL",
-            Command::Rcpt { .. } => "RCPT",
-            Command::Data => "DATA",
-            Command::Rset => "RSET",
-            Command::Vrfy { .. } => "VRFY",
-            Command::Expn { .. } => "EXPN",
-            Command::Help { .. } => "HELP",
-            Command::Noop { .. } => "NOOP",
-            Command::Quit => "QUIT",
-            // Extensions
-            Command::StartTls => "STARTTLS",
-            // TODO: SMTP AUTH LOGIN
-            Command::AuthLogin(_) => "AUTHLOGIN",
-            // TODO: SMTP AUTH PLAIN
-            Command::AuthPlain(_) => "AUTHPLAIN",
+            Command::Helo { domain_or_address }
+            | Command::Ehlo { domain_or_address }
+            | Command::Lhlo { domain_or_address } => match domain_or_address {
+                HeloIdentity::Domain(domain) => check_domain(domain, limits),
+                HeloIdentity::AddressLiteral(_) => Ok(()),
+            },
+            Command::Mail {
+                reverse_path: ReversePath::Path(path),
+                ..
+            } => check_path(&path.mailbox, limits),
+            Command::Rcpt {
+                forward_path: ForwardPath::Path(path),
+                ..
+            } => check_path(&path.mailbox, limits),
+            Command::Rcpt {
+                forward_path: ForwardPath::Postmaster(Some(domain)),
+                ..
+            } => check_domain(domain, limits),
+            _ => Ok(()),
         }
     }
 
-    pub fn serialize(&self, writer: &mut impl Write) -> std::io::Result<()> {
+    fn is_ascii(&self) -> bool {
+        let mut line = Vec::new();
+        self.write(&mut line).is_ok() && line.is_ascii()
+    }
+
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
         use Command::*;
 
         match self {
@@ -172,14 +1607,17 @@ impl Command {
                 writer.write_all(b"EHLO ")?;
                 domain_or_address.serialize(writer)?;
             }
+            // lhlo = "LHLO" SP ( Domain / address-literal ) CRLF
+            Lhlo { domain_or_address } => {
+                writer.write_all(b"LHLO ")?;
+                domain_or_address.serialize(writer)?;
+            }
             // mail = "MAIL FROM:" Reverse-path [SP Mail-parameters] CRLF
             Mail {
                 reverse_path,
                 parameters,
             } => {
-                writer.write_all(b"MAIL FROM:<")?;
-                writer.write_all(reverse_path.as_bytes())?;
-                writer.write_all(b">")?;
+                write!(writer, "MAIL FROM:<{}>", reverse_path)?;
 
                 for parameter in parameters {
                     writer.write_all(b" ")?;
@@ -191,9 +1629,7 @@ impl Command {
                 forward_path,
                 parameters,
             } => {
-                writer.write_all(b"RCPT TO:<")?;
-                writer.write_all(forward_path.as_bytes())?;
-                writer.write_all(b">")?;
+                write!(writer, "RCPT TO:<{}>", forward_path)?;
 
                 for parameter in parameters {
                     writer.write_all(b" ")?;
@@ -235,21 +1671,44 @@ impl Command {
             // ----- Extensions -----
             // starttls = "STARTTLS" CRLF
             StartTls => writer.write_all(b"STARTTLS")?,
-            // auth_login_command = "AUTH LOGIN" [SP username] CRLF
-            AuthLogin(None) => {
-                writer.write_all(b"AUTH LOGIN")?;
+            // auth-command = "AUTH" SP sasl-mech [SP initial-response] CRLF
+            Auth {
+                mechanism,
+                initial_response,
+            } => {
+                writer.write_all(b"AUTH ")?;
+                mechanism.serialize(writer)?;
+
+                match initial_response.as_ref().map(Secret::expose) {
+                    Some(data) if data.is_empty() => writer.write_all(b" =")?,
+                    Some(data) => write!(writer, " {}", BASE64.encode(data))?,
+                    None => {}
+                }
             }
-            AuthLogin(Some(data)) => {
-                writer.write_all(b"AUTH LOGIN ")?;
-                writer.write_all(data.as_bytes())?;
+            // bdat-cmd = "BDAT" SP chunk-size [ SP end-marker ] CRLF
+            Bdat { size, last: false } => write!(writer, "BDAT {}", size)?,
+            Bdat { size, last: true } => write!(writer, "BDAT {} LAST", size)?,
+            // xclient-command = "XCLIENT" 1*( SP attribute-name "=" attribute-value ) CRLF
+            Xclient { attributes } => {
+                writer.write_all(b"XCLIENT")?;
+                for attribute in attributes {
+                    writer.write_all(b" ")?;
+                    attribute.serialize(writer)?;
+                }
             }
-            // auth_plain_command = "AUTH PLAIN" [SP base64] CRLF
-            AuthPlain(None) => {
-                writer.write_all(b"AUTH PLAIN")?;
+            // xforward-command = "XFORWARD" 1*( SP attribute-name "=" attribute-value ) CRLF
+            Xforward { attributes } => {
+                writer.write_all(b"XFORWARD")?;
+                for attribute in attributes {
+                    writer.write_all(b" ")?;
+                    attribute.serialize(writer)?;
+                }
             }
-            AuthPlain(Some(data)) => {
-                writer.write_all(b"AUTH PLAIN ")?;
-                writer.write_all(data.as_bytes())?;
+            Unknown { verb, arguments } | Extension { verb, arguments } => {
+                writer.write_all(verb.as_bytes())?;
+                if let Some(arguments) = arguments {
+                    write!(writer, " {}", arguments)?;
+                }
             }
         }
 
@@ -257,13 +1716,203 @@ impl Command {
     }
 }
 
-impl Parameter {
-    pub fn serialize(&self, writer: &mut impl Write) -> std::io::Result<()> {
+/// The command line as on the wire, but without the final CRLF
+///
+/// Neither the limits nor SMTPUTF8 are checked, see [`Command::encode`]. The initial response of
+/// AUTH is included; use the `Debug` form for logs.
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut line = Vec::new();
+        // Writing to a `Vec` does not fail.
+        let _ = self.write(&mut line);
+        fmt_line(f, &line)
+    }
+}
+
+/// Write serialized octets without the final CRLF. They are UTF-8, as they were written from
+/// strings.
+fn fmt_line(f: &mut fmt::Formatter<'_>, serialized: &[u8]) -> fmt::Result {
+    let serialized = serialized.strip_suffix(b"\r\n").unwrap_or(serialized);
+    f.write_str(&String::from_utf8_lossy(serialized))
+}
+
+/// Write the output of a `serialize` method, which only fails when the writer fails.
+fn fmt_serialized(
+    f: &mut fmt::Formatter<'_>,
+    serialize: impl FnOnce(&mut Vec<u8>) -> io::Result<()>,
+) -> fmt::Result {
+    let mut serialized = Vec::new();
+    let _ = serialize(&mut serialized);
+    f.write_str(&String::from_utf8_lossy(&serialized))
+}
+
+impl fmt::Display for EsmtpParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_serialized(f, |writer| self.serialize(writer))
+    }
+}
+
+impl fmt::Display for DeliverBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_serialized(f, |writer| self.serialize(writer))
+    }
+}
+
+impl fmt::Display for Notify {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_serialized(f, |writer| self.serialize(writer))
+    }
+}
+
+impl fmt::Display for AtomOrQuoted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_serialized(f, |writer| self.serialize(writer))
+    }
+}
+
+/// Allowance of the parameters of MAIL or RCPT, see [`Limits::command`].
+fn parameter_allowance<P>(parameters: impl IntoIterator<Item = P>, dsn_allowance: usize) -> usize
+where
+    P: core::borrow::Borrow<EsmtpParam>,
+{
+    let mut allowance = 0;
+    let mut dsn = false;
+    for parameter in parameters {
+        allowance += match parameter.borrow() {
+            // RFC 1870, 4.
+            EsmtpParam::Size(_) => 26,
+            // RFC 4954, 5.
+            EsmtpParam::Auth(_) => 500,
+            // RFC 6531, 3.4.
+            EsmtpParam::SmtpUtf8 => 10,
+            // RFC 2852, 4.
+            EsmtpParam::By(_) => 17,
+            // RFC 6710, 3.
+            EsmtpParam::MtPriority(_) => 16,
+            // RFC 3461, 5.; once per command.
+            EsmtpParam::Ret(_)
+            | EsmtpParam::Envid(_)
+            | EsmtpParam::Notify(_)
+            | EsmtpParam::Orcpt { .. }
+                if !dsn =>
+            {
+                dsn = true;
+                dsn_allowance
+            }
+            _ => 0,
+        };
+    }
+
+    allowance
+}
+
+/// Path as stored in [`Command::Mail`] and [`Command::Rcpt`], i.e., without angle brackets
+fn check_path(path: &str, limits: &Limits) -> Result<(), SmtpParseError> {
+    // The domain of the mailbox cannot contain "@", the local part can, if quoted.
+    let Some((local_part, domain)) = path.rsplit_once('@') else {
+        return Ok(());
+    };
+    // Strip the source route, e.g., "@a,@b:" (RFC 5321, 4.1.2.)
+    let local_part = match local_part.starts_with('@') {
+        true => local_part
+            .split_once(':')
+            .map_or(local_part, |(_, local_part)| local_part),
+        false => local_part,
+    };
+
+    if local_part.len() > limits.local_part {
+        return Err(SmtpParseError::LocalPartTooLong {
+            limit: limits.local_part,
+        });
+    }
+    check_domain(domain, limits)
+}
+
+fn check_domain(domain: &str, limits: &Limits) -> Result<(), SmtpParseError> {
+    match domain.len() > limits.domain {
+        true => Err(SmtpParseError::DomainTooLong {
+            limit: limits.domain,
+        }),
+        false => Ok(()),
+    }
+}
+
+/// Check the domains of a command, see [`SyntaxOptions::strict_domains`].
+fn check_domains(command: &Command) -> Result<(), SmtpParseError> {
+    let invalid = validate::command(command)
+        .iter()
+        .any(validate::Violation::is_invalid_domain);
+
+    match (invalid, command) {
+        (false, _) => Ok(()),
+        (true, Command::Ehlo { .. } | Command::Helo { .. } | Command::Lhlo { .. }) => {
+            Err(SmtpParseError::InvalidDomain)
+        }
+        (true, _) => Err(SmtpParseError::InvalidMailbox),
+    }
+}
+
+/// Check the mailboxes of MAIL and RCPT against the policy.
+fn check_domain_policy(command: &Command, policy: &DomainPolicy) -> Result<(), SmtpParseError> {
+    let domain = match command {
+        Command::Mail {
+            reverse_path: ReversePath::Path(path),
+            ..
+        }
+        | Command::Rcpt {
+            forward_path: ForwardPath::Path(path),
+            ..
+        } => path.mailbox.rsplit_once('@').map(|(_, domain)| domain),
+        Command::Rcpt {
+            forward_path: ForwardPath::Postmaster(domain),
+            ..
+        } => domain.as_deref(),
+        _ => None,
+    };
+
+    match domain.is_none_or(|domain| policy.allows(domain)) {
+        true => Ok(()),
+        false => Err(SmtpParseError::ForbiddenDomain),
+    }
+}
+
+/// The position of the first CRLF
+pub(crate) fn find_crlf(input: &[u8]) -> Option<usize> {
+    memchr::memmem::find(input, b"\r\n")
+}
+
+/// Whether a field contains octets that would end the command line
+pub(crate) fn has_line_break_or_nul(field: &[u8]) -> bool {
+    field
+        .iter()
+        .any(|byte| matches!(byte, b'\r' | b'\n' | b'\0'))
+}
+
+impl EsmtpParam {
+    pub fn serialize(&self, writer: &mut impl Write) -> io::Result<()> {
         match self {
-            Parameter::Size(size) => {
+            EsmtpParam::Size(size) => {
                 write!(writer, "SIZE={}", size)?;
             }
-            Parameter::Other { keyword, value } => {
+            EsmtpParam::Body(body) => write!(writer, "BODY={}", body.as_str())?,
+            EsmtpParam::Ret(ret) => write!(writer, "RET={}", ret.as_str())?,
+            EsmtpParam::Envid(envid) => write!(writer, "ENVID={}", encode_xtext(envid))?,
+            EsmtpParam::Notify(notify) => {
+                writer.write_all(b"NOTIFY=")?;
+                notify.serialize(writer)?;
+            }
+            EsmtpParam::Orcpt { addr_type, address } => {
+                write!(writer, "ORCPT={};{}", addr_type, encode_xtext(address))?;
+            }
+            EsmtpParam::Auth(mailbox) => write!(writer, "AUTH={}", mailbox)?,
+            EsmtpParam::SmtpUtf8 => writer.write_all(b"SMTPUTF8")?,
+            EsmtpParam::By(by) => {
+                writer.write_all(b"BY=")?;
+                by.serialize(writer)?;
+            }
+            EsmtpParam::MtPriority(priority) => write!(writer, "MT-PRIORITY={}", priority.get())?,
+            EsmtpParam::RequireTls => writer.write_all(b"REQUIRETLS")?,
+            EsmtpParam::Other { keyword, value } => {
                 writer.write_all(keyword.as_bytes())?;
 
                 if let Some(ref value) = value {
@@ -278,7 +1927,7 @@ impl Parameter {
 }
 
 impl AtomOrQuoted {
-    pub fn serialize(&self, writer: &mut impl Write) -> std::io::Result<()> {
+    pub fn serialize(&self, writer: &mut impl Write) -> io::Result<()> {
         match self {
             AtomOrQuoted::Atom(atom) => {
                 writer.write_all(atom.as_bytes())?;
@@ -296,6 +1945,273 @@ impl AtomOrQuoted {
 
 // -------------------------------------------------------------------------------------------------
 
+/// A command that borrows from the input, see [`Command`].
+///
+/// Arguments are kept as they were sent, i.e., they are neither decoded nor copied. Use
+/// [`CommandRef::to_owned`] for the interpreted form.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CommandRef<'a> {
+    /// Domain or address literal with brackets, e.g., "example.org" or "[192.0.2.1]"
+    Ehlo {
+        domain_or_address: &'a str,
+    },
+    Helo {
+        domain_or_address: &'a str,
+    },
+    /// The reverse-path is empty for the null reverse-path "<>".
+    Mail {
+        reverse_path: &'a str,
+        parameters: ParametersRef<'a>,
+    },
+    Rcpt {
+        forward_path: &'a str,
+        parameters: ParametersRef<'a>,
+    },
+    Data,
+    Rset,
+    Vrfy {
+        user_or_mailbox: AtomOrQuotedRef<'a>,
+    },
+    Expn {
+        mailing_list: AtomOrQuotedRef<'a>,
+    },
+    Help {
+        argument: Option<AtomOrQuotedRef<'a>>,
+    },
+    Noop {
+        argument: Option<AtomOrQuotedRef<'a>>,
+    },
+    Quit,
+    StartTls,
+    /// The initial response is base64-encoded, or "=" for an empty response.
+    Auth {
+        mechanism: &'a str,
+        initial_response: Option<Secret<&'a str>>,
+    },
+    Bdat {
+        size: u64,
+        last: bool,
+    },
+    Lhlo {
+        domain_or_address: &'a str,
+    },
+    /// The attributes as sent, e.g., "NAME=spike.porcupine.org ADDR=168.100.189.2"
+    Xclient {
+        attributes: &'a str,
+    },
+    /// The attributes as sent
+    Xforward {
+        attributes: &'a str,
+    },
+    Unknown {
+        verb: &'a str,
+        arguments: Option<&'a str>,
+    },
+    Extension {
+        verb: &'a str,
+        arguments: Option<&'a str>,
+    },
+}
+
+impl<'a> CommandRef<'a> {
+    pub fn from_bytes(input: &'a [u8]) -> ParseResult<'a, Self> {
+        Self::from_bytes_with(input, SyntaxOptions::default())
+    }
+
+    /// Like [`CommandRef::from_bytes`], but with the given syntax extensions enabled.
+    pub fn from_bytes_with(input: &'a [u8], options: SyntaxOptions) -> ParseResult<'a, Self> {
+        use crate::parse::command::{command_error, command_ref};
+
+        let (remaining, command) =
+            command_ref(input).map_err(|error| command_error(input, error, &options.limits))?;
+
+        let line = &input[..input.len() - remaining.len()];
+        if !options.smtputf8 && !line.is_ascii() {
+            return Err(SmtpParseError::NonAscii);
+        }
+        command.check_limits(line.len(), &options.limits)?;
+        if options.strict_domains || options.domain_policy.is_restricted() {
+            let command = command.to_owned();
+            if options.strict_domains {
+                check_domains(&command)?;
+            }
+            check_domain_policy(&command, &options.domain_policy)?;
+        }
+
+        Ok((remaining, command))
+    }
+
+    /// Like [`Command::check_limits`], without decoding the arguments.
+    fn check_limits(&self, length: usize, limits: &Limits) -> Result<(), SmtpParseError> {
+        let limit = match self {
+            CommandRef::Mail { parameters, .. } => limits
+                .command
+                .saturating_add(parameter_allowance(parameters.iter(), 100)),
+            CommandRef::Rcpt { parameters, .. } => limits
+                .command
+                .saturating_add(parameter_allowance(parameters.iter(), 500)),
+            CommandRef::Auth { .. } => limits.auth,
+            _ => limits.command,
+        };
+        if length > limit {
+            return Err(SmtpParseError::LineTooLong { limit });
+        }
+
+        match self {
+            CommandRef::Helo { domain_or_address }
+            | CommandRef::Ehlo { domain_or_address }
+            | CommandRef::Lhlo { domain_or_address }
+                if !domain_or_address.starts_with('[') =>
+            {
+                check_domain(domain_or_address, limits)
+            }
+            CommandRef::Mail { reverse_path, .. } => check_path(reverse_path, limits),
+            CommandRef::Rcpt { forward_path, .. } => check_path(forward_path, limits),
+            _ => Ok(()),
+        }
+    }
+
+    /// Decode the arguments and copy them into a [`Command`].
+    pub fn to_owned(&self) -> Command {
+        use crate::parse::{
+            address::address_literal,
+            command::{to_auth_mechanism, xclient_attributes},
+        };
+
+        let domain_or_address = |raw: &str| match address_literal(raw.as_bytes()) {
+            Ok((_, address)) => HeloIdentity::AddressLiteral(address),
+            Err(_) => HeloIdentity::Domain(raw.to_owned()),
+        };
+
+        match self {
+            CommandRef::Ehlo {
+                domain_or_address: raw,
+            } => Command::Ehlo {
+                domain_or_address: domain_or_address(raw),
+            },
+            CommandRef::Helo {
+                domain_or_address: raw,
+            } => Command::Helo {
+                domain_or_address: domain_or_address(raw),
+            },
+            CommandRef::Mail {
+                reverse_path,
+                parameters,
+            } => Command::Mail {
+                reverse_path: ReversePath::from(*reverse_path),
+                parameters: parameters.iter().collect(),
+            },
+            CommandRef::Rcpt {
+                forward_path,
+                parameters,
+            } => Command::Rcpt {
+                forward_path: ForwardPath::from(*forward_path),
+                parameters: parameters.iter().collect(),
+            },
+            CommandRef::Data => Command::Data,
+            CommandRef::Rset => Command::Rset,
+            CommandRef::Vrfy { user_or_mailbox } => Command::Vrfy {
+                user_or_mailbox: user_or_mailbox.to_owned(),
+            },
+            CommandRef::Expn { mailing_list } => Command::Expn {
+                mailing_list: mailing_list.to_owned(),
+            },
+            CommandRef::Help { argument } => Command::Help {
+                argument: argument.as_ref().map(AtomOrQuotedRef::to_owned),
+            },
+            CommandRef::Noop { argument } => Command::Noop {
+                argument: argument.as_ref().map(AtomOrQuotedRef::to_owned),
+            },
+            CommandRef::Quit => Command::Quit,
+            CommandRef::StartTls => Command::StartTls,
+            CommandRef::Auth {
+                mechanism,
+                initial_response,
+            } => Command::Auth {
+                mechanism: to_auth_mechanism(mechanism),
+                // The parser checked that the initial response is valid base64.
+                initial_response: initial_response.map(|response| {
+                    Secret::new(match *response.expose() {
+                        "=" => Vec::new(),
+                        response => BASE64.decode(response).unwrap(),
+                    })
+                }),
+            },
+            CommandRef::Bdat { size, last } => Command::Bdat {
+                size: *size,
+                last: *last,
+            },
+            CommandRef::Lhlo {
+                domain_or_address: raw,
+            } => Command::Lhlo {
+                domain_or_address: domain_or_address(raw),
+            },
+            CommandRef::Xclient { attributes } => Command::Xclient {
+                attributes: xclient_attributes(attributes),
+            },
+            CommandRef::Xforward { attributes } => Command::Xforward {
+                attributes: xclient_attributes(attributes),
+            },
+            CommandRef::Unknown { verb, arguments } => Command::Unknown {
+                verb: verb.to_string(),
+                arguments: arguments.map(String::from),
+            },
+            CommandRef::Extension { verb, arguments } => Command::Extension {
+                verb: verb.to_string(),
+                arguments: arguments.map(String::from),
+            },
+        }
+    }
+}
+
+/// Parameters of MAIL or RCPT as sent, e.g., "SIZE=1000 BODY=8BITMIME"
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ParametersRef<'a>(pub(crate) &'a str);
+
+impl<'a> ParametersRef<'a> {
+    pub fn as_str(&self) -> &'a str {
+        self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Decode the parameters.
+    pub fn iter(&self) -> impl Iterator<Item = EsmtpParam> + 'a {
+        self.0
+            .split(' ')
+            .filter(|param| !param.is_empty())
+            .map(crate::parse::command::parse_esmtp_param)
+    }
+}
+
+/// An atom or the content of a quoted string, which is still escaped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AtomOrQuotedRef<'a> {
+    Atom(&'a str),
+    Quoted(&'a str),
+}
+
+impl<'a> AtomOrQuotedRef<'a> {
+    /// The atom, or the unescaped content of the quoted string.
+    pub fn value(&self) -> Cow<'a, str> {
+        match self {
+            AtomOrQuotedRef::Atom(atom) => Cow::Borrowed(atom),
+            AtomOrQuotedRef::Quoted(quoted) => parse::unescape_quoted(quoted),
+        }
+    }
+
+    pub fn to_owned(&self) -> AtomOrQuoted {
+        match self {
+            AtomOrQuotedRef::Atom(atom) => AtomOrQuoted::Atom(atom.to_string()),
+            AtomOrQuotedRef::Quoted(_) => AtomOrQuoted::Quoted(self.value().into_owned()),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[non_exhaustive]
@@ -315,9 +2231,185 @@ pub enum Response {
     },
 }
 
+/// The kind of reply a client expects next.
+///
+/// Greetings and EHLO replies have their own grammar; everything else is a generic reply.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResponseKind {
+    Greeting,
+    Ehlo,
+    Other,
+}
+
 impl Response {
-    pub fn parse_greeting(input: &[u8]) -> IResult<&[u8], Self> {
-        crate::parse::response::greeting(input)
+    /// Parse a reply of the given kind.
+    ///
+    /// Replies that do not match the grammar of a greeting or an EHLO reply, e.g., a "554" greeting
+    /// or a "502" reply to EHLO, are parsed as [`Response::Other`].
+    pub fn parse(kind: ResponseKind, input: &[u8]) -> ParseResult<'_, Self> {
+        Self::parse_with(kind, input, SyntaxOptions::default())
+    }
+
+    /// Like [`Response::parse`], but with the given syntax extensions enabled.
+    ///
+    /// The text may contain UTF-8 with [`SyntaxOptions::smtputf8`] only (RFC 6531, 3.7.4.). The
+    /// lines of a multiline reply must have the same code, see
+    /// [`SmtpParseError::MismatchedReplyCodes`].
+    ///
+    /// In [`ParseMode::Lenient`], the violations are accepted silently, see
+    /// [`Response::parse_lenient`].
+    pub fn parse_with(
+        kind: ResponseKind,
+        input: &[u8],
+        options: SyntaxOptions,
+    ) -> ParseResult<'_, Self> {
+        use crate::parse::response::response;
+
+        let result = match options.mode {
+            ParseMode::Strict => response(kind, input)
+                .map_err(SmtpParseError::from)
+                .and_then(|(remaining, response)| {
+                    response.check(&input[..input.len() - remaining.len()], &options)?;
+                    Ok((remaining, response))
+                }),
+            ParseMode::Lenient => Self::parse_lenient(kind, input, options)
+                .map(|(remaining, (response, _))| (remaining, response)),
+        };
+
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok((_, response)) => {
+                tracing::debug!(code = u16::from(response.code()), ?kind, "parsed reply")
+            }
+            Err(error) if !error.is_incomplete() => tracing::debug!(%error, ?kind, "invalid reply"),
+            Err(_) => {}
+        }
+
+        result
+    }
+
+    /// Parse a reply, and repair the violations of [`ParseMode::Lenient`].
+    ///
+    /// Returns the reply and the violations it had, e.g., [`Violation::MismatchedReplyCodes`],
+    /// so that a client can decide whether to rely on it. A line is complete once it ends with
+    /// LF. 8-bit octets that are not valid UTF-8 are replaced with U+FFFD, e.g., the Latin-1 of
+    /// older servers.
+    pub fn parse_lenient(
+        kind: ResponseKind,
+        input: &[u8],
+        options: SyntaxOptions,
+    ) -> ParseResult<'_, (Self, Vec<Violation>)> {
+        use crate::parse::{complete, lenient::repair_reply, response::response};
+
+        let Some((repaired, consumed, violations)) = repair_reply(input, options.smtputf8) else {
+            return Err(SmtpParseError::UnexpectedEof { needed: None });
+        };
+        let (_, response) = complete(|input| response(kind, input), &repaired)?;
+
+        #[cfg(feature = "tracing")]
+        if !violations.is_empty() {
+            tracing::info!(
+                ?violations,
+                code = u16::from(response.code()),
+                "repaired reply"
+            );
+        }
+
+        Ok((&input[consumed..], (response, violations)))
+    }
+
+    /// Like [`Response::parse`], but for input that is known to be complete.
+    ///
+    /// The final CRLF may be omitted, and incomplete input is an error.
+    pub fn parse_complete(kind: ResponseKind, input: &[u8]) -> ParseResult<'_, Self> {
+        let (remaining, response) =
+            crate::parse::complete(|input| crate::parse::response::response(kind, input), input)?;
+        response.check(
+            &input[..input.len() - remaining.len()],
+            &SyntaxOptions::default(),
+        )?;
+
+        Ok((remaining, response))
+    }
+
+    /// The checks of the parsed `lines` that the grammar leaves to [`SyntaxOptions`]
+    fn check(&self, lines: &[u8], options: &SyntaxOptions) -> Result<(), SmtpParseError> {
+        if !options.smtputf8 && !self.is_ascii() {
+            return Err(SmtpParseError::NonAscii);
+        }
+        if crate::parse::response::has_mismatched_codes(lines) {
+            return Err(SmtpParseError::MismatchedReplyCodes);
+        }
+
+        Ok(())
+    }
+
+    fn is_ascii(&self) -> bool {
+        match self {
+            Response::Greeting { domain, text } => domain.is_ascii() && text.is_ascii(),
+            Response::Ehlo { domain, greet, .. } => {
+                domain.is_ascii() && greet.as_deref().is_none_or(str::is_ascii)
+            }
+            Response::Other { lines, .. } => lines.iter().all(|line| line.is_ascii()),
+        }
+    }
+
+    pub fn code(&self) -> ReplyCode {
+        match self {
+            Response::Greeting { .. } => ReplyCode::Ready,
+            Response::Ehlo { .. } => ReplyCode::Ok,
+            Response::Other { code, .. } => *code,
+        }
+    }
+
+    /// 2yz, the requested action was completed.
+    pub fn is_positive_completion(&self) -> bool {
+        self.severity() == Some(Severity::PositiveCompletion)
+    }
+
+    /// 3yz, the server awaits further information, e.g., the message content after DATA.
+    pub fn is_intermediate(&self) -> bool {
+        self.severity() == Some(Severity::PositiveIntermediate)
+    }
+
+    /// 4yz, the action was not taken, but may succeed when it is repeated later.
+    pub fn is_transient_failure(&self) -> bool {
+        self.severity() == Some(Severity::TransientNegative)
+    }
+
+    /// 5yz, the action was not taken and should not be repeated as it is.
+    pub fn is_permanent_failure(&self) -> bool {
+        self.severity() == Some(Severity::PermanentNegative)
+    }
+
+    pub fn severity(&self) -> Option<Severity> {
+        self.code().severity()
+    }
+
+    pub fn category(&self) -> Option<Category> {
+        self.code().category()
+    }
+
+    /// The enhanced status code at the start of the reply text.
+    ///
+    /// Only meaningful when the server advertised ENHANCEDSTATUSCODES. Codes whose class does not
+    /// match the class of the basic reply code are ignored (RFC 3463, 2.).
+    pub fn enhanced_code(&self) -> Option<EnhancedStatusCode> {
+        let line = match self {
+            Response::Other { lines, .. } => lines.first()?,
+            _ => return None,
+        };
+
+        let (enhanced, _) = EnhancedStatusCode::parse(line)?;
+        match u16::from(enhanced.class) == u16::from(self.code()) / 100 {
+            true => Some(enhanced),
+            false => None,
+        }
+    }
+
+    pub fn parse_greeting(input: &[u8]) -> ParseResult<'_, Self> {
+        Ok(crate::parse::response::greeting(input)?)
     }
 
     pub fn greeting<D, T>(domain: D, text: T) -> Response
@@ -331,12 +2423,12 @@ impl Response {
         }
     }
 
-    pub fn parse_ehlo(input: &[u8]) -> IResult<&[u8], Self> {
-        crate::parse::response::ehlo_ok_rsp(input)
+    pub fn parse_ehlo(input: &[u8]) -> ParseResult<'_, Self> {
+        Ok(crate::parse::response::ehlo_ok_rsp(input)?)
     }
 
-    pub fn parse_other(input: &[u8]) -> IResult<&[u8], Self> {
-        crate::parse::response::reply_lines(input)
+    pub fn parse_other(input: &[u8]) -> ParseResult<'_, Self> {
+        Ok(crate::parse::response::reply_lines(input)?)
     }
 
     pub fn ehlo<D, G>(domain: D, greet: Option<G>, capabilities: Vec<Capability>) -> Response
@@ -351,6 +2443,12 @@ impl Response {
         }
     }
 
+    /// Start building a reply with text and an enhanced status code, see
+    /// [`builder::ResponseBuilder`].
+    pub fn builder(code: impl Into<ReplyCode>) -> builder::ResponseBuilder {
+        builder::ResponseBuilder::new(code)
+    }
+
     pub fn other<T>(code: ReplyCode, text: TextString<'static>) -> Response
     where
         T: Into<String>,
@@ -361,26 +2459,34 @@ impl Response {
         }
     }
 
-    pub fn serialize(&self, writer: &mut impl Write) -> std::io::Result<()> {
-        match self {
-            Response::Greeting { domain, text } => {
-                let lines = text.lines().collect::<Vec<_>>();
+    /// Serialize the reply.
+    ///
+    /// Text lines that exceed the limit of 512 octets per reply line are wrapped, preferably at
+    /// a space. Fails with [`io::ErrorKind::InvalidInput`] when the reply code is not in the
+    /// range of [`ReplyCode::is_valid`].
+    pub fn serialize(&self, writer: &mut impl Write) -> io::Result<()> {
+        if !self.code().is_valid() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "reply code out of range",
+            ));
+        }
 
-                if let Some((first, tail)) = lines.split_first() {
-                    if let Some((last, head)) = tail.split_last() {
-                        write!(writer, "220-{} {}\r\n", domain, first)?;
+        self.write(writer)
+    }
 
-                        for line in head {
-                            write!(writer, "220-{}\r\n", line)?;
-                        }
+    /// Write the reply lines, without checking the reply code.
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        match self {
+            Response::Greeting { domain, text } => {
+                let mut lines = text.lines();
+                let first = match lines.next() {
+                    Some(first) => format!("{} {}", domain, first),
+                    None => domain.clone(),
+                };
 
-                        write!(writer, "220 {}\r\n", last)?;
-                    } else {
-                        write!(writer, "220 {} {}\r\n", domain, first)?;
-                    }
-                } else {
-                    write!(writer, "220 {}\r\n", domain)?;
-                }
+                let lines = core::iter::once(first.as_str()).chain(lines);
+                write_reply_lines(writer, 220, lines.flat_map(wrap))?;
             }
             Response::Ehlo {
                 domain,
@@ -409,20 +2515,277 @@ impl Response {
                 }
             }
             Response::Other { code, lines } => {
-                let code = u16::from(*code);
-                for line in lines.iter().take(lines.len().saturating_sub(1)) {
-                    write!(writer, "{}-{}\r\n", code, line,)?;
-                }
-
-                match lines.last() {
-                    Some(s) => write!(writer, "{} {}\r\n", code, s)?,
-                    None => write!(writer, "{}\r\n", code)?,
-                };
+                let lines = lines.iter().flat_map(|line| wrap(line));
+                write_reply_lines(writer, u16::from(*code), lines)?;
             }
         }
 
         Ok(())
     }
+
+    pub fn to_vec(&self) -> io::Result<Vec<u8>> {
+        let mut serialized = Vec::new();
+        self.serialize(&mut serialized)?;
+        Ok(serialized)
+    }
+
+    /// Write the reply as on the wire, i.e., the [`Display`](fmt::Display) form followed by
+    /// CRLF.
+    pub fn write_wire(&self, writer: &mut impl fmt::Write) -> fmt::Result {
+        write!(writer, "{}\r\n", self)
+    }
+}
+
+/// The reply lines as on the wire, separated by CRLF, but without the final CRLF
+///
+/// Unlike [`Response::serialize`], the reply code is not checked.
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut serialized = Vec::new();
+        // Writing to a `Vec` does not fail.
+        let _ = self.write(&mut serialized);
+        fmt_line(f, &serialized)
+    }
+}
+
+/// Interpretation of a reply to VRFY or EXPN (RFC 5321, 3.5.)
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerifyResult {
+    /// 250 or 251. EXPN replies with one mailbox per line.
+    Found(Vec<VerifiedMailbox>),
+    /// 553, with the possible mailboxes, if any were listed
+    Ambiguous(Vec<VerifiedMailbox>),
+    /// 252, the server cannot verify the user, but will accept the message
+    Unverified,
+    /// 550 or 551
+    NotFound,
+}
+
+/// A mailbox in a reply to VRFY or EXPN, e.g., "Fred Smith <fred@example.org>"
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifiedMailbox {
+    pub name: Option<String>,
+    pub mailbox: String,
+}
+
+impl VerifyResult {
+    /// Interpret a reply to VRFY or EXPN.
+    ///
+    /// Returns `None` when the reply does not answer the query, e.g., "502 Command not
+    /// implemented". Lines without a valid mailbox are skipped, and enhanced status codes are
+    /// ignored.
+    pub fn from_response(response: &Response) -> Option<Self> {
+        let (code, lines) = match response {
+            Response::Other { code, lines } => (u16::from(*code), lines),
+            _ => return None,
+        };
+        let mailboxes = || {
+            lines
+                .iter()
+                .filter_map(|line| VerifiedMailbox::parse(line, code != 251))
+                .collect()
+        };
+
+        match code {
+            250 | 251 => Some(VerifyResult::Found(mailboxes())),
+            252 => Some(VerifyResult::Unverified),
+            553 => Some(VerifyResult::Ambiguous(mailboxes())),
+            550 | 551 => Some(VerifyResult::NotFound),
+            _ => None,
+        }
+    }
+}
+
+impl VerifiedMailbox {
+    /// "User Name <local-part@domain>", "<local-part@domain>", or "local-part@domain"
+    ///
+    /// With `named` unset, the text before the mailbox is not a name, e.g., in "251 User not
+    /// local; will forward to <fred@example.org>".
+    fn parse(line: &str, named: bool) -> Option<Self> {
+        let line = match EnhancedStatusCode::parse(line) {
+            Some((_, rest)) => rest,
+            None => line,
+        };
+
+        let (name, mailbox) = match (line.rfind('<'), line.rfind('>')) {
+            (Some(start), Some(end)) if start < end => {
+                (line[..start].trim(), &line[start + 1..end])
+            }
+            _ => ("", line.trim()),
+        };
+
+        // Terminate the input, so that the streaming parser sees the end of the domain.
+        let terminated = format!("{}\r\n", mailbox);
+        match crate::parse::command::mailbox(terminated.as_bytes()) {
+            Ok((b"\r\n", _)) => Some(VerifiedMailbox {
+                name: match named && !name.is_empty() {
+                    true => Some(name.to_owned()),
+                    false => None,
+                },
+                mailbox: mailbox.to_owned(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Interpretation of a reply to HELP (RFC 5321, 4.1.1.8.)
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HelpResponse {
+    /// The command the reply describes, in upper case, if the first line starts with it, e.g.,
+    /// "MAIL" for "214-MAIL FROM:\<sender> \[\<parameters>\]"
+    pub topic: Option<String>,
+    /// The text of the reply without enhanced status codes
+    pub lines: Vec<String>,
+}
+
+impl HelpResponse {
+    /// Interpret a "211" or "214" reply. Returns `None` for other replies, e.g., "502 Command not
+    /// implemented" or "504 HELP topic unknown".
+    pub fn from_response(response: &Response) -> Option<Self> {
+        let lines = match response {
+            Response::Other {
+                code: ReplyCode::SystemStatus | ReplyCode::HelpMessage,
+                lines,
+            } => lines,
+            _ => return None,
+        };
+
+        let lines: Vec<String> = lines
+            .iter()
+            .map(|line| match EnhancedStatusCode::parse(line) {
+                Some((_, rest)) => rest.to_owned(),
+                None => line.to_string(),
+            })
+            .collect();
+
+        let topic = lines
+            .first()
+            .and_then(|line| line.split([' ', ':']).next())
+            .map(str::to_ascii_uppercase)
+            .filter(|keyword| {
+                matches!(
+                    keyword.as_str(),
+                    "HELO"
+                        | "EHLO"
+                        | "LHLO"
+                        | "MAIL"
+                        | "RCPT"
+                        | "DATA"
+                        | "BDAT"
+                        | "RSET"
+                        | "VRFY"
+                        | "EXPN"
+                        | "HELP"
+                        | "NOOP"
+                        | "QUIT"
+                        | "STARTTLS"
+                        | "AUTH"
+                )
+            });
+
+        Some(HelpResponse { topic, lines })
+    }
+}
+
+/// A reply that borrows from the input, see [`Response`].
+///
+/// Only the generic reply grammar is applied, i.e., greetings and EHLO replies are not
+/// interpreted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResponseRef<'a> {
+    code: ReplyCode,
+    /// Reply lines including codes and CRLFs
+    raw: &'a str,
+}
+
+impl<'a> ResponseRef<'a> {
+    pub fn parse(input: &'a [u8]) -> ParseResult<'a, Self> {
+        Ok(crate::parse::response::reply_lines_ref(input)?)
+    }
+
+    /// The code of the last line
+    pub fn code(&self) -> ReplyCode {
+        self.code
+    }
+
+    /// The reply as sent
+    pub fn as_str(&self) -> &'a str {
+        self.raw
+    }
+
+    /// The text of the lines, omitting lines without text
+    pub fn lines(&self) -> impl Iterator<Item = &'a str> {
+        self.raw
+            .split_terminator("\r\n")
+            .filter_map(|line| line.get(4..))
+            .filter(|text| !text.is_empty())
+    }
+
+    /// Copy the reply into a [`Response::Other`].
+    pub fn to_owned(&self) -> Response {
+        Response::Other {
+            code: self.code,
+            lines: self
+                .lines()
+                .map(|line| TextString(Cow::Owned(line.to_owned())))
+                .collect(),
+        }
+    }
+}
+
+/// Maximum length of the text of a reply line (RFC 5321, 4.5.3.1.5.)
+///
+/// The line is limited to 512 octets, including the reply code, the separator, and CRLF.
+const MAX_REPLY_TEXT: usize = 512 - 6;
+
+/// Split a line of reply text into lines of at most [`MAX_REPLY_TEXT`] octets.
+fn wrap(mut line: &str) -> Vec<&str> {
+    let mut wrapped = Vec::new();
+
+    while line.len() > MAX_REPLY_TEXT {
+        let mut end = MAX_REPLY_TEXT;
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let (head, tail) = match line[..=end].rfind(' ') {
+            Some(space) if space > 0 => (&line[..space], &line[space + 1..]),
+            _ => line.split_at(end),
+        };
+        wrapped.push(head);
+        line = tail;
+    }
+    wrapped.push(line);
+
+    wrapped
+}
+
+/// Reply-line = *( Reply-code "-" [ textstring ] CRLF )
+///                 Reply-code [ SP textstring ] CRLF
+fn write_reply_lines<'a>(
+    writer: &mut impl Write,
+    code: u16,
+    lines: impl Iterator<Item = &'a str>,
+) -> io::Result<()> {
+    let mut lines = lines.peekable();
+
+    if lines.peek().is_none() {
+        return write!(writer, "{}\r\n", code);
+    }
+
+    while let Some(line) = lines.next() {
+        let separator = match lines.peek() {
+            Some(_) => '-',
+            None => ' ',
+        };
+        write!(writer, "{}{}{}\r\n", code, separator, line)?;
+    }
+
+    Ok(())
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -487,7 +2850,9 @@ pub enum Capability {
     Checkpoint,
 
     /// Deliver By [RFC2852]
-    DeliverBy,
+    ///
+    /// The minimum by-time in seconds, or 0 if none is declared.
+    DeliverBy(u32),
 
     /// Command Pipelining [RFC2920]
     Pipelining,
@@ -535,7 +2900,9 @@ pub enum Capability {
     SmtpUtf8,
 
     /// Priority Message Handling [RFC6710]
-    // MTPRIORITY,
+    ///
+    /// The priority assignment policy, e.g., "MIXER", if declared.
+    MtPriority(Option<String>),
 
     /// Require Recipient Valid Since [RFC7293]
     Rrvs,
@@ -556,7 +2923,7 @@ pub enum Capability {
 }
 
 impl Capability {
-    pub fn serialize(&self, writer: &mut impl Write) -> std::io::Result<()> {
+    pub fn serialize(&self, writer: &mut impl Write) -> io::Result<()> {
         match self {
             Capability::Expn => writer.write_all(b"EXPN"),
             Capability::Help => writer.write_all(b"HELP"),
@@ -565,7 +2932,8 @@ impl Capability {
             Capability::Chunking => writer.write_all(b"CHUNKING"),
             Capability::BinaryMime => writer.write_all(b"BINARYMIME"),
             Capability::Checkpoint => writer.write_all(b"CHECKPOINT"),
-            Capability::DeliverBy => writer.write_all(b"DELIVERBY"),
+            Capability::DeliverBy(0) => writer.write_all(b"DELIVERBY"),
+            Capability::DeliverBy(time) => write!(writer, "DELIVERBY {}", time),
             Capability::Pipelining => writer.write_all(b"PIPELINING"),
             Capability::Dsn => writer.write_all(b"DSN"),
             Capability::Etrn => writer.write_all(b"ETRN"),
@@ -589,6 +2957,8 @@ impl Capability {
             }
             Capability::Burl => writer.write_all(b"BURL"),
             Capability::SmtpUtf8 => writer.write_all(b"SMTPUTF8"),
+            Capability::MtPriority(None) => writer.write_all(b"MT-PRIORITY"),
+            Capability::MtPriority(Some(profile)) => write!(writer, "MT-PRIORITY {}", profile),
             Capability::Rrvs => writer.write_all(b"RRVS"),
             Capability::RequireTls => writer.write_all(b"REQUIRETLS"),
             Capability::Other { keyword, params } => {
@@ -610,8 +2980,294 @@ impl Capability {
     }
 }
 
+/// The EHLO keyword and parameters, e.g., "SIZE 10240000"
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_serialized(f, |writer| self.serialize(writer))
+    }
+}
+
+/// The set of extensions advertised in an EHLO reply.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    pub expn: bool,
+    pub help: bool,
+    pub eight_bit_mime: bool,
+    /// Maximum message size. `Some(0)` means that no fixed maximum is declared.
+    pub size: Option<u32>,
+    pub chunking: bool,
+    pub binary_mime: bool,
+    pub checkpoint: bool,
+    /// Minimum by-time in seconds. `Some(0)` means that no minimum is declared.
+    pub deliver_by: Option<u32>,
+    pub pipelining: bool,
+    pub dsn: bool,
+    pub etrn: bool,
+    pub enhanced_status_codes: bool,
+    pub start_tls: bool,
+    pub mtrk: bool,
+    pub atrn: bool,
+    /// Supported authentication mechanisms, if AUTH is advertised.
+    pub auth: Option<Vec<AuthMechanism>>,
+    pub burl: bool,
+    pub smtp_utf8: bool,
+    pub mt_priority: bool,
+    /// Priority assignment policy declared with MT-PRIORITY
+    pub mt_priority_profile: Option<String>,
+    pub rrvs: bool,
+    pub require_tls: bool,
+    /// Unknown extensions as (keyword, params)
+    pub other: Vec<(String, Vec<String>)>,
+}
+
+impl Capabilities {
+    /// Check whether the server supports the given authentication mechanism.
+    pub fn supports_auth(&self, mechanism: &AuthMechanism) -> bool {
+        match self.auth {
+            Some(ref mechanisms) => mechanisms.contains(mechanism),
+            None => false,
+        }
+    }
+
+    /// The fixed maximum message size the server declared with SIZE, if any (RFC 1870, 4.)
+    pub fn max_size(&self) -> Option<u32> {
+        self.size.filter(|&size| size > 0)
+    }
+
+    /// Start building the capabilities a server advertises, see
+    /// [`builder::CapabilitiesBuilder`].
+    pub fn builder() -> builder::CapabilitiesBuilder {
+        builder::CapabilitiesBuilder::new()
+    }
+
+    /// The capabilities in the order of [`Capability`], followed by the unknown extensions
+    ///
+    /// This is the inverse of collecting the capabilities, e.g., from a parsed EHLO reply.
+    pub fn to_vec(&self) -> Vec<Capability> {
+        let known = [
+            self.expn.then_some(Capability::Expn),
+            self.help.then_some(Capability::Help),
+            self.eight_bit_mime.then_some(Capability::EightBitMime),
+            self.size.map(Capability::Size),
+            self.chunking.then_some(Capability::Chunking),
+            self.binary_mime.then_some(Capability::BinaryMime),
+            self.checkpoint.then_some(Capability::Checkpoint),
+            self.deliver_by.map(Capability::DeliverBy),
+            self.pipelining.then_some(Capability::Pipelining),
+            self.dsn.then_some(Capability::Dsn),
+            self.etrn.then_some(Capability::Etrn),
+            self.enhanced_status_codes
+                .then_some(Capability::EnhancedStatusCodes),
+            self.start_tls.then_some(Capability::StartTls),
+            self.mtrk.then_some(Capability::Mtrk),
+            self.atrn.then_some(Capability::Atrn),
+            self.auth.clone().map(Capability::Auth),
+            self.burl.then_some(Capability::Burl),
+            self.smtp_utf8.then_some(Capability::SmtpUtf8),
+            self.mt_priority
+                .then(|| Capability::MtPriority(self.mt_priority_profile.clone())),
+            self.rrvs.then_some(Capability::Rrvs),
+            self.require_tls.then_some(Capability::RequireTls),
+        ];
+        let other = self
+            .other
+            .iter()
+            .map(|(keyword, params)| Capability::Other {
+                keyword: keyword.clone(),
+                params: params.clone(),
+            });
+
+        known.into_iter().flatten().chain(other).collect()
+    }
+
+    /// The reply to EHLO that advertises these capabilities
+    pub fn to_response<D, G>(&self, domain: D, greet: Option<G>) -> Response
+    where
+        D: Into<String>,
+        G: Into<String>,
+    {
+        Response::ehlo(domain, greet, self.to_vec())
+    }
+
+    fn insert(&mut self, capability: Capability) {
+        match capability {
+            Capability::Expn => self.expn = true,
+            Capability::Help => self.help = true,
+            Capability::EightBitMime => self.eight_bit_mime = true,
+            Capability::Size(size) => self.size = Some(size),
+            Capability::Chunking => self.chunking = true,
+            Capability::BinaryMime => self.binary_mime = true,
+            Capability::Checkpoint => self.checkpoint = true,
+            Capability::DeliverBy(time) => self.deliver_by = Some(time),
+            Capability::Pipelining => self.pipelining = true,
+            Capability::Dsn => self.dsn = true,
+            Capability::Etrn => self.etrn = true,
+            Capability::EnhancedStatusCodes => self.enhanced_status_codes = true,
+            Capability::StartTls => self.start_tls = true,
+            Capability::Mtrk => self.mtrk = true,
+            Capability::Atrn => self.atrn = true,
+            // Servers often advertise both "AUTH" and "AUTH=".
+            Capability::Auth(mechanisms) => {
+                let known = self.auth.get_or_insert_with(Vec::new);
+
+                for mechanism in mechanisms {
+                    if !known.contains(&mechanism) {
+                        known.push(mechanism);
+                    }
+                }
+            }
+            Capability::Burl => self.burl = true,
+            Capability::SmtpUtf8 => self.smtp_utf8 = true,
+            Capability::MtPriority(profile) => {
+                self.mt_priority = true;
+                self.mt_priority_profile = profile;
+            }
+            Capability::Rrvs => self.rrvs = true,
+            Capability::RequireTls => self.require_tls = true,
+            Capability::Other { keyword, params } => self.other.push((keyword, params)),
+        }
+    }
+}
+
+impl FromIterator<Capability> for Capabilities {
+    fn from_iter<I: IntoIterator<Item = Capability>>(iter: I) -> Self {
+        let mut capabilities = Capabilities::default();
+
+        for capability in iter {
+            capabilities.insert(capability);
+        }
+
+        capabilities
+    }
+}
+
+impl Extend<Capability> for Capabilities {
+    fn extend<I: IntoIterator<Item = Capability>>(&mut self, iter: I) {
+        for capability in iter {
+            self.insert(capability);
+        }
+    }
+}
+
+impl From<Vec<Capability>> for Capabilities {
+    fn from(capabilities: Vec<Capability>) -> Self {
+        capabilities.into_iter().collect()
+    }
+}
+
+/// The initial reply of the server (RFC 5321, 3.1.)
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Greeting {
+    /// "220", the server is ready.
+    Ready { domain: String, text: String },
+    /// "554", the server does not accept mail on this connection, but waits for QUIT.
+    Rejected {
+        /// The first word of the text, if it has the syntax of a domain, like in a "220" greeting
+        ///
+        /// The domain is optional in a "554" greeting, so this may also be a word of the text.
+        domain: Option<String>,
+        lines: Vec<TextString<'static>>,
+    },
+}
+
+impl Greeting {
+    pub fn parse(input: &[u8]) -> ParseResult<'_, Self> {
+        Ok(crate::parse::response::greeting_or_rejection(input)?)
+    }
+
+    /// Like [`Greeting::parse`], but for input that is known to be complete.
+    pub fn parse_complete(input: &[u8]) -> ParseResult<'_, Self> {
+        Ok(crate::parse::complete(
+            crate::parse::response::greeting_or_rejection,
+            input,
+        )?)
+    }
+
+    pub fn domain(&self) -> Option<&str> {
+        match self {
+            Greeting::Ready { domain, .. } => Some(domain),
+            Greeting::Rejected { domain, .. } => domain.as_deref(),
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        matches!(self, Greeting::Ready { .. })
+    }
+}
+
+impl TryFrom<Response> for Greeting {
+    type Error = Response;
+
+    /// Convert a [`Response::Greeting`] or a "554" reply. Other replies are returned as error.
+    fn try_from(response: Response) -> Result<Self, Self::Error> {
+        match response {
+            Response::Greeting { domain, text } => Ok(Greeting::Ready { domain, text }),
+            Response::Other {
+                code: ReplyCode::TransactionFailed,
+                lines,
+            } => Ok(Greeting::Rejected {
+                domain: lines
+                    .first()
+                    .and_then(|line| crate::parse::response::leading_domain(line)),
+                lines,
+            }),
+            response => Err(response),
+        }
+    }
+}
+
+/// A positive reply to EHLO.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EhloResponse {
+    pub domain: String,
+    pub greet: Option<String>,
+    pub capabilities: Capabilities,
+}
+
+impl EhloResponse {
+    pub fn parse(input: &[u8]) -> ParseResult<'_, Self> {
+        Ok(crate::parse::response::ehlo_response(input)?)
+    }
+
+    /// Like [`EhloResponse::parse`], but for input that is known to be complete.
+    pub fn parse_complete(input: &[u8]) -> ParseResult<'_, Self> {
+        Ok(crate::parse::complete(
+            crate::parse::response::ehlo_response,
+            input,
+        )?)
+    }
+}
+
+impl TryFrom<Response> for EhloResponse {
+    type Error = Response;
+
+    /// Convert a [`Response::Ehlo`]. Other replies are returned as error.
+    fn try_from(response: Response) -> Result<Self, Self::Error> {
+        match response {
+            Response::Ehlo {
+                domain,
+                greet,
+                capabilities,
+            } => Ok(EhloResponse {
+                domain,
+                greet,
+                capabilities: capabilities.into(),
+            }),
+            response => Err(response),
+        }
+    }
+}
+
+/// Reply code (RFC 5321, 4.2.)
+///
+/// Codes compare by their value, so that, e.g., `ReplyCode::Other(250)` equals
+/// [`ReplyCode::Ok`]. The associated constants name the codes of RFC 5321 and its extensions,
+/// including those without a variant, e.g., [`ReplyCode::AUTHENTICATION_REQUIRED_530`].
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug)]
 pub enum ReplyCode {
     /// 211 System status, or system help reply
     SystemStatus,
@@ -681,6 +3337,81 @@ pub enum ReplyCode {
 }
 
 impl ReplyCode {
+    pub const SYSTEM_STATUS_211: Self = Self::SystemStatus;
+    pub const HELP_MESSAGE_214: Self = Self::HelpMessage;
+    pub const SERVICE_READY_220: Self = Self::Ready;
+    pub const CLOSING_CHANNEL_221: Self = Self::ClosingChannel;
+    /// 235 Authentication succeeded (RFC 4954, 6.)
+    pub const AUTHENTICATION_SUCCEEDED_235: Self = Self::Other(235);
+    pub const OK_250: Self = Self::Ok;
+    pub const USER_NOT_LOCAL_WILL_FORWARD_251: Self = Self::UserNotLocalWillForward;
+    pub const CANNOT_VRFY_252: Self = Self::CannotVrfy;
+    /// 334 Server challenge of an AUTH exchange (RFC 4954, 4.)
+    pub const AUTH_CHALLENGE_334: Self = Self::Other(334);
+    pub const START_MAIL_INPUT_354: Self = Self::StartMailInput;
+    pub const SERVICE_NOT_AVAILABLE_421: Self = Self::NotAvailable;
+    /// 432 A password transition is needed (RFC 4954, 6.)
+    pub const PASSWORD_TRANSITION_NEEDED_432: Self = Self::Other(432);
+    pub const MAILBOX_UNAVAILABLE_450: Self = Self::MailboxTemporarilyUnavailable;
+    pub const LOCAL_ERROR_451: Self = Self::ProcessingError;
+    pub const INSUFFICIENT_STORAGE_452: Self = Self::InsufficientStorage;
+    /// 454 Temporary authentication failure (RFC 4954, 6.), or TLS not available (RFC 3207,
+    /// 4.)
+    pub const TEMPORARY_FAILURE_454: Self = Self::Other(454);
+    pub const UNABLE_TO_ACCOMMODATE_PARAMETERS_455: Self = Self::UnableToAccommodateParameters;
+    pub const SYNTAX_ERROR_500: Self = Self::SyntaxError;
+    pub const PARAMETER_SYNTAX_ERROR_501: Self = Self::ParameterSyntaxError;
+    pub const COMMAND_NOT_IMPLEMENTED_502: Self = Self::CommandNotImplemented;
+    pub const BAD_SEQUENCE_503: Self = Self::BadSequence;
+    pub const PARAMETER_NOT_IMPLEMENTED_504: Self = Self::ParameterNotImplemented;
+    pub const NO_MAIL_SERVICE_521: Self = Self::NoMailService;
+    /// 530 Authentication required (RFC 4954, 6.)
+    pub const AUTHENTICATION_REQUIRED_530: Self = Self::Other(530);
+    /// 534 Authentication mechanism is too weak (RFC 4954, 6.)
+    pub const MECHANISM_TOO_WEAK_534: Self = Self::Other(534);
+    /// 535 Authentication credentials invalid (RFC 4954, 6.)
+    pub const INVALID_CREDENTIALS_535: Self = Self::Other(535);
+    /// 538 Encryption required for the authentication mechanism (RFC 4954, 6.)
+    pub const ENCRYPTION_REQUIRED_538: Self = Self::Other(538);
+    pub const MAILBOX_UNAVAILABLE_550: Self = Self::MailboxPermanentlyUnavailable;
+    pub const USER_NOT_LOCAL_551: Self = Self::UserNotLocal;
+    pub const EXCEEDED_STORAGE_ALLOCATION_552: Self = Self::ExceededStorageAllocation;
+    pub const MAILBOX_NAME_NOT_ALLOWED_553: Self = Self::MailboxNameNotAllowed;
+    pub const TRANSACTION_FAILED_554: Self = Self::TransactionFailed;
+    pub const PARAMETERS_NOT_IMPLEMENTED_555: Self = Self::ParametersNotImplemented;
+    /// 556 The domain does not accept mail (RFC 7504, 3.)
+    pub const DOMAIN_DOES_NOT_ACCEPT_MAIL_556: Self = Self::Other(556);
+
+    /// The code, if it is in the range of the grammar, see [`ReplyCode::is_valid`]
+    pub fn new(code: u16) -> Option<Self> {
+        let code = Self::from(code);
+        match code.is_valid() {
+            true => Some(code),
+            false => None,
+        }
+    }
+
+    /// The three digits of the code, or `None` when it does not have three digits.
+    pub fn digits(&self) -> Option<[u8; 3]> {
+        let code = u16::from(*self);
+        match (100..1000).contains(&code) {
+            true => Some([
+                (code / 100) as u8,
+                (code / 10 % 10) as u8,
+                (code % 10) as u8,
+            ]),
+            false => None,
+        }
+    }
+
+    /// Whether the code can be sent, i.e., whether it is in the range of the grammar.
+    ///
+    /// Reply-code = %x32-35 %x30-35 %x30-39
+    pub fn is_valid(&self) -> bool {
+        let code = u16::from(*self);
+        (200..600).contains(&code) && (code / 10) % 10 <= 5
+    }
+
     pub fn is_completed(&self) -> bool {
         let code = u16::from(*self);
         code > 199 && code < 300
@@ -700,6 +3431,85 @@ impl ReplyCode {
         let code = u16::from(*self);
         code > 499 && code < 600
     }
+
+    /// The first digit of the code, or `None` when it is not in the range of the grammar.
+    pub fn severity(&self) -> Option<Severity> {
+        match u16::from(*self) / 100 {
+            2 => Some(Severity::PositiveCompletion),
+            3 => Some(Severity::PositiveIntermediate),
+            4 => Some(Severity::TransientNegative),
+            5 => Some(Severity::PermanentNegative),
+            _ => None,
+        }
+    }
+
+    /// The second digit of the code, or `None` when it is not in the range of the grammar.
+    pub fn category(&self) -> Option<Category> {
+        match (u16::from(*self) / 10) % 10 {
+            0 => Some(Category::Syntax),
+            1 => Some(Category::Information),
+            2 => Some(Category::Connections),
+            3 | 4 => Some(Category::Unspecified),
+            5 => Some(Category::MailSystem),
+            _ => None,
+        }
+    }
+}
+
+impl PartialEq for ReplyCode {
+    fn eq(&self, other: &Self) -> bool {
+        u16::from(*self) == u16::from(*other)
+    }
+}
+
+impl Eq for ReplyCode {}
+
+impl core::hash::Hash for ReplyCode {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        u16::from(*self).hash(state);
+    }
+}
+
+impl PartialOrd for ReplyCode {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ReplyCode {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        u16::from(*self).cmp(&u16::from(*other))
+    }
+}
+
+/// First digit of a reply code (RFC 5321, 4.2.1.)
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Severity {
+    /// 2yz Positive Completion reply
+    PositiveCompletion,
+    /// 3yz Positive Intermediate reply
+    PositiveIntermediate,
+    /// 4yz Transient Negative Completion reply
+    TransientNegative,
+    /// 5yz Permanent Negative Completion reply
+    PermanentNegative,
+}
+
+/// Second digit of a reply code (RFC 5321, 4.2.1.)
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Category {
+    /// x0z Syntax errors, and syntactically correct commands that are not implemented
+    Syntax,
+    /// x1z Replies to requests for information, such as status or help
+    Information,
+    /// x2z Replies referring to the transmission channel
+    Connections,
+    /// x3z and x4z are not specified
+    Unspecified,
+    /// x5z Status of the receiver mail system regarding the requested action
+    MailSystem,
 }
 
 impl From<u16> for ReplyCode {
@@ -768,6 +3578,110 @@ impl From<ReplyCode> for u16 {
     }
 }
 
+/// Enhanced mail system status code (RFC 3463)
+///
+/// status-code = class "." subject "." detail
+/// class       = "2" / "4" / "5"
+/// subject     = 1*3digit
+/// detail      = 1*3digit
+///
+/// Servers that advertise ENHANCEDSTATUSCODES prefix the text of their replies with it, e.g.,
+/// "550 5.1.1 No such user" (RFC 2034).
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EnhancedStatusCode {
+    pub class: u8,
+    pub subject: u16,
+    pub detail: u16,
+}
+
+impl EnhancedStatusCode {
+    pub fn new(class: u8, subject: u16, detail: u16) -> Self {
+        Self {
+            class,
+            subject,
+            detail,
+        }
+    }
+
+    /// Parse the enhanced status code at the start of a reply line.
+    ///
+    /// Returns the code and the remaining text.
+    pub fn parse(text: &str) -> Option<(Self, &str)> {
+        let (remaining, code) = parse::response::enhanced_status_code(text.as_bytes()).ok()?;
+
+        // Safe to index: the parser only consumed ASCII bytes.
+        Some((code, &text[text.len() - remaining.len()..]))
+    }
+
+    /// Human-readable meaning of well-known codes (RFC 3463, 3., and RFC 4954, 6.)
+    pub fn description(&self) -> Option<&'static str> {
+        let description = match (self.subject, self.detail) {
+            (0, 0) => "Other undefined status",
+            (1, 0) => "Other address status",
+            (1, 1) => "Bad destination mailbox address",
+            (1, 2) => "Bad destination system address",
+            (1, 3) => "Bad destination mailbox address syntax",
+            (1, 4) => "Destination mailbox address ambiguous",
+            (1, 5) => "Destination mailbox address valid",
+            (1, 6) => "Mailbox has moved",
+            (1, 7) => "Bad sender's mailbox address syntax",
+            (1, 8) => "Bad sender's system address",
+            (2, 0) => "Other or undefined mailbox status",
+            (2, 1) => "Mailbox disabled, not accepting messages",
+            (2, 2) => "Mailbox full",
+            (2, 3) => "Message length exceeds administrative limit",
+            (2, 4) => "Mailing list expansion problem",
+            (3, 0) => "Other or undefined mail system status",
+            (3, 1) => "Mail system full",
+            (3, 2) => "System not accepting network messages",
+            (3, 3) => "System not capable of selected features",
+            (3, 4) => "Message too big for system",
+            (3, 5) => "System incorrectly configured",
+            (4, 0) => "Other or undefined network or routing status",
+            (4, 1) => "No answer from host",
+            (4, 2) => "Bad connection",
+            (4, 3) => "Directory server failure",
+            (4, 4) => "Unable to route",
+            (4, 5) => "Mail system congestion",
+            (4, 6) => "Routing loop detected",
+            (4, 7) => "Delivery time expired",
+            (5, 0) => "Other or undefined protocol status",
+            (5, 1) => "Invalid command",
+            (5, 2) => "Syntax error",
+            (5, 3) => "Too many recipients",
+            (5, 4) => "Invalid command arguments",
+            (5, 5) => "Wrong protocol version",
+            (6, 0) => "Other or undefined media error",
+            (6, 1) => "Media not supported",
+            (6, 2) => "Conversion required and prohibited",
+            (6, 3) => "Conversion required but not supported",
+            (6, 4) => "Conversion with loss performed",
+            (6, 5) => "Conversion failed",
+            (7, 0) => "Other or undefined security status",
+            (7, 1) => "Delivery not authorized, message refused",
+            (7, 2) => "Mailing list expansion prohibited",
+            (7, 3) => "Security conversion required but not possible",
+            (7, 4) => "Security features not supported",
+            (7, 5) => "Cryptographic failure",
+            (7, 6) => "Cryptographic algorithm not supported",
+            (7, 7) => "Message integrity failure",
+            (7, 8) => "Authentication credentials invalid",
+            (7, 9) => "Authentication mechanism is too weak",
+            (7, 11) => "Encryption required for requested authentication mechanism",
+            _ => return None,
+        };
+
+        Some(description)
+    }
+}
+
+impl fmt::Display for EnhancedStatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.class, self.subject, self.detail)
+    }
+}
+
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[non_exhaustive]
@@ -779,6 +3693,7 @@ pub enum AuthMechanism {
     CramMd5,
     CramSha1,
     ScramMd5,
+    ScramSha256,
     DigestMd5,
     Ntlm,
 
@@ -786,7 +3701,7 @@ pub enum AuthMechanism {
 }
 
 impl AuthMechanism {
-    pub fn serialize(&self, writer: &mut impl Write) -> std::io::Result<()> {
+    pub fn serialize(&self, writer: &mut impl Write) -> io::Result<()> {
         match self {
             AuthMechanism::Plain => writer.write_all(b"PLAIN"),
             AuthMechanism::Login => writer.write_all(b"LOGIN"),
@@ -795,15 +3710,77 @@ impl AuthMechanism {
             AuthMechanism::CramMd5 => writer.write_all(b"CRAM-MD5"),
             AuthMechanism::CramSha1 => writer.write_all(b"CRAM-SHA1"),
             AuthMechanism::ScramMd5 => writer.write_all(b"SCRAM-MD5"),
+            AuthMechanism::ScramSha256 => writer.write_all(b"SCRAM-SHA-256"),
             AuthMechanism::DigestMd5 => writer.write_all(b"DIGEST-MD5"),
             AuthMechanism::Ntlm => writer.write_all(b"NTLM"),
 
             AuthMechanism::Other(other) => writer.write_all(other.as_bytes()),
         }
     }
+
+    /// Whether the mechanism sends the password in plain text, i.e., PLAIN and LOGIN, which
+    /// should only be used with TLS
+    pub fn is_plaintext(&self) -> bool {
+        matches!(self, AuthMechanism::Plain | AuthMechanism::Login)
+    }
+}
+
+impl fmt::Display for AuthMechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_serialized(f, |writer| self.serialize(writer))
+    }
+}
+
+/// A credential, e.g., a password or the initial response of AUTH
+///
+/// `Debug` and `Display` print "\[REDACTED\]", so that credentials don't end up in logs. Use
+/// [`Secret::expose`] to access the value. With the `serde` feature, the value is serialized as
+/// is; see [`transcript`] for recording sessions without credentials.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Secret(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Secret(value)
+    }
+}
+
+impl From<&str> for Secret<String> {
+    fn from(value: &str) -> Self {
+        Secret(value.to_owned())
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+impl<T> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
 }
 
 /// A string containing of tab, space and printable ASCII characters
+///
+/// Parsed text may contain UTF-8 as well, see [`Response::parse_with`].
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct TextString<'a>(pub(crate) Cow<'a, str>);
@@ -844,11 +3821,35 @@ impl fmt::Display for InvalidTextString {
     }
 }
 
-impl std::error::Error for InvalidTextString {}
+impl core::error::Error for InvalidTextString {}
 
 #[cfg(test)]
 mod tests {
-    use super::{Capability, ReplyCode, Response, TextString};
+    use super::{
+        AddressLiteral, AtomOrQuoted, Capability, Category, Command, CommandRef, DomainPolicy,
+        EhloResponse, EncodeError, EsmtpParam, ForwardPath, Greeting, HeloIdentity, HelpResponse,
+        Limits, LocalPart, ParametersRef, ParseMode, Path, ReplyCode, Response, ResponseKind,
+        ResponseRef, ReversePath, Severity, SmtpParseError, SyntaxOptions, TextString,
+        VerifiedMailbox, VerifyResult, Violation,
+    };
+
+    #[test]
+    fn test_parse_complete() {
+        let (rem, response) =
+            Response::parse_complete(ResponseKind::Other, b"250-A\r\n250 B").unwrap();
+        assert_eq!(rem, b"");
+        assert_eq!(
+            response,
+            Response::Other {
+                code: ReplyCode::Ok,
+                lines: vec![TextString::new("A").unwrap(), TextString::new("B").unwrap()],
+            }
+        );
+        assert!(Response::parse_complete(ResponseKind::Other, b"250-A\r\n").is_err());
+
+        let (_, ehlo) = EhloResponse::parse_complete(b"250-example.org\r\n250 SIZE").unwrap();
+        assert_eq!(ehlo.capabilities.size, Some(0));
+    }
 
     #[test]
     fn test_serialize_greeting() {
@@ -966,4 +3967,926 @@ mod tests {
             assert_eq!(expected, &got);
         }
     }
+
+    #[test]
+    fn test_serialize_long_lines() {
+        let word = "a".repeat(300);
+        let response = Response::Other {
+            code: ReplyCode::MailboxPermanentlyUnavailable,
+            lines: vec![TextString::new(&format!("{} {}", word, word))
+                .unwrap()
+                .into_owned()],
+        };
+        assert_eq!(
+            response.to_vec().unwrap(),
+            format!("550-{}\r\n550 {}\r\n", word, word).into_bytes()
+        );
+
+        let long = "a".repeat(600);
+        let response = Response::greeting("example.org", long.as_str());
+        let serialized = response.to_vec().unwrap();
+        assert_eq!(
+            serialized,
+            format!(
+                "220-example.org\r\n220-{}\r\n220 {}\r\n",
+                &long[..506],
+                &long[506..]
+            )
+            .into_bytes()
+        );
+        assert!(serialized
+            .split(|&b| b == b'\n')
+            .all(|line| line.len() <= 512));
+    }
+
+    #[test]
+    fn test_serialize_invalid_code() {
+        for code in [199, 260, 299, 600, 1000] {
+            let response = Response::Other {
+                code: ReplyCode::Other(code),
+                lines: vec![],
+            };
+            assert!(response.to_vec().is_err(), "{}", code);
+        }
+
+        let response = Response::Other {
+            code: ReplyCode::Other(259),
+            lines: vec![],
+        };
+        assert_eq!(response.to_vec().unwrap(), b"259\r\n");
+    }
+
+    #[test]
+    fn test_encode_injection_attempt() {
+        let commands = [
+            Command::Noop {
+                argument: Some(AtomOrQuoted::Quoted("a\r\nRSET".into())),
+            },
+            Command::Vrfy {
+                user_or_mailbox: AtomOrQuoted::Atom("a\0".into()),
+            },
+            Command::Rcpt {
+                forward_path: "b@example.org>\nDATA".into(),
+                parameters: vec![],
+            },
+            Command::Helo {
+                domain_or_address: HeloIdentity::Domain("example.org\r\nQUIT".into()),
+            },
+        ];
+
+        for command in commands {
+            assert_eq!(
+                command.encode(SyntaxOptions::default()),
+                Err(EncodeError::InjectionAttempt)
+            );
+            assert!(command.serialize(&mut Vec::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_encode_line_limits() {
+        let command = Command::Vrfy {
+            user_or_mailbox: AtomOrQuoted::Atom("a".repeat(505)),
+        };
+        assert_eq!(command.encode(SyntaxOptions::default()).unwrap().len(), 512);
+
+        let command = Command::Vrfy {
+            user_or_mailbox: AtomOrQuoted::Atom("a".repeat(506)),
+        };
+        assert_eq!(
+            command.encode(SyntaxOptions::default()),
+            Err(EncodeError::LineTooLong {
+                length: 513,
+                limit: 512
+            })
+        );
+        assert!(command.serialize(&mut Vec::new()).is_err());
+
+        let options = SyntaxOptions {
+            limits: Limits {
+                command: 1024,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(command.encode(options).is_ok());
+
+        // The SIZE allowance applies to MAIL.
+        let command = Command::Mail {
+            reverse_path: Path::new(format!("{}@example.org", "a".repeat(490))).into(),
+            parameters: vec![EsmtpParam::Size(1000)],
+        };
+        assert!(command.encode(SyntaxOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_command_ref() {
+        let inputs: &[&[u8]] = &[
+            b"EHLO [IPv6:2001:db8::1]\r\n",
+            b"LHLO example.org\r\n",
+            b"MAIL FROM:<a@example.org> SIZE=1000 ENVID=QQ+20314159 X-FOO\r\n",
+            b"RCPT TO:<b@example.org>\r\n",
+            b"VRFY \"a \\\"b\\\"\"\r\n",
+            b"NOOP\r\n",
+            b"AUTH PLAIN =\r\n",
+            b"AUTH PLAIN AGEAYg==\r\n",
+            b"BDAT 10 LAST\r\n",
+        ];
+
+        for input in inputs {
+            let (rem, borrowed) = CommandRef::from_bytes(input).unwrap();
+            assert!(rem.is_empty());
+            assert_eq!(borrowed.to_owned(), Command::from_bytes(input).unwrap().1);
+        }
+
+        let (_, borrowed) = CommandRef::from_bytes(b"MAIL FROM:<> BODY=8BITMIME\r\n").unwrap();
+        assert_eq!(
+            borrowed,
+            CommandRef::Mail {
+                reverse_path: "",
+                parameters: ParametersRef("BODY=8BITMIME"),
+            }
+        );
+        assert!(CommandRef::from_bytes(b"AUTH PLAIN A\r\n").is_err());
+    }
+
+    #[test]
+    fn test_response_ref() {
+        let input = b"250-example.org\r\n250-\r\n250 SIZE 1000\r\nrest";
+        let (rem, borrowed) = ResponseRef::parse(input).unwrap();
+        assert_eq!(rem, b"rest");
+        assert_eq!(borrowed.code(), ReplyCode::Ok);
+        assert_eq!(
+            borrowed.lines().collect::<Vec<_>>(),
+            ["example.org", "SIZE 1000"]
+        );
+        assert_eq!(borrowed.to_owned(), Response::parse_other(input).unwrap().1);
+    }
+
+    #[test]
+    fn test_classification() {
+        let response = Response::parse_other(b"452 4.5.3 Too many recipients\r\n")
+            .unwrap()
+            .1;
+        assert!(response.is_transient_failure());
+        assert!(!response.is_positive_completion());
+        assert_eq!(response.severity(), Some(Severity::TransientNegative));
+        assert_eq!(response.category(), Some(Category::MailSystem));
+
+        assert!(Response::greeting("example.org", "").is_positive_completion());
+        assert_eq!(
+            ReplyCode::StartMailInput.severity(),
+            Some(Severity::PositiveIntermediate)
+        );
+        assert_eq!(ReplyCode::SyntaxError.category(), Some(Category::Syntax));
+        assert_eq!(ReplyCode::Other(199).severity(), None);
+        assert_eq!(ReplyCode::Other(261).category(), None);
+    }
+
+    #[test]
+    fn test_reply_code() {
+        assert_eq!(ReplyCode::Other(250), ReplyCode::OK_250);
+        assert_eq!(ReplyCode::from(530), ReplyCode::AUTHENTICATION_REQUIRED_530);
+        assert!(ReplyCode::SERVICE_READY_220 < ReplyCode::Other(235));
+        assert!(ReplyCode::Other(235) < ReplyCode::OK_250);
+
+        assert_eq!(ReplyCode::new(554), Some(ReplyCode::TransactionFailed));
+        assert_eq!(ReplyCode::new(199), None);
+        assert_eq!(ReplyCode::new(261), None);
+        assert_eq!(ReplyCode::new(600), None);
+
+        assert_eq!(ReplyCode::INVALID_CREDENTIALS_535.digits(), Some([5, 3, 5]));
+        assert_eq!(ReplyCode::Other(1000).digits(), None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let tests: &[(&[u8], SmtpParseError)] = &[
+            (
+                b"MAIL FROM:<a@",
+                SmtpParseError::UnexpectedEof { needed: Some(1) },
+            ),
+            (b"FOO:bar\r\n", SmtpParseError::UnknownCommand),
+            (b"EHLO -example.org\r\n", SmtpParseError::InvalidDomain),
+            (b"MAIL FROM:<a@@b>\r\n", SmtpParseError::InvalidMailbox),
+            (b"RCPT TO:<b>\r\n", SmtpParseError::InvalidMailbox),
+            (b"MAIL FROM:<a@b> SIZE\x01\r\n", SmtpParseError::Syntax),
+            (b"RCPT TO:<Postmaster> =\r\n", SmtpParseError::Syntax),
+            (b"VRFY j\xc3\xb6rg\r\n", SmtpParseError::NonAscii),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Command::from_bytes(input).unwrap_err(), *expected);
+        }
+
+        let long = [b"NOOP ".as_ref(), &[b'a'; 13000]].concat();
+        assert_eq!(
+            Command::from_bytes(&long).unwrap_err(),
+            SmtpParseError::LineTooLong { limit: 12288 }
+        );
+        assert_eq!(
+            Command::from_bytes_complete(b"MAIL FROM:<>").unwrap().1,
+            Command::from_bytes(b"MAIL FROM:<>\r\n").unwrap().1
+        );
+        // UTF-8 requires SMTPUTF8, as with the streaming parser.
+        assert_eq!(
+            Command::from_bytes_complete("MAIL FROM:<jörg@bücher.example>".as_bytes()).unwrap_err(),
+            SmtpParseError::NonAscii
+        );
+        assert!(Response::parse_complete(ResponseKind::Other, b"250-A\r\n")
+            .unwrap_err()
+            .is_incomplete());
+        // The same checks as the streaming parser
+        for input in [&b"250-A\r\n251 B"[..], "250 Caf\u{e9}".as_bytes()] {
+            let mut line = input.to_vec();
+            line.extend_from_slice(b"\r\n");
+            assert_eq!(
+                Response::parse_complete(ResponseKind::Other, input).unwrap_err(),
+                Response::parse(ResponseKind::Other, &line).unwrap_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let tests: &[(&[u8], &str)] = &[
+            (
+                b"EHLO -example.org\r\n",
+                "invalid domain or address literal at offset 5: label \"-example\" starts or ends \
+                 with a hyphen",
+            ),
+            (
+                b"EHLO a..b\r\n",
+                "invalid domain or address literal at offset 5: domain has an empty label",
+            ),
+            (
+                b"MAIL FROM:<a@@b>\r\n",
+                "invalid mailbox at offset 13: unexpected '@'",
+            ),
+            (
+                b"RCPT TO:<b>\r\n",
+                "invalid mailbox at offset 10: unexpected '>'",
+            ),
+            (
+                b"MAIL FROM:<a@b> SIZE\x01\r\n",
+                "syntax error at offset 20: unexpected '\\u{1}'",
+            ),
+            (b"QUIT now\r\n", "syntax error at offset 4: unexpected ' '"),
+            (
+                b"VRFY j\xc3\xb6rg\r\n",
+                "non-ASCII text requires SMTPUTF8 at offset 0",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let diagnostic = Command::diagnose(input, SyntaxOptions::default()).unwrap();
+            assert_eq!(diagnostic.to_string(), *expected);
+        }
+        assert_eq!(
+            Command::diagnose(b"NOOP\r\n", SyntaxOptions::default()),
+            None
+        );
+        assert_eq!(Command::diagnose(b"NOOP", SyntaxOptions::default()), None);
+    }
+
+    #[test]
+    fn test_greeting() {
+        let (_, greeting) = Greeting::parse(b"220 example.org ESMTP\r\n").unwrap();
+        assert_eq!(
+            greeting,
+            Greeting::Ready {
+                domain: "example.org".into(),
+                text: "ESMTP".into(),
+            }
+        );
+        assert!(greeting.is_ready());
+
+        let (rem, greeting) =
+            Greeting::parse(b"554-example.org No SMTP service here\r\n554 Bye\r\n").unwrap();
+        assert!(rem.is_empty());
+        assert_eq!(greeting.domain(), Some("example.org"));
+        assert!(!greeting.is_ready());
+
+        let (_, greeting) = Greeting::parse_complete(b"554 No; service").unwrap();
+        assert_eq!(
+            greeting,
+            Greeting::Rejected {
+                domain: None,
+                lines: vec![TextString::new("No; service").unwrap()],
+            }
+        );
+
+        assert!(Greeting::parse(b"421 Busy\r\n").is_err());
+        assert!(Greeting::parse(b"220 example.org")
+            .unwrap_err()
+            .is_incomplete());
+    }
+
+    #[test]
+    fn test_help_response() {
+        let (_, response) = Response::parse_other(
+            b"214-2.0.0 MAIL FROM: <sender> [ <parameters> ]\r\n\
+214-2.0.0     Specifies the sender.\r\n\
+214 2.0.0 End of HELP info\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            HelpResponse::from_response(&response),
+            Some(HelpResponse {
+                topic: Some("MAIL".into()),
+                lines: vec![
+                    "MAIL FROM: <sender> [ <parameters> ]".into(),
+                    "    Specifies the sender.".into(),
+                    "End of HELP info".into(),
+                ],
+            })
+        );
+
+        let (_, response) = Response::parse_other(b"214 See https://example.org/\r\n").unwrap();
+        let help = HelpResponse::from_response(&response).unwrap();
+        assert_eq!(help.topic, None);
+        assert_eq!(help.lines, vec!["See https://example.org/"]);
+
+        let (_, response) = Response::parse_other(b"504 HELP topic unknown\r\n").unwrap();
+        assert_eq!(HelpResponse::from_response(&response), None);
+    }
+
+    #[test]
+    fn test_return_path_header() {
+        let tests = [
+            (ReversePath::Null, "Return-Path: <>\r\n"),
+            (
+                ReversePath::from("@relay.example:fred@example.org"),
+                "Return-Path: <fred@example.org>\r\n",
+            ),
+            (
+                ReversePath::from("\"first last\"@example.org"),
+                "Return-Path: <\"first last\"@example.org>\r\n",
+            ),
+            (
+                Path::new("first last@example.org").into(),
+                "Return-Path: <\"first last\"@example.org>\r\n",
+            ),
+            (
+                Path::new("a..b\"c@example.org").into(),
+                "Return-Path: <\"a..b\\\"c\"@example.org>\r\n",
+            ),
+        ];
+
+        for (reverse_path, expected) in tests {
+            assert_eq!(reverse_path.to_return_path_header(), expected);
+        }
+    }
+
+    #[test]
+    fn test_verify_result() {
+        let verify = |input: &[u8]| {
+            let (_, response) = Response::parse_other(input).unwrap();
+            VerifyResult::from_response(&response)
+        };
+        let mailbox = |name: Option<&str>, mailbox: &str| VerifiedMailbox {
+            name: name.map(Into::into),
+            mailbox: mailbox.into(),
+        };
+
+        assert_eq!(
+            verify(b"250 Fred Smith <Smith@example.org>\r\n"),
+            Some(VerifyResult::Found(vec![mailbox(
+                Some("Fred Smith"),
+                "Smith@example.org"
+            )]))
+        );
+        assert_eq!(
+            verify(
+                b"250-2.1.5 Jon Postel <Postel@example.org>\r\n250 2.1.5 <Quux@example.org>\r\n"
+            ),
+            Some(VerifyResult::Found(vec![
+                mailbox(Some("Jon Postel"), "Postel@example.org"),
+                mailbox(None, "Quux@example.org"),
+            ]))
+        );
+        assert_eq!(
+            verify(b"251 User not local; will forward to <Jones@example.org>\r\n"),
+            Some(VerifyResult::Found(vec![mailbox(
+                None,
+                "Jones@example.org"
+            )]))
+        );
+        assert_eq!(
+            verify(b"553-Ambiguous; Possibilities are\r\n553-Joe Smith <jsmith@example.org>\r\n553 harry@example.org\r\n"),
+            Some(VerifyResult::Ambiguous(vec![
+                mailbox(Some("Joe Smith"), "jsmith@example.org"),
+                mailbox(None, "harry@example.org"),
+            ]))
+        );
+        assert_eq!(
+            verify(b"252 Cannot VRFY user\r\n"),
+            Some(VerifyResult::Unverified)
+        );
+        assert_eq!(
+            verify(b"550 No such user\r\n"),
+            Some(VerifyResult::NotFound)
+        );
+        assert_eq!(verify(b"502 Command not implemented\r\n"), None);
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let local_part = "a".repeat(65);
+        let domain = format!("{}.example", "a".repeat(250));
+        let tests = [
+            (
+                format!("MAIL FROM:<{}@example.org>\r\n", local_part),
+                SmtpParseError::LocalPartTooLong { limit: 64 },
+            ),
+            (
+                format!("RCPT TO:<@relay.example:{}@example.org>\r\n", local_part),
+                SmtpParseError::LocalPartTooLong { limit: 64 },
+            ),
+            (
+                format!("EHLO {}\r\n", domain),
+                SmtpParseError::DomainTooLong { limit: 255 },
+            ),
+            (
+                format!("NOOP {}\r\n", "a".repeat(600)),
+                SmtpParseError::LineTooLong { limit: 512 },
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Command::from_bytes(input.as_bytes()).unwrap_err(), expected);
+            assert_eq!(
+                CommandRef::from_bytes(input.as_bytes()).unwrap_err(),
+                expected
+            );
+        }
+
+        let options = SyntaxOptions {
+            limits: Limits {
+                local_part: 100,
+                ..Limits::default()
+            },
+            ..SyntaxOptions::default()
+        };
+        let input = format!("MAIL FROM:<{}@example.org>\r\n", local_part);
+        assert!(Command::from_bytes_with(input.as_bytes(), options).is_ok());
+    }
+
+    #[test]
+    fn test_parse_strict() {
+        let tests: [(&[u8], &[u8]); 3] = [
+            (
+                b"mail from:<a@example.org>\r\n",
+                b"MAIL FROM:<a@example.org>\r\n",
+            ),
+            (
+                b"RCPT TO:<b@example.org> notify=never\r\n",
+                b"RCPT TO:<b@example.org> NOTIFY=NEVER\r\n",
+            ),
+            (b"QUIT\r\n", b"QUIT\r\n"),
+        ];
+
+        for (input, canonical) in tests {
+            let mut pipelined = input.to_vec();
+            pipelined.extend_from_slice(b"NOOP\r\n");
+            let (remaining, parsed) =
+                Command::parse_strict(&pipelined, SyntaxOptions::default()).unwrap();
+            assert_eq!(remaining, b"NOOP\r\n");
+            assert_eq!(parsed.is_canonical(), input == canonical);
+
+            let mut original = Vec::new();
+            parsed.serialize_original(&mut original).unwrap();
+            assert_eq!(original, input);
+            let mut normalized = Vec::new();
+            parsed.serialize_canonical(&mut normalized).unwrap();
+            assert_eq!(normalized, canonical);
+        }
+
+        let (_, parsed) =
+            Command::parse_strict(b"MaIl FrOm:<a@example.org>\r\n", SyntaxOptions::default())
+                .unwrap();
+        assert_eq!(parsed.verb(), "MaIl");
+        assert_eq!(parsed.command.name(), "MAIL");
+    }
+
+    #[test]
+    fn test_helo_identity() {
+        let tests = [
+            ("example.org", HeloIdentity::Domain("example.org".into())),
+            (
+                "[192.0.2.1]",
+                HeloIdentity::AddressLiteral(AddressLiteral::Ip("192.0.2.1".parse().unwrap())),
+            ),
+            (
+                "[IPv6:2001:db8::1]",
+                HeloIdentity::AddressLiteral(AddressLiteral::Ip("2001:db8::1".parse().unwrap())),
+            ),
+        ];
+
+        for (test, expected) in tests {
+            let identity = test.parse::<HeloIdentity>().unwrap();
+            assert_eq!(identity, expected);
+            assert_eq!(identity.to_string(), test);
+            assert_eq!(identity.is_address_literal(), identity.domain().is_none());
+        }
+
+        for test in ["", "example.org foo", "[192.0.2.1", "exa_mple.org"] {
+            assert_eq!(
+                test.parse::<HeloIdentity>(),
+                Err(SmtpParseError::InvalidDomain)
+            );
+        }
+    }
+
+    #[test]
+    fn test_lenient() {
+        let options = SyntaxOptions {
+            mode: ParseMode::Lenient,
+            ..SyntaxOptions::default()
+        };
+        let input = b"mail from: a@example.org \nrcpt to:<b@example.org>\r\n";
+
+        assert!(Command::from_bytes(input).is_err());
+        let (remaining, (command, violations)) =
+            Command::from_bytes_lenient(input, options).unwrap();
+        assert_eq!(remaining, b"rcpt to:<b@example.org>\r\n");
+        assert_eq!(
+            command,
+            Command::Mail {
+                reverse_path: "a@example.org".into(),
+                parameters: vec![],
+            }
+        );
+        assert_eq!(
+            violations,
+            [
+                Violation::BareLf,
+                Violation::TrailingWhitespace,
+                Violation::SpaceAfterColon,
+                Violation::UnbracketedPath
+            ]
+        );
+
+        let (_, (_, violations)) = Command::from_bytes_lenient(remaining, options).unwrap();
+        assert!(violations.is_empty());
+        assert_eq!(
+            Command::from_bytes_lenient(b"MAIL FROM:a@", options),
+            Err(SmtpParseError::UnexpectedEof { needed: None })
+        );
+        assert_eq!(
+            Command::from_bytes_with(b"HELP \xe4\n", options),
+            Ok((
+                &b""[..],
+                Command::Help {
+                    argument: Some(AtomOrQuoted::Atom("\u{fffd}".into()))
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_strict_domains() {
+        let strict = SyntaxOptions {
+            smtputf8: true,
+            strict_domains: true,
+            ..Default::default()
+        };
+        let long = "a".repeat(64);
+
+        let tests = [
+            ("EHLO example.org\r\n".to_owned(), Ok(())),
+            ("EHLO [192.0.2.1]\r\n".to_owned(), Ok(())),
+            // A numeric TLD is not rejected.
+            ("EHLO example.123\r\n".to_owned(), Ok(())),
+            (
+                format!("EHLO {}.example\r\n", long),
+                Err(SmtpParseError::InvalidDomain),
+            ),
+            (
+                "MAIL FROM:<a@-bücher-.example>\r\n".to_owned(),
+                Err(SmtpParseError::InvalidMailbox),
+            ),
+            (
+                format!("RCPT TO:<@{}.example:b@example.org>\r\n", long),
+                Err(SmtpParseError::InvalidMailbox),
+            ),
+        ];
+
+        for (line, expected) in tests {
+            let input = line.as_bytes();
+            assert!(Command::from_bytes_with(
+                input,
+                SyntaxOptions {
+                    strict_domains: false,
+                    ..strict
+                }
+            )
+            .is_ok());
+            assert_eq!(
+                Command::from_bytes_with(input, strict).map(|_| ()),
+                expected,
+                "{}",
+                line
+            );
+            assert_eq!(
+                CommandRef::from_bytes_with(input, strict).map(|_| ()),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_domain_policy() {
+        let submission = SyntaxOptions {
+            domain_policy: DomainPolicy {
+                require_fqdn: true,
+                forbid_address_literals: true,
+                forbid_single_label: true,
+            },
+            ..Default::default()
+        };
+        let tests: [(&[u8], _); 8] = [
+            (b"MAIL FROM:<a@example.org>\r\n", Ok(())),
+            (b"MAIL FROM:<>\r\n", Ok(())),
+            (b"RCPT TO:<Postmaster>\r\n", Ok(())),
+            (
+                b"MAIL FROM:<a@localhost>\r\n",
+                Err(SmtpParseError::ForbiddenDomain),
+            ),
+            (
+                b"MAIL FROM:<a@host.localdomain>\r\n",
+                Err(SmtpParseError::ForbiddenDomain),
+            ),
+            (
+                b"RCPT TO:<b@[192.0.2.1]>\r\n",
+                Err(SmtpParseError::ForbiddenDomain),
+            ),
+            (
+                b"RCPT TO:<Postmaster@mail>\r\n",
+                Err(SmtpParseError::ForbiddenDomain),
+            ),
+            // EHLO is not restricted.
+            (b"EHLO [192.0.2.1]\r\n", Ok(())),
+        ];
+
+        for (input, expected) in tests {
+            assert!(Command::from_bytes(input).is_ok());
+            assert_eq!(
+                Command::from_bytes_with(input, submission).map(|_| ()),
+                expected
+            );
+            assert_eq!(
+                CommandRef::from_bytes_with(input, submission).map(|_| ()),
+                expected
+            );
+        }
+
+        let single_label = DomainPolicy {
+            forbid_single_label: true,
+            ..Default::default()
+        };
+        assert!(!single_label.allows("localhost"));
+        assert!(single_label.allows("example.123"));
+        assert!(single_label.allows("[IPv6:::1]"));
+        assert!(!submission.domain_policy.allows("example.123"));
+    }
+
+    #[test]
+    fn test_response_parse_with() {
+        let input = "550 Empfänger unbekannt\r\n".as_bytes();
+        assert_eq!(
+            Response::parse(ResponseKind::Other, input),
+            Err(SmtpParseError::NonAscii)
+        );
+
+        let smtputf8 = SyntaxOptions {
+            smtputf8: true,
+            ..SyntaxOptions::default()
+        };
+        let (_, response) = Response::parse_with(ResponseKind::Other, input, smtputf8).unwrap();
+        assert_eq!(response.to_string(), "550 Empfänger unbekannt");
+
+        let lenient = SyntaxOptions {
+            mode: ParseMode::Lenient,
+            ..SyntaxOptions::default()
+        };
+        let input = b"220-mx.example.org ESMTP\n220 Gr\xfc\xdfe\r\nEHLO";
+        assert!(Response::parse(ResponseKind::Greeting, input).is_err());
+        let (remaining, response) =
+            Response::parse_with(ResponseKind::Greeting, input, lenient).unwrap();
+        assert_eq!(remaining, b"EHLO");
+        assert_eq!(
+            response,
+            Response::Greeting {
+                domain: "mx.example.org".into(),
+                text: "ESMTP\nGr\u{fffd}\u{fffd}e".into(),
+            }
+        );
+        assert_eq!(
+            Response::parse_with(ResponseKind::Other, b"250-a\n250 b", lenient),
+            Err(SmtpParseError::UnexpectedEof { needed: None })
+        );
+    }
+
+    #[test]
+    fn test_mismatched_reply_codes() {
+        let input = b"250-Accepted\r\n550 Rejected\r\n";
+        assert_eq!(
+            Response::parse(ResponseKind::Other, input),
+            Err(SmtpParseError::MismatchedReplyCodes)
+        );
+
+        let lenient = SyntaxOptions {
+            mode: ParseMode::Lenient,
+            ..SyntaxOptions::default()
+        };
+        let (_, (response, violations)) =
+            Response::parse_lenient(ResponseKind::Other, input, lenient).unwrap();
+        assert_eq!(response.code(), ReplyCode::MailboxPermanentlyUnavailable);
+        assert_eq!(violations, [Violation::MismatchedReplyCodes]);
+        assert_eq!(
+            Response::parse_with(ResponseKind::Other, input, lenient)
+                .unwrap()
+                .1,
+            response
+        );
+
+        let (_, (_, violations)) =
+            Response::parse_lenient(ResponseKind::Other, b"250-a\n250-b\n250 c\r\n", lenient)
+                .unwrap();
+        assert_eq!(violations, [Violation::BareLf]);
+    }
+
+    #[test]
+    fn test_secret() {
+        let (_, command) = Command::from_bytes(b"AUTH PLAIN AHVzZXIAcGFzcw==\r\n").unwrap();
+        let debug = format!("{:?}", command);
+        assert!(debug.contains("[REDACTED]"));
+        assert!(!debug.contains("AHVzZXIAcGFzcw"));
+
+        let Command::Auth {
+            initial_response: Some(initial_response),
+            ..
+        } = command
+        else {
+            panic!("expected AUTH with an initial response");
+        };
+        assert_eq!(initial_response.expose(), b"\0user\0pass");
+    }
+
+    #[test]
+    fn test_local_part() {
+        let path = Path::from("first.last@example.com");
+        let local_part = path.local_part().unwrap();
+        assert_eq!(local_part, LocalPart::DotString("first.last".into()));
+        assert_eq!(
+            local_part.atoms().unwrap().collect::<Vec<_>>(),
+            ["first", "last"]
+        );
+
+        let tests = [
+            ("\"first\\ last\"", LocalPart::Quoted("first last".into())),
+            ("\"a\\\"b\"", LocalPart::Quoted("a\"b".into())),
+            ("\"john\"", LocalPart::Quoted("john".into())),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(input.parse::<LocalPart>().unwrap(), expected);
+        }
+        assert!("first..last".parse::<LocalPart>().is_err());
+        assert!("a@b".parse::<LocalPart>().is_err());
+
+        assert_eq!(LocalPart::new("first.last").to_string(), "first.last");
+        assert_eq!(LocalPart::new("first last").to_string(), "\"first last\"");
+        assert_eq!(LocalPart::new(".a").to_string(), "\".a\"");
+        assert_eq!(LocalPart::new("a\"b").to_string(), "\"a\\\"b\"");
+
+        let quoted = "\"john\"".parse::<LocalPart>().unwrap();
+        assert!(quoted.is_quoted());
+        assert!(!quoted.needs_quoting());
+        assert_eq!(quoted.to_string(), "john");
+        assert!(LocalPart::new("first last").needs_quoting());
+        assert_eq!(
+            LocalPart::from(AtomOrQuoted::Quoted("a b".into())).to_string(),
+            "\"a b\""
+        );
+        assert_eq!(
+            LocalPart::from(AtomOrQuoted::Atom("john".into())),
+            LocalPart::DotString("john".into())
+        );
+
+        assert!(LocalPart::try_new(&"a".repeat(64)).is_ok());
+        assert_eq!(
+            LocalPart::try_new(&"a".repeat(65)),
+            Err(SmtpParseError::LocalPartTooLong { limit: 64 })
+        );
+        // The quotes count.
+        assert!(LocalPart::try_new(&" ".repeat(63)).is_err());
+    }
+
+    #[test]
+    fn test_normalized() {
+        let tests = [
+            ("\"john\"@Example.ORG", "john@example.org"),
+            ("\"John.Smith\"@example.org", "John.Smith@example.org"),
+            ("\"first\\ last\"@example.org", "\"first last\"@example.org"),
+            ("\"a..b\"@example.org", "\"a..b\"@example.org"),
+            (
+                "@relay.example:a@B\u{dc}CHER.example",
+                "a@b\u{fc}cher.example",
+            ),
+            ("a@[IPv6:2001:db8:0:0:0:0:0:1]", "a@[IPv6:2001:db8::1]"),
+            ("a@[192.0.2.1]", "a@[192.0.2.1]"),
+        ];
+        for (path, expected) in tests {
+            assert_eq!(
+                Path::from(path).normalized(),
+                Path::new(expected),
+                "{}",
+                path
+            );
+        }
+
+        assert!(ForwardPath::from("\"b\"@EXAMPLE.org").matches(&"b@example.org".into()));
+        assert!(!ForwardPath::from("B@example.org").matches(&"b@example.org".into()));
+        assert!(ForwardPath::from("postmaster@Example.org")
+            .matches(&ForwardPath::from("Postmaster@example.ORG")));
+    }
+
+    #[test]
+    fn test_display() {
+        let tests: [&[u8]; 4] = [
+            b"EHLO [IPv6:2001:db8::1]\r\n",
+            b"MAIL FROM:<a@example.org> SIZE=1000 BODY=8BITMIME\r\n",
+            b"RCPT TO:<Postmaster> NOTIFY=SUCCESS,DELAY\r\n",
+            b"VRFY \"first last\"\r\n",
+        ];
+        for input in tests {
+            let (_, command) = Command::from_bytes(input).unwrap();
+            let line = core::str::from_utf8(input).unwrap();
+            assert_eq!(command.to_string(), line.trim_end());
+
+            let mut wire = String::new();
+            command.write_wire(&mut wire).unwrap();
+            assert_eq!(wire, line);
+        }
+
+        let response = Response::parse_complete(
+            ResponseKind::Ehlo,
+            b"250-example.org\r\n250-SIZE 1000\r\n250 AUTH PLAIN\r\n",
+        )
+        .unwrap()
+        .1;
+        assert_eq!(
+            response.to_string(),
+            "250-example.org\r\n250-SIZE 1000\r\n250 AUTH PLAIN"
+        );
+        assert_eq!(Capability::Size(1000).to_string(), "SIZE 1000");
+
+        // Unlike serialize, Display does not check the reply code.
+        let response = Response::Other {
+            code: ReplyCode::Other(600),
+            lines: vec![TextString::new("No").unwrap()],
+        };
+        assert_eq!(response.to_string(), "600 No");
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_redacted() {
+        let tests: [(&[u8], &str); 3] = [
+            (b"AUTH PLAIN AHVzZXIAcGFzcw==\r\n", "AUTH PLAIN [REDACTED]"),
+            (b"AUTH LOGIN\r\n", "AUTH LOGIN"),
+            (
+                b"MAIL FROM:<a@example.org>\r\n",
+                "MAIL FROM:<a@example.org>",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let (_, command) = Command::from_bytes(input).unwrap();
+            assert_eq!(command.redacted(), expected);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let commands: [&[u8]; 3] = [
+            b"EHLO [IPv6:2001:db8::1]\r\n",
+            b"MAIL FROM:<a@example.org> SIZE=1000 BODY=8BITMIME BY=120;RT MT-PRIORITY=-3\r\n",
+            b"RCPT TO:<b@example.org> NOTIFY=SUCCESS,DELAY ORCPT=rfc822;b@example.org\r\n",
+        ];
+        for input in commands {
+            let command = Command::from_bytes(input).unwrap().1;
+            let json = serde_json::to_string(&command).unwrap();
+            assert_eq!(serde_json::from_str::<Command>(&json).unwrap(), command);
+        }
+
+        let (_, ehlo) = EhloResponse::parse_complete(b"250-example.org\r\n250 SIZE 1000").unwrap();
+        let json = serde_json::to_string(&ehlo).unwrap();
+        assert_eq!(serde_json::from_str::<EhloResponse>(&json).unwrap(), ehlo);
+
+        assert!(serde_json::from_str::<EsmtpParam>(r#"{"MtPriority":10}"#).is_err());
+        assert!(serde_json::from_str::<EsmtpParam>(r#"{"Notify":8}"#).is_err());
+    }
 }
@@ -0,0 +1,203 @@
+//! The header block of a message (RFC 5322, 2.2.)
+//!
+//! [`split_headers`] splits the header fields off the content, e.g., to log the Message-ID, or
+//! to check that the "From:" field is present. It does not decode MIME, encoded words, or
+//! addresses:
+//!
+//! ```
+//! use instant_smtp::message::split_headers;
+//!
+//! let content = b"Subject: A long\r\n subject\r\nMessage-ID: <1@example.org>\r\n\r\nHello\r\n";
+//! let (headers, body) = split_headers(content);
+//!
+//! assert_eq!(headers.get("subject").unwrap().unfolded(), "A long subject");
+//! assert_eq!(headers.get("Message-Id").unwrap().unfolded(), "<1@example.org>");
+//! assert_eq!(body, b"Hello\r\n");
+//! ```
+
+use alloc::{borrow::Cow, string::String, vec::Vec};
+
+/// A header field as received
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Field<'a> {
+    pub name: &'a str,
+    /// The value following the colon, including the line breaks of folding, but not the final
+    /// line break
+    pub value: &'a [u8],
+}
+
+impl<'a> Field<'a> {
+    /// The value without the line breaks of folding and without leading and trailing
+    /// whitespace (RFC 5322, 2.2.3.)
+    ///
+    /// Octets that are not UTF-8 are replaced with U+FFFD.
+    pub fn unfolded(&self) -> Cow<'a, str> {
+        let value = trim(self.value);
+        if !value.contains(&b'\n') {
+            return String::from_utf8_lossy(value);
+        }
+
+        let unfolded: Vec<u8> = value
+            .iter()
+            .copied()
+            .filter(|&byte| byte != b'\r' && byte != b'\n')
+            .collect();
+        Cow::Owned(String::from_utf8_lossy(&unfolded).into_owned())
+    }
+}
+
+/// The header fields of a message, in their order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Headers<'a> {
+    fields: Vec<Field<'a>>,
+}
+
+impl<'a> Headers<'a> {
+    /// The first field with the name, which is compared case-insensitively
+    pub fn get(&self, name: &str) -> Option<&Field<'a>> {
+        self.fields
+            .iter()
+            .find(|field| field.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The fields with the name, e.g., all "Received:" fields
+    pub fn get_all<'b>(&'b self, name: &'b str) -> impl Iterator<Item = &'b Field<'a>> + 'b {
+        self.fields
+            .iter()
+            .filter(move |field| field.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Field<'a>> {
+        self.fields.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+/// Split the content of a message into its header fields and the body.
+///
+/// The header block ends with an empty line, which is part of neither. A line that is neither
+/// a field nor the continuation of one also ends it, and starts the body. Lines may end with
+/// CRLF or a bare LF.
+pub fn split_headers(content: &[u8]) -> (Headers<'_>, &[u8]) {
+    let mut fields = Vec::new();
+    let mut rest = content;
+
+    loop {
+        let line_end = |input: &[u8]| {
+            memchr::memchr(b'\n', input).map_or(input.len(), |position| position + 1)
+        };
+        let first = line_end(rest);
+        if matches!(&rest[..first], b"\r\n" | b"\n") {
+            rest = &rest[first..];
+            break;
+        }
+
+        let Some(colon) = rest[..first].iter().position(|&byte| byte == b':') else {
+            break;
+        };
+        let Some(name) = field_name(&rest[..colon]) else {
+            break;
+        };
+
+        // Continuation lines start with whitespace.
+        let mut end = first;
+        while end < rest.len() && matches!(rest[end], b' ' | b'\t') {
+            end += line_end(&rest[end..]);
+        }
+
+        fields.push(Field {
+            name,
+            value: trim_line_break(&rest[colon + 1..end]),
+        });
+        rest = &rest[end..];
+        if rest.is_empty() {
+            break;
+        }
+    }
+
+    (Headers { fields }, rest)
+}
+
+/// field-name = 1*ftext, ftext = %d33-57 / %d59-126
+fn field_name(name: &[u8]) -> Option<&str> {
+    match !name.is_empty() && name.iter().all(|&byte| matches!(byte, 33..=57 | 59..=126)) {
+        true => core::str::from_utf8(name).ok(),
+        false => None,
+    }
+}
+
+fn trim_line_break(value: &[u8]) -> &[u8] {
+    let value = value.strip_suffix(b"\n").unwrap_or(value);
+    value.strip_suffix(b"\r").unwrap_or(value)
+}
+
+fn trim(value: &[u8]) -> &[u8] {
+    let is_space = |byte: &u8| matches!(byte, b' ' | b'\t' | b'\r' | b'\n');
+    let start = value
+        .iter()
+        .position(|byte| !is_space(byte))
+        .unwrap_or(value.len());
+    let end = value
+        .iter()
+        .rposition(|byte| !is_space(byte))
+        .map_or(start, |end| end + 1);
+    &value[start..end]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_headers() {
+        let content = b"Received: from a\r\n\tby b; Wed, 14 Oct 2026 12:00:00 +0000\r\n\
+Received: from c\r\nFrom:fred@example.org\r\nSubject: \xe2\x9c\x93\r\n\r\nBody\r\n\r\nMore\r\n";
+        let (headers, body) = split_headers(content);
+
+        assert_eq!(headers.len(), 4);
+        assert_eq!(
+            headers
+                .get_all("RECEIVED")
+                .map(|field| field.unfolded())
+                .collect::<Vec<_>>(),
+            ["from a\tby b; Wed, 14 Oct 2026 12:00:00 +0000", "from c"]
+        );
+        assert_eq!(
+            headers.get("Received").unwrap().value,
+            b" from a\r\n\tby b; Wed, 14 Oct 2026 12:00:00 +0000"
+        );
+        assert_eq!(headers.get("from").unwrap().unfolded(), "fred@example.org");
+        assert_eq!(headers.get("Subject").unwrap().unfolded(), "\u{2713}");
+        assert_eq!(headers.get("To"), None);
+        assert_eq!(body, b"Body\r\n\r\nMore\r\n");
+    }
+
+    #[test]
+    fn test_split_headers_malformed() {
+        // Without a body, with bare LF
+        let (headers, body) = split_headers(b"Subject: Test\nTo: a@example.org");
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers.get("To").unwrap().value, b" a@example.org");
+        assert_eq!(body, b"");
+
+        // Without headers
+        let (headers, body) = split_headers(b"\r\nHello\r\n");
+        assert!(headers.is_empty());
+        assert_eq!(body, b"Hello\r\n");
+
+        // A line that is not a field starts the body.
+        let (headers, body) = split_headers(b"Subject: Test\r\nHello world\r\n");
+        assert_eq!(headers.len(), 1);
+        assert_eq!(body, b"Hello world\r\n");
+        let (headers, body) = split_headers(b"Bad Name: Test\r\n");
+        assert!(headers.is_empty());
+        assert_eq!(body, b"Bad Name: Test\r\n");
+    }
+}
@@ -0,0 +1,82 @@
+//! Generation of Message-ID values (RFC 5322, 3.6.4.)
+//!
+//! A submission server adds a "Message-ID:" field to messages that lack one (RFC 6409, 8.3.):
+//!
+//! ```
+//! use instant_smtp::{message::split_headers, message_id};
+//!
+//! let content = b"Subject: Test\r\n\r\nHello\r\n";
+//! let (headers, _) = split_headers(content);
+//! if headers.get("Message-ID").is_none() {
+//!     let header = message_id::header("mail.example.org");
+//!     assert!(header.starts_with("Message-ID: <") && header.ends_with("@mail.example.org>\r\n"));
+//! }
+//! ```
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Distinguishes the values generated within the same nanosecond.
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// msg-id = "<" id-left "@" id-right ">"
+///
+/// The left part is the time in seconds since 1970 and 64 random bits, both in hexadecimal,
+/// e.g., `<66f0c3a2.8c1d2e3f4a5b6c7d@mail.example.org>`. `domain` should be the name of the
+/// host, so that values of different hosts don't collide.
+///
+/// The random part is unique, but not unpredictable enough to serve as a secret.
+pub fn generate(domain: &str) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    // The keys of `RandomState` are random for every process.
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(now.as_nanos());
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u32(std::process::id());
+
+    format!(
+        "<{:x}.{:016x}@{}>",
+        now.as_secs(),
+        hasher.finish(),
+        domain.trim_end_matches('.')
+    )
+}
+
+/// The "Message-ID:" field with a value of [`generate`], including the final CRLF
+pub fn header(domain: &str) -> String {
+    format!("Message-ID: {}\r\n", generate(domain))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::message::split_headers;
+
+    #[test]
+    fn test_generate() {
+        let ids: HashSet<_> = (0..1000).map(|_| generate("mail.example.org.")).collect();
+        assert_eq!(ids.len(), 1000);
+
+        let id = ids.into_iter().next().unwrap();
+        let (left, right) = id.split_once('@').unwrap();
+        assert_eq!(right, "mail.example.org>");
+        // id-left = dot-atom-text
+        assert!(left.starts_with('<'));
+        assert!(left[1..]
+            .split('.')
+            .all(|atom| !atom.is_empty() && atom.bytes().all(|byte| byte.is_ascii_hexdigit())));
+
+        let header = header("example.org");
+        let (headers, _) = split_headers(header.as_bytes());
+        assert!(headers.get("message-id").is_some());
+    }
+}
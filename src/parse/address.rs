@@ -1,16 +1,19 @@
 //! 4.1.3.  Address Literals (RFC 5321)
 
+use alloc::borrow::ToOwned;
+use core::net::{Ipv4Addr, Ipv6Addr};
+
 use nom::{
     branch::alt,
     bytes::streaming::{tag, tag_no_case, take_while1, take_while_m_n},
     character::{is_digit, is_hex_digit},
-    combinator::{map_res, opt, recognize},
-    multi::{count, many_m_n},
-    sequence::{delimited, tuple},
+    combinator::{map, map_res, verify},
+    multi::count,
+    sequence::{delimited, preceded, separated_pair, tuple},
     IResult,
 };
 
-use crate::parse::ldh_str;
+use crate::{parse::ldh_str, AddressLiteral};
 
 /// address-literal = "[" (
 ///                       IPv4-address-literal /
@@ -18,138 +21,80 @@ use crate::parse::ldh_str;
 ///                       General-address-literal
 ///                   ) "]"
 ///                     ; See Section 4.1.3
-pub fn address_literal(input: &[u8]) -> IResult<&[u8], &str> {
+pub fn address_literal(input: &[u8]) -> IResult<&[u8], AddressLiteral> {
     delimited(
         tag(b"["),
-        map_res(
-            alt((
-                ipv4_address_literal,
-                ipv6_address_literal,
-                general_address_literal,
-            )),
-            std::str::from_utf8,
-        ),
+        alt((
+            map(ipv4_address_literal, |ip| AddressLiteral::Ip(ip.into())),
+            map(ipv6_address_literal, |ip| AddressLiteral::Ip(ip.into())),
+            map(general_address_literal, |(tag, content)| {
+                AddressLiteral::General {
+                    tag: tag.to_owned(),
+                    content: content.to_owned(),
+                }
+            }),
+        )),
         tag(b"]"),
     )(input)
 }
 
 /// IPv4-address-literal = Snum 3("."  Snum)
-pub fn ipv4_address_literal(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    let parser = tuple((snum, count(tuple((tag(b"."), snum)), 3)));
+pub fn ipv4_address_literal(input: &[u8]) -> IResult<&[u8], Ipv4Addr> {
+    let mut parser = tuple((snum, count(preceded(tag(b"."), snum), 3)));
 
-    let (remaining, parsed) = recognize(parser)(input)?;
+    let (remaining, (first, rest)) = parser(input)?;
 
-    Ok((remaining, parsed))
+    Ok((remaining, Ipv4Addr::new(first, rest[0], rest[1], rest[2])))
 }
 
 /// Representing a decimal integer value in the range 0 through 255
 ///
 /// Snum = 1*3DIGIT
-pub fn snum(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    take_while_m_n(1, 3, is_digit)(input)
+pub fn snum(input: &[u8]) -> IResult<&[u8], u8> {
+    map_res(
+        map_res(take_while_m_n(1, 3, is_digit), core::str::from_utf8),
+        str::parse,
+    )(input)
 }
 
 /// IPv6-address-literal = "IPv6:" IPv6-addr
-pub fn ipv6_address_literal(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    let parser = tuple((tag_no_case(b"IPv6:"), ipv6_addr));
-
-    let (remaining, parsed) = recognize(parser)(input)?;
-
-    Ok((remaining, parsed))
+pub fn ipv6_address_literal(input: &[u8]) -> IResult<&[u8], Ipv6Addr> {
+    preceded(tag_no_case(b"IPv6:"), ipv6_addr)(input)
 }
 
 /// IPv6-addr = IPv6-full / IPv6-comp / IPv6v4-full / IPv6v4-comp
-pub fn ipv6_addr(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    let parser = alt((ipv6_full, ipv6_comp, ipv6v4_full, ipv6v4_comp));
-
-    let (remaining, parsed) = recognize(parser)(input)?;
-
-    Ok((remaining, parsed))
-}
-
-/// IPv6-full = IPv6-hex 7(":" IPv6-hex)
-pub fn ipv6_full(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    let parser = tuple((ipv6_hex, count(tuple((tag(b":"), ipv6_hex)), 7)));
-
-    let (remaining, parsed) = recognize(parser)(input)?;
-
-    Ok((remaining, parsed))
-}
-
-/// IPv6-hex = 1*4HEXDIG
-pub fn ipv6_hex(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    take_while_m_n(1, 4, is_hex_digit)(input)
-}
-
-/// The "::" represents at least 2 16-bit groups of zeros.
-/// No more than 6 groups in addition to the "::" may be present.
 ///
-/// IPv6-comp = [IPv6-hex *5(":" IPv6-hex)] "::" [IPv6-hex *5(":" IPv6-hex)]
-pub fn ipv6_comp(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    let parser = tuple((
-        opt(tuple((
-            ipv6_hex,
-            many_m_n(0, 5, tuple((tag(b":"), ipv6_hex))),
-        ))),
-        tag(b"::"),
-        opt(tuple((
-            ipv6_hex,
-            many_m_n(0, 5, tuple((tag(b":"), ipv6_hex))),
-        ))),
-    ));
-
-    let (remaining, parsed) = recognize(parser)(input)?;
-
-    Ok((remaining, parsed))
-}
-
+/// IPv6-full   = IPv6-hex 7(":" IPv6-hex)
+/// IPv6-comp   = [IPv6-hex *5(":" IPv6-hex)] "::" [IPv6-hex *5(":" IPv6-hex)]
 /// IPv6v4-full = IPv6-hex 5(":" IPv6-hex) ":" IPv4-address-literal
-pub fn ipv6v4_full(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    let parser = tuple((
-        ipv6_hex,
-        count(tuple((tag(b":"), ipv6_hex)), 5),
-        tag(b":"),
-        ipv4_address_literal,
-    ));
-
-    let (remaining, parsed) = recognize(parser)(input)?;
-
-    Ok((remaining, parsed))
-}
-
-/// The "::" represents at least 2 16-bit groups of zeros.
-/// No more than 4 groups in addition to the "::" and IPv4-address-literal may be present.
-///
 /// IPv6v4-comp = [IPv6-hex *3(":" IPv6-hex)] "::"
 ///               [IPv6-hex *3(":" IPv6-hex) ":"]
 ///               IPv4-address-literal
-pub fn ipv6v4_comp(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    let parser = tuple((
-        opt(tuple((
-            ipv6_hex,
-            many_m_n(0, 3, tuple((tag(b":"), ipv6_hex))),
-        ))),
-        tag(b"::"),
-        opt(tuple((
-            ipv6_hex,
-            many_m_n(0, 3, tuple((tag(b":"), ipv6_hex))),
-            tag(b":"),
-        ))),
-        ipv4_address_literal,
-    ));
-
-    let (remaining, parsed) = recognize(parser)(input)?;
-
-    Ok((remaining, parsed))
+///
+/// These are the textual forms of RFC 4291, 2.2., which `Ipv6Addr` parses.
+pub fn ipv6_addr(input: &[u8]) -> IResult<&[u8], Ipv6Addr> {
+    map_res(
+        map_res(
+            take_while1(|byte| is_hex_digit(byte) || byte == b':' || byte == b'.'),
+            core::str::from_utf8,
+        ),
+        str::parse,
+    )(input)
 }
 
 /// General-address-literal = Standardized-tag ":" 1*dcontent
-pub fn general_address_literal(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    let parser = tuple((standardized_tag, tag(b":"), take_while1(is_dcontent)));
-
-    let (remaining, parsed) = recognize(parser)(input)?;
+///
+/// The IPv6 tag is reserved for IPv6-address-literal.
+pub fn general_address_literal(input: &[u8]) -> IResult<&[u8], (&str, &str)> {
+    let parser = separated_pair(
+        map_res(standardized_tag, core::str::from_utf8),
+        tag(b":"),
+        map_res(take_while1(is_dcontent), core::str::from_utf8),
+    );
 
-    Ok((remaining, parsed))
+    verify(parser, |(tag, _): &(&str, &str)| {
+        !tag.is_empty() && !tag.eq_ignore_ascii_case("IPv6")
+    })(input)
 }
 
 /// Standardized-tag MUST be specified in a Standards-Track RFC and registered with IANA
@@ -165,3 +110,64 @@ pub fn standardized_tag(input: &[u8]) -> IResult<&[u8], &[u8]> {
 pub fn is_dcontent(byte: u8) -> bool {
     matches!(byte, 33..=90 | 94..=126)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_address_literal() {
+        let tests: &[(&[u8], AddressLiteral)] = &[
+            (b"[1.2.3.4]", AddressLiteral::Ip([1, 2, 3, 4].into())),
+            (b"[001.02.3.255]", AddressLiteral::Ip([1, 2, 3, 255].into())),
+            (
+                b"[IPv6:2001:db8:0:0:0:0:0:1]",
+                AddressLiteral::Ip("2001:db8::1".parse().unwrap()),
+            ),
+            (
+                b"[ipv6:2001:db8::1]",
+                AddressLiteral::Ip("2001:db8::1".parse().unwrap()),
+            ),
+            (
+                b"[IPv6:::ffff:192.0.2.1]",
+                AddressLiteral::Ip("::ffff:192.0.2.1".parse().unwrap()),
+            ),
+            (
+                b"[IPv6:1:2:3:4:5:6:1.2.3.4]",
+                AddressLiteral::Ip("1:2:3:4:5:6:102:304".parse().unwrap()),
+            ),
+            (
+                b"[x-tag:some-content]",
+                AddressLiteral::General {
+                    tag: "x-tag".into(),
+                    content: "some-content".into(),
+                },
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let (rem, parsed) = address_literal(input).unwrap();
+            assert!(rem.is_empty());
+            assert_eq!(&parsed, expected);
+        }
+
+        let ip = AddressLiteral::Ip("2001:db8::1".parse().unwrap());
+        assert_eq!(ip.to_string(), "[IPv6:2001:db8::1]");
+    }
+
+    #[test]
+    fn test_address_literal_invalid() {
+        let tests: &[&[u8]] = &[
+            b"[256.1.1.1]",
+            b"[1.2.3]",
+            b"[IPv6:1:2:3]",
+            b"[IPv6:1::2::3]",
+            b"[IPv6:garbage]",
+            b"[:content]",
+        ];
+
+        for input in tests {
+            assert!(address_literal(input).is_err(), "{:?}", input);
+        }
+    }
+}
@@ -1,65 +1,251 @@
+use alloc::{
+    borrow::ToOwned,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use nom::{
     branch::alt,
     bytes::streaming::{tag, tag_no_case, take_while, take_while1, take_while_m_n},
-    character::{is_alphabetic, is_digit},
-    combinator::{map, map_res, opt, recognize, value},
-    multi::separated_list1,
-    sequence::{delimited, preceded, tuple},
+    character::{is_alphabetic, is_digit, streaming::digit1},
+    combinator::{map, map_res, opt, recognize, value, verify},
+    multi::{many0_count, separated_list1},
+    sequence::{delimited, pair, preceded, terminated, tuple},
     IResult,
 };
 
 use crate::{
-    parse::{address::address_literal, atom, base64, domain, quoted_string, string},
-    {Command, DomainOrAddress, Parameter},
+    parse::{
+        address::address_literal, atom, base64, decode_xtext, domain, is_utf8_non_ascii,
+        quoted_string, quoted_string_raw, string, u_domain,
+    },
+    sasl::Continuation,
+    validate,
+    xclient::{Attribute, AttributeName},
+    {
+        AtomOrQuotedRef, AuthMechanism, Body, ByMode, Command, CommandRef, DeliverBy, EsmtpParam,
+        ForwardPath, HeloIdentity, Limits, LocalPart, Notify, ParametersRef, Priority, Ret,
+        ReversePath, Secret, SmtpParseError,
+    },
 };
 
 pub fn command(input: &[u8]) -> IResult<&[u8], Command> {
     alt((
         helo, ehlo, mail, rcpt, data, rset, vrfy, expn, help, noop, quit,
-        starttls,   // Extensions
-        auth_login, // https://interoperability.blob.core.windows.net/files/MS-XLOGIN/[MS-XLOGIN].pdf
-        auth_plain, // RFC 4616
+        starttls, // Extensions
+        auth,     // RFC 4954
+        bdat,     // RFC 3030
+        lhlo,     // RFC 2033
+        xclient,  // Postfix
+        xforward, // Postfix
+        unknown,
     ))(input)
 }
 
+/// The verbs of [`command`]
+const VERBS: [&str; 17] = [
+    "HELO", "EHLO", "MAIL", "RCPT", "DATA", "RSET", "VRFY", "EXPN", "HELP", "NOOP", "QUIT",
+    "STARTTLS", "AUTH", "BDAT", "LHLO", "XCLIENT", "XFORWARD",
+];
+
+/// Classify the error of [`command`] or [`command_ref`] by looking at the offending line.
+pub fn command_error(
+    input: &[u8],
+    error: nom::Err<nom::error::Error<&[u8]>>,
+    limits: &Limits,
+) -> SmtpParseError {
+    if let nom::Err::Incomplete(_) = error {
+        // AUTH allows the longest lines.
+        let limit = limits.command.max(limits.auth);
+        return match input.len() > limit {
+            true => SmtpParseError::LineTooLong { limit },
+            false => error.into(),
+        };
+    }
+
+    let line = match crate::find_crlf(input) {
+        Some(position) => &input[..position],
+        None => input,
+    };
+    let keyword = keyword(line);
+    // The path is valid, so the error must be in the parameters.
+    let path_is_valid = |prefix: usize, path: fn(&[u8]) -> IResult<&[u8], &str>| {
+        line.get(prefix..).is_some_and(|rest| path(rest).is_ok())
+    };
+
+    match &keyword[..] {
+        b"HELO" | b"EHLO" | b"LHLO" => SmtpParseError::InvalidDomain,
+        b"MAIL" if !path_is_valid("MAIL FROM:".len(), reverse_path) => {
+            SmtpParseError::InvalidMailbox
+        }
+        b"RCPT" if !path_is_valid("RCPT TO:".len(), rcpt_path) => SmtpParseError::InvalidMailbox,
+        b"MAIL" | b"RCPT" | b"DATA" | b"RSET" | b"VRFY" | b"EXPN" | b"HELP" | b"NOOP" | b"QUIT"
+        | b"STARTTLS" | b"AUTH" | b"BDAT" | b"XCLIENT" | b"XFORWARD" => SmtpParseError::Syntax,
+        _ => SmtpParseError::UnknownCommand,
+    }
+}
+
+/// The offset of the octet at which a line with a known verb violates the grammar, and what is
+/// wrong there
+pub fn locate_error(input: &[u8]) -> Option<(usize, String)> {
+    let offset = error_offset(input)?;
+
+    let keyword = keyword(input);
+    let located = match &keyword[..] {
+        // The identity follows the space.
+        b"HELO" | b"EHLO" | b"LHLO" if input.get(keyword.len()) == Some(&b' ') => {
+            locate_domain(input, keyword.len() + 1)
+        }
+        b"MAIL" | b"RCPT" if input.get(offset) == Some(&b'<') => locate_mailbox(input, offset + 1),
+        _ => None,
+    };
+
+    Some(located.unwrap_or_else(|| (offset, unexpected(input, offset))))
+}
+
+/// Like [`locate_error`] for the mailbox of a path that starts at `start`, after "<"
+fn locate_mailbox(input: &[u8], start: usize) -> Option<(usize, String)> {
+    let offset = |remaining: &[u8]| input.len() - remaining.len();
+
+    // Skip a source route.
+    let start = match pair(a_d_l, tag(b":"))(&input[start..]) {
+        Ok((remaining, _)) => offset(remaining),
+        Err(_) => start,
+    };
+    let at = match local_part(&input[start..]) {
+        Ok((remaining, _)) => offset(remaining),
+        Err(_) => return Some((start, unexpected(input, start))),
+    };
+
+    match input.get(at) {
+        Some(b'@') => locate_domain(input, at + 1),
+        _ => Some((at, unexpected(input, at))),
+    }
+}
+
+/// Like [`locate_error`] for the domain or address literal that starts at `start`
+///
+/// Returns `None` if the domain is valid.
+fn locate_domain(input: &[u8], start: usize) -> Option<(usize, String)> {
+    let rest = &input[start..];
+    let token = rest
+        .iter()
+        .position(|byte| b" <>\r\n".contains(byte))
+        .map_or(rest, |end| &rest[..end]);
+
+    if token.starts_with(b"[") {
+        return match address_literal(rest) {
+            Ok(_) => None,
+            Err(_) => Some((start, "invalid address literal".to_owned())),
+        };
+    }
+    let violation = core::str::from_utf8(token).ok().and_then(|token| {
+        validate::domain(token)
+            .into_iter()
+            .find(validate::Violation::is_invalid_domain)
+    });
+    if let (false, Some(violation)) = (token.is_empty(), violation) {
+        return Some((start, violation.to_string()));
+    }
+
+    match u_domain(rest) {
+        Ok(([byte, ..], _)) if b" >\r".contains(byte) => None,
+        Ok((remaining, _)) => {
+            let end = input.len() - remaining.len();
+            Some((end, unexpected(input, end)))
+        }
+        Err(_) => Some((start, unexpected(input, start))),
+    }
+}
+
+fn unexpected(input: &[u8], offset: usize) -> String {
+    match String::from_utf8_lossy(&input[offset..]).chars().next() {
+        None | Some('\r' | '\n') => "unexpected end of line".to_owned(),
+        Some(character) => format!("unexpected {:?}", character),
+    }
+}
+
+/// The offset of the octet at which the parser of the verb fails, if the verb is known
+///
+/// The error of [`command`] is that of its last alternative, so that the parser of the verb is
+/// applied again.
+fn error_offset(input: &[u8]) -> Option<usize> {
+    type Parser = fn(&[u8]) -> IResult<&[u8], Command>;
+
+    let parser: Parser = match &keyword(input)[..] {
+        b"HELO" => helo,
+        b"EHLO" => ehlo,
+        b"MAIL" => mail,
+        b"RCPT" => rcpt,
+        b"DATA" => data,
+        b"RSET" => rset,
+        b"VRFY" => vrfy,
+        b"EXPN" => expn,
+        b"HELP" => help,
+        b"NOOP" => noop,
+        b"QUIT" => quit,
+        b"STARTTLS" => starttls,
+        b"AUTH" => auth,
+        b"BDAT" => bdat,
+        b"LHLO" => lhlo,
+        b"XCLIENT" => xclient,
+        b"XFORWARD" => xforward,
+        _ => return None,
+    };
+
+    match parser(input) {
+        Err(nom::Err::Error(error) | nom::Err::Failure(error)) => {
+            Some(input.len() - error.input.len())
+        }
+        _ => None,
+    }
+}
+
+/// The verb in uppercase
+fn keyword(line: &[u8]) -> Vec<u8> {
+    line.iter()
+        .take_while(|byte| byte.is_ascii_alphabetic())
+        .map(u8::to_ascii_uppercase)
+        .collect()
+}
+
 /// helo = "HELO" tag(" ") Domain CRLF
 pub fn helo(input: &[u8]) -> IResult<&[u8], Command> {
-    let mut parser = tuple((
-        tag_no_case(b"HELO"),
-        tag(" "),
-        alt((
-            map(domain, |domain| DomainOrAddress::Domain(domain.into())),
-            map(address_literal, |address| {
-                DomainOrAddress::Address(address.into())
-            }),
-        )),
-        tag("\r\n"),
-    ));
+    let mut parser = tuple((tag_no_case(b"HELO"), tag(" "), helo_identity, tag("\r\n")));
 
     let (remaining, (_, _, domain_or_address, _)) = parser(input)?;
 
     Ok((remaining, Command::Helo { domain_or_address }))
 }
 
+/// Domain / address-literal
+pub fn helo_identity(input: &[u8]) -> IResult<&[u8], HeloIdentity> {
+    alt((
+        map(domain, |domain| HeloIdentity::Domain(domain.into())),
+        map(address_literal, HeloIdentity::AddressLiteral),
+    ))(input)
+}
+
 /// ehlo = "EHLO" tag(" ") ( Domain / address-literal ) CRLF
 pub fn ehlo(input: &[u8]) -> IResult<&[u8], Command> {
-    let mut parser = tuple((
-        tag_no_case(b"EHLO"),
-        tag(" "),
-        alt((
-            map(domain, |domain| DomainOrAddress::Domain(domain.into())),
-            map(address_literal, |address| {
-                DomainOrAddress::Address(address.into())
-            }),
-        )),
-        tag("\r\n"),
-    ));
+    let mut parser = tuple((tag_no_case(b"EHLO"), tag(" "), helo_identity, tag("\r\n")));
 
     let (remaining, (_, _, domain_or_address, _)) = parser(input)?;
 
     Ok((remaining, Command::Ehlo { domain_or_address }))
 }
 
+/// lhlo = "LHLO" tag(" ") ( Domain / address-literal ) CRLF
+pub fn lhlo(input: &[u8]) -> IResult<&[u8], Command> {
+    let mut parser = tuple((tag_no_case(b"LHLO"), tag(" "), helo_identity, tag("\r\n")));
+
+    let (remaining, (_, _, domain_or_address, _)) = parser(input)?;
+
+    Ok((remaining, Command::Lhlo { domain_or_address }))
+}
+
 /// mail = "MAIL FROM:" Reverse-path [tag(" ") Mail-parameters] CRLF
 pub fn mail(input: &[u8]) -> IResult<&[u8], Command> {
     let mut parser = tuple((
@@ -75,31 +261,126 @@ pub fn mail(input: &[u8]) -> IResult<&[u8], Command> {
     Ok((
         remaining,
         Command::Mail {
-            reverse_path: data.into(),
+            reverse_path: ReversePath::from(data),
             parameters: maybe_params.unwrap_or_default(),
         },
     ))
 }
 
 /// Mail-parameters = esmtp-param *(tag(" ") esmtp-param)
-pub fn mail_parameters(input: &[u8]) -> IResult<&[u8], Vec<Parameter>> {
+pub fn mail_parameters(input: &[u8]) -> IResult<&[u8], Vec<EsmtpParam>> {
     separated_list1(tag(" "), esmtp_param)(input)
 }
 
 /// esmtp-param = esmtp-keyword ["=" esmtp-value]
-pub fn esmtp_param(input: &[u8]) -> IResult<&[u8], Parameter> {
-    alt((
-        map_res(tuple((tag_no_case(b"SIZE="), esmtp_value)), |(_, value)| {
-            value.parse().map(Parameter::Size)
+pub fn esmtp_param(input: &[u8]) -> IResult<&[u8], EsmtpParam> {
+    map(
+        tuple((esmtp_keyword, opt(preceded(tag(b"="), esmtp_value)))),
+        |(keyword, value)| to_esmtp_param(keyword, value),
+    )(input)
+}
+
+/// Interpret a parameter that was already checked against the esmtp-param grammar.
+pub fn parse_esmtp_param(param: &str) -> EsmtpParam {
+    match param.split_once('=') {
+        Some((keyword, value)) => to_esmtp_param(keyword, Some(value)),
+        None => to_esmtp_param(param, None),
+    }
+}
+
+fn to_esmtp_param(keyword: &str, value: Option<&str>) -> EsmtpParam {
+    known_esmtp_param(keyword, value).unwrap_or_else(|| EsmtpParam::Other {
+        keyword: keyword.to_owned(),
+        value: value.map(String::from),
+    })
+}
+
+fn known_esmtp_param(keyword: &str, value: Option<&str>) -> Option<EsmtpParam> {
+    let param = match (keyword.to_ascii_uppercase().as_str(), value) {
+        ("SIZE", Some(value)) => EsmtpParam::Size(value.parse().ok()?),
+        ("BODY", Some(value)) => EsmtpParam::Body(match value.to_ascii_uppercase().as_str() {
+            "7BIT" => Body::SevenBit,
+            "8BITMIME" => Body::EightBitMime,
+            "BINARYMIME" => Body::BinaryMime,
+            _ => return None,
         }),
-        map(
-            tuple((esmtp_keyword, opt(preceded(tag(b"="), esmtp_value)))),
-            |(keyword, value)| Parameter::Other {
-                keyword: keyword.to_owned(),
-                value: value.map(String::from),
-            },
-        ),
-    ))(input)
+        ("RET", Some(value)) => EsmtpParam::Ret(match value.to_ascii_uppercase().as_str() {
+            "FULL" => Ret::Full,
+            "HDRS" => Ret::Hdrs,
+            _ => return None,
+        }),
+        ("ENVID", Some(value)) => EsmtpParam::Envid(decode_xtext(value)?.into_owned()),
+        ("NOTIFY", Some(value)) => EsmtpParam::Notify(notify(value)?),
+        ("ORCPT", Some(value)) => {
+            let (addr_type, address) = value.split_once(';')?;
+            EsmtpParam::Orcpt {
+                addr_type: addr_type.to_owned(),
+                address: decode_xtext(address)?.into_owned(),
+            }
+        }
+        ("AUTH", Some(value)) => EsmtpParam::Auth(value.to_owned()),
+        ("SMTPUTF8", None) => EsmtpParam::SmtpUtf8,
+        ("BY", Some(value)) => EsmtpParam::By(deliver_by(value)?),
+        ("MT-PRIORITY", Some(value)) => EsmtpParam::MtPriority(priority(value)?),
+        ("REQUIRETLS", None) => EsmtpParam::RequireTls,
+        _ => return None,
+    };
+
+    Some(param)
+}
+
+/// by-value = by-time ";" by-mode [ by-trace ]
+fn deliver_by(value: &str) -> Option<DeliverBy> {
+    let (time, mode) = value.split_once(';')?;
+
+    let digits = time.strip_prefix(['-', '+']).unwrap_or(time);
+    if digits.is_empty() || digits.len() > 9 || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let time = time.parse().ok()?;
+
+    let (mode, trace) = match mode.to_ascii_uppercase().as_str() {
+        "N" => (ByMode::Notify, false),
+        "NT" => (ByMode::Notify, true),
+        "R" => (ByMode::Return, false),
+        "RT" => (ByMode::Return, true),
+        _ => return None,
+    };
+    // RFC 2852, 4.1.
+    if mode == ByMode::Return && time <= 0 {
+        return None;
+    }
+
+    Some(DeliverBy { time, mode, trace })
+}
+
+/// priority-value = [ "-" / "+" ] DIGIT
+fn priority(value: &str) -> Option<Priority> {
+    let digit = value.strip_prefix(['-', '+']).unwrap_or(value);
+    if digit.len() != 1 || !digit.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    Priority::new(value.parse().ok()?)
+}
+
+/// notify-esmtp-value = "NEVER" / 1#notify-list-element
+fn notify(value: &str) -> Option<Notify> {
+    if value.eq_ignore_ascii_case("NEVER") {
+        return Some(Notify::NEVER);
+    }
+
+    let mut notify = Notify::NEVER;
+    for element in value.split(',') {
+        notify.insert(match element.to_ascii_uppercase().as_str() {
+            "SUCCESS" => Notify::SUCCESS,
+            "FAILURE" => Notify::FAILURE,
+            "DELAY" => Notify::DELAY,
+            _ => return None,
+        });
+    }
+
+    Some(notify)
 }
 
 /// esmtp-keyword = (ALPHA / DIGIT) *(ALPHA / DIGIT / "-")
@@ -109,7 +390,7 @@ pub fn esmtp_keyword(input: &[u8]) -> IResult<&[u8], &str> {
         take_while(|byte| is_alphabetic(byte) || is_digit(byte) || byte == b'-'),
     ));
 
-    let (remaining, parsed) = map_res(recognize(parser), std::str::from_utf8)(input)?;
+    let (remaining, parsed) = map_res(recognize(parser), core::str::from_utf8)(input)?;
 
     Ok((remaining, parsed))
 }
@@ -119,12 +400,14 @@ pub fn esmtp_keyword(input: &[u8]) -> IResult<&[u8], &str> {
 /// then the "xtext" syntax [32] SHOULD be used.
 ///
 /// esmtp-value = 1*(%d33-60 / %d62-126)
+///
+/// esmtp-value =/ UTF8-non-ascii (RFC 6531)
 pub fn esmtp_value(input: &[u8]) -> IResult<&[u8], &str> {
     fn is_value_character(byte: u8) -> bool {
-        matches!(byte, 33..=60 | 62..=126)
+        matches!(byte, 33..=60 | 62..=126) || is_utf8_non_ascii(byte)
     }
 
-    map_res(take_while1(is_value_character), std::str::from_utf8)(input)
+    map_res(take_while1(is_value_character), core::str::from_utf8)(input)
 }
 
 /// rcpt = "RCPT TO:" ( "<Postmaster@" Domain ">" / "<Postmaster>" / Forward-path ) [tag(" ") Rcpt-parameters] CRLF
@@ -136,14 +419,7 @@ pub fn rcpt(input: &[u8]) -> IResult<&[u8], Command> {
     let mut parser = tuple((
         tag_no_case(b"RCPT TO:"),
         opt(tag(" ")), // Out-of-tag(" ")ec, but Outlook does it ...
-        alt((
-            map_res(
-                recognize(tuple((tag_no_case("<Postmaster@"), domain, tag(">")))),
-                std::str::from_utf8,
-            ),
-            map_res(tag_no_case("<Postmaster>"), std::str::from_utf8),
-            forward_path,
-        )),
+        rcpt_path,
         opt(preceded(tag(" "), rcpt_parameters)),
         tag("\r\n"),
     ));
@@ -153,14 +429,14 @@ pub fn rcpt(input: &[u8]) -> IResult<&[u8], Command> {
     Ok((
         remaining,
         Command::Rcpt {
-            forward_path: data.into(),
+            forward_path: ForwardPath::from(data),
             parameters: maybe_params.unwrap_or_default(),
         },
     ))
 }
 
 /// Rcpt-parameters = esmtp-param *(tag(" ") esmtp-param)
-pub fn rcpt_parameters(input: &[u8]) -> IResult<&[u8], Vec<Parameter>> {
+pub fn rcpt_parameters(input: &[u8]) -> IResult<&[u8], Vec<EsmtpParam>> {
     separated_list1(tag(" "), esmtp_param)(input)
 }
 
@@ -245,48 +521,368 @@ pub fn starttls(input: &[u8]) -> IResult<&[u8], Command> {
     )(input)
 }
 
-/// https://interoperability.blob.core.windows.net/files/MS-XLOGIN/[MS-XLOGIN].pdf
+/// auth-command     = "AUTH" SP sasl-mech [SP initial-response] CRLF (RFC 4954)
+/// initial-response = base64 / "="
 ///
-/// username = 1*CHAR ; Base64-encoded username
-/// password = 1*CHAR ; Base64-encoded password
+/// "=" is an empty initial response.
+pub fn auth(input: &[u8]) -> IResult<&[u8], Command> {
+    let mut parser = tuple((
+        tag_no_case(b"AUTH"),
+        tag(" "),
+        sasl_mech,
+        opt(preceded(
+            tag(" "),
+            alt((value(Vec::new(), tag(b"=")), decoded_base64)),
+        )),
+        tag("\r\n"),
+    ));
+
+    let (remaining, (_, _, mechanism, initial_response, _)) = parser(input)?;
+
+    Ok((
+        remaining,
+        Command::Auth {
+            mechanism,
+            initial_response: initial_response.map(Secret::new),
+        },
+    ))
+}
+
+/// sasl-mech = 1*20mech-char (RFC 4422)
+/// mech-char = UPPER-ALPHA / DIGIT / HYPHEN / UNDERSCORE
 ///
-/// auth_login_command = "AUTH LOGIN" [tag(" ") username] CRLF
+/// Lower case letters are accepted, too.
+pub fn sasl_mech(input: &[u8]) -> IResult<&[u8], AuthMechanism> {
+    map(sasl_mech_name, to_auth_mechanism)(input)
+}
+
+fn sasl_mech_name(input: &[u8]) -> IResult<&[u8], &str> {
+    fn is_mech_char(byte: u8) -> bool {
+        byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_'
+    }
+
+    map_res(take_while_m_n(1, 20, is_mech_char), core::str::from_utf8)(input)
+}
+
+pub fn to_auth_mechanism(name: &str) -> AuthMechanism {
+    match name.to_ascii_uppercase().as_str() {
+        "PLAIN" => AuthMechanism::Plain,
+        "LOGIN" => AuthMechanism::Login,
+        "GSSAPI" => AuthMechanism::GssApi,
+        "CRAM-MD5" => AuthMechanism::CramMd5,
+        "CRAM-SHA1" => AuthMechanism::CramSha1,
+        "SCRAM-MD5" => AuthMechanism::ScramMd5,
+        "SCRAM-SHA-256" => AuthMechanism::ScramSha256,
+        "DIGEST-MD5" => AuthMechanism::DigestMd5,
+        "NTLM" => AuthMechanism::Ntlm,
+        other => AuthMechanism::Other(other.to_owned()),
+    }
+}
+
+/// Response of the client to a "334" challenge (RFC 4954, 4.)
 ///
-/// auth_login_username_challenge = "334 VXNlcm5hbWU6" CRLF
-/// auth_login_username_retag(" ")onse  = username CRLF
-/// auth_login_password_challenge = "334 UGFzc3dvcmQ6" CRLF
-/// auth_login_password_retag(" ")onse  = password CRLF
-pub fn auth_login(input: &[u8]) -> IResult<&[u8], Command> {
+/// continuation = ( base64 / "*" ) CRLF
+pub fn sasl_continuation(input: &[u8]) -> IResult<&[u8], Continuation> {
+    terminated(
+        alt((
+            value(Continuation::Cancel, tag(b"*")),
+            map(decoded_base64, |data| {
+                Continuation::Response(Secret::new(data))
+            }),
+        )),
+        tag("\r\n"),
+    )(input)
+}
+
+fn decoded_base64(input: &[u8]) -> IResult<&[u8], Vec<u8>> {
+    map_res(base64, |data| BASE64.decode(data))(input)
+}
+
+/// bdat-cmd   = "BDAT" SP chunk-size [ SP end-marker ] CR LF
+/// chunk-size = 1*DIGIT
+/// end-marker = "LAST"
+pub fn bdat(input: &[u8]) -> IResult<&[u8], Command> {
     let mut parser = tuple((
-        tag_no_case(b"AUTH"),
+        tag_no_case(b"BDAT"),
         tag(" "),
-        tag_no_case("LOGIN"),
-        opt(preceded(tag(" "), base64)),
+        map_res(map_res(digit1, core::str::from_utf8), str::parse::<u64>),
+        opt(preceded(tag(" "), tag_no_case("LAST"))),
         tag("\r\n"),
     ));
 
-    let (remaining, (_, _, _, maybe_username_b64, _)) = parser(input)?;
+    let (remaining, (_, _, size, maybe_last, _)) = parser(input)?;
 
     Ok((
         remaining,
-        Command::AuthLogin(maybe_username_b64.map(|i| i.to_owned())),
+        Command::Bdat {
+            size,
+            last: maybe_last.is_some(),
+        },
     ))
 }
 
-pub fn auth_plain(input: &[u8]) -> IResult<&[u8], Command> {
+/// xclient-command = "XCLIENT" 1*( SP attribute-name "=" attribute-value ) CRLF
+pub fn xclient(input: &[u8]) -> IResult<&[u8], Command> {
+    map(attributes_ref("XCLIENT"), |attributes| Command::Xclient {
+        attributes: xclient_attributes(attributes),
+    })(input)
+}
+
+/// xforward-command = "XFORWARD" 1*( SP attribute-name "=" attribute-value ) CRLF
+pub fn xforward(input: &[u8]) -> IResult<&[u8], Command> {
+    map(attributes_ref("XFORWARD"), |attributes| Command::Xforward {
+        attributes: xclient_attributes(attributes),
+    })(input)
+}
+
+/// keyword 1*( SP attribute-name "=" attribute-value ) CRLF
+///
+/// attribute-name  = 1*( ALPHA / DIGIT / "_" / "-" )
+/// attribute-value = xtext / "[UNAVAILABLE]"
+fn attributes_ref(keyword: &'static str) -> impl Fn(&[u8]) -> IResult<&[u8], &str> {
+    move |input| {
+        let name = take_while1(|byte| {
+            is_alphabetic(byte) || is_digit(byte) || byte == b'_' || byte == b'-'
+        });
+        let attribute = verify(
+            map_res(
+                recognize(tuple((name, tag("="), esmtp_value))),
+                core::str::from_utf8,
+            ),
+            |attribute: &str| xclient_attribute(attribute).is_some(),
+        );
+
+        delimited(
+            tuple((tag_no_case(keyword), tag(" "))),
+            map_res(
+                recognize(separated_list1(tag(" "), attribute)),
+                core::str::from_utf8,
+            ),
+            tag("\r\n"),
+        )(input)
+    }
+}
+
+/// Convert attributes checked by [`attributes_ref`].
+pub fn xclient_attributes(attributes: &str) -> Vec<Attribute> {
+    attributes
+        .split(' ')
+        .filter_map(xclient_attribute)
+        .collect()
+}
+
+fn xclient_attribute(attribute: &str) -> Option<Attribute> {
+    let (name, value) = attribute.split_once('=')?;
+    let value = match value {
+        "[UNAVAILABLE]" => None,
+        value => Some(decode_xtext(value)?.into_owned()),
+    };
+
+    Some(Attribute {
+        name: AttributeName::from(name),
+        value,
+    })
+}
+
+/// Keyword [ SP Arguments ] CRLF, where Keyword is not one of the known verbs
+///
+/// Keyword is any sequence of ALPHA, DIGIT, and "-". The arguments are the rest of the line.
+/// Keywords starting with "X" are private extensions (RFC 5321, 4.1.5.), e.g., XUSR, and
+/// parsed as [`Command::Extension`].
+pub fn unknown(input: &[u8]) -> IResult<&[u8], Command> {
+    map(unknown_ref, |(verb, arguments)| {
+        let (verb, arguments) = (verb.to_owned(), arguments.map(String::from));
+        match is_extension(&verb) {
+            true => Command::Extension { verb, arguments },
+            false => Command::Unknown { verb, arguments },
+        }
+    })(input)
+}
+
+fn is_extension(verb: &str) -> bool {
+    verb.starts_with(['X', 'x'])
+}
+
+fn unknown_ref(input: &[u8]) -> IResult<&[u8], (&str, Option<&str>)> {
+    let verb = verify(
+        map_res(
+            take_while1(|byte| is_alphabetic(byte) || is_digit(byte) || byte == b'-'),
+            core::str::from_utf8,
+        ),
+        |verb: &str| !VERBS.iter().any(|known| known.eq_ignore_ascii_case(verb)),
+    );
+    let arguments = map_res(
+        take_while(|byte| byte != b'\r' && byte != b'\n'),
+        core::str::from_utf8,
+    );
+
+    terminated(pair(verb, opt(preceded(tag(" "), arguments))), tag("\r\n"))(input)
+}
+
+// ----- Borrowed commands -----
+
+/// Like [`command`], but borrows the arguments from the input.
+pub fn command_ref(input: &[u8]) -> IResult<&[u8], CommandRef<'_>> {
+    let keyword = |keyword: &'static str, command: CommandRef<'static>| {
+        value(command, tuple((tag_no_case(keyword), tag("\r\n"))))
+    };
+
+    alt((
+        map(hello_ref("HELO"), |domain_or_address| CommandRef::Helo {
+            domain_or_address,
+        }),
+        map(hello_ref("EHLO"), |domain_or_address| CommandRef::Ehlo {
+            domain_or_address,
+        }),
+        mail_ref,
+        rcpt_ref,
+        keyword("DATA", CommandRef::Data),
+        keyword("RSET", CommandRef::Rset),
+        map(
+            delimited(tag_no_case(b"VRFY "), string_ref, tag("\r\n")),
+            |user_or_mailbox| CommandRef::Vrfy { user_or_mailbox },
+        ),
+        map(
+            delimited(tag_no_case(b"EXPN "), string_ref, tag("\r\n")),
+            |mailing_list| CommandRef::Expn { mailing_list },
+        ),
+        map(
+            delimited(
+                tag_no_case(b"HELP"),
+                opt(preceded(tag(" "), string_ref)),
+                tag("\r\n"),
+            ),
+            |argument| CommandRef::Help { argument },
+        ),
+        map(
+            delimited(
+                tag_no_case(b"NOOP"),
+                opt(preceded(tag(" "), string_ref)),
+                tag("\r\n"),
+            ),
+            |argument| CommandRef::Noop { argument },
+        ),
+        keyword("QUIT", CommandRef::Quit),
+        keyword("STARTTLS", CommandRef::StartTls),
+        auth_ref,
+        map(bdat, |command| match command {
+            Command::Bdat { size, last } => CommandRef::Bdat { size, last },
+            _ => unreachable!(),
+        }),
+        map(hello_ref("LHLO"), |domain_or_address| CommandRef::Lhlo {
+            domain_or_address,
+        }),
+        map(attributes_ref("XCLIENT"), |attributes| {
+            CommandRef::Xclient { attributes }
+        }),
+        map(attributes_ref("XFORWARD"), |attributes| {
+            CommandRef::Xforward { attributes }
+        }),
+        map(unknown_ref, |(verb, arguments)| match is_extension(verb) {
+            true => CommandRef::Extension { verb, arguments },
+            false => CommandRef::Unknown { verb, arguments },
+        }),
+    ))(input)
+}
+
+/// keyword SP ( Domain / address-literal ) CRLF
+fn hello_ref(keyword: &'static str) -> impl Fn(&[u8]) -> IResult<&[u8], &str> {
+    move |input| {
+        delimited(
+            tuple((tag_no_case(keyword), tag(" "))),
+            map_res(
+                alt((recognize(domain), recognize(address_literal))),
+                core::str::from_utf8,
+            ),
+            tag("\r\n"),
+        )(input)
+    }
+}
+
+fn mail_ref(input: &[u8]) -> IResult<&[u8], CommandRef<'_>> {
+    let mut parser = tuple((
+        tag_no_case(b"MAIL FROM:"),
+        opt(tag(" ")),
+        reverse_path,
+        opt(preceded(tag(" "), parameters_ref)),
+        tag("\r\n"),
+    ));
+
+    let (remaining, (_, _, reverse_path, parameters, _)) = parser(input)?;
+
+    Ok((
+        remaining,
+        CommandRef::Mail {
+            reverse_path,
+            parameters: parameters.unwrap_or_default(),
+        },
+    ))
+}
+
+fn rcpt_ref(input: &[u8]) -> IResult<&[u8], CommandRef<'_>> {
+    let mut parser = tuple((
+        tag_no_case(b"RCPT TO:"),
+        opt(tag(" ")),
+        rcpt_path,
+        opt(preceded(tag(" "), parameters_ref)),
+        tag("\r\n"),
+    ));
+
+    let (remaining, (_, _, forward_path, parameters, _)) = parser(input)?;
+
+    Ok((
+        remaining,
+        CommandRef::Rcpt {
+            forward_path,
+            parameters: parameters.unwrap_or_default(),
+        },
+    ))
+}
+
+/// esmtp-param *(SP esmtp-param)
+fn parameters_ref(input: &[u8]) -> IResult<&[u8], ParametersRef<'_>> {
+    let param = || tuple((esmtp_keyword, opt(preceded(tag(b"="), esmtp_value))));
+
+    map(
+        map_res(
+            recognize(pair(param(), many0_count(pair(tag(" "), param())))),
+            core::str::from_utf8,
+        ),
+        ParametersRef,
+    )(input)
+}
+
+/// String = Atom / Quoted-string
+fn string_ref(input: &[u8]) -> IResult<&[u8], AtomOrQuotedRef<'_>> {
+    alt((
+        map(atom, AtomOrQuotedRef::Atom),
+        map(quoted_string_raw, AtomOrQuotedRef::Quoted),
+    ))(input)
+}
+
+fn auth_ref(input: &[u8]) -> IResult<&[u8], CommandRef<'_>> {
     let mut parser = tuple((
         tag_no_case(b"AUTH"),
         tag(" "),
-        tag_no_case("PLAIN"),
-        opt(preceded(tag(" "), base64)),
+        sasl_mech_name,
+        opt(preceded(
+            tag(" "),
+            alt((
+                map_res(tag(b"="), core::str::from_utf8),
+                verify(base64, |base64: &str| BASE64.decode(base64).is_ok()),
+            )),
+        )),
         tag("\r\n"),
     ));
 
-    let (remaining, (_, _, _, maybe_credentials_b64, _)) = parser(input)?;
+    let (remaining, (_, _, mechanism, initial_response, _)) = parser(input)?;
 
     Ok((
         remaining,
-        Command::AuthPlain(maybe_credentials_b64.map(|i| i.to_owned())),
+        CommandRef::Auth {
+            mechanism,
+            initial_response: initial_response.map(Secret::new),
+        },
     ))
 }
 
@@ -302,13 +898,32 @@ pub fn forward_path(input: &[u8]) -> IResult<&[u8], &str> {
     path(input)
 }
 
+/// ( "<Postmaster@" Domain ">" / "<Postmaster>" / Forward-path ), without the angle brackets
+///
+/// "Postmaster" is case-insensitive.
+pub fn rcpt_path(input: &[u8]) -> IResult<&[u8], &str> {
+    let postmaster = recognize(pair(
+        tag_no_case(b"Postmaster"),
+        opt(pair(tag(b"@"), domain)),
+    ));
+
+    alt((
+        delimited(
+            tag(b"<"),
+            map_res(postmaster, core::str::from_utf8),
+            tag(b">"),
+        ),
+        forward_path,
+    ))(input)
+}
+
 // Path = "<" [ A-d-l ":" ] Mailbox ">"
 pub fn path(input: &[u8]) -> IResult<&[u8], &str> {
     delimited(
         tag(b"<"),
         map_res(
             recognize(tuple((opt(tuple((a_d_l, tag(b":")))), mailbox))),
-            std::str::from_utf8,
+            core::str::from_utf8,
         ),
         tag(b">"),
     )(input)
@@ -319,7 +934,7 @@ pub fn path(input: &[u8]) -> IResult<&[u8], &str> {
 ///          ; route", MUST BE accepted, SHOULD NOT be
 ///          ; generated, and SHOULD be ignored.
 pub fn a_d_l(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    let parser = separated_list1(tag(b","), at_domain);
+    let parser = pair(at_domain, many0_count(pair(tag(b","), at_domain)));
 
     let (remaining, parsed) = recognize(parser)(input)?;
 
@@ -336,8 +951,14 @@ pub fn at_domain(input: &[u8]) -> IResult<&[u8], &[u8]> {
 }
 
 /// Mailbox = Local-part "@" ( Domain / address-literal )
+///
+/// Domain may contain U-labels (RFC 6531).
 pub fn mailbox(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    let parser = tuple((local_part, tag(b"@"), alt((domain, address_literal))));
+    let parser = tuple((
+        local_part,
+        tag(b"@"),
+        alt((recognize(u_domain), recognize(address_literal))),
+    ));
 
     let (remaining, parsed) = recognize(parser)(input)?;
 
@@ -347,33 +968,49 @@ pub fn mailbox(input: &[u8]) -> IResult<&[u8], &[u8]> {
 /// Local-part = Dot-string / Quoted-string
 ///               ; MAY be case-sensitive
 pub fn local_part(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    alt((recognize(dot_string), recognize(quoted_string)))(input)
+    alt((recognize(dot_string), recognize(quoted_string_raw)))(input)
+}
+
+/// Like [`local_part`], but returns the interpreted [`LocalPart`].
+pub fn local_part_value(input: &[u8]) -> IResult<&[u8], LocalPart> {
+    alt((
+        map(dot_string, |dot_string| {
+            LocalPart::DotString(dot_string.to_owned())
+        }),
+        map(quoted_string, |quoted| {
+            LocalPart::Quoted(quoted.into_owned())
+        }),
+    ))(input)
 }
 
 /// Dot-string = Atom *("."  Atom)
 pub fn dot_string(input: &[u8]) -> IResult<&[u8], &str> {
     map_res(
-        recognize(separated_list1(tag(b"."), atom)),
-        std::str::from_utf8,
+        recognize(pair(atom, many0_count(pair(tag(b"."), atom)))),
+        core::str::from_utf8,
     )(input)
 }
 
 // Not used?
-/// Keyword = Ldh-str
+// Keyword = Ldh-str
 //pub fn Keyword(input: &[u8]) -> IResult<&[u8], &[u8]> {
 //    Ldh_str(input)
 //}
 
 // Not used?
-/// Argument = Atom
+// Argument = Atom
 //pub fn Argument(input: &[u8]) -> IResult<&[u8], &[u8]> {
 //    Atom(input)
 //}
 
 #[cfg(test)]
 mod test {
-    use super::{ehlo, helo, mail, Parameter};
-    use crate::{Command, DomainOrAddress};
+    use super::{
+        auth, bdat, command, command_error, command_ref, ehlo, esmtp_param, helo, lhlo, mail, rcpt,
+        Attribute, AttributeName, AuthMechanism, Body, ByMode, DeliverBy, EsmtpParam, ForwardPath,
+        Limits, Notify, Priority, Ret, ReversePath, Secret, SmtpParseError,
+    };
+    use crate::{AddressLiteral, Command, HeloIdentity, Path, SyntaxOptions};
 
     #[test]
     fn test_ehlo() {
@@ -381,7 +1018,9 @@ mod test {
         assert_eq!(
             parsed,
             Command::Ehlo {
-                domain_or_address: DomainOrAddress::Address("123.123.123.123".into()),
+                domain_or_address: HeloIdentity::AddressLiteral(AddressLiteral::Ip(
+                    [123, 123, 123, 123].into()
+                )),
             }
         );
         assert_eq!(rem, b"???");
@@ -393,12 +1032,26 @@ mod test {
         assert_eq!(
             parsed,
             Command::Helo {
-                domain_or_address: DomainOrAddress::Domain("example.com".into()),
+                domain_or_address: HeloIdentity::Domain("example.com".into()),
             }
         );
         assert_eq!(rem, b"???");
     }
 
+    #[test]
+    fn test_lhlo() {
+        let (rem, parsed) = lhlo(b"LHLO example.com\r\n???").unwrap();
+        let expected = Command::Lhlo {
+            domain_or_address: HeloIdentity::Domain("example.com".into()),
+        };
+        assert_eq!(parsed, expected);
+        assert_eq!(rem, b"???");
+
+        let mut serialized = Vec::new();
+        expected.serialize(&mut serialized).unwrap();
+        assert_eq!(serialized, b"LHLO example.com\r\n");
+    }
+
     #[test]
     fn test_mail() {
         let (rem, parsed) = mail(b"MAIL FROM:<userx@y.foo.org> size=12345\r\n???").unwrap();
@@ -406,9 +1059,401 @@ mod test {
             parsed,
             Command::Mail {
                 reverse_path: "userx@y.foo.org".into(),
-                parameters: vec![Parameter::Size(12345)],
+                parameters: vec![EsmtpParam::Size(12345)],
+            }
+        );
+        assert_eq!(rem, b"???");
+
+        let (_, parsed) = mail(b"MAIL FROM:<>\r\n").unwrap();
+        assert_eq!(
+            parsed,
+            Command::Mail {
+                reverse_path: ReversePath::Null,
+                parameters: vec![],
+            }
+        );
+        let mut serialized = Vec::new();
+        parsed.serialize(&mut serialized).unwrap();
+        assert_eq!(serialized, b"MAIL FROM:<>\r\n");
+    }
+
+    #[test]
+    fn test_source_route() {
+        let input = b"RCPT TO:<@hosta.int,@jkl.org:userc@d.bar.org>\r\n";
+        let (_, parsed) = rcpt(input).unwrap();
+        let path = Path {
+            route: Some(vec!["hosta.int".into(), "jkl.org".into()]),
+            mailbox: "userc@d.bar.org".into(),
+        };
+        assert_eq!(
+            parsed,
+            Command::Rcpt {
+                forward_path: ForwardPath::Path(path.clone()),
+                parameters: vec![],
+            }
+        );
+
+        let mut serialized = Vec::new();
+        parsed.serialize(&mut serialized).unwrap();
+        assert_eq!(serialized, input);
+        assert_eq!(path.without_route(), Path::new("userc@d.bar.org"));
+    }
+
+    #[test]
+    fn test_postmaster() {
+        let tests: &[(&[u8], ForwardPath, &[u8])] = &[
+            (
+                b"RCPT TO:<postmaster>\r\n",
+                ForwardPath::Postmaster(None),
+                b"RCPT TO:<Postmaster>\r\n",
+            ),
+            (
+                b"RCPT TO:<POSTMASTER@example.org>\r\n",
+                ForwardPath::Postmaster(Some("example.org".into())),
+                b"RCPT TO:<Postmaster@example.org>\r\n",
+            ),
+            (
+                b"RCPT TO:<postmaster@[192.0.2.1]>\r\n",
+                ForwardPath::Path(Path::new("postmaster@[192.0.2.1]")),
+                b"RCPT TO:<postmaster@[192.0.2.1]>\r\n",
+            ),
+        ];
+
+        for (input, forward_path, serialized) in tests {
+            let (_, parsed) = rcpt(input).unwrap();
+            assert_eq!(
+                parsed,
+                Command::Rcpt {
+                    forward_path: forward_path.clone(),
+                    parameters: vec![],
+                }
+            );
+
+            let mut buffer = Vec::new();
+            parsed.serialize(&mut buffer).unwrap();
+            assert_eq!(buffer, *serialized);
+        }
+    }
+
+    #[test]
+    fn test_bdat() {
+        let (rem, parsed) = bdat(b"BDAT 1000 last\r\n???").unwrap();
+        assert_eq!(
+            parsed,
+            Command::Bdat {
+                size: 1000,
+                last: true,
             }
         );
         assert_eq!(rem, b"???");
+
+        let (_, parsed) = bdat(b"BDAT 0\r\n").unwrap();
+        assert_eq!(
+            parsed,
+            Command::Bdat {
+                size: 0,
+                last: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_esmtp_param() {
+        let tests: &[(&[u8], EsmtpParam)] = &[
+            (b"SIZE=123", EsmtpParam::Size(123)),
+            (b"body=8bitmime", EsmtpParam::Body(Body::EightBitMime)),
+            (b"RET=HDRS", EsmtpParam::Ret(Ret::Hdrs)),
+            (b"ENVID=QQ+2B314159", EsmtpParam::Envid("QQ+314159".into())),
+            (
+                b"NOTIFY=SUCCESS,FAILURE",
+                EsmtpParam::Notify(Notify::SUCCESS | Notify::FAILURE),
+            ),
+            (b"NOTIFY=NEVER", EsmtpParam::Notify(Notify::NEVER)),
+            (
+                b"ORCPT=rfc822;Bob+20Smith@Example.COM",
+                EsmtpParam::Orcpt {
+                    addr_type: "rfc822".into(),
+                    address: "Bob Smith@Example.COM".into(),
+                },
+            ),
+            (b"AUTH=<>", EsmtpParam::Auth("<>".into())),
+            (b"SMTPUTF8", EsmtpParam::SmtpUtf8),
+            (b"REQUIRETLS", EsmtpParam::RequireTls),
+            (
+                b"BY=120;R",
+                EsmtpParam::By(DeliverBy {
+                    time: 120,
+                    mode: ByMode::Return,
+                    trace: false,
+                }),
+            ),
+            (
+                b"BY=-30;nt",
+                EsmtpParam::By(DeliverBy {
+                    time: -30,
+                    mode: ByMode::Notify,
+                    trace: true,
+                }),
+            ),
+            (
+                b"BY=-30;R",
+                EsmtpParam::Other {
+                    keyword: "BY".into(),
+                    value: Some("-30;R".into()),
+                },
+            ),
+            (
+                b"MT-PRIORITY=-4",
+                EsmtpParam::MtPriority(Priority::new(-4).unwrap()),
+            ),
+            (b"mt-priority=9", EsmtpParam::MtPriority(Priority::MAX)),
+            (
+                b"MT-PRIORITY=10",
+                EsmtpParam::Other {
+                    keyword: "MT-PRIORITY".into(),
+                    value: Some("10".into()),
+                },
+            ),
+            (
+                b"NOTIFY=NEVER,DELAY",
+                EsmtpParam::Other {
+                    keyword: "NOTIFY".into(),
+                    value: Some("NEVER,DELAY".into()),
+                },
+            ),
+            (
+                b"BODY=UNKNOWN",
+                EsmtpParam::Other {
+                    keyword: "BODY".into(),
+                    value: Some("UNKNOWN".into()),
+                },
+            ),
+            (
+                b"X-FOO",
+                EsmtpParam::Other {
+                    keyword: "X-FOO".into(),
+                    value: None,
+                },
+            ),
+        ];
+
+        for (test, expected) in tests {
+            let mut input = test.to_vec();
+            input.push(b' ');
+
+            let (rem, parsed) = esmtp_param(&input).unwrap();
+            assert_eq!(rem, b" ");
+            assert_eq!(&parsed, expected);
+
+            let mut serialized = Vec::new();
+            parsed.serialize(&mut serialized).unwrap();
+            assert!(serialized.eq_ignore_ascii_case(test));
+        }
+    }
+
+    #[test]
+    fn test_smtputf8() {
+        let input = "MAIL FROM:<jörg@bücher.example> SMTPUTF8\r\n".as_bytes();
+
+        assert!(Command::from_bytes(input).is_err());
+
+        let options = SyntaxOptions {
+            smtputf8: true,
+            ..Default::default()
+        };
+        let (rem, parsed) = Command::from_bytes_with(input, options).unwrap();
+        assert!(rem.is_empty());
+        assert_eq!(
+            parsed,
+            Command::Mail {
+                reverse_path: "jörg@bücher.example".into(),
+                parameters: vec![EsmtpParam::SmtpUtf8],
+            }
+        );
+
+        assert!(parsed.serialize(&mut Vec::new()).is_err());
+        let mut serialized = Vec::new();
+        parsed.serialize_with(&mut serialized, options).unwrap();
+        assert_eq!(serialized, input);
+
+        let (_, parsed) =
+            Command::from_bytes_with(b"RCPT TO:<\"a b\"@example.org>\r\n", options).unwrap();
+        assert!(parsed.serialize(&mut Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn test_auth() {
+        let tests: &[(&[u8], Command)] = &[
+            (
+                b"AUTH PLAIN AHVzZXIAcGFzcw==\r\n",
+                Command::Auth {
+                    mechanism: AuthMechanism::Plain,
+                    initial_response: Some(Secret::new(b"\0user\0pass".to_vec())),
+                },
+            ),
+            (
+                b"AUTH LOGIN\r\n",
+                Command::Auth {
+                    mechanism: AuthMechanism::Login,
+                    initial_response: None,
+                },
+            ),
+            (
+                b"AUTH X-OAUTH2 =\r\n",
+                Command::Auth {
+                    mechanism: AuthMechanism::Other("X-OAUTH2".into()),
+                    initial_response: Some(Secret::new(Vec::new())),
+                },
+            ),
+        ];
+
+        for (test, expected) in tests {
+            let (rem, parsed) = auth(test).unwrap();
+            assert!(rem.is_empty());
+            assert_eq!(&parsed, expected);
+
+            let mut serialized = Vec::new();
+            parsed.serialize(&mut serialized).unwrap();
+            assert_eq!(&serialized, test);
+        }
+
+        assert!(auth(b"AUTH PLAIN ?\r\n").is_err());
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let tests: &[(&[u8], &[u8])] = &[
+            (b"eHlO example.org\r\n", b"EHLO example.org\r\n"),
+            (
+                b"MaIl FrOm:<a@example.org> body=8bitmime\r\n",
+                b"MAIL FROM:<a@example.org> BODY=8BITMIME\r\n",
+            ),
+            (b"rcpt to:<postmaster>\r\n", b"RCPT TO:<Postmaster>\r\n"),
+            (
+                b"Rcpt To:<b@example.org> notify=success,delay\r\n",
+                b"RCPT TO:<b@example.org> NOTIFY=SUCCESS,DELAY\r\n",
+            ),
+            (b"data\r\n", b"DATA\r\n"),
+            (b"vRfY fred\r\n", b"VRFY fred\r\n"),
+            (b"starttls\r\n", b"STARTTLS\r\n"),
+            (b"auth plain =\r\n", b"AUTH PLAIN =\r\n"),
+            (b"bdat 3 last\r\n", b"BDAT 3 LAST\r\n"),
+        ];
+
+        for (test, expected) in tests {
+            let (rem, parsed) = command(test).unwrap();
+            assert!(rem.is_empty());
+            assert!(command_ref(test).is_ok());
+
+            let mut serialized = Vec::new();
+            parsed.serialize(&mut serialized).unwrap();
+            assert_eq!(&serialized, expected);
+        }
+    }
+
+    #[test]
+    fn test_unknown() {
+        let tests: &[(&[u8], Command)] = &[
+            (
+                b"FOO\r\n",
+                Command::Unknown {
+                    verb: "FOO".into(),
+                    arguments: None,
+                },
+            ),
+            (
+                b"FOO-1 a=b  <c>\r\n",
+                Command::Unknown {
+                    verb: "FOO-1".into(),
+                    arguments: Some("a=b  <c>".into()),
+                },
+            ),
+            (
+                b"XUSR a=b\r\n",
+                Command::Extension {
+                    verb: "XUSR".into(),
+                    arguments: Some("a=b".into()),
+                },
+            ),
+            (
+                b"xexch50\r\n",
+                Command::Extension {
+                    verb: "xexch50".into(),
+                    arguments: None,
+                },
+            ),
+        ];
+
+        for (test, expected) in tests {
+            let (rem, parsed) = command(test).unwrap();
+            assert!(rem.is_empty());
+            assert_eq!(&parsed, expected);
+            assert_eq!(command_ref(test).unwrap().1.to_owned(), *expected);
+
+            let mut serialized = Vec::new();
+            parsed.serialize(&mut serialized).unwrap();
+            assert_eq!(&serialized, test);
+        }
+
+        // Known verbs with invalid arguments remain errors.
+        for test in [&b"MAIL FROM:a@example.org\r\n"[..], b"quit now\r\n"] {
+            assert!(command(test).is_err());
+        }
+        assert!(command(b"FOO").unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn test_xclient() {
+        let tests: &[(&[u8], Command)] = &[
+            (
+                b"XCLIENT NAME=spike.porcupine.org ADDR=168.100.189.2 LOGIN=[UNAVAILABLE]\r\n",
+                Command::Xclient {
+                    attributes: vec![
+                        Attribute::new(AttributeName::Name, "spike.porcupine.org"),
+                        Attribute::new(AttributeName::Addr, "168.100.189.2"),
+                        Attribute::unavailable(AttributeName::Login),
+                    ],
+                },
+            ),
+            (
+                b"XFORWARD IDENT=a+2Bb SOURCE=REMOTE X-OTHER=1\r\n",
+                Command::Xforward {
+                    attributes: vec![
+                        Attribute::new(AttributeName::Ident, "a+b"),
+                        Attribute::new(AttributeName::Source, "REMOTE"),
+                        Attribute::new(AttributeName::Other("X-OTHER".into()), "1"),
+                    ],
+                },
+            ),
+        ];
+
+        for (test, expected) in tests {
+            let (rem, parsed) = command(test).unwrap();
+            assert!(rem.is_empty());
+            assert_eq!(&parsed, expected);
+            assert_eq!(command_ref(test).unwrap().1.to_owned(), *expected);
+
+            let mut serialized = Vec::new();
+            parsed.serialize(&mut serialized).unwrap();
+            assert_eq!(&serialized, test);
+        }
+
+        assert_eq!(
+            command(b"xclient reverse_name=example.org\r\n").unwrap().1,
+            Command::Xclient {
+                attributes: vec![Attribute::new(AttributeName::ReverseName, "example.org")],
+            }
+        );
+        for test in [
+            &b"XCLIENT\r\n"[..],
+            b"XCLIENT NAME\r\n",
+            b"XFORWARD IDENT=a+2x\r\n",
+        ] {
+            assert!(command(test).is_err());
+            assert_eq!(
+                command_error(test, command(test).unwrap_err(), &Limits::default()),
+                SmtpParseError::Syntax
+            );
+        }
     }
 }
@@ -0,0 +1,152 @@
+//! Repairs of the violations accepted by [`ParseMode::Lenient`](crate::ParseMode::Lenient)
+
+use alloc::{string::String, vec::Vec};
+
+use crate::Violation;
+
+/// Rewrite a command line, which ends with LF, into one that the strict parsers accept.
+///
+/// Returns the repaired line, which ends with CRLF, and the violations that were repaired.
+pub fn repair_command(line: &[u8], smtputf8: bool) -> (Vec<u8>, Vec<Violation>) {
+    let mut violations = Vec::new();
+
+    let mut line = match line.strip_suffix(b"\r\n") {
+        Some(line) => line,
+        None => {
+            violations.push(Violation::BareLf);
+            line.strip_suffix(b"\n").unwrap_or(line)
+        }
+    };
+
+    let trimmed = line
+        .iter()
+        .rposition(|byte| !matches!(byte, b' ' | b'\t'))
+        .map_or(0, |position| position + 1);
+    if trimmed < line.len() {
+        violations.push(Violation::TrailingWhitespace);
+        line = &line[..trimmed];
+    }
+
+    let mut repaired = Vec::with_capacity(line.len() + 4);
+    let prefix = [&b"MAIL FROM:"[..], &b"RCPT TO:"[..]]
+        .into_iter()
+        .find(|prefix| {
+            line.get(..prefix.len())
+                .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+        });
+    match prefix {
+        Some(prefix) => {
+            let (start, mut rest) = line.split_at(prefix.len());
+            repaired.extend_from_slice(start);
+
+            let spaces = rest.iter().take_while(|byte| **byte == b' ').count();
+            if spaces > 0 {
+                violations.push(Violation::SpaceAfterColon);
+                rest = &rest[spaces..];
+            }
+
+            let end = rest
+                .iter()
+                .position(|byte| *byte == b' ')
+                .unwrap_or(rest.len());
+            if end > 0 && !rest.starts_with(b"<") {
+                violations.push(Violation::UnbracketedPath);
+                repaired.push(b'<');
+                repaired.extend_from_slice(&rest[..end]);
+                repaired.push(b'>');
+                rest = &rest[end..];
+            }
+            repaired.extend_from_slice(rest);
+        }
+        None => repaired.extend_from_slice(line),
+    }
+    repaired.extend_from_slice(b"\r\n");
+
+    match core::str::from_utf8(&repaired) {
+        Ok(_) if smtputf8 || repaired.is_ascii() => {}
+        Ok(_) => violations.push(Violation::EightBit),
+        Err(_) => {
+            violations.push(Violation::EightBit);
+            repaired = String::from_utf8_lossy(&repaired).into_owned().into_bytes();
+        }
+    }
+
+    (repaired, violations)
+}
+
+/// Rewrite the lines of a reply, which end with LF, into ones that the strict parsers accept.
+///
+/// Returns the repaired reply, the length of the input it was repaired from, and the violations
+/// that were repaired, or `None` if the final line was not received yet. 8-bit octets that are
+/// not valid UTF-8 are replaced with U+FFFD.
+pub fn repair_reply(input: &[u8], smtputf8: bool) -> Option<(Vec<u8>, usize, Vec<Violation>)> {
+    let mut repaired = Vec::with_capacity(input.len());
+    let mut violations = Vec::new();
+    let mut consumed = 0;
+
+    loop {
+        let end = consumed + memchr::memchr(b'\n', &input[consumed..])? + 1;
+        let line = &input[consumed..end];
+        consumed = end;
+
+        let text = match line.strip_suffix(b"\r\n") {
+            Some(text) => text,
+            None => {
+                violations.push(Violation::BareLf);
+                line.strip_suffix(b"\n").unwrap_or(line)
+            }
+        };
+        if !smtputf8 && !text.is_ascii() {
+            violations.push(Violation::EightBit);
+        }
+        repaired.extend_from_slice(String::from_utf8_lossy(text).as_bytes());
+        repaired.extend_from_slice(b"\r\n");
+
+        // Reply-code "-" marks all but the last line.
+        if line.get(3) != Some(&b'-') {
+            if crate::parse::response::has_mismatched_codes(&repaired) {
+                violations.push(Violation::MismatchedReplyCodes);
+            }
+            violations.dedup();
+            return Some((repaired, consumed, violations));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_repair_command() {
+        let tests: &[(&[u8], &[u8], &[Violation])] = &[
+            (b"NOOP\r\n", b"NOOP\r\n", &[]),
+            (
+                b"QUIT \t\n",
+                b"QUIT\r\n",
+                &[Violation::BareLf, Violation::TrailingWhitespace],
+            ),
+            (
+                b"MAIL FROM:  a@example.org SIZE=10\r\n",
+                b"MAIL FROM:<a@example.org> SIZE=10\r\n",
+                &[Violation::SpaceAfterColon, Violation::UnbracketedPath],
+            ),
+            (
+                b"rcpt to: <b@example.org>\r\n",
+                b"rcpt to:<b@example.org>\r\n",
+                &[Violation::SpaceAfterColon],
+            ),
+            (
+                b"VRFY M\xfcller\r\n",
+                "VRFY M\u{fffd}ller\r\n".as_bytes(),
+                &[Violation::EightBit],
+            ),
+        ];
+
+        for (line, expected, violations) in tests {
+            let (repaired, found) = repair_command(line, false);
+            assert_eq!(&repaired, expected);
+            assert_eq!(&found, violations);
+        }
+    }
+}
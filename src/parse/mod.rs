@@ -1,4 +1,5 @@
-use std::{borrow::Cow, str::from_utf8};
+use alloc::{borrow::Cow, format, string::String, vec::Vec};
+use core::str::from_utf8;
 
 use nom::{
     branch::alt,
@@ -6,8 +7,9 @@ use nom::{
     character::streaming::digit1,
     character::{is_alphabetic, is_digit},
     combinator::{map, map_res, opt, recognize},
-    multi::{many0, separated_list1},
-    sequence::{delimited, tuple},
+    error::Error,
+    multi::many0_count,
+    sequence::{delimited, pair, tuple},
     IResult,
 };
 
@@ -15,6 +17,7 @@ use super::AtomOrQuoted;
 
 pub mod address;
 pub mod command;
+pub mod lenient;
 pub mod response;
 
 pub fn base64(input: &[u8]) -> IResult<&[u8], &str> {
@@ -39,6 +42,36 @@ pub fn number(input: &[u8]) -> IResult<&[u8], u32> {
     map_res(map_res(digit1, from_utf8), str::parse::<u32>)(input) // FIXME(perf): use from_utf8_unchecked
 }
 
+/// Apply a streaming parser to input that is known to be complete.
+///
+/// A missing CRLF at the end of the input is tolerated, so that, e.g., `b"NOOP"` is parsed like
+/// `b"NOOP\r\n"`. Errors of the retry refer to the original input.
+pub(crate) fn complete<O, F>(parser: F, input: &[u8]) -> IResult<&[u8], O>
+where
+    F: Fn(&[u8]) -> IResult<&[u8], O>,
+{
+    match parser(input) {
+        Err(nom::Err::Incomplete(_)) if !input.ends_with(b"\r\n") => {
+            let mut terminated = input.to_vec();
+            terminated.extend_from_slice(b"\r\n");
+
+            match parser(&terminated) {
+                // The output is owned, only the remaining input must be mapped back.
+                Ok((remaining, parsed)) => {
+                    let consumed = terminated.len() - remaining.len();
+                    Ok((&input[consumed.min(input.len())..], parsed))
+                }
+                Err(nom::Err::Incomplete(needed)) => Err(nom::Err::Incomplete(needed)),
+                Err(nom::Err::Error(error)) => Err(nom::Err::Error(Error::new(input, error.code))),
+                Err(nom::Err::Failure(error)) => {
+                    Err(nom::Err::Failure(Error::new(input, error.code)))
+                }
+            }
+        }
+        result => result,
+    }
+}
+
 // -------------------------------------------------------------------------------------------------
 
 /// String = Atom / Quoted-string
@@ -51,7 +84,7 @@ pub fn string(input: &[u8]) -> IResult<&[u8], AtomOrQuoted> {
 
 /// Atom = 1*atext
 pub fn atom(input: &[u8]) -> IResult<&[u8], &str> {
-    map_res(take_while1(is_atext), std::str::from_utf8)(input)
+    map_res(take_while1(is_atext), core::str::from_utf8)(input)
 }
 
 /// Printable US-ASCII characters not including specials.
@@ -68,21 +101,33 @@ pub fn atom(input: &[u8]) -> IResult<&[u8], &str> {
 ///          "`" / "{" /
 ///          "|" / "}" /
 ///          "~"
+///
+/// atext =/ UTF8-non-ascii (RFC 6531)
 pub fn is_atext(byte: u8) -> bool {
     let allowed = b"!#$%&'*+-/=?^_`{|}~";
 
-    is_alphabetic(byte) || is_digit(byte) || allowed.contains(&byte)
+    is_alphabetic(byte) || is_digit(byte) || allowed.contains(&byte) || is_utf8_non_ascii(byte)
+}
+
+/// Any byte of a non-ASCII UTF-8 character. The sequence is validated by the caller when it is
+/// converted to `str`.
+///
+/// UTF8-non-ascii = UTF8-2 / UTF8-3 / UTF8-4 (RFC 6532)
+pub fn is_utf8_non_ascii(byte: u8) -> bool {
+    byte >= 0x80
 }
 
 /// Quoted-string = DQUOTE *QcontentSMTP DQUOTE
 pub fn quoted_string(input: &[u8]) -> IResult<&[u8], Cow<'_, str>> {
-    map(
-        delimited(
-            tag("\""),
-            map_res(recognize(many0(q_content_smtp)), std::str::from_utf8),
-            tag("\""),
-        ),
-        unescape_quoted,
+    map(quoted_string_raw, unescape_quoted)(input)
+}
+
+/// Like [`quoted_string`], but returns the content without unescaping it.
+pub fn quoted_string_raw(input: &[u8]) -> IResult<&[u8], &str> {
+    delimited(
+        tag("\""),
+        map_res(recognize(many0_count(q_content_smtp)), core::str::from_utf8),
+        tag("\""),
     )(input)
 }
 
@@ -99,23 +144,21 @@ pub fn q_content_smtp(input: &[u8]) -> IResult<&[u8], &[u8]> {
 /// without blackslash-quoting except double-quote and the backslash itself.
 ///
 /// qtextSMTP = %d32-33 / %d35-91 / %d93-126
+///
+/// qtextSMTP =/ UTF8-non-ascii (RFC 6531)
 pub fn is_qtext_smtp(byte: u8) -> bool {
-    matches!(byte, 32..=33 | 35..=91 | 93..=126)
+    matches!(byte, 32..=33 | 35..=91 | 93..=126) || is_utf8_non_ascii(byte)
 }
 
 /// Backslash followed by any ASCII graphic (including itself) or SPace
 ///
 /// quoted-pairSMTP = %d92 %d32-126
 ///
-/// FIXME: How should e.g. "\a" be interpreted?
+/// The pair stands for the character following the backslash, e.g., "\a" for "a", see
+/// [`unescape_quoted`].
 pub fn quoted_pair_smtp(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    //fn is_value(byte: u8) -> bool {
-    //    matches!(byte, 32..=126)
-    //}
-
-    // FIXME: Only allow "\\" and "\"" for now ...
     fn is_value(byte: u8) -> bool {
-        byte == b'\\' || byte == b'\"'
+        matches!(byte, 32..=126)
     }
 
     let parser = tuple((tag("\\"), take_while_m_n(1, 1, is_value)));
@@ -129,9 +172,9 @@ pub fn quoted_pair_smtp(input: &[u8]) -> IResult<&[u8], &[u8]> {
 
 /// Domain = sub-domain *("." sub-domain)
 pub fn domain(input: &[u8]) -> IResult<&[u8], &str> {
-    let parser = separated_list1(tag(b"."), sub_domain);
+    let parser = pair(sub_domain, many0_count(pair(tag(b"."), sub_domain)));
 
-    let (remaining, parsed) = map_res(recognize(parser), std::str::from_utf8)(input)?;
+    let (remaining, parsed) = map_res(recognize(parser), core::str::from_utf8)(input)?;
 
     Ok((remaining, parsed))
 }
@@ -145,6 +188,32 @@ pub fn sub_domain(input: &[u8]) -> IResult<&[u8], &[u8]> {
     Ok((remaining, parsed))
 }
 
+/// Domain of a mailbox, which may contain U-labels
+///
+/// sub-domain =/ U-label (RFC 6531)
+pub fn u_domain(input: &[u8]) -> IResult<&[u8], &str> {
+    let label = || alt((u_label, sub_domain));
+    let parser = pair(label(), many0_count(pair(tag(b"."), label())));
+
+    map_res(recognize(parser), core::str::from_utf8)(input)
+}
+
+/// A label with at least one non-ASCII character. The label is not checked against IDNA2008
+/// (RFC 5890) beyond being UTF-8.
+pub fn u_label(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    fn is_ldh(byte: u8) -> bool {
+        is_let_dig(byte) || byte == b'-'
+    }
+
+    let parser = tuple((
+        take_while(is_ldh),
+        take_while_m_n(1, 1, is_utf8_non_ascii),
+        take_while(|byte| is_ldh(byte) || is_utf8_non_ascii(byte)),
+    ));
+
+    recognize(parser)(input)
+}
+
 /// Let-dig = ALPHA / DIGIT
 pub fn is_let_dig(byte: u8) -> bool {
     is_alphabetic(byte) || is_digit(byte)
@@ -152,7 +221,7 @@ pub fn is_let_dig(byte: u8) -> bool {
 
 /// Ldh-str = *( ALPHA / DIGIT / "-" ) Let-dig
 pub fn ldh_str(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    let parser = many0(alt((
+    let parser = many0_count(alt((
         take_while_m_n(1, 1, is_alphabetic),
         take_while_m_n(1, 1, is_digit),
         recognize(tuple((tag(b"-"), take_while_m_n(1, 1, is_let_dig)))),
@@ -165,7 +234,9 @@ pub fn ldh_str(input: &[u8]) -> IResult<&[u8], &[u8]> {
 
 // -------------------------------------------------------------------------------------------------
 
-pub(crate) fn escape_quoted(unescaped: &str) -> Cow<str> {
+/// Escape the content of a Quoted-string. Only "\\" and "\"" are escaped, as the other characters
+/// of quoted-pairSMTP are allowed as qtextSMTP.
+pub(crate) fn escape_quoted(unescaped: &str) -> Cow<'_, str> {
     let mut escaped = Cow::Borrowed(unescaped);
 
     if escaped.contains('\\') {
@@ -179,23 +250,90 @@ pub(crate) fn escape_quoted(unescaped: &str) -> Cow<str> {
     escaped
 }
 
-pub(crate) fn unescape_quoted(escaped: &str) -> Cow<str> {
-    let mut unescaped = Cow::Borrowed(escaped);
+/// Unescape the content of a Quoted-string, i.e., drop the backslash of each quoted-pairSMTP.
+pub(crate) fn unescape_quoted(escaped: &str) -> Cow<'_, str> {
+    if !escaped.contains('\\') {
+        return Cow::Borrowed(escaped);
+    }
 
-    if unescaped.contains("\\\\") {
-        unescaped = Cow::Owned(unescaped.replace("\\\\", "\\"));
+    let mut unescaped = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
     }
 
-    if unescaped.contains("\\\"") {
-        unescaped = Cow::Owned(unescaped.replace("\\\"", "\""));
+    Cow::Owned(unescaped)
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// xtext = *( xchar / hexchar ) (RFC 3461, 4.)
+///
+/// xchar   = any ASCII CHAR between "!" (33) and "~" (126) inclusive, except for "+" and "=".
+/// hexchar = ASCII "+" immediately followed by two upper case hexadecimal digits
+pub(crate) fn encode_xtext(decoded: &str) -> Cow<'_, str> {
+    fn is_xchar(byte: u8) -> bool {
+        matches!(byte, 33..=126) && byte != b'+' && byte != b'='
+    }
+
+    if decoded.bytes().all(is_xchar) {
+        return Cow::Borrowed(decoded);
+    }
+
+    let mut encoded = String::with_capacity(decoded.len() + 8);
+    for byte in decoded.bytes() {
+        match is_xchar(byte) {
+            true => encoded.push(byte as char),
+            false => encoded.push_str(&format!("+{:02X}", byte)),
+        }
+    }
+
+    Cow::Owned(encoded)
+}
+
+/// Decode xtext, see [`encode_xtext`].
+///
+/// Returns `None` when a "+" is not followed by two upper case hexadecimal digits, or when the
+/// decoded value is not valid UTF-8.
+pub(crate) fn decode_xtext(encoded: &str) -> Option<Cow<'_, str>> {
+    if !encoded.contains('+') {
+        return Some(Cow::Borrowed(encoded));
+    }
+
+    fn hex(byte: u8) -> Option<u8> {
+        match byte {
+            b'0'..=b'9' => Some(byte - b'0'),
+            b'A'..=b'F' => Some(byte - b'A' + 10),
+            _ => None,
+        }
+    }
+
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut bytes = encoded.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'+' => {
+                let high = hex(bytes.next()?)?;
+                let low = hex(bytes.next()?)?;
+                decoded.push(high << 4 | low);
+            }
+            _ => decoded.push(byte),
+        }
     }
 
-    unescaped
+    String::from_utf8(decoded).ok().map(Cow::Owned)
 }
 
 #[cfg(test)]
 pub mod test {
-    use super::sub_domain;
+    use super::{
+        command::command, complete, decode_xtext, encode_xtext, escape_quoted, quoted_string,
+        sub_domain,
+    };
+    use crate::Command;
 
     #[test]
     fn test_subdomain() {
@@ -203,4 +341,69 @@ pub mod test {
         assert_eq!(parsed, b"example");
         assert_eq!(rem, b"???");
     }
+
+    #[test]
+    fn test_quoted_string() {
+        let tests: [(&[u8], &str); 5] = [
+            (b"\"a b\"", "a b"),
+            (b"\"a\\ b\"", "a b"),
+            (b"\"\\a\\\\\\\"\"", "a\\\""),
+            (b"\"\\\\\\\"\"", "\\\""),
+            (b"\"\"", ""),
+        ];
+
+        for (input, expected) in tests {
+            let (_, parsed) = quoted_string(input).unwrap();
+            assert_eq!(parsed, expected);
+
+            let serialized = format!("\"{}\"", escape_quoted(&parsed));
+            assert_eq!(quoted_string(serialized.as_bytes()).unwrap().1, parsed);
+        }
+
+        // Only ASCII graphics and space may be escaped.
+        assert!(quoted_string(b"\"a\\\tb\"").is_err());
+
+        let input = b"RCPT TO:<\"a\\ b\"@example.org>\r\n";
+        let (_, parsed) = command(input).unwrap();
+        let mut serialized = Vec::new();
+        parsed.serialize(&mut serialized).unwrap();
+        assert_eq!(serialized, input);
+    }
+
+    #[test]
+    fn test_xtext() {
+        let tests = [
+            ("QQ314159", "QQ314159"),
+            ("a+b=c", "a+2Bb+3Dc"),
+            (
+                "Joe Smith <joe@example.org>",
+                "Joe+20Smith+20<joe@example.org>",
+            ),
+            ("\u{e4}", "+C3+A4"),
+        ];
+
+        for (decoded, encoded) in tests {
+            assert_eq!(encode_xtext(decoded), encoded);
+            assert_eq!(decode_xtext(encoded).unwrap(), decoded);
+        }
+
+        assert_eq!(decode_xtext("a+2b"), None);
+        assert_eq!(decode_xtext("a+2"), None);
+        assert_eq!(decode_xtext("+FF"), None);
+    }
+
+    #[test]
+    fn test_complete() {
+        assert!(command(b"NOOP").is_err());
+        assert_eq!(
+            complete(command, b"NOOP"),
+            Ok((&b""[..], Command::Noop { argument: None }))
+        );
+        assert_eq!(
+            complete(command, b"RSET\r\nQUIT"),
+            Ok((&b"QUIT"[..], Command::Rset))
+        );
+        assert!(complete(command, b"MAIL FROM:<a@exa").is_err());
+        assert!(complete(command, b"MAIL FROM:<a@example.org>\r").is_err());
+    }
 }
@@ -1,20 +1,38 @@
-use std::str::FromStr;
+use alloc::{
+    borrow::ToOwned,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::str::FromStr;
 
 use nom::{
     branch::alt,
     bytes::streaming::{tag, tag_no_case, take_while, take_while1, take_while_m_n},
     character::{is_alphabetic, is_digit},
-    combinator::{map, map_res, opt, recognize, value},
-    multi::{many0, separated_list0},
-    sequence::{delimited, preceded, tuple},
+    combinator::{consumed, map, map_res, opt, recognize, value},
+    multi::{many0, many0_count, separated_list0},
+    sequence::{delimited, preceded, terminated, tuple},
     IResult,
 };
 
 use crate::{
-    parse::{address::address_literal, domain, number},
-    {AuthMechanism, Capability, ReplyCode, Response, TextString},
+    parse::{address::address_literal, domain, is_utf8_non_ascii, number},
+    {
+        AuthMechanism, Capability, EhloResponse, EnhancedStatusCode, Greeting, ReplyCode, Response,
+        ResponseKind, ResponseRef, TextString,
+    },
 };
 
+/// A reply of the given kind, or any reply when it does not match the grammar of the kind.
+pub fn response(kind: ResponseKind, input: &[u8]) -> IResult<&[u8], Response> {
+    match kind {
+        ResponseKind::Greeting => alt((greeting, reply_lines))(input),
+        ResponseKind::Ehlo => alt((ehlo_ok_rsp, reply_lines))(input),
+        ResponseKind::Other => reply_lines(input),
+    }
+}
+
 /// Greeting = ( "220 " (Domain / address-literal) [ SP textstring ] CRLF ) /
 ///            ( "220-" (Domain / address-literal) [ SP textstring ] CRLF
 ///           *( "220-" [ textstring ] CRLF )
@@ -24,7 +42,10 @@ pub fn greeting(input: &[u8]) -> IResult<&[u8], Response> {
         map(
             tuple((
                 tag(b"220 "),
-                alt((domain, address_literal)),
+                alt((
+                    domain,
+                    map_res(recognize(address_literal), core::str::from_utf8),
+                )),
                 opt(preceded(tag(" "), textstring)),
                 tag("\r\n"),
             )),
@@ -38,7 +59,10 @@ pub fn greeting(input: &[u8]) -> IResult<&[u8], Response> {
         map(
             tuple((
                 tag(b"220-"),
-                alt((domain, address_literal)),
+                alt((
+                    domain,
+                    map_res(recognize(address_literal), core::str::from_utf8),
+                )),
                 opt(preceded(tag(" "), textstring)),
                 tag("\r\n"),
                 many0(delimited(tag(b"220-"), opt(textstring), tag("\r\n"))),
@@ -76,12 +100,41 @@ pub fn greeting(input: &[u8]) -> IResult<&[u8], Response> {
     Ok((remaining, parsed))
 }
 
+/// A greeting, or a "554" reply that rejects the connection
+pub fn greeting_or_rejection(input: &[u8]) -> IResult<&[u8], Greeting> {
+    map_res(
+        |input| response(ResponseKind::Greeting, input),
+        Greeting::try_from,
+    )(input)
+}
+
+/// The Domain or address-literal at the start of a reply line, as in a greeting
+pub fn leading_domain(line: &str) -> Option<String> {
+    let word = line.split(' ').next()?;
+    let terminated = format!("{}\r\n", word);
+
+    let (remaining, domain) = alt((
+        domain,
+        map_res(recognize(address_literal), core::str::from_utf8),
+    ))(terminated.as_bytes())
+    .ok()?;
+    match remaining == b"\r\n" {
+        true => Some(domain.to_owned()),
+        false => None,
+    }
+}
+
 /// HT, tag(" "), Printable US-ASCII
 ///
 /// textstring = 1*(%d09 / %d32-126)
+///
+/// Edit: UTF-8 is accepted, as sent with SMTPUTF8 (RFC 6531, 3.7.4.), and rejected later
+/// otherwise, see [`Response::parse_with`].
 pub fn textstring(input: &[u8]) -> IResult<&[u8], TextString<'_>> {
-    let (remaining, parsed) =
-        map_res(take_while1(is_text_string_byte), std::str::from_utf8)(input)?;
+    let (remaining, parsed) = map_res(
+        take_while1(|byte| is_text_string_byte(byte) || is_utf8_non_ascii(byte)),
+        core::str::from_utf8,
+    )(input)?;
 
     Ok((remaining, TextString(parsed.into())))
 }
@@ -94,23 +147,25 @@ pub(crate) fn is_text_string_byte(byte: u8) -> bool {
 
 /// Reply-line = *( Reply-code "-" [ textstring ] CRLF )
 ///                 Reply-code [ SP textstring ] CRLF
+///
+/// Edit: SP without textstring is accepted, e.g., for an empty challenge "334 " (RFC 4954)
 pub fn reply_lines(input: &[u8]) -> IResult<&[u8], Response> {
     let mut parser = map(
         tuple((
             many0(tuple((reply_code, tag(b"-"), opt(textstring), tag("\r\n")))),
             reply_code,
-            opt(tuple((tag(" "), textstring))),
+            opt(preceded(tag(" "), opt(textstring))),
             tag("\r\n"),
         )),
         |(intermediate, code, text, _)| {
-            let mut lines = Vec::with_capacity(intermediate.len() + usize::from(text.is_some()));
+            let mut lines = Vec::with_capacity(intermediate.len() + 1);
             for (_, _, text, _) in intermediate {
                 if let Some(line) = text {
                     lines.push(line.into_owned());
                 }
             }
 
-            if let Some((_, line)) = text {
+            if let Some(Some(line)) = text {
                 lines.push(line.into_owned());
             }
 
@@ -123,6 +178,39 @@ pub fn reply_lines(input: &[u8]) -> IResult<&[u8], Response> {
     Ok((remaining, parsed))
 }
 
+/// Like [`reply_lines`], but borrows the lines from the input.
+pub fn reply_lines_ref(input: &[u8]) -> IResult<&[u8], ResponseRef<'_>> {
+    let mut parser = consumed(tuple((
+        many0_count(tuple((reply_code, tag(b"-"), opt(textstring), tag("\r\n")))),
+        reply_code,
+        opt(preceded(tag(" "), opt(textstring))),
+        tag("\r\n"),
+    )));
+
+    let (remaining, (raw, (_, code, _, _))) = parser(input)?;
+
+    Ok((
+        remaining,
+        ResponseRef {
+            code,
+            // Reply codes are ASCII, and textstrings are UTF-8.
+            raw: core::str::from_utf8(raw).unwrap(),
+        },
+    ))
+}
+
+/// Whether the lines of a reply have different codes, e.g., "250-" followed by "550 "
+///
+/// All lines must have the same code (RFC 5321, 4.2.1.).
+pub fn has_mismatched_codes(reply: &[u8]) -> bool {
+    let mut codes = reply
+        .split(|byte| *byte == b'\n')
+        .filter_map(|line| line.get(..3));
+    let first = codes.next();
+
+    codes.any(|code| Some(code) != first)
+}
+
 /// Reply-code = %x32-35 %x30-35 %x30-39
 ///
 ///   2345
@@ -133,12 +221,43 @@ pub fn reply_code(input: &[u8]) -> IResult<&[u8], ReplyCode> {
     map_res(
         map_res(
             take_while_m_n(3, 3, nom::character::is_digit),
-            std::str::from_utf8,
+            core::str::from_utf8,
         ),
         |s| u16::from_str(s).map(ReplyCode::from),
     )(input)
 }
 
+/// status-code = class "." subject "." detail (RFC 3463)
+///
+/// The code must be followed by SP or the end of the reply line. As reply lines are complete
+/// when this is applied, the end of input is not treated as incomplete.
+pub fn enhanced_status_code(input: &[u8]) -> IResult<&[u8], EnhancedStatusCode> {
+    use nom::{
+        bytes::complete::{tag, take_while_m_n},
+        character::complete::one_of,
+        combinator::eof,
+    };
+
+    fn digits(input: &[u8]) -> IResult<&[u8], u16> {
+        map_res(
+            map_res(take_while_m_n(1, 3, is_digit), core::str::from_utf8),
+            u16::from_str,
+        )(input)
+    }
+
+    let class = map(one_of("245"), |class| class as u8 - b'0');
+
+    let mut parser = terminated(
+        map(
+            tuple((class, tag(b"."), digits, tag(b"."), digits)),
+            |(class, _, subject, _, detail)| EnhancedStatusCode::new(class, subject, detail),
+        ),
+        alt((map(tag(b" "), |_| ()), map(eof, |_| ()))),
+    );
+
+    parser(input)
+}
+
 // -------------------------------------------------------------------------------------------------
 
 /// ehlo-ok-rsp = ( "250 " Domain [ SP ehlo-greet ] CRLF ) /
@@ -189,15 +308,21 @@ pub fn ehlo_ok_rsp(input: &[u8]) -> IResult<&[u8], Response> {
     Ok((remaining, parsed))
 }
 
+pub fn ehlo_response(input: &[u8]) -> IResult<&[u8], EhloResponse> {
+    map_res(ehlo_ok_rsp, EhloResponse::try_from)(input)
+}
+
 /// String of any characters other than CR or LF.
 ///
 /// ehlo-greet = 1*(%d0-9 / %d11-12 / %d14-127)
+///
+/// Edit: UTF-8 is accepted as in [`textstring`].
 pub fn ehlo_greet(input: &[u8]) -> IResult<&[u8], &str> {
     fn is_valid_character(byte: u8) -> bool {
-        matches!(byte, 0..=9 | 11..=12 | 14..=127)
+        matches!(byte, 0..=9 | 11..=12 | 14..=127) || is_utf8_non_ascii(byte)
     }
 
-    map_res(take_while1(is_valid_character), std::str::from_utf8)(input)
+    map_res(take_while1(is_valid_character), core::str::from_utf8)(input)
 }
 
 /// ehlo-line = ehlo-keyword *( SP ehlo-param )
@@ -211,22 +336,35 @@ pub fn ehlo_line(input: &[u8]) -> IResult<&[u8], Capability> {
     ));
 
     let other = tuple((
-        map_res(ehlo_keyword, std::str::from_utf8),
+        map_res(ehlo_keyword, core::str::from_utf8),
         opt(preceded(
             alt((tag(" "), tag("="))), // TODO: For Outlook?
             separated_list0(tag(" "), ehlo_param),
         )),
     ));
 
-    alt((
+    let mt_priority = preceded(
+        tag_no_case("MT-PRIORITY"),
+        opt(preceded(tag(" "), ehlo_param)),
+    );
+
+    // Split, as `alt` takes at most 21 parsers.
+    let known = alt((
         value(Capability::Expn, tag_no_case("EXPN")),
         value(Capability::Help, tag_no_case("HELP")),
         value(Capability::EightBitMime, tag_no_case("8BITMIME")),
-        map(preceded(tag_no_case("SIZE "), number), Capability::Size),
+        // A missing size means that no fixed maximum is declared. (See RFC 1870, 4.)
+        map(
+            preceded(tag_no_case("SIZE"), opt(preceded(tag(" "), number))),
+            |size| Capability::Size(size.unwrap_or(0)),
+        ),
         value(Capability::Chunking, tag_no_case("CHUNKING")),
         value(Capability::BinaryMime, tag_no_case("BINARYMIME")),
         value(Capability::Checkpoint, tag_no_case("CHECKPOINT")),
-        value(Capability::DeliverBy, tag_no_case("DELIVERBY")),
+        map(
+            preceded(tag_no_case("DELIVERBY"), opt(preceded(tag(" "), number))),
+            |time| Capability::DeliverBy(time.unwrap_or(0)),
+        ),
         value(Capability::Pipelining, tag_no_case("PIPELINING")),
         value(Capability::Dsn, tag_no_case("DSN")),
         value(Capability::Etrn, tag_no_case("ETRN")),
@@ -238,13 +376,19 @@ pub fn ehlo_line(input: &[u8]) -> IResult<&[u8], Capability> {
         // FIXME: NO-SOLICITING
         value(Capability::Mtrk, tag_no_case("MTRK")),
         value(Capability::Atrn, tag_no_case("ATRN")),
+    ));
+
+    alt((
+        known,
         map(auth, |(_, _, mechanisms)| Capability::Auth(mechanisms)),
         value(Capability::Burl, tag_no_case("BURL")),
         // FIXME: FUTURERELEASE
         // FIXME: CONPERM
         // FIXME: CONNEG
         value(Capability::SmtpUtf8, tag_no_case("SMTPUTF8")),
-        // FIXME: MT-PRIORITY
+        map(mt_priority, |profile| {
+            Capability::MtPriority(profile.map(String::from))
+        }),
         value(Capability::Rrvs, tag_no_case("RRVS")),
         value(Capability::RequireTls, tag_no_case("REQUIRETLS")),
         map(other, |(keyword, params)| Capability::Other {
@@ -279,7 +423,7 @@ pub fn ehlo_param(input: &[u8]) -> IResult<&[u8], &str> {
         matches!(byte, 33..=126)
     }
 
-    map_res(take_while1(is_valid_character), std::str::from_utf8)(input)
+    map_res(take_while1(is_valid_character), core::str::from_utf8)(input)
 }
 
 pub fn auth_mechanism(input: &[u8]) -> IResult<&[u8], AuthMechanism> {
@@ -290,6 +434,7 @@ pub fn auth_mechanism(input: &[u8]) -> IResult<&[u8], AuthMechanism> {
         value(AuthMechanism::CramSha1, tag_no_case("CRAM-SHA1")),
         value(AuthMechanism::DigestMd5, tag_no_case("DIGEST-MD5")),
         value(AuthMechanism::ScramMd5, tag_no_case("SCRAM-MD5")),
+        value(AuthMechanism::ScramSha256, tag_no_case("SCRAM-SHA-256")),
         value(AuthMechanism::GssApi, tag_no_case("GSSAPI")),
         value(AuthMechanism::Ntlm, tag_no_case("NTLM")),
         map(ehlo_param, |param| AuthMechanism::Other(param.to_string())),
@@ -301,7 +446,7 @@ pub fn auth_mechanism(input: &[u8]) -> IResult<&[u8], AuthMechanism> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::AuthMechanism;
+    use crate::{AuthMechanism, Capabilities};
 
     #[test]
     fn test_greeting() {
@@ -364,5 +509,125 @@ and/or bulk e-mail."
         let (rem, capability) = ehlo_line(b"SIZE 123456\r\n").unwrap();
         assert_eq!(rem, b"\r\n");
         assert_eq!(capability, Capability::Size(123456));
+
+        let (_, capability) = ehlo_line(b"DELIVERBY 240\r\n").unwrap();
+        assert_eq!(capability, Capability::DeliverBy(240));
+        let (_, capability) = ehlo_line(b"DELIVERBY\r\n").unwrap();
+        assert_eq!(capability, Capability::DeliverBy(0));
+
+        let (_, capability) = ehlo_line(b"MT-PRIORITY MIXER\r\n").unwrap();
+        assert_eq!(capability, Capability::MtPriority(Some("MIXER".into())));
+        let (_, capability) = ehlo_line(b"MT-PRIORITY\r\n").unwrap();
+        assert_eq!(capability, Capability::MtPriority(None));
+    }
+
+    #[test]
+    fn test_ehlo_response() {
+        let (rem, out) = ehlo_response(
+            b"250-example.org\r\n\
+250-AUTH LOGIN\r\n\
+250-AUTH=LOGIN PLAIN\r\n\
+250-SIZE\r\n\
+250-PIPELINING\r\n\
+250-X-UNKNOWN a b\r\n\
+250 STARTTLS\r\n",
+        )
+        .unwrap();
+        assert_eq!(rem, b"");
+        assert_eq!(out.domain, "example.org");
+        assert_eq!(
+            out.capabilities,
+            Capabilities {
+                auth: Some(vec![AuthMechanism::Login, AuthMechanism::Plain]),
+                size: Some(0),
+                pipelining: true,
+                start_tls: true,
+                other: vec![("X-UNKNOWN".into(), vec!["a".into(), "b".into()])],
+                ..Capabilities::default()
+            }
+        );
+        assert!(out.capabilities.supports_auth(&AuthMechanism::Plain));
+    }
+
+    #[test]
+    fn test_enhanced_status_code() {
+        let (rem, code) = enhanced_status_code(b"5.1.1 No such user").unwrap();
+        assert_eq!(rem, b"No such user");
+        assert_eq!(code, EnhancedStatusCode::new(5, 1, 1));
+        assert_eq!(code.to_string(), "5.1.1");
+        assert_eq!(code.description(), Some("Bad destination mailbox address"));
+
+        let (rem, code) = enhanced_status_code(b"2.0.0").unwrap();
+        assert_eq!(rem, b"");
+        assert_eq!(code, EnhancedStatusCode::new(2, 0, 0));
+
+        assert!(enhanced_status_code(b"3.0.0 Go ahead").is_err());
+        assert!(enhanced_status_code(b"5.1.1234").is_err());
+        assert!(enhanced_status_code(b"5.1 x").is_err());
+
+        let (_, response) = reply_lines(b"550-5.7.1 Relaying denied\r\n550 5.7.1 Bye\r\n").unwrap();
+        assert_eq!(
+            response.enhanced_code(),
+            Some(EnhancedStatusCode::new(5, 7, 1))
+        );
+
+        let (_, response) = reply_lines(b"250 5.0.0 Mismatched class\r\n").unwrap();
+        assert_eq!(response.enhanced_code(), None);
+    }
+
+    #[test]
+    fn test_reply_lines() {
+        let tests: &[(&[u8], ReplyCode, &[&str])] = &[
+            // Exchange
+            (
+                b"250 2.6.0 <a@example.org> [InternalId=123, Hostname=EX01] Queued mail for delivery\r\n",
+                ReplyCode::Ok,
+                &["2.6.0 <a@example.org> [InternalId=123, Hostname=EX01] Queued mail for delivery"],
+            ),
+            // qmail
+            (
+                b"451 qq temporary problem (#4.3.0)\r\n",
+                ReplyCode::ProcessingError,
+                &["qq temporary problem (#4.3.0)"],
+            ),
+            (b"354 go ahead\r\n", ReplyCode::StartMailInput, &["go ahead"]),
+            // Exim
+            (
+                b"550-Verification failed for <a@example.org>\r\n550-\r\n550 Sender verify failed\r\n",
+                ReplyCode::MailboxPermanentlyUnavailable,
+                &["Verification failed for <a@example.org>", "Sender verify failed"],
+            ),
+            // No text, a SP without text, and text starting with "-"
+            (b"250\r\n", ReplyCode::Ok, &[]),
+            (b"334 \r\n", ReplyCode::Other(334), &[]),
+            (b"250 -\r\n", ReplyCode::Ok, &["-"]),
+            (b"250- a\r\n250  b\t\r\n", ReplyCode::Ok, &[" a", " b\t"]),
+            // SMTPUTF8
+            (
+                "550 5.1.1 Unbekannter Empfänger\r\n".as_bytes(),
+                ReplyCode::MailboxPermanentlyUnavailable,
+                &["5.1.1 Unbekannter Empfänger"],
+            ),
+        ];
+
+        for (test, code, lines) in tests {
+            let (rem, response) = reply_lines(test).unwrap();
+            assert!(rem.is_empty());
+            assert_eq!(
+                response,
+                Response::Other {
+                    code: *code,
+                    lines: lines
+                        .iter()
+                        .map(|line| TextString(line.to_string().into()))
+                        .collect(),
+                }
+            );
+            assert_eq!(reply_lines_ref(test).unwrap().1.as_str().as_bytes(), *test);
+        }
+
+        assert!(reply_lines(b"250-a\r\n").unwrap_err().is_incomplete());
+        assert!(reply_lines(b"250\x01\r\n").is_err());
+        assert!(reply_lines(b"250 M\xfcller\r\n").is_err());
     }
 }
@@ -0,0 +1,307 @@
+//! Command pipelining (RFC 2920)
+//!
+//! A [`Pipeline`] does not perform any I/O. The caller queues commands with
+//! [`Pipeline::push`], writes the groups returned by [`Pipeline::poll_transmit`] to the
+//! connection, and passes the replies to [`Pipeline::handle`], which returns the command each
+//! reply belongs to.
+//!
+//! RSET, MAIL, and RCPT are sent together with the following commands. Any other command, e.g.,
+//! EHLO, DATA, or QUIT, ends a group: the commands after it are only sent once all replies to
+//! the group were received (RFC 2920, 3.1.). The server must advertise PIPELINING.
+//!
+//! Callers that group commands themselves check the groups with a [`PipelineValidator`].
+
+use alloc::{borrow::ToOwned, collections::VecDeque, string::String, vec::Vec};
+use core::fmt;
+
+use crate::{Command, EncodeError, Response, ResponseKind, SyntaxOptions};
+
+#[derive(Clone, Debug, Default)]
+pub struct Pipeline {
+    options: SyntaxOptions,
+    /// Commands that were not sent yet, with their serialization
+    queued: VecDeque<(Command, Vec<u8>)>,
+    /// Commands that were sent and await a reply, in order
+    in_flight: VecDeque<Command>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_options(options: SyntaxOptions) -> Self {
+        Self {
+            options,
+            ..Self::default()
+        }
+    }
+
+    /// Queue a command.
+    ///
+    /// The command is encoded right away, so that a command that is too long is rejected before
+    /// anything of its group was sent.
+    pub fn push(&mut self, command: Command) -> Result<(), EncodeError> {
+        let encoded = command.encode(self.options)?;
+        self.queued.push_back((command, encoded));
+        Ok(())
+    }
+
+    /// Take the next group of commands that must be sent to the server.
+    ///
+    /// Returns `None` while replies to the previous group are outstanding. After DATA, the
+    /// caller must not push further commands before the message content was sent.
+    pub fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+        if !self.in_flight.is_empty() || self.queued.is_empty() {
+            return None;
+        }
+
+        let mut group = Vec::new();
+        while let Some((command, encoded)) = self.queued.pop_front() {
+            group.extend_from_slice(&encoded);
+
+            let last = !command.is_pipelinable();
+            self.in_flight.push_back(command);
+            if last {
+                break;
+            }
+        }
+
+        Some(group)
+    }
+
+    /// The reply to parse next, or `None` when no reply is expected.
+    pub fn expected(&self) -> Option<ResponseKind> {
+        match self.in_flight.front()? {
+            Command::Ehlo { .. } | Command::Lhlo { .. } => Some(ResponseKind::Ehlo),
+            _ => Some(ResponseKind::Other),
+        }
+    }
+
+    /// Correlate a reply with the command it answers.
+    ///
+    /// Replies arrive in the order of the commands. Returns `None` when no command awaits a
+    /// reply.
+    pub fn handle(&mut self, response: Response) -> Option<(Command, Response)> {
+        let command = self.in_flight.pop_front()?;
+        Some((command, response))
+    }
+
+    /// The number of commands that were sent and await a reply.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Whether all queued commands were sent and answered.
+    pub fn is_idle(&self) -> bool {
+        self.queued.is_empty() && self.in_flight.is_empty()
+    }
+}
+
+/// Checks groups of commands that are sent without waiting for replies
+///
+/// Commands are passed in the order they are written with [`PipelineValidator::check`], and
+/// [`PipelineValidator::end_group`] is called before waiting for the replies.
+#[derive(Clone, Debug, Default)]
+pub struct PipelineValidator {
+    /// Whether the server advertised PIPELINING
+    pipelining: bool,
+    /// The command of the current group that may only be the last one, e.g., DATA
+    ended_by: Option<String>,
+    /// Whether the current group contains a command
+    started: bool,
+}
+
+impl PipelineValidator {
+    /// Without PIPELINING, every command is a group of its own.
+    pub fn new(pipelining: bool) -> Self {
+        Self {
+            pipelining,
+            ..Self::default()
+        }
+    }
+
+    /// Check the next command of the current group.
+    pub fn check(&mut self, command: &Command) -> Result<(), PipelineError> {
+        if let Some(ended_by) = &self.ended_by {
+            return Err(PipelineError::AfterGroupEnd {
+                ended_by: ended_by.clone(),
+                command: command.name().to_owned(),
+            });
+        }
+        if self.started && !self.pipelining {
+            return Err(PipelineError::NotAdvertised);
+        }
+
+        self.started = true;
+        if !command.is_pipelinable() {
+            self.ended_by = Some(command.name().to_owned());
+        }
+
+        Ok(())
+    }
+
+    /// Start a new group, after all replies to the current one were received.
+    pub fn end_group(&mut self) {
+        self.ended_by = None;
+        self.started = false;
+    }
+
+    /// Check a complete group.
+    pub fn validate(&mut self, group: &[Command]) -> Result<(), PipelineError> {
+        self.end_group();
+        let result = group.iter().try_for_each(|command| self.check(command));
+        self.end_group();
+        result
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PipelineError {
+    /// A command follows one that may only be the last command of a group, e.g., DATA, QUIT,
+    /// or STARTTLS.
+    AfterGroupEnd { ended_by: String, command: String },
+    /// The group contains more than one command, but the server did not advertise PIPELINING.
+    NotAdvertised,
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::AfterGroupEnd { ended_by, command } => {
+                write!(f, "{} must not follow {} in a group", command, ended_by)
+            }
+            PipelineError::NotAdvertised => write!(f, "server does not support PIPELINING"),
+        }
+    }
+}
+
+impl core::error::Error for PipelineError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HeloIdentity, Limits, ReplyCode, TextString};
+
+    fn ok() -> Response {
+        Response::Other {
+            code: ReplyCode::Ok,
+            lines: vec![TextString::new("OK").unwrap()],
+        }
+    }
+
+    fn rcpt(forward_path: &str) -> Command {
+        Command::Rcpt {
+            forward_path: forward_path.into(),
+            parameters: vec![],
+        }
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let mut pipeline = Pipeline::new();
+
+        pipeline
+            .push(Command::Ehlo {
+                domain_or_address: HeloIdentity::Domain("client.example.org".into()),
+            })
+            .unwrap();
+        pipeline
+            .push(Command::Mail {
+                reverse_path: "a@example.org".into(),
+                parameters: vec![],
+            })
+            .unwrap();
+        pipeline.push(rcpt("b@example.org")).unwrap();
+        pipeline.push(rcpt("c@example.org")).unwrap();
+        pipeline.push(Command::Data).unwrap();
+        pipeline.push(Command::Quit).unwrap();
+
+        assert_eq!(
+            pipeline.poll_transmit().unwrap(),
+            b"EHLO client.example.org\r\n"
+        );
+        assert_eq!(pipeline.poll_transmit(), None);
+        assert_eq!(pipeline.expected(), Some(ResponseKind::Ehlo));
+        assert!(matches!(
+            pipeline.handle(ok()),
+            Some((Command::Ehlo { .. }, _))
+        ));
+
+        assert_eq!(
+            pipeline.poll_transmit().unwrap(),
+            &b"MAIL FROM:<a@example.org>\r\nRCPT TO:<b@example.org>\r\n\
+RCPT TO:<c@example.org>\r\nDATA\r\n"[..]
+        );
+        assert_eq!(pipeline.in_flight(), 4);
+        assert_eq!(pipeline.expected(), Some(ResponseKind::Other));
+        assert!(matches!(
+            pipeline.handle(ok()),
+            Some((Command::Mail { .. }, _))
+        ));
+        assert_eq!(pipeline.handle(ok()).unwrap().0, rcpt("b@example.org"));
+        assert_eq!(pipeline.handle(ok()).unwrap().0, rcpt("c@example.org"));
+        assert_eq!(pipeline.poll_transmit(), None);
+        assert_eq!(pipeline.handle(ok()).unwrap().0, Command::Data);
+
+        assert_eq!(pipeline.poll_transmit().unwrap(), b"QUIT\r\n");
+        assert_eq!(pipeline.handle(ok()).unwrap().0, Command::Quit);
+        assert!(pipeline.is_idle());
+        assert_eq!(pipeline.expected(), None);
+        assert_eq!(pipeline.handle(ok()), None);
+    }
+
+    #[test]
+    fn test_validator() {
+        let mail = Command::Mail {
+            reverse_path: "a@example.org".into(),
+            parameters: vec![],
+        };
+        let mut validator = PipelineValidator::new(true);
+
+        assert_eq!(
+            validator.validate(&[
+                Command::Rset,
+                mail.clone(),
+                rcpt("b@example.org"),
+                Command::Data
+            ]),
+            Ok(())
+        );
+        assert_eq!(
+            validator.validate(&[Command::Data, Command::Quit]),
+            Err(PipelineError::AfterGroupEnd {
+                ended_by: "DATA".into(),
+                command: "QUIT".into()
+            })
+        );
+        assert!(validator.check(&Command::StartTls).is_ok());
+        assert!(validator.check(&mail).is_err());
+        validator.end_group();
+        assert!(validator.check(&mail).is_ok());
+
+        let mut validator = PipelineValidator::new(false);
+        assert_eq!(
+            validator.validate(&[mail, rcpt("b@example.org")]),
+            Err(PipelineError::NotAdvertised)
+        );
+        assert_eq!(validator.validate(&[Command::Quit]), Ok(()));
+    }
+
+    #[test]
+    fn test_pipeline_line_limit() {
+        let mut pipeline = Pipeline::with_options(SyntaxOptions {
+            limits: Limits {
+                command: 16,
+                ..Limits::default()
+            },
+            ..SyntaxOptions::default()
+        });
+
+        assert!(matches!(
+            pipeline.push(rcpt("recipient@example.org")),
+            Err(EncodeError::LineTooLong { .. })
+        ));
+        assert!(pipeline.is_idle());
+    }
+}
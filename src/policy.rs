@@ -0,0 +1,177 @@
+//! Policy decisions of a server
+//!
+//! A [`Policy`] decides whether to accept the client identity, the reverse path, each
+//! recipient, and the message. The [`Session`] converts its [`Verdict`]s into replies, and
+//! reverts the transitions of rejected commands:
+//!
+//! ```
+//! use instant_smtp::{
+//!     policy::{Policy, Verdict},
+//!     server::Session,
+//!     Command, ForwardPath, HeloIdentity, ReplyCode, ReversePath,
+//! };
+//!
+//! struct OnlyExampleOrg;
+//!
+//! impl Policy for OnlyExampleOrg {
+//!     fn on_rcpt(&mut self, _: &Session, forward_path: &ForwardPath) -> Verdict {
+//!         match forward_path.to_string().ends_with("@example.org>") {
+//!             true => Verdict::Accept,
+//!             false => Verdict::Reject,
+//!         }
+//!     }
+//! }
+//!
+//! let mut session = Session::new();
+//! let mut policy = OnlyExampleOrg;
+//! let ehlo = Command::Ehlo {
+//!     domain_or_address: HeloIdentity::Domain("client.example.com".into()),
+//! };
+//! let mail = Command::Mail {
+//!     reverse_path: ReversePath::Null,
+//!     parameters: vec![],
+//! };
+//! let rcpt = Command::Rcpt {
+//!     forward_path: "fred@example.com".into(),
+//!     parameters: vec![],
+//! };
+//! assert!(session.handle_with(ehlo, &mut policy).is_ok());
+//! assert!(session.handle_with(mail, &mut policy).is_ok());
+//! assert_eq!(
+//!     session.handle_with(rcpt, &mut policy).unwrap_err().code(),
+//!     ReplyCode::MailboxPermanentlyUnavailable
+//! );
+//! assert_eq!(session.recipient_count(), 0);
+//! ```
+//!
+//! The content of the message does not pass the session, so the server asks
+//! [`Policy::on_data_end`] itself.
+//!
+//! A policy that has to wait, e.g., for a database, does not implement [`Policy`]. Instead,
+//! the server calls [`Session::handle`], awaits its decision for [`Session::decision_point`],
+//! and passes it to [`Session::apply`].
+
+use alloc::vec;
+
+use crate::{
+    server::{Session, Transaction},
+    ForwardPath, HeloIdentity, ReplyCode, Response, ReversePath, TextString,
+};
+
+/// The outcome of a decision
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    /// Reject permanently, with "550" or "554" and the enhanced code 5.7.1.
+    Reject,
+    /// Reject temporarily, with "450" or "451" and the enhanced code 4.7.1, e.g., when a rate
+    /// limit is exceeded.
+    Defer,
+}
+
+/// The step of the session a [`Verdict`] applies to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecisionPoint {
+    /// EHLO, HELO, or LHLO
+    Ehlo,
+    Mail,
+    Rcpt,
+    /// DATA, or the first BDAT of the transaction
+    DataStart,
+    /// The end of the message content, see [`Session::data_end`]
+    DataEnd,
+}
+
+impl DecisionPoint {
+    /// The reply for a verdict at this point, if it is not accepted
+    ///
+    /// The codes are those RFC 5321 lists for the command (4.3.2.), e.g., "451" rather than
+    /// "450" for MAIL. It lists no temporary failure of EHLO, which is deferred with "450".
+    pub fn reply(self, verdict: Verdict) -> Option<Response> {
+        let (code, text) = match (verdict, self) {
+            (Verdict::Accept, _) => return None,
+            (Verdict::Reject, DecisionPoint::Ehlo | DecisionPoint::Mail | DecisionPoint::Rcpt) => {
+                (ReplyCode::MailboxPermanentlyUnavailable, REJECTED)
+            }
+            (Verdict::Reject, DecisionPoint::DataStart | DecisionPoint::DataEnd) => {
+                (ReplyCode::TransactionFailed, REJECTED)
+            }
+            (Verdict::Defer, DecisionPoint::Ehlo | DecisionPoint::Rcpt) => {
+                (ReplyCode::MailboxTemporarilyUnavailable, DEFERRED)
+            }
+            (Verdict::Defer, _) => (ReplyCode::ProcessingError, DEFERRED),
+        };
+
+        Some(Response::Other {
+            code,
+            lines: vec![TextString::new(text).unwrap()],
+        })
+    }
+}
+
+const REJECTED: &str = "5.7.1 Rejected by policy";
+const DEFERRED: &str = "4.7.1 Deferred by policy, try again later";
+
+/// The decisions of a server, see [`Session::handle_with`]
+///
+/// All decisions accept by default. They are only asked for commands the session accepts,
+/// i.e., that are valid in the current state.
+pub trait Policy {
+    fn on_ehlo(&mut self, session: &Session, identity: &HeloIdentity) -> Verdict {
+        let _ = (session, identity);
+        Verdict::Accept
+    }
+
+    fn on_mail(&mut self, session: &Session, reverse_path: &ReversePath) -> Verdict {
+        let _ = (session, reverse_path);
+        Verdict::Accept
+    }
+
+    fn on_rcpt(&mut self, session: &Session, forward_path: &ForwardPath) -> Verdict {
+        let _ = (session, forward_path);
+        Verdict::Accept
+    }
+
+    /// Decide on DATA, or on the first BDAT, once all recipients are known.
+    fn on_data_start(&mut self, session: &Session) -> Verdict {
+        let _ = session;
+        Verdict::Accept
+    }
+
+    /// Decide on the message, e.g., after a content scan.
+    ///
+    /// [`Session::handle_with`] can't call this, as the content does not pass the session. Pass
+    /// the verdict for the transaction returned by [`Session::data_end`] to [`Session::apply`].
+    fn on_data_end(&mut self, transaction: &Transaction) -> Verdict {
+        let _ = transaction;
+        Verdict::Accept
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reply() {
+        assert_eq!(DecisionPoint::Mail.reply(Verdict::Accept), None);
+
+        let code = |point: DecisionPoint, verdict| point.reply(verdict).unwrap().code();
+        assert_eq!(
+            code(DecisionPoint::Rcpt, Verdict::Defer),
+            ReplyCode::from(450)
+        );
+        assert_eq!(
+            code(DecisionPoint::Mail, Verdict::Defer),
+            ReplyCode::from(451)
+        );
+        assert_eq!(
+            code(DecisionPoint::Rcpt, Verdict::Reject),
+            ReplyCode::from(550)
+        );
+        assert_eq!(
+            code(DecisionPoint::DataEnd, Verdict::Reject),
+            ReplyCode::from(554)
+        );
+    }
+}
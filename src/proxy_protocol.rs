@@ -0,0 +1,331 @@
+//! The PROXY protocol of HAProxy, version 1 and 2
+//!
+//! A load balancer in front of an SMTP server sends a PROXY header before the first octet of
+//! the connection, so that the server learns the addresses of the original client instead of
+//! the ones of the load balancer:
+//!
+//! ```text
+//! PROXY TCP4 192.0.2.1 198.51.100.1 56324 25\r\n
+//! ```
+//!
+//! The header precedes the greeting of the server, and is consumed from the same [`Decoder`]
+//! that decodes the commands thereafter:
+//!
+//! ```
+//! use instant_smtp::{decoder::Decoder, Command};
+//!
+//! let mut decoder = Decoder::new();
+//! decoder.push(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 25\r\nQUIT\r\n");
+//!
+//! let header = decoder.decode_proxy_header().unwrap().unwrap();
+//! assert_eq!(header.addresses.unwrap().source, "192.0.2.1:56324".parse().unwrap());
+//! assert_eq!(decoder.decode_command().unwrap(), Some(Command::Quit));
+//! ```
+//!
+//! See <https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt>.
+
+use alloc::vec::Vec;
+use core::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use crate::decoder::Decoder;
+
+/// Signature of a version 2 header
+const SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+/// Maximum length of a version 1 header including CRLF
+const V1_LENGTH: usize = 107;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProxyHeader {
+    pub version: Version,
+    /// The addresses of the original connection, or `None` for "UNKNOWN", the LOCAL command
+    /// (e.g., a health check of the load balancer), and address families other than TCP and
+    /// UDP over IPv4 and IPv6
+    pub addresses: Option<Addresses>,
+    /// The type-length-value vectors following the addresses (version 2 only)
+    pub tlvs: Vec<Tlv>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Version {
+    /// The human-readable header
+    V1,
+    /// The binary header
+    V2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Addresses {
+    /// The address of the original client
+    pub source: SocketAddr,
+    /// The address the client connected to
+    pub destination: SocketAddr,
+}
+
+/// A type-length-value vector, e.g., PP2_TYPE_AUTHORITY (0x02) with the server name
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tlv {
+    pub kind: u8,
+    pub value: Vec<u8>,
+}
+
+/// Parse the header at the start of the input.
+///
+/// Returns the header and its length, or `None` if more input is needed.
+pub fn parse(input: &[u8]) -> Result<Option<(ProxyHeader, usize)>, ProxyError> {
+    if input.starts_with(b"PROXY ") {
+        return parse_v1(input);
+    }
+    if input.starts_with(SIGNATURE) {
+        return parse_v2(input);
+    }
+
+    match b"PROXY ".starts_with(input) || SIGNATURE.starts_with(input) {
+        true => Ok(None),
+        false => Err(ProxyError::Missing),
+    }
+}
+
+/// "PROXY" SP ( "TCP4" / "TCP6" ) SP source SP destination SP source-port SP destination-port CRLF
+/// "PROXY" SP "UNKNOWN" *( any octet except CR ) CRLF
+fn parse_v1(input: &[u8]) -> Result<Option<(ProxyHeader, usize)>, ProxyError> {
+    let end = match crate::find_crlf(input) {
+        Some(position) if position + 2 <= V1_LENGTH => position + 2,
+        Some(_) => return Err(ProxyError::Invalid),
+        None if input.len() >= V1_LENGTH => return Err(ProxyError::Invalid),
+        None => return Ok(None),
+    };
+    let line =
+        core::str::from_utf8(&input["PROXY ".len()..end - 2]).map_err(|_| ProxyError::Invalid)?;
+
+    let addresses = match line.split(' ').collect::<Vec<_>>()[..] {
+        ["UNKNOWN", ..] => None,
+        [family @ ("TCP4" | "TCP6"), source, destination, source_port, destination_port] => {
+            let address = |address: &str| match (family, address.parse()) {
+                ("TCP4", Ok(address @ IpAddr::V4(_))) | ("TCP6", Ok(address @ IpAddr::V6(_))) => {
+                    Ok(address)
+                }
+                _ => Err(ProxyError::Invalid),
+            };
+            // Unlike `str::parse`, no sign or leading zeros.
+            let port = |port: &str| match port.bytes().all(|byte| byte.is_ascii_digit()) {
+                true if port == "0" || !port.starts_with('0') => {
+                    port.parse().map_err(|_| ProxyError::Invalid)
+                }
+                _ => Err(ProxyError::Invalid),
+            };
+
+            Some(Addresses {
+                source: SocketAddr::new(address(source)?, port(source_port)?),
+                destination: SocketAddr::new(address(destination)?, port(destination_port)?),
+            })
+        }
+        _ => return Err(ProxyError::Invalid),
+    };
+
+    let header = ProxyHeader {
+        version: Version::V1,
+        addresses,
+        tlvs: Vec::new(),
+    };
+    Ok(Some((header, end)))
+}
+
+/// signature version-command family length addresses *tlv
+fn parse_v2(input: &[u8]) -> Result<Option<(ProxyHeader, usize)>, ProxyError> {
+    let Some(&[version_command, family, high, low]) = input.get(12..16) else {
+        return Ok(None);
+    };
+    let version = version_command >> 4;
+    if version != 2 {
+        return Err(ProxyError::UnsupportedVersion(version));
+    }
+    let end = 16 + usize::from(u16::from_be_bytes([high, low]));
+    let Some(payload) = input.get(16..end) else {
+        return Ok(None);
+    };
+
+    let (addresses, rest) = match (version_command & 0x0f, family >> 4) {
+        // LOCAL
+        (0x0, _) => (None, payload),
+        // PROXY over AF_INET
+        (0x1, 0x1) if payload.len() >= 12 => {
+            let ip =
+                |octets: &[u8]| IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(octets).unwrap()));
+            let addresses = Addresses {
+                source: SocketAddr::new(ip(&payload[0..4]), port(&payload[8..10])),
+                destination: SocketAddr::new(ip(&payload[4..8]), port(&payload[10..12])),
+            };
+            (Some(addresses), &payload[12..])
+        }
+        // PROXY over AF_INET6
+        (0x1, 0x2) if payload.len() >= 36 => {
+            let ip =
+                |octets: &[u8]| IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(octets).unwrap()));
+            let addresses = Addresses {
+                source: SocketAddr::new(ip(&payload[0..16]), port(&payload[32..34])),
+                destination: SocketAddr::new(ip(&payload[16..32]), port(&payload[34..36])),
+            };
+            (Some(addresses), &payload[36..])
+        }
+        // PROXY over AF_UNIX
+        (0x1, 0x3) if payload.len() >= 216 => (None, &payload[216..]),
+        (0x1, 0x0) => (None, payload),
+        _ => return Err(ProxyError::Invalid),
+    };
+
+    let header = ProxyHeader {
+        version: Version::V2,
+        // The addresses of other transports than TCP and UDP are not known.
+        addresses: addresses.filter(|_| matches!(family & 0x0f, 0x1 | 0x2)),
+        tlvs: parse_tlvs(rest)?,
+    };
+    Ok(Some((header, end)))
+}
+
+fn port(octets: &[u8]) -> u16 {
+    u16::from_be_bytes([octets[0], octets[1]])
+}
+
+/// tlv = type length-hi length-lo *value
+fn parse_tlvs(mut input: &[u8]) -> Result<Vec<Tlv>, ProxyError> {
+    let mut tlvs = Vec::new();
+
+    while let [kind, high, low, rest @ ..] = input {
+        let length = usize::from(u16::from_be_bytes([*high, *low]));
+        let value = rest.get(..length).ok_or(ProxyError::Invalid)?;
+        tlvs.push(Tlv {
+            kind: *kind,
+            value: value.to_vec(),
+        });
+        input = &rest[length..];
+    }
+
+    match input.is_empty() {
+        true => Ok(tlvs),
+        false => Err(ProxyError::Invalid),
+    }
+}
+
+impl Decoder {
+    /// The PROXY header preceding the first command, or `None` if more input is needed.
+    ///
+    /// The header is consumed, so that the commands can be decoded thereafter. Nothing is
+    /// consumed on error.
+    pub fn decode_proxy_header(&mut self) -> Result<Option<ProxyHeader>, ProxyError> {
+        match parse(self.buffered())? {
+            Some((header, length)) => {
+                self.take(length);
+                Ok(Some(header))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProxyError {
+    /// The input does not start with a PROXY header.
+    Missing,
+    /// The header is malformed.
+    Invalid,
+    /// The binary header has a version other than 2.
+    UnsupportedVersion(u8),
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyError::Missing => write!(f, "missing PROXY header"),
+            ProxyError::Invalid => write!(f, "invalid PROXY header"),
+            ProxyError::UnsupportedVersion(version) => {
+                write!(f, "unsupported PROXY protocol version {}", version)
+            }
+        }
+    }
+}
+
+impl core::error::Error for ProxyError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_v1() {
+        let input = b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 25\r\nEHLO";
+        let (header, length) = parse(input).unwrap().unwrap();
+        assert_eq!(length, input.len() - 4);
+        assert_eq!(
+            header.addresses,
+            Some(Addresses {
+                source: "[2001:db8::1]:56324".parse().unwrap(),
+                destination: "[2001:db8::2]:25".parse().unwrap(),
+            })
+        );
+
+        let (header, _) = parse(b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(header.version, Version::V1);
+        assert_eq!(header.addresses, None);
+
+        assert_eq!(parse(b"PROXY TCP4 192.0.2.1"), Ok(None));
+        assert_eq!(parse(b"PRO"), Ok(None));
+        assert_eq!(parse(b"EHLO example.org\r\n"), Err(ProxyError::Missing));
+        for input in [
+            &b"PROXY TCP4 2001:db8::1 192.0.2.2 1 2\r\n"[..],
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 +1 2\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 01 65536\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 1\r\n",
+            &[b"PROXY UNKNOWN ".as_slice(), &[b'a'; 100]].concat(),
+        ] {
+            assert_eq!(parse(input), Err(ProxyError::Invalid));
+        }
+    }
+
+    #[test]
+    fn test_parse_v2() {
+        let mut input = SIGNATURE.to_vec();
+        input.extend_from_slice(&[0x21, 0x11, 0x00, 0x13]);
+        input.extend_from_slice(&[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x00, 0x19]);
+        input.extend_from_slice(&[0x02, 0x00, 0x04]);
+        input.extend_from_slice(b"mx.1QUIT\r\n");
+
+        for end in 0..input.len() - 7 {
+            assert_eq!(parse(&input[..end]), Ok(None));
+        }
+        let (header, length) = parse(&input).unwrap().unwrap();
+        assert_eq!(length, input.len() - 6);
+        assert_eq!(
+            header,
+            ProxyHeader {
+                version: Version::V2,
+                addresses: Some(Addresses {
+                    source: "192.0.2.1:56324".parse().unwrap(),
+                    destination: "198.51.100.1:25".parse().unwrap(),
+                }),
+                tlvs: vec![Tlv {
+                    kind: 0x02,
+                    value: b"mx.1".to_vec(),
+                }],
+            }
+        );
+
+        let mut decoder = Decoder::new();
+        decoder.push(&input);
+        assert!(decoder.decode_proxy_header().unwrap().is_some());
+        assert_eq!(decoder.buffered(), b"QUIT\r\n");
+
+        // LOCAL, e.g., a health check
+        let local = [SIGNATURE.as_slice(), &[0x20, 0x00, 0x00, 0x00]].concat();
+        assert_eq!(parse(&local).unwrap().unwrap().0.addresses, None);
+
+        let version = [SIGNATURE.as_slice(), &[0x11, 0x00, 0x00, 0x00]].concat();
+        assert_eq!(parse(&version), Err(ProxyError::UnsupportedVersion(1)));
+        let truncated = [SIGNATURE.as_slice(), &[0x21, 0x11, 0x00, 0x04, 0, 0, 0, 0]].concat();
+        assert_eq!(parse(&truncated), Err(ProxyError::Invalid));
+    }
+}
@@ -0,0 +1,172 @@
+//! Classification of failed deliveries for retries
+//!
+//! A sender that queues messages retries transient failures (4yz) later, and returns permanent
+//! failures (5yz) to the sender of the message (RFC 5321, 4.5.4.1.):
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use instant_smtp::{
+//!     retry::{Decision, RetryPolicy},
+//!     ReplyCode, Response,
+//! };
+//!
+//! let policy = RetryPolicy::default();
+//! let busy = Response::Other {
+//!     code: ReplyCode::ProcessingError,
+//!     lines: vec![],
+//! };
+//! assert_eq!(
+//!     policy.classify(&busy, 1),
+//!     Some(Decision::Retryable {
+//!         after: Duration::from_secs(30 * 60)
+//!     })
+//! );
+//! ```
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::{EnhancedStatusCode, Response, Severity};
+
+/// What to do after a failed attempt
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// Try again after the given delay.
+    Retryable { after: Duration },
+    /// Give up, e.g., to send a delivery status notification.
+    Permanent,
+}
+
+/// When to retry, and which failures to treat differently than their reply code suggests
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Delay before the first retry, which should be at least 30 minutes (RFC 5321, 4.5.4.1.)
+    pub initial_delay: Duration,
+    /// Maximum delay between retries
+    pub max_delay: Duration,
+    /// Factor by which the delay grows after each attempt
+    pub backoff: u32,
+    /// Time after which to give up, which should be at least 4-5 days (RFC 5321, 4.5.4.1.)
+    pub give_up_after: Duration,
+    /// Codes of 5yz replies that are retried nevertheless, e.g., 5.2.2 (mailbox full) for
+    /// servers that reject transient conditions permanently
+    pub retryable: Vec<EnhancedStatusCode>,
+    /// Codes of 4yz replies that are not retried, e.g., 4.7.1 for servers that use it for
+    /// permanent policy rejections
+    pub permanent: Vec<EnhancedStatusCode>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        const MINUTE: Duration = Duration::from_secs(60);
+
+        Self {
+            initial_delay: 30 * MINUTE,
+            max_delay: 8 * 60 * MINUTE,
+            backoff: 2,
+            give_up_after: 5 * 24 * 60 * MINUTE,
+            retryable: Vec::new(),
+            permanent: Vec::new(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Classify the reply to the `attempt`-th attempt, counting from 1.
+    ///
+    /// Returns `None` for replies that are not failures. The enhanced status code, if any, is
+    /// looked up in [`RetryPolicy::retryable`] and [`RetryPolicy::permanent`] first. Transient
+    /// failures are permanent once the delays of all attempts exceed
+    /// [`RetryPolicy::give_up_after`].
+    pub fn classify(&self, response: &Response, attempt: u32) -> Option<Decision> {
+        let transient = match (response.severity()?, response.enhanced_code()) {
+            (Severity::TransientNegative, Some(code)) => !self.permanent.contains(&code),
+            (Severity::TransientNegative, None) => true,
+            (Severity::PermanentNegative, Some(code)) => self.retryable.contains(&code),
+            (Severity::PermanentNegative, None) => false,
+            _ => return None,
+        };
+        if !transient {
+            return Some(Decision::Permanent);
+        }
+
+        let elapsed: Duration = (1..attempt).map(|attempt| self.delay(attempt)).sum();
+        match elapsed < self.give_up_after {
+            true => Some(Decision::Retryable {
+                after: self.delay(attempt),
+            }),
+            false => Some(Decision::Permanent),
+        }
+    }
+
+    /// Delay following the `attempt`-th attempt
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = self.backoff.saturating_pow(attempt.saturating_sub(1));
+        self.initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ReplyCode, TextString};
+
+    fn response(code: u16, text: &str) -> Response {
+        Response::Other {
+            code: ReplyCode::from(code),
+            lines: vec![TextString::new(text).unwrap().into_owned()],
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        let hours = |hours: u64| Duration::from_secs(60 * 60 * hours);
+        let policy = RetryPolicy {
+            retryable: vec![EnhancedStatusCode::new(5, 2, 2)],
+            permanent: vec![EnhancedStatusCode::new(4, 7, 1)],
+            ..RetryPolicy::default()
+        };
+
+        let busy = response(451, "4.3.0 Try again later");
+        assert_eq!(
+            policy.classify(&busy, 2),
+            Some(Decision::Retryable { after: hours(1) })
+        );
+        assert_eq!(
+            policy.classify(&busy, 10),
+            Some(Decision::Retryable { after: hours(8) })
+        );
+        // 0.5 + 1 + 2 + 4 hours, then 8 hours each, exceed 5 days with the 20th attempt.
+        assert_eq!(
+            policy.classify(&busy, 19),
+            Some(Decision::Retryable { after: hours(8) })
+        );
+        assert_eq!(policy.classify(&busy, 20), Some(Decision::Permanent));
+
+        assert_eq!(
+            policy.classify(&response(550, "5.1.1 No such user"), 1),
+            Some(Decision::Permanent)
+        );
+        assert_eq!(
+            policy.classify(&response(552, "5.2.2 Mailbox full"), 1),
+            Some(Decision::Retryable {
+                after: policy.initial_delay
+            })
+        );
+        assert_eq!(
+            policy.classify(&response(450, "4.7.1 Blocked"), 1),
+            Some(Decision::Permanent)
+        );
+        // The class of the enhanced code does not match, so it is ignored.
+        assert_eq!(
+            policy.classify(&response(450, "5.7.1 Blocked"), 1),
+            Some(Decision::Retryable {
+                after: policy.initial_delay
+            })
+        );
+        assert_eq!(policy.classify(&response(250, "OK"), 1), None);
+    }
+}
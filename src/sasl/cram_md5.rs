@@ -0,0 +1,92 @@
+//! CRAM-MD5 (RFC 2195)
+
+use alloc::{format, string::String, vec::Vec};
+
+use hmac::{Hmac, Mac};
+use md5::Md5;
+
+use super::{SaslError, SaslMechanism};
+use crate::{AuthMechanism, Secret};
+
+/// The server sends a challenge, the client answers with its username and the keyed MD5 digest
+/// of the challenge.
+///
+/// response = username SP lowercase-hex(HMAC-MD5(password, challenge))
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CramMd5 {
+    username: String,
+    password: Secret<String>,
+    answered: bool,
+}
+
+impl CramMd5 {
+    pub fn new<U: Into<String>, P: Into<String>>(username: U, password: P) -> Self {
+        Self {
+            username: username.into(),
+            password: Secret::new(password.into()),
+            answered: false,
+        }
+    }
+}
+
+impl SaslMechanism for CramMd5 {
+    fn mechanism(&self) -> AuthMechanism {
+        AuthMechanism::CramMd5
+    }
+
+    fn initial_response(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn respond(&mut self, challenge: &[u8]) -> Result<Vec<u8>, SaslError> {
+        if self.answered {
+            return Err(SaslError::UnexpectedChallenge);
+        }
+        self.answered = true;
+
+        // HMAC accepts keys of any length.
+        let mut mac = Hmac::<Md5>::new_from_slice(self.password.expose().as_bytes()).unwrap();
+        mac.update(challenge);
+
+        let mut response = format!("{} ", self.username);
+        for byte in mac.finalize().into_bytes() {
+            response.push_str(&format!("{:02x}", byte));
+        }
+
+        Ok(response.into_bytes())
+    }
+
+    fn is_complete(&self) -> bool {
+        self.answered
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cram_md5() {
+        let mut mechanism = CramMd5::new("tim", "tanstaaftanstaaf");
+
+        assert_eq!(mechanism.initial_response(), None);
+        assert!(!mechanism.is_complete());
+        assert_eq!(
+            mechanism
+                .respond(b"<1896.697170952@postoffice.reston.mci.net>")
+                .unwrap(),
+            b"tim b913a602c7eda7a495b4e6e7334d3890"
+        );
+        assert!(mechanism.is_complete());
+        assert_eq!(
+            mechanism.respond(b"again"),
+            Err(SaslError::UnexpectedChallenge)
+        );
+    }
+
+    #[test]
+    fn test_debug_redacts_password() {
+        let mechanism = CramMd5::new("tim", "tanstaaftanstaaf");
+        assert!(!format!("{:?}", mechanism).contains("tanstaaf"));
+    }
+}
@@ -0,0 +1,525 @@
+//! SASL exchange following AUTH (RFC 4954, 4.)
+//!
+//! The server sends challenges as "334" replies with base64-encoded text. The client answers
+//! each challenge with a base64-encoded line, or cancels the exchange with "*". The exchange
+//! ends with "235" when the client is authenticated, or with an error reply.
+//!
+//! Mechanisms implement [`SaslMechanism`]. [`Plain`] and [`Login`] are built in, CRAM-MD5 and
+//! SCRAM-SHA-256 are available with the `cram-md5` and `scram` features.
+
+use alloc::{boxed::Box, format, string::String, vec, vec::Vec};
+use core::fmt;
+
+use crate::io::Write;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+use crate::{
+    AuthMechanism, Command, EnhancedStatusCode, ParseResult, ReplyCode, Response, Secret, Severity,
+    TextString,
+};
+
+#[cfg(feature = "cram-md5")]
+mod cram_md5;
+#[cfg(feature = "scram")]
+mod scram;
+#[cfg(feature = "cram-md5")]
+pub use cram_md5::CramMd5;
+#[cfg(feature = "scram")]
+pub use scram::ScramSha256;
+
+/// The mechanisms a client can use with a password, strongest first
+///
+/// SCRAM-SHA-256 is not included, as it also needs a nonce, see [`ScramSha256::new`].
+pub fn password_mechanisms() -> Vec<AuthMechanism> {
+    let mut mechanisms = Vec::new();
+    #[cfg(feature = "cram-md5")]
+    mechanisms.push(AuthMechanism::CramMd5);
+    mechanisms.extend([AuthMechanism::Plain, AuthMechanism::Login]);
+
+    mechanisms
+}
+
+/// Pick the first mechanism of `preference` that the server supports.
+///
+/// Mechanisms that send the password in plain text are skipped unless the connection uses TLS,
+/// see [`AuthMechanism::is_plaintext`].
+pub fn choose_mechanism(
+    supported: &[AuthMechanism],
+    preference: &[AuthMechanism],
+    tls: bool,
+) -> Option<AuthMechanism> {
+    preference
+        .iter()
+        .filter(|mechanism| tls || !mechanism.is_plaintext())
+        .find(|mechanism| supported.contains(mechanism))
+        .cloned()
+}
+
+/// Client side of a SASL mechanism.
+///
+/// Challenges and responses are passed without base64 encoding.
+pub trait SaslMechanism: fmt::Debug {
+    fn mechanism(&self) -> AuthMechanism;
+
+    /// The response to send with AUTH, if the mechanism is client-first.
+    fn initial_response(&mut self) -> Option<Vec<u8>>;
+
+    /// Answer a challenge of the server.
+    fn respond(&mut self, challenge: &[u8]) -> Result<Vec<u8>, SaslError>;
+
+    /// Whether the exchange may end now.
+    ///
+    /// Mechanisms that authenticate the server return `false` until the server proved its
+    /// identity, so that a "235" reply from an impostor is not accepted.
+    fn is_complete(&self) -> bool {
+        true
+    }
+}
+
+/// PLAIN (RFC 4616)
+///
+/// message = \[authzid\] UTF8NUL authcid UTF8NUL passwd
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Plain {
+    /// Identity to act as, if different from `username`
+    pub authzid: Option<String>,
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+impl Plain {
+    pub fn new<U: Into<String>, P: Into<String>>(username: U, password: P) -> Self {
+        Self {
+            authzid: None,
+            username: username.into(),
+            password: Secret::new(password.into()),
+        }
+    }
+
+    fn message(&self) -> Vec<u8> {
+        let authzid = self.authzid.as_deref().unwrap_or_default();
+        let password = self.password.expose();
+
+        format!("{}\0{}\0{}", authzid, self.username, password).into_bytes()
+    }
+}
+
+impl SaslMechanism for Plain {
+    fn mechanism(&self) -> AuthMechanism {
+        AuthMechanism::Plain
+    }
+
+    fn initial_response(&mut self) -> Option<Vec<u8>> {
+        Some(self.message())
+    }
+
+    /// The server sends an empty challenge when AUTH had no initial response.
+    fn respond(&mut self, challenge: &[u8]) -> Result<Vec<u8>, SaslError> {
+        match challenge.is_empty() {
+            true => Ok(self.message()),
+            false => Err(SaslError::UnexpectedChallenge),
+        }
+    }
+}
+
+/// LOGIN (draft-murchison-sasl-login)
+///
+/// The username is sent as initial response, the password as answer to the next challenge.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Login {
+    username: String,
+    password: Secret<String>,
+    username_sent: bool,
+    password_sent: bool,
+}
+
+impl Login {
+    pub fn new<U: Into<String>, P: Into<String>>(username: U, password: P) -> Self {
+        Self {
+            username: username.into(),
+            password: Secret::new(password.into()),
+            username_sent: false,
+            password_sent: false,
+        }
+    }
+}
+
+impl SaslMechanism for Login {
+    fn mechanism(&self) -> AuthMechanism {
+        AuthMechanism::Login
+    }
+
+    fn initial_response(&mut self) -> Option<Vec<u8>> {
+        self.username_sent = true;
+        Some(self.username.clone().into_bytes())
+    }
+
+    /// The challenges are usually "Username:" and "Password:", but their content is not
+    /// specified, so only their order is relied on.
+    fn respond(&mut self, _challenge: &[u8]) -> Result<Vec<u8>, SaslError> {
+        if !self.username_sent {
+            self.username_sent = true;
+            Ok(self.username.clone().into_bytes())
+        } else if !self.password_sent {
+            self.password_sent = true;
+            Ok(self.password.expose().clone().into_bytes())
+        } else {
+            Err(SaslError::UnexpectedChallenge)
+        }
+    }
+}
+
+/// A line sent by the client in answer to a "334" challenge.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Continuation {
+    /// Response to the challenge, base64-encoded when serialized
+    Response(Secret<Vec<u8>>),
+    /// "*" aborts the exchange. The server replies with "501".
+    Cancel,
+}
+
+impl Continuation {
+    pub fn from_bytes(input: &[u8]) -> ParseResult<'_, Self> {
+        Ok(crate::parse::command::sasl_continuation(input)?)
+    }
+
+    pub fn serialize(&self, writer: &mut impl Write) -> crate::io::Result<()> {
+        match self {
+            Continuation::Response(data) => {
+                write!(writer, "{}\r\n", BASE64.encode(data.expose()))
+            }
+            Continuation::Cancel => writer.write_all(b"*\r\n"),
+        }
+    }
+}
+
+/// Decode the challenge of a "334" reply. Returns `None` for other replies.
+pub fn challenge(response: &Response) -> Option<Result<Vec<u8>, SaslError>> {
+    match response {
+        Response::Other { code, lines } if *code == ReplyCode::AUTH_CHALLENGE_334 => {
+            let text = lines.first().map(|line| line.trim()).unwrap_or_default();
+
+            Some(BASE64.decode(text).map_err(|_| SaslError::InvalidChallenge))
+        }
+        _ => None,
+    }
+}
+
+/// Build the "334" reply for a challenge (server side).
+pub fn challenge_response(challenge: &[u8]) -> Response {
+    let lines = match challenge.is_empty() {
+        true => Vec::new(),
+        // Base64 consists of text string characters only.
+        false => vec![TextString::new(&BASE64.encode(challenge))
+            .unwrap()
+            .into_owned()],
+    };
+
+    Response::Other {
+        code: ReplyCode::AUTH_CHALLENGE_334,
+        lines,
+    }
+}
+
+/// Client side of the exchange.
+#[derive(Debug)]
+pub struct Authenticator {
+    mechanism: Box<dyn SaslMechanism + Send>,
+}
+
+/// What to do after a reply, see [`Authenticator::handle`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Send the continuation line and wait for the next reply.
+    Continue(Continuation),
+    /// The server accepted the credentials ("235").
+    Authenticated,
+    /// The server rejected the exchange, e.g., with "535".
+    Rejected,
+}
+
+/// Interpretation of the reply that ends the exchange (RFC 4954, 6.)
+///
+/// The enhanced status code takes precedence over the reply code, which is only considered when
+/// there is none, e.g., "554 5.7.8" and "534 5.7.8" denote invalid credentials.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthOutcome {
+    /// 235 2.7.0 Authentication successful
+    Authenticated,
+    /// 432 4.7.12 A password transition is needed
+    TransitionNeeded,
+    /// 454 4.7.0 Temporary authentication failure, or any other 4yz reply
+    TemporaryFailure,
+    /// 534 5.7.9 Authentication mechanism is too weak
+    MechanismTooWeak,
+    /// 535 5.7.8 Authentication credentials invalid
+    InvalidCredentials,
+    /// 538 5.7.11 Encryption required for requested authentication mechanism
+    EncryptionRequired,
+    /// 504 5.5.4 Unrecognized authentication type
+    UnsupportedMechanism,
+    /// Any other 5yz reply, e.g., "501" when the exchange was cancelled
+    Failed,
+}
+
+impl AuthOutcome {
+    /// Classify a reply to AUTH or to a continuation line.
+    ///
+    /// Returns `None` for a "334" challenge, as the exchange continues.
+    pub fn from_response(response: &Response) -> Option<Self> {
+        if response.code() == ReplyCode::AUTH_CHALLENGE_334 {
+            return None;
+        }
+
+        let outcome = match response.enhanced_code() {
+            Some(EnhancedStatusCode {
+                class,
+                subject,
+                detail,
+            }) => match (class, subject, detail) {
+                (4, 7, 12) => Some(AuthOutcome::TransitionNeeded),
+                (5, 7, 9) => Some(AuthOutcome::MechanismTooWeak),
+                (5, 7, 8) => Some(AuthOutcome::InvalidCredentials),
+                (5, 7, 11) => Some(AuthOutcome::EncryptionRequired),
+                (5, 5, 4) => Some(AuthOutcome::UnsupportedMechanism),
+                _ => None,
+            },
+            None => match u16::from(response.code()) {
+                432 => Some(AuthOutcome::TransitionNeeded),
+                534 => Some(AuthOutcome::MechanismTooWeak),
+                535 => Some(AuthOutcome::InvalidCredentials),
+                538 => Some(AuthOutcome::EncryptionRequired),
+                504 => Some(AuthOutcome::UnsupportedMechanism),
+                _ => None,
+            },
+        };
+        let outcome = match outcome {
+            Some(outcome) => outcome,
+            None => match response.severity()? {
+                Severity::PositiveCompletion => AuthOutcome::Authenticated,
+                Severity::TransientNegative => AuthOutcome::TemporaryFailure,
+                Severity::PermanentNegative => AuthOutcome::Failed,
+                Severity::PositiveIntermediate => return None,
+            },
+        };
+
+        Some(outcome)
+    }
+
+    /// Whether another mechanism may succeed where this one failed, e.g., a stronger one, or
+    /// PLAIN after TLS was established for [`AuthOutcome::EncryptionRequired`]
+    pub fn try_other_mechanism(&self) -> bool {
+        matches!(
+            self,
+            AuthOutcome::MechanismTooWeak
+                | AuthOutcome::EncryptionRequired
+                | AuthOutcome::UnsupportedMechanism
+        )
+    }
+
+    /// Whether the same credentials may succeed later
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            AuthOutcome::TransitionNeeded | AuthOutcome::TemporaryFailure
+        )
+    }
+}
+
+impl Authenticator {
+    pub fn new<M: SaslMechanism + Send + 'static>(mechanism: M) -> Self {
+        Self {
+            mechanism: Box::new(mechanism),
+        }
+    }
+
+    /// The AUTH command that starts the exchange.
+    pub fn command(&mut self) -> Command {
+        Command::Auth {
+            mechanism: self.mechanism.mechanism(),
+            initial_response: self.mechanism.initial_response().map(Secret::new),
+        }
+    }
+
+    /// Advance the exchange with a reply from the server.
+    ///
+    /// On error, the client should send [`Continuation::Cancel`] when the reply was a "334"
+    /// challenge.
+    pub fn handle(&mut self, response: &Response) -> Result<Step, SaslError> {
+        if let Some(challenge) = challenge(response) {
+            let data = self.mechanism.respond(&challenge?)?;
+            return Ok(Step::Continue(Continuation::Response(Secret::new(data))));
+        }
+
+        match u16::from(response.code()) {
+            235 if self.mechanism.is_complete() => Ok(Step::Authenticated),
+            235 => Err(SaslError::Mechanism("exchange ended early".into())),
+            _ => Ok(Step::Rejected),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SaslError {
+    /// The text of a "334" reply is not base64.
+    InvalidChallenge,
+    /// The mechanism did not expect (another) challenge.
+    UnexpectedChallenge,
+    /// The mechanism failed, e.g., because the server could not be verified.
+    Mechanism(String),
+}
+
+impl fmt::Display for SaslError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaslError::InvalidChallenge => write!(f, "challenge is not base64"),
+            SaslError::UnexpectedChallenge => write!(f, "unexpected challenge"),
+            SaslError::Mechanism(message) => write!(f, "mechanism failed: {}", message),
+        }
+    }
+}
+
+impl core::error::Error for SaslError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn reply(input: &[u8]) -> Response {
+        Response::parse_other(input).unwrap().1
+    }
+
+    #[test]
+    fn test_plain() {
+        let mut authenticator = Authenticator::new(Plain::new("user", "pass"));
+
+        let mut command = Vec::new();
+        authenticator.command().serialize(&mut command).unwrap();
+        assert_eq!(command, b"AUTH PLAIN AHVzZXIAcGFzcw==\r\n");
+        assert_eq!(
+            authenticator.handle(&reply(b"235 2.7.0 OK\r\n")),
+            Ok(Step::Authenticated)
+        );
+
+        // Server-first: AUTH PLAIN without initial response is answered with an empty challenge.
+        let mut plain = Plain::new("user", "pass");
+        assert_eq!(plain.respond(b""), Ok(b"\0user\0pass".to_vec()));
+        assert_eq!(plain.respond(b"x"), Err(SaslError::UnexpectedChallenge));
+    }
+
+    #[test]
+    fn test_login() {
+        let mut authenticator = Authenticator::new(Login::new("user", "pass"));
+
+        let mut command = Vec::new();
+        authenticator.command().serialize(&mut command).unwrap();
+        assert_eq!(command, b"AUTH LOGIN dXNlcg==\r\n");
+
+        let step = authenticator
+            .handle(&reply(b"334 UGFzc3dvcmQ6\r\n"))
+            .unwrap();
+        let mut line = Vec::new();
+        match step {
+            Step::Continue(continuation) => continuation.serialize(&mut line).unwrap(),
+            step => panic!("unexpected {:?}", step),
+        }
+        assert_eq!(line, b"cGFzcw==\r\n");
+
+        assert_eq!(
+            authenticator.handle(&reply(b"535 5.7.8 Invalid credentials\r\n")),
+            Ok(Step::Rejected)
+        );
+        assert_eq!(
+            authenticator.handle(&reply(b"334 ???\r\n")),
+            Err(SaslError::InvalidChallenge)
+        );
+    }
+
+    #[test]
+    fn test_continuation() {
+        assert_eq!(
+            Continuation::from_bytes(b"dXNlcg==\r\n"),
+            Ok((&b""[..], Continuation::Response(b"user".to_vec().into())))
+        );
+        assert_eq!(
+            Continuation::from_bytes(b"*\r\n"),
+            Ok((&b""[..], Continuation::Cancel))
+        );
+        assert!(Continuation::from_bytes(b"d?\r\n").is_err());
+
+        let mut serialized = Vec::new();
+        challenge_response(b"Password:")
+            .serialize(&mut serialized)
+            .unwrap();
+        assert_eq!(serialized, b"334 UGFzc3dvcmQ6\r\n");
+        assert_eq!(challenge(&reply(b"334 \r\n")), Some(Ok(Vec::new())));
+        assert_eq!(challenge(&reply(b"250 OK\r\n")), None);
+    }
+
+    #[test]
+    fn test_auth_outcome() {
+        let tests: [(&[u8], _); 10] = [
+            (
+                b"235 2.7.0 Authentication successful\r\n",
+                Some(AuthOutcome::Authenticated),
+            ),
+            (b"334 \r\n", None),
+            (
+                b"454 4.7.0 Try again later\r\n",
+                Some(AuthOutcome::TemporaryFailure),
+            ),
+            (
+                b"432 4.7.12 Password transition needed\r\n",
+                Some(AuthOutcome::TransitionNeeded),
+            ),
+            (
+                b"535 5.7.8 Bad credentials\r\n",
+                Some(AuthOutcome::InvalidCredentials),
+            ),
+            (
+                b"554 5.7.8 Bad credentials\r\n",
+                Some(AuthOutcome::InvalidCredentials),
+            ),
+            (b"534 Too weak\r\n", Some(AuthOutcome::MechanismTooWeak)),
+            // The enhanced status code takes precedence.
+            (
+                b"534 5.7.8 Bad credentials\r\n",
+                Some(AuthOutcome::InvalidCredentials),
+            ),
+            (
+                b"538 5.7.11 Encryption required\r\n",
+                Some(AuthOutcome::EncryptionRequired),
+            ),
+            (b"501 5.5.2 Cannot decode\r\n", Some(AuthOutcome::Failed)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(AuthOutcome::from_response(&reply(input)), expected);
+        }
+        assert!(AuthOutcome::EncryptionRequired.try_other_mechanism());
+        assert!(!AuthOutcome::InvalidCredentials.try_other_mechanism());
+        assert!(AuthOutcome::TemporaryFailure.is_transient());
+    }
+
+    #[test]
+    fn test_choose_mechanism() {
+        let preference = [
+            AuthMechanism::ScramSha256,
+            AuthMechanism::Plain,
+            AuthMechanism::Login,
+        ];
+        let supported = [AuthMechanism::Login, AuthMechanism::Plain];
+
+        assert_eq!(
+            choose_mechanism(&supported, &preference, true),
+            Some(AuthMechanism::Plain)
+        );
+        assert_eq!(choose_mechanism(&supported, &preference, false), None);
+        assert_eq!(
+            choose_mechanism(&[AuthMechanism::ScramSha256], &preference, false),
+            Some(AuthMechanism::ScramSha256)
+        );
+        assert_eq!(choose_mechanism(&[], &preference, true), None);
+    }
+}
@@ -0,0 +1,245 @@
+//! SCRAM-SHA-256 (RFC 5802, RFC 7677)
+//!
+//! Channel binding is not supported, i.e., the GS2 header is always "n,,". The username and
+//! password are used as given, without SASLprep (RFC 4013).
+
+use alloc::{borrow::ToOwned, format, string::String, vec::Vec};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use super::{SaslError, SaslMechanism};
+use crate::{AuthMechanism, Secret};
+
+const GS2_HEADER: &str = "n,,";
+
+/// Lower bound for the iteration count of the server, see RFC 7677, 4.
+const MIN_ITERATIONS: u32 = 4096;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScramSha256 {
+    username: String,
+    password: Secret<String>,
+    nonce: String,
+    state: State,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum State {
+    Initial,
+    /// client-first-message-bare was sent.
+    ClientFirst {
+        client_first_bare: String,
+    },
+    /// client-final-message was sent; the server must prove it knows the password.
+    ClientFinal {
+        server_signature: Vec<u8>,
+    },
+    /// server-final-message was verified.
+    Verified,
+}
+
+impl ScramSha256 {
+    /// Create the mechanism with a client nonce.
+    ///
+    /// The nonce must be unpredictable and unique per exchange, e.g., 18 random bytes in base64.
+    /// It must consist of printable ASCII characters other than ",".
+    pub fn new<U, P, N>(username: U, password: P, nonce: N) -> Self
+    where
+        U: Into<String>,
+        P: Into<String>,
+        N: Into<String>,
+    {
+        Self {
+            username: username.into(),
+            password: Secret::new(password.into()),
+            nonce: nonce.into(),
+            state: State::Initial,
+        }
+    }
+
+    /// server-first-message = [reserved-mext ","] nonce "," salt "," iteration-count
+    ///                        ["," extensions]
+    fn client_final(
+        &mut self,
+        client_first_bare: &str,
+        server_first: &str,
+    ) -> Result<Vec<u8>, SaslError> {
+        let mut nonce = None;
+        let mut salt = None;
+        let mut iterations = None;
+
+        for attribute in server_first.split(',') {
+            match attribute.split_once('=') {
+                Some(("r", value)) => nonce = Some(value),
+                Some(("s", value)) => salt = BASE64.decode(value).ok(),
+                Some(("i", value)) => iterations = value.parse::<u32>().ok(),
+                Some(("m", _)) => return Err(error("mandatory extension not supported")),
+                _ => {}
+            }
+        }
+
+        let nonce = nonce.ok_or_else(|| error("missing nonce"))?;
+        let salt = salt.ok_or_else(|| error("missing or invalid salt"))?;
+        let iterations = iterations.ok_or_else(|| error("missing or invalid iteration count"))?;
+
+        if !nonce.starts_with(&self.nonce) || nonce.len() == self.nonce.len() {
+            return Err(error("server nonce does not extend client nonce"));
+        }
+        if iterations < MIN_ITERATIONS {
+            return Err(error("iteration count too low"));
+        }
+
+        let client_final_without_proof = format!("c={},r={}", BASE64.encode(GS2_HEADER), nonce);
+        let auth_message = format!(
+            "{},{},{}",
+            client_first_bare, server_first, client_final_without_proof
+        );
+
+        let salted_password = hi(self.password.expose().as_bytes(), &salt, iterations);
+        let client_key = hmac(&salted_password, b"Client Key");
+        let stored_key = Sha256::digest(&client_key);
+        let client_signature = hmac(&stored_key, auth_message.as_bytes());
+        let proof = client_key
+            .iter()
+            .zip(client_signature)
+            .map(|(key, signature)| key ^ signature)
+            .collect::<Vec<_>>();
+
+        let server_key = hmac(&salted_password, b"Server Key");
+        self.state = State::ClientFinal {
+            server_signature: hmac(&server_key, auth_message.as_bytes()),
+        };
+
+        Ok(format!("{},p={}", client_final_without_proof, BASE64.encode(proof)).into_bytes())
+    }
+}
+
+impl SaslMechanism for ScramSha256 {
+    fn mechanism(&self) -> AuthMechanism {
+        AuthMechanism::ScramSha256
+    }
+
+    /// client-first-message = gs2-header client-first-message-bare
+    /// client-first-message-bare = "n=" saslname ",r=" c-nonce
+    fn initial_response(&mut self) -> Option<Vec<u8>> {
+        let username = self.username.replace('=', "=3D").replace(',', "=2C");
+        let client_first_bare = format!("n={},r={}", username, self.nonce);
+        let client_first = format!("{}{}", GS2_HEADER, client_first_bare);
+
+        self.state = State::ClientFirst { client_first_bare };
+        Some(client_first.into_bytes())
+    }
+
+    fn respond(&mut self, challenge: &[u8]) -> Result<Vec<u8>, SaslError> {
+        let challenge = core::str::from_utf8(challenge).map_err(|_| error("invalid UTF-8"))?;
+
+        match core::mem::replace(&mut self.state, State::Initial) {
+            // The server did not accept the initial response and sent an empty challenge.
+            State::Initial if challenge.is_empty() => Ok(self.initial_response().unwrap()),
+            State::ClientFirst { client_first_bare } => {
+                self.client_final(&client_first_bare, challenge)
+            }
+            // server-final-message = (server-error / verifier) ["," extensions]
+            State::ClientFinal { server_signature } => {
+                let verifier = challenge.split(',').next().unwrap_or_default();
+
+                match verifier.split_once('=') {
+                    Some(("v", value)) if BASE64.decode(value).ok() == Some(server_signature) => {
+                        self.state = State::Verified;
+                        Ok(Vec::new())
+                    }
+                    Some(("e", value)) => Err(error(value)),
+                    _ => Err(error("invalid server signature")),
+                }
+            }
+            _ => Err(SaslError::UnexpectedChallenge),
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.state == State::Verified
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    // HMAC accepts keys of any length.
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Hi(str, salt, i) is PBKDF2 with HMAC-SHA-256 and a single block (RFC 5802, 2.2.)
+fn hi(password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+    let mut block = salt.to_vec();
+    block.extend_from_slice(&1u32.to_be_bytes());
+
+    let mut u = hmac(password, &block);
+    let mut result = u.clone();
+    for _ in 1..iterations {
+        u = hmac(password, &u);
+        result.iter_mut().zip(&u).for_each(|(r, u)| *r ^= u);
+    }
+
+    result
+}
+
+fn error(message: &str) -> SaslError {
+    SaslError::Mechanism(message.to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SERVER_FIRST: &[u8] =
+        b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+
+    /// Example of RFC 7677, 3.
+    #[test]
+    fn test_scram_sha_256() {
+        let mut mechanism = ScramSha256::new("user", "pencil", "rOprNGfwEbeRWgbNEkqO");
+
+        assert_eq!(
+            mechanism.initial_response().unwrap(),
+            b"n,,n=user,r=rOprNGfwEbeRWgbNEkqO"
+        );
+        assert_eq!(
+            mechanism.respond(SERVER_FIRST).unwrap(),
+            &b"c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="[..]
+        );
+        assert!(!mechanism.is_complete());
+        assert_eq!(
+            mechanism
+                .respond(b"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=")
+                .unwrap(),
+            b""
+        );
+        assert!(mechanism.is_complete());
+    }
+
+    #[test]
+    fn test_scram_sha_256_errors() {
+        let mut mechanism = ScramSha256::new("user", "pencil", "rOprNGfwEbeRWgbNEkqO");
+        mechanism.initial_response();
+        mechanism.respond(SERVER_FIRST).unwrap();
+        assert!(mechanism.respond(b"v=AAAA").is_err());
+        assert!(!mechanism.is_complete());
+
+        let mut mechanism = ScramSha256::new("user", "pencil", "abc");
+        mechanism.initial_response();
+        assert!(mechanism.respond(b"r=xyz123,s=AAAA,i=4096").is_err());
+
+        let mut mechanism = ScramSha256::new("user", "pencil", "abc");
+        mechanism.initial_response();
+        assert!(mechanism.respond(b"r=abc123,s=AAAA,i=1").is_err());
+    }
+
+    #[test]
+    fn test_debug_redacts_password() {
+        let mechanism = ScramSha256::new("user", "pencil", "rOprNGfwEbeRWgbNEkqO");
+        assert!(!format!("{:?}", mechanism).contains("pencil"));
+    }
+}
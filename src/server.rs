@@ -0,0 +1,1707 @@
+//! Server-side session state (RFC 5321, 4.1.4.  Order of Commands)
+//!
+//! A [`Session`] does not perform any I/O and does not reply to accepted commands itself. It
+//! tracks the state of the session and the mail transaction and rejects commands that are sent
+//! out of order with "503 Bad sequence of commands".
+//!
+//! With [`Protocol::Lmtp`], the session follows LMTP (RFC 2033) instead: the client identifies
+//! itself with LHLO, and the server replies once per accepted recipient after the message
+//! content, see [`Session::data_end_with`].
+//!
+//! STARTTLS is accepted after EHLO, outside of a mail transaction. After it, the session refuses
+//! all commands until [`Session::tls_established`] is called, and the client has to identify
+//! itself again (RFC 3207, 4.2.). On a connection that is encrypted from the start, e.g.,
+//! submission on port 465 (RFC 8314, 3.3.), use [`Session::set_implicit_tls`] instead.
+//!
+//! AUTH is passed to the server as [`Event::Other`]. After a successful exchange, the server
+//! calls [`Session::set_authenticated`], which [`ServerProfile::Submission`] requires before MAIL.
+//!
+//! The session can't perform DNS lookups. The server supplies the reverse lookup of the client
+//! address with [`Session::set_connection`], and the addresses of the EHLO domain with
+//! [`Session::set_helo_addresses`], and the session records the mismatches as [`Observation`]s.
+//!
+//! The session counts unknown commands, and the server reports lines that don't parse with
+//! [`Session::record_syntax_error`], and input before the greeting with
+//! [`Session::record_early_talker`]. Past the thresholds of [`AbuseLimits`], the session
+//! recommends to slow down or disconnect the client, see [`Session::recommendation`].
+//!
+//! With the address of the client, the session provides the [`TransactionFingerprint`] for
+//! greylisting, see [`Session::greylist_with`].
+//!
+//! Messages declared with BODY=BINARYMIME must be sent with BDAT. DATA is rejected with "503",
+//! and the transaction then fails with "554" until it is reset (RFC 3030, 3.).
+
+use alloc::{string::String, vec, vec::Vec};
+use core::{fmt, net::IpAddr};
+
+use crate::{
+    policy::{DecisionPoint, Policy, Verdict},
+    AddressLiteral, Body, Capability, Command, EsmtpParam, ForwardPath, HeloIdentity, Limits, Path,
+    ReplyCode, Response, ReversePath, TextString,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    Smtp,
+    /// Local Mail Transfer Protocol (RFC 2033)
+    Lmtp,
+}
+
+/// The role of the server, which determines the commands it requires
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ServerProfile {
+    /// An MTA that accepts mail for its domains, or relays it, from any client
+    #[default]
+    Relay,
+    /// A message submission agent (RFC 6409)
+    ///
+    /// HELO is rejected with "502", as AUTH requires EHLO. MAIL is rejected with "530" until
+    /// [`Session::set_authenticated`] is called (RFC 4954, 6.), and RCPT with a source route with
+    /// "550".
+    Submission,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    /// The session was opened, but the client did not send EHLO or HELO yet.
+    Connected,
+    /// The client identified itself, no mail transaction is in progress.
+    Ready,
+    /// MAIL was accepted.
+    Mail,
+    /// MAIL and at least one RCPT were accepted.
+    Rcpt,
+    /// BDAT without LAST was accepted, more chunks are expected.
+    Chunking,
+    /// DATA or BDAT LAST was accepted, the server is receiving the message content.
+    Data,
+    /// STARTTLS was accepted, the TLS handshake is pending.
+    TlsHandshake,
+    /// QUIT was accepted.
+    Closed,
+}
+
+/// A command that was accepted in the current state of the session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// EHLO or HELO, or LHLO in LMTP. Aborts any mail transaction in progress.
+    Hello {
+        extended: bool,
+        domain_or_address: HeloIdentity,
+    },
+    /// MAIL. Starts a new mail transaction.
+    Mail {
+        reverse_path: ReversePath,
+        parameters: Vec<EsmtpParam>,
+    },
+    /// RCPT. Adds a recipient to the mail transaction.
+    Rcpt {
+        forward_path: ForwardPath,
+        parameters: Vec<EsmtpParam>,
+    },
+    /// DATA. The server should send "354" and receive the message content (see
+    /// [`DotStuffDecoder`](crate::data::DotStuffDecoder)), followed by a call to
+    /// [`Session::data_end`]. The content can be passed to a [`DataSink`] as it is received
+    /// with [`DotStuffDecoder::decode_into`](crate::data::DotStuffDecoder::decode_into).
+    ///
+    /// [`DataSink`]: crate::data::DataSink
+    Data,
+    /// BDAT. The server should receive `size` octets of message content and reply, e.g., by
+    /// passing them to [`DataSink::write_chunk`]. After the last chunk, it should call
+    /// [`Session::data_end`].
+    ///
+    /// [`DataSink::write_chunk`]: crate::data::DataSink::write_chunk
+    Bdat { size: u64, last: bool },
+    /// RSET. Aborts any mail transaction in progress, see [`Session::poll_aborted`].
+    Reset,
+    /// STARTTLS. The server should send "220", perform the TLS handshake, and call
+    /// [`Session::tls_established`].
+    ///
+    /// Any input that was received after the STARTTLS command must be discarded, as it was not
+    /// protected by TLS.
+    NeedsTlsUpgrade,
+    /// QUIT. The server should send "221" and close the connection. Any mail transaction in
+    /// progress is abandoned, and nothing is delivered, see [`Session::poll_aborted`].
+    Quit,
+    /// Any other command. These do not affect the state of the session.
+    Other(Command),
+}
+
+/// The envelope of a mail transaction.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Transaction {
+    pub reverse_path: ReversePath,
+    pub parameters: Vec<EsmtpParam>,
+    pub recipients: Vec<Recipient>,
+    /// The observations about the client when MAIL was accepted, see [`Session::observations`]
+    pub observations: Vec<Observation>,
+    /// Number of RCPT commands that were rejected because of [`Limits::recipients`]
+    pub excess_recipients: usize,
+}
+
+impl Transaction {
+    /// The BODY parameter of MAIL, if any (RFC 6152, RFC 3030)
+    pub fn body(&self) -> Option<Body> {
+        self.parameters
+            .iter()
+            .find_map(|parameter| match parameter {
+                EsmtpParam::Body(body) => Some(*body),
+                _ => None,
+            })
+    }
+}
+
+/// A mail transaction that ended before its content was complete
+///
+/// The server should discard what it stored for the transaction, e.g., the chunks of BDAT
+/// that were already received.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransactionAborted {
+    pub transaction: Transaction,
+    pub reason: AbortReason,
+}
+
+/// The command that ended the mail transaction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AbortReason {
+    Reset,
+    /// EHLO, HELO, or LHLO
+    Hello,
+    /// The TLS handshake following STARTTLS
+    TlsUpgrade,
+    Quit,
+}
+
+/// The "triplet" that greylisting policies key on (RFC 6647, 2.)
+///
+/// The paths are as sent by the client. Policies usually mask the client address, e.g., to
+/// its /24 network, as senders retry from other addresses of their pool.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TransactionFingerprint {
+    /// The peer address of [`Session::set_connection`]
+    pub client: IpAddr,
+    pub reverse_path: ReversePath,
+    /// The first accepted recipient
+    pub forward_path: ForwardPath,
+}
+
+/// The result of a greylisting policy, see [`Session::greylist_with`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GreylistDecision {
+    Accept,
+    /// Reject the recipient temporarily, expecting a legitimate sender to retry.
+    Defer,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recipient {
+    pub forward_path: ForwardPath,
+    pub parameters: Vec<EsmtpParam>,
+}
+
+/// What the server knows about the client from outside the session, e.g., from the DNS
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Connection {
+    /// The address the client connected from
+    pub peer: IpAddr,
+    /// The name of the PTR record of the peer address, if any
+    pub ptr: Option<String>,
+    /// The addresses the PTR name resolves to
+    pub ptr_addresses: Vec<IpAddr>,
+}
+
+impl Connection {
+    /// The PTR name if it resolves back to the peer address, e.g., for [`Received::client_name`]
+    ///
+    /// [`Received::client_name`]: crate::trace::Received::client_name
+    pub fn confirmed_name(&self) -> Option<&str> {
+        match self.ptr_addresses.contains(&self.peer) {
+            true => self.ptr.as_deref(),
+            false => None,
+        }
+    }
+}
+
+/// A mismatch between the identity the client claims and its connection
+///
+/// These are not reasons to reject mail by themselves (RFC 5321, 4.1.4.), but inputs to a
+/// policy, or comments for the trace header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Observation {
+    /// The peer address has no PTR record.
+    NoPtr,
+    /// The PTR name does not resolve back to the peer address.
+    PtrMismatch { ptr: String },
+    /// The domain of EHLO or HELO does not resolve to the peer address.
+    HeloMismatch { domain: String },
+    /// The address literal of EHLO or HELO is not the peer address.
+    HeloLiteralMismatch { literal: AddressLiteral },
+}
+
+impl fmt::Display for Observation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Observation::NoPtr => write!(f, "no PTR record"),
+            Observation::PtrMismatch { ptr } => {
+                write!(f, "PTR {} does not resolve to the client", ptr)
+            }
+            Observation::HeloMismatch { domain } => {
+                write!(f, "HELO {} does not resolve to the client", domain)
+            }
+            Observation::HeloLiteralMismatch { literal } => {
+                write!(f, "HELO {} is not the client address", literal)
+            }
+        }
+    }
+}
+
+/// What to do with a misbehaving client
+///
+/// Ordered by severity, so that the recommendation of a session is the maximum of its counters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Recommendation {
+    /// Delay the replies, e.g., by a second per error, to slow down probing.
+    SlowDown,
+    /// Reply with "421" and close the connection.
+    Disconnect,
+}
+
+/// When to slow down or disconnect a client, counting errors of one kind
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Threshold {
+    /// Number of errors from which on to slow down, if any
+    pub slow_down: Option<u32>,
+    /// Number of errors from which on to disconnect, if any
+    pub disconnect: Option<u32>,
+}
+
+impl Threshold {
+    fn recommendation(&self, count: u32) -> Option<Recommendation> {
+        let reached = |limit: Option<u32>| limit.is_some_and(|limit| count >= limit);
+
+        match (reached(self.disconnect), reached(self.slow_down)) {
+            (true, _) => Some(Recommendation::Disconnect),
+            (false, true) => Some(Recommendation::SlowDown),
+            (false, false) => None,
+        }
+    }
+}
+
+impl Default for Threshold {
+    /// Like the defaults of Postfix' `smtpd_soft_error_limit` and `smtpd_hard_error_limit`
+    fn default() -> Self {
+        Self {
+            slow_down: Some(10),
+            disconnect: Some(20),
+        }
+    }
+}
+
+/// The thresholds of the anti-abuse counters of a session
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AbuseLimits {
+    pub syntax_errors: Threshold,
+    pub unknown_commands: Threshold,
+    /// What to do with a client that sends before the greeting, if anything
+    ///
+    /// Clients must wait for the greeting (RFC 5321, 3.1.), which spam bots often don't.
+    pub early_talker: Option<Recommendation>,
+}
+
+impl Default for AbuseLimits {
+    fn default() -> Self {
+        Self {
+            syntax_errors: Threshold::default(),
+            unknown_commands: Threshold::default(),
+            early_talker: Some(Recommendation::Disconnect),
+        }
+    }
+}
+
+/// The misbehavior of the client so far
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AbuseCounters {
+    /// Lines that did not parse, as reported by [`Session::record_syntax_error`]
+    pub syntax_errors: u32,
+    /// Commands that are unknown, or not part of the protocol of the session
+    pub unknown_commands: u32,
+    /// Whether the client sent before the greeting, as reported by
+    /// [`Session::record_early_talker`]
+    pub early_talker: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Session {
+    protocol: Protocol,
+    profile: ServerProfile,
+    limits: Limits,
+    max_size: Option<u32>,
+    state: State,
+    transaction: Option<Transaction>,
+    tls: bool,
+    implicit_tls: bool,
+    authenticated: bool,
+    /// DATA was sent for a BINARYMIME message, the transaction must be reset.
+    failed: bool,
+    connection: Option<Connection>,
+    helo: Option<HeloIdentity>,
+    /// The addresses the domain of `helo` resolves to, if known
+    helo_addresses: Option<Vec<IpAddr>>,
+    /// The transaction ended by the last command, see [`Session::poll_aborted`]
+    aborted: Option<TransactionAborted>,
+    abuse_limits: AbuseLimits,
+    abuse_counters: AbuseCounters,
+    /// The step the last transition is subject to, see [`Session::apply`]
+    decision: Option<DecisionPoint>,
+    undo: Option<Undo>,
+}
+
+/// How to revert the last transition, see [`Session::reject`].
+#[derive(Clone, Copy, Debug)]
+enum Undo {
+    Mail,
+    Rcpt,
+    Data,
+    Bdat(State),
+    StartTls(State),
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::with_protocol(Protocol::Smtp)
+    }
+
+    pub fn with_protocol(protocol: Protocol) -> Self {
+        Self {
+            protocol,
+            profile: ServerProfile::default(),
+            limits: Limits::default(),
+            max_size: None,
+            state: State::Connected,
+            transaction: None,
+            tls: false,
+            implicit_tls: false,
+            authenticated: false,
+            failed: false,
+            connection: None,
+            helo: None,
+            helo_addresses: None,
+            aborted: None,
+            abuse_limits: AbuseLimits::default(),
+            abuse_counters: AbuseCounters::default(),
+            decision: None,
+            undo: None,
+        }
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    pub fn set_profile(&mut self, profile: ServerProfile) {
+        self.profile = profile;
+    }
+
+    pub fn profile(&self) -> ServerProfile {
+        self.profile
+    }
+
+    /// Signal that the AUTH exchange succeeded.
+    ///
+    /// Further AUTH commands are rejected with "503" (RFC 4954, 4.). The authentication is
+    /// discarded with the TLS handshake following STARTTLS.
+    pub fn set_authenticated(&mut self) {
+        self.authenticated = true;
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    /// Set the limits, of which the session enforces [`Limits::recipients`].
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Set the maximum message size, which the server should advertise with SIZE (RFC 1870).
+    ///
+    /// MAIL commands that declare a larger size with the SIZE parameter are rejected with "552".
+    pub fn set_max_size(&mut self, max_size: Option<u32>) {
+        self.max_size = max_size;
+    }
+
+    pub fn max_size(&self) -> Option<u32> {
+        self.max_size
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Set whether the connection used TLS from the start (implicit TLS), rather than STARTTLS.
+    ///
+    /// Call this before the first command. STARTTLS is then rejected with "503", and the session
+    /// counts as TLS, e.g., for [`trace::Protocol::of_session`](crate::trace::Protocol::of_session).
+    pub fn set_implicit_tls(&mut self, implicit_tls: bool) {
+        self.implicit_tls = implicit_tls;
+        self.tls = implicit_tls;
+    }
+
+    pub fn is_implicit_tls(&self) -> bool {
+        self.implicit_tls
+    }
+
+    /// Whether the connection uses TLS, i.e., the TLS handshake following STARTTLS completed,
+    /// or the connection uses implicit TLS.
+    pub fn is_tls(&self) -> bool {
+        self.tls
+    }
+
+    /// The capabilities to advertise in the reply to EHLO or LHLO, out of those the server
+    /// supports
+    ///
+    /// STARTTLS is dropped once the connection uses TLS (RFC 3207, 4.2.).
+    pub fn capabilities<I>(&self, supported: I) -> Vec<Capability>
+    where
+        I: IntoIterator<Item = Capability>,
+    {
+        supported
+            .into_iter()
+            .filter(|capability| !(self.tls && *capability == Capability::StartTls))
+            .collect()
+    }
+
+    /// Supply the peer address and its reverse lookup.
+    pub fn set_connection(&mut self, connection: Connection) {
+        self.connection = Some(connection);
+    }
+
+    pub fn connection(&self) -> Option<&Connection> {
+        self.connection.as_ref()
+    }
+
+    /// Supply the addresses the domain of the last EHLO, HELO, or LHLO resolves to.
+    ///
+    /// The addresses are forgotten when the client identifies itself again.
+    pub fn set_helo_addresses(&mut self, addresses: Vec<IpAddr>) {
+        self.helo_addresses = Some(addresses);
+    }
+
+    /// The identity the client sent with the last EHLO, HELO, or LHLO
+    pub fn helo(&self) -> Option<&HeloIdentity> {
+        self.helo.as_ref()
+    }
+
+    /// The mismatches between the identity of the client and its connection
+    ///
+    /// Requires [`Session::set_connection`]. The domain of EHLO is only checked once its
+    /// addresses were supplied with [`Session::set_helo_addresses`].
+    pub fn observations(&self) -> Vec<Observation> {
+        let mut observations = Vec::new();
+        let Some(connection) = &self.connection else {
+            return observations;
+        };
+
+        match &connection.ptr {
+            None => observations.push(Observation::NoPtr),
+            Some(ptr) if connection.confirmed_name().is_none() => {
+                observations.push(Observation::PtrMismatch { ptr: ptr.clone() })
+            }
+            Some(_) => {}
+        }
+
+        match (&self.helo, &self.helo_addresses) {
+            (Some(HeloIdentity::Domain(domain)), Some(addresses))
+                if !addresses.contains(&connection.peer) =>
+            {
+                observations.push(Observation::HeloMismatch {
+                    domain: domain.clone(),
+                })
+            }
+            (Some(HeloIdentity::AddressLiteral(literal @ AddressLiteral::Ip(address))), _)
+                if *address != connection.peer =>
+            {
+                observations.push(Observation::HeloLiteralMismatch {
+                    literal: literal.clone(),
+                })
+            }
+            _ => {}
+        }
+
+        observations
+    }
+
+    /// Number of recipients accepted in the mail transaction in progress
+    ///
+    /// RCPT commands beyond [`Limits::recipients`] are rejected with "452 4.5.3", and counted in
+    /// [`Transaction::excess_recipients`].
+    pub fn recipient_count(&self) -> usize {
+        self.transaction
+            .as_ref()
+            .map_or(0, |transaction| transaction.recipients.len())
+    }
+
+    /// The mail transaction in progress, if any.
+    pub fn transaction(&self) -> Option<&Transaction> {
+        self.transaction.as_ref()
+    }
+
+    /// The fingerprint of the mail transaction in progress
+    ///
+    /// Requires [`Session::set_connection`] and an accepted recipient.
+    pub fn fingerprint(&self) -> Option<TransactionFingerprint> {
+        let connection = self.connection.as_ref()?;
+        let transaction = self.transaction.as_ref()?;
+        let recipient = transaction.recipients.first()?;
+
+        Some(TransactionFingerprint {
+            client: connection.peer,
+            reverse_path: transaction.reverse_path.clone(),
+            forward_path: recipient.forward_path.clone(),
+        })
+    }
+
+    /// Apply a greylisting policy after the first RCPT of a mail transaction was accepted.
+    ///
+    /// Call this after [`Session::handle`] returned [`Event::Rcpt`]. `decide` is only called
+    /// for the first recipient, with its [`Session::fingerprint`]. When it defers, the RCPT is
+    /// rejected as with [`Session::reject`], and the reply "451 4.7.1" to send instead is
+    /// returned.
+    pub fn greylist_with<F>(&mut self, decide: F) -> Option<Response>
+    where
+        F: FnOnce(&TransactionFingerprint) -> GreylistDecision,
+    {
+        if !matches!(self.undo, Some(Undo::Rcpt)) || self.recipient_count() != 1 {
+            return None;
+        }
+
+        match decide(&self.fingerprint()?) {
+            GreylistDecision::Accept => None,
+            GreylistDecision::Defer => {
+                self.reject();
+                Some(greylisted())
+            }
+        }
+    }
+
+    /// Take the mail transaction that was aborted by the last call to [`Session::handle`] or
+    /// [`Session::tls_established`], if any.
+    ///
+    /// A transaction is aborted by RSET, EHLO, HELO, LHLO, STARTTLS, or QUIT while it is in
+    /// progress, i.e., after MAIL and before the end of the content.
+    pub fn poll_aborted(&mut self) -> Option<TransactionAborted> {
+        self.aborted.take()
+    }
+
+    pub fn set_abuse_limits(&mut self, abuse_limits: AbuseLimits) {
+        self.abuse_limits = abuse_limits;
+    }
+
+    pub fn abuse_limits(&self) -> &AbuseLimits {
+        &self.abuse_limits
+    }
+
+    pub fn abuse_counters(&self) -> AbuseCounters {
+        self.abuse_counters
+    }
+
+    /// Count a line that could not be parsed as a command, e.g., after
+    /// [`DecodeError::Syntax`](crate::decoder::DecodeError::Syntax), and return the resulting
+    /// recommendation.
+    pub fn record_syntax_error(&mut self) -> Option<Recommendation> {
+        self.abuse_counters.syntax_errors = self.abuse_counters.syntax_errors.saturating_add(1);
+        self.recommendation()
+    }
+
+    /// Signal that the client sent data before the greeting, and return the resulting
+    /// recommendation.
+    ///
+    /// The session can't tell, as it does not know when the greeting was sent. A server that
+    /// delays its greeting checks for input in the meantime.
+    pub fn record_early_talker(&mut self) -> Option<Recommendation> {
+        self.abuse_counters.early_talker = true;
+        self.recommendation()
+    }
+
+    /// What to do with the client, given its misbehavior so far and the [`AbuseLimits`]
+    ///
+    /// Check this after each command that was rejected, or reported with
+    /// [`Session::record_syntax_error`].
+    pub fn recommendation(&self) -> Option<Recommendation> {
+        let AbuseCounters {
+            syntax_errors,
+            unknown_commands,
+            early_talker,
+        } = self.abuse_counters;
+        let limits = &self.abuse_limits;
+
+        [
+            limits.syntax_errors.recommendation(syntax_errors),
+            limits.unknown_commands.recommendation(unknown_commands),
+            limits.early_talker.filter(|_| early_talker),
+        ]
+        .into_iter()
+        .max()
+        .flatten()
+    }
+
+    /// Advance the session with a command received from the client.
+    ///
+    /// Returns the reply to send when the command is not allowed in the current state. When the
+    /// command is allowed, the session assumes that the server accepts it. Use
+    /// [`Session::reject`] when the server rejects it nonetheless.
+    pub fn handle(&mut self, command: Command) -> Result<Event, Response> {
+        #[cfg(feature = "tracing")]
+        let (from, name) = (self.state, command.name().to_owned());
+
+        let result = self.transition(command);
+
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok(_) => tracing::trace!(command = name, ?from, to = ?self.state, "accepted command"),
+            Err(reply) => tracing::debug!(
+                command = name,
+                state = ?from,
+                code = u16::from(reply.code()),
+                "rejected command"
+            ),
+        }
+
+        result
+    }
+
+    /// Like [`Session::handle`], but also ask `policy` about the accepted command.
+    ///
+    /// When the policy does not accept, the transition is reverted, and the reply to send is
+    /// returned, see [`Session::apply`].
+    pub fn handle_with<P>(&mut self, command: Command, policy: &mut P) -> Result<Event, Response>
+    where
+        P: Policy + ?Sized,
+    {
+        let event = self.handle(command)?;
+
+        let verdict = match (&event, self.decision) {
+            (
+                Event::Hello {
+                    domain_or_address, ..
+                },
+                _,
+            ) => policy.on_ehlo(self, domain_or_address),
+            (Event::Mail { reverse_path, .. }, _) => policy.on_mail(self, reverse_path),
+            (Event::Rcpt { forward_path, .. }, _) => policy.on_rcpt(self, forward_path),
+            (_, Some(DecisionPoint::DataStart)) => policy.on_data_start(self),
+            _ => Verdict::Accept,
+        };
+        self.apply(verdict)?;
+
+        Ok(event)
+    }
+
+    /// The step the last call to [`Session::handle`] or [`Session::data_end`] is subject to,
+    /// if any
+    pub fn decision_point(&self) -> Option<DecisionPoint> {
+        self.decision
+    }
+
+    /// Apply the verdict of a policy on the [`Session::decision_point`].
+    ///
+    /// Returns the reply to send when the verdict is not to accept. A rejected EHLO, HELO, or
+    /// LHLO leaves the client unidentified, and a rejected MAIL, RCPT, DATA, or BDAT is reverted
+    /// as with [`Session::reject`]. Has no effect without a decision point, or when it was
+    /// already applied.
+    pub fn apply(&mut self, verdict: Verdict) -> Result<(), Response> {
+        let Some(point) = self.decision.take() else {
+            return Ok(());
+        };
+        let Some(reply) = point.reply(verdict) else {
+            return Ok(());
+        };
+
+        match point {
+            DecisionPoint::Ehlo => {
+                self.state = State::Connected;
+                self.helo = None;
+                self.helo_addresses = None;
+            }
+            DecisionPoint::DataEnd => {}
+            DecisionPoint::Mail | DecisionPoint::Rcpt | DecisionPoint::DataStart => self.reject(),
+        }
+        Err(reply)
+    }
+
+    fn transition(&mut self, command: Command) -> Result<Event, Response> {
+        self.undo = None;
+        self.aborted = None;
+        self.decision = None;
+
+        match (self.state, command) {
+            (State::Closed | State::Data | State::TlsHandshake, _) => Err(bad_sequence()),
+            (
+                _,
+                Command::Mail { .. } | Command::Rcpt { .. } | Command::Data | Command::Bdat { .. },
+            ) if self.failed => Err(transaction_failed()),
+            (_, Command::Unknown { .. }) => Err(self.unknown_command()),
+            // LHLO replaces EHLO and HELO in LMTP (RFC 2033, 4.1.)
+            (_, Command::Lhlo { .. }) if self.protocol == Protocol::Smtp => {
+                Err(self.unknown_command())
+            }
+            (_, Command::Ehlo { .. }) | (_, Command::Helo { .. })
+                if self.protocol == Protocol::Lmtp =>
+            {
+                Err(self.unknown_command())
+            }
+            (_, Command::Ehlo { domain_or_address } | Command::Lhlo { domain_or_address }) => {
+                self.reset(State::Ready, AbortReason::Hello);
+                self.set_helo(&domain_or_address);
+                self.decision = Some(DecisionPoint::Ehlo);
+                Ok(Event::Hello {
+                    extended: true,
+                    domain_or_address,
+                })
+            }
+            (_, Command::Helo { .. }) if self.profile == ServerProfile::Submission => {
+                Err(ehlo_required())
+            }
+            (_, Command::Helo { domain_or_address }) => {
+                self.reset(State::Ready, AbortReason::Hello);
+                self.set_helo(&domain_or_address);
+                self.decision = Some(DecisionPoint::Ehlo);
+                Ok(Event::Hello {
+                    extended: false,
+                    domain_or_address,
+                })
+            }
+            (State::Ready, Command::Mail { .. })
+                if self.profile == ServerProfile::Submission && !self.authenticated =>
+            {
+                Err(authentication_required())
+            }
+            (
+                State::Ready,
+                Command::Mail {
+                    reverse_path,
+                    parameters,
+                },
+            ) => {
+                let declared = parameters.iter().find_map(|parameter| match parameter {
+                    EsmtpParam::Size(size) => Some(*size),
+                    _ => None,
+                });
+                if let (Some(declared), Some(max_size)) = (declared, self.max_size) {
+                    if declared > max_size {
+                        return Err(size_exceeded());
+                    }
+                }
+
+                self.state = State::Mail;
+                self.transaction = Some(Transaction {
+                    reverse_path: reverse_path.clone(),
+                    parameters: parameters.clone(),
+                    recipients: Vec::new(),
+                    observations: self.observations(),
+                    excess_recipients: 0,
+                });
+                self.undo = Some(Undo::Mail);
+                self.decision = Some(DecisionPoint::Mail);
+                Ok(Event::Mail {
+                    reverse_path,
+                    parameters,
+                })
+            }
+            (
+                State::Mail | State::Rcpt,
+                Command::Rcpt {
+                    forward_path: ForwardPath::Path(Path { route: Some(_), .. }),
+                    ..
+                },
+            ) if self.profile == ServerProfile::Submission => Err(source_route_rejected()),
+            (
+                State::Mail | State::Rcpt,
+                Command::Rcpt {
+                    forward_path,
+                    parameters,
+                },
+            ) => {
+                if self.recipient_count() >= self.limits.recipients {
+                    if let Some(transaction) = self.transaction.as_mut() {
+                        transaction.excess_recipients += 1;
+                    }
+                    return Err(too_many_recipients());
+                }
+
+                self.state = State::Rcpt;
+                if let Some(transaction) = self.transaction.as_mut() {
+                    transaction.recipients.push(Recipient {
+                        forward_path: forward_path.clone(),
+                        parameters: parameters.clone(),
+                    });
+                }
+                self.undo = Some(Undo::Rcpt);
+                self.decision = Some(DecisionPoint::Rcpt);
+                Ok(Event::Rcpt {
+                    forward_path,
+                    parameters,
+                })
+            }
+            (State::Rcpt, Command::Data) if self.is_binary() => {
+                self.failed = true;
+                Err(binary_requires_bdat())
+            }
+            (State::Rcpt, Command::Data) => {
+                self.state = State::Data;
+                self.undo = Some(Undo::Data);
+                self.decision = Some(DecisionPoint::DataStart);
+                Ok(Event::Data)
+            }
+            (state @ (State::Rcpt | State::Chunking), Command::Bdat { size, last }) => {
+                self.state = match last {
+                    true => State::Data,
+                    false => State::Chunking,
+                };
+                self.undo = Some(Undo::Bdat(state));
+                if state == State::Rcpt {
+                    self.decision = Some(DecisionPoint::DataStart);
+                }
+                Ok(Event::Bdat { size, last })
+            }
+            (_, Command::Mail { .. })
+            | (_, Command::Rcpt { .. })
+            | (_, Command::Data)
+            | (_, Command::Bdat { .. }) => Err(bad_sequence()),
+            // AUTH requires EHLO, and is not allowed twice or during a mail transaction
+            // (RFC 4954, 4.)
+            (
+                State::Connected | State::Mail | State::Rcpt | State::Chunking,
+                Command::Auth { .. },
+            ) => Err(bad_sequence()),
+            (_, Command::Auth { .. }) if self.authenticated => Err(bad_sequence()),
+            // A TLS session is already active (RFC 3207, 4.)
+            (_, Command::StartTls) if self.tls => Err(bad_sequence()),
+            // STARTTLS requires EHLO, and discards any mail transaction in progress (RFC 3207, 4.2.)
+            (State::Connected | State::Mail | State::Rcpt | State::Chunking, Command::StartTls) => {
+                Err(bad_sequence())
+            }
+            (state, Command::StartTls) => {
+                self.state = State::TlsHandshake;
+                self.undo = Some(Undo::StartTls(state));
+                Ok(Event::NeedsTlsUpgrade)
+            }
+            (state, Command::Rset) => {
+                let state = match state {
+                    State::Connected => State::Connected,
+                    _ => State::Ready,
+                };
+                self.reset(state, AbortReason::Reset);
+                Ok(Event::Reset)
+            }
+            (_, Command::Quit) => {
+                self.reset(State::Closed, AbortReason::Quit);
+                Ok(Event::Quit)
+            }
+            (_, command) => Ok(Event::Other(command)),
+        }
+    }
+
+    /// Revert the transition made by the last call to [`Session::handle`].
+    ///
+    /// Use this when the server rejects a MAIL, RCPT, DATA, BDAT, or STARTTLS command for reasons other than
+    /// the order of commands, e.g., because the recipient is unknown. Has no effect for other
+    /// commands.
+    pub fn reject(&mut self) {
+        match self.undo.take() {
+            Some(Undo::Mail) => {
+                self.state = State::Ready;
+                self.transaction = None;
+            }
+            Some(Undo::Rcpt) => {
+                if let Some(transaction) = self.transaction.as_mut() {
+                    transaction.recipients.pop();
+
+                    if transaction.recipients.is_empty() {
+                        self.state = State::Mail;
+                    }
+                }
+            }
+            Some(Undo::Data) => self.state = State::Rcpt,
+            Some(Undo::Bdat(state)) | Some(Undo::StartTls(state)) => self.state = state,
+            None => {}
+        }
+    }
+
+    /// Signal that the TLS handshake following STARTTLS completed.
+    ///
+    /// The session is reset to its initial state, i.e., the client has to send EHLO again, and
+    /// any mail transaction is discarded. Has no effect unless the session is in
+    /// [`State::TlsHandshake`].
+    pub fn tls_established(&mut self) {
+        if self.state == State::TlsHandshake {
+            self.undo = None;
+            self.tls = true;
+            self.authenticated = false;
+            self.reset(State::Connected, AbortReason::TlsUpgrade);
+            self.helo = None;
+            self.helo_addresses = None;
+        }
+    }
+
+    /// Finish receiving the message content.
+    ///
+    /// Returns the envelope of the completed mail transaction, or `None` when the session was
+    /// not receiving message content.
+    pub fn data_end(&mut self) -> Option<Transaction> {
+        if self.state != State::Data {
+            return None;
+        }
+
+        self.undo = None;
+        self.decision = Some(DecisionPoint::DataEnd);
+        self.state = State::Ready;
+        self.transaction.take()
+    }
+
+    /// Like [`Session::data_end`], but also build the replies to the message content.
+    ///
+    /// In SMTP, `reply` is called once with `None` for the whole transaction. In LMTP, it is
+    /// called for every accepted recipient, in the order of the RCPT commands, and each reply
+    /// must be sent (RFC 2033, 4.2.)
+    pub fn data_end_with<F>(&mut self, mut reply: F) -> Option<(Transaction, Vec<Response>)>
+    where
+        F: FnMut(Option<&Recipient>) -> Response,
+    {
+        let transaction = self.data_end()?;
+
+        let replies = match self.protocol {
+            Protocol::Smtp => vec![reply(None)],
+            Protocol::Lmtp => transaction
+                .recipients
+                .iter()
+                .map(|recipient| reply(Some(recipient)))
+                .collect(),
+        };
+
+        Some((transaction, replies))
+    }
+
+    fn reset(&mut self, state: State, reason: AbortReason) {
+        self.state = state;
+        self.failed = false;
+        self.aborted = self
+            .transaction
+            .take()
+            .map(|transaction| TransactionAborted {
+                transaction,
+                reason,
+            });
+    }
+
+    fn unknown_command(&mut self) -> Response {
+        self.abuse_counters.unknown_commands =
+            self.abuse_counters.unknown_commands.saturating_add(1);
+        unrecognized()
+    }
+
+    fn set_helo(&mut self, identity: &HeloIdentity) {
+        self.helo = Some(identity.clone());
+        self.helo_addresses = None;
+    }
+
+    fn is_binary(&self) -> bool {
+        self.transaction
+            .as_ref()
+            .is_some_and(|transaction| transaction.body() == Some(Body::BinaryMime))
+    }
+}
+
+fn bad_sequence() -> Response {
+    Response::Other {
+        code: ReplyCode::BadSequence,
+        lines: vec![TextString::new("Bad sequence of commands").unwrap()],
+    }
+}
+
+/// RFC 4954, 6.
+fn authentication_required() -> Response {
+    Response::Other {
+        code: ReplyCode::AUTHENTICATION_REQUIRED_530,
+        lines: vec![TextString::new("Authentication required").unwrap()],
+    }
+}
+
+fn ehlo_required() -> Response {
+    Response::Other {
+        code: ReplyCode::CommandNotImplemented,
+        lines: vec![TextString::new("Send EHLO").unwrap()],
+    }
+}
+
+fn source_route_rejected() -> Response {
+    Response::Other {
+        code: ReplyCode::MailboxPermanentlyUnavailable,
+        lines: vec![TextString::new("Source routes are not accepted").unwrap()],
+    }
+}
+
+/// RFC 3030, 3.
+fn binary_requires_bdat() -> Response {
+    Response::Other {
+        code: ReplyCode::BadSequence,
+        lines: vec![TextString::new("BINARYMIME requires BDAT").unwrap()],
+    }
+}
+
+fn transaction_failed() -> Response {
+    Response::Other {
+        code: ReplyCode::TransactionFailed,
+        lines: vec![TextString::new("Transaction failed, send RSET").unwrap()],
+    }
+}
+
+/// RFC 1870, 6.1.
+pub(crate) fn size_exceeded() -> Response {
+    Response::Other {
+        code: ReplyCode::ExceededStorageAllocation,
+        lines: vec![TextString::new("Message size exceeds fixed maximum message size").unwrap()],
+    }
+}
+
+/// RFC 5321, 4.5.3.1.10., and RFC 3463, 3.6.
+fn too_many_recipients() -> Response {
+    Response::Other {
+        code: ReplyCode::InsufficientStorage,
+        lines: vec![TextString::new("4.5.3 Too many recipients").unwrap()],
+    }
+}
+
+/// RFC 6647, 2.1.
+fn greylisted() -> Response {
+    Response::Other {
+        code: ReplyCode::ProcessingError,
+        lines: vec![TextString::new("4.7.1 Greylisted, try again later").unwrap()],
+    }
+}
+
+fn unrecognized() -> Response {
+    Response::Other {
+        code: ReplyCode::SyntaxError,
+        lines: vec![TextString::new("Command unrecognized").unwrap()],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mail(reverse_path: &str) -> Command {
+        Command::Mail {
+            reverse_path: reverse_path.into(),
+            parameters: vec![],
+        }
+    }
+
+    fn rcpt(forward_path: &str) -> Command {
+        Command::Rcpt {
+            forward_path: forward_path.into(),
+            parameters: vec![],
+        }
+    }
+
+    fn ehlo() -> Command {
+        Command::Ehlo {
+            domain_or_address: HeloIdentity::Domain("example.org".into()),
+        }
+    }
+
+    #[test]
+    fn test_transaction() {
+        let mut session = Session::new();
+
+        assert!(session.handle(ehlo()).is_ok());
+        assert!(session.handle(mail("a@example.org")).is_ok());
+        assert!(session.handle(rcpt("b@example.org")).is_ok());
+        assert!(session.handle(rcpt("c@example.org")).is_ok());
+        assert_eq!(session.handle(Command::Data), Ok(Event::Data));
+        assert_eq!(session.state(), State::Data);
+
+        let transaction = session.data_end().unwrap();
+        assert_eq!(transaction.reverse_path, "a@example.org".into());
+        assert_eq!(transaction.recipients.len(), 2);
+        assert_eq!(session.state(), State::Ready);
+        assert_eq!(session.handle(Command::Quit), Ok(Event::Quit));
+    }
+
+    #[test]
+    fn test_bad_sequence() {
+        let mut session = Session::new();
+
+        let response = session.handle(mail("a@example.org")).unwrap_err();
+        assert!(matches!(
+            response,
+            Response::Other {
+                code: ReplyCode::BadSequence,
+                ..
+            }
+        ));
+
+        assert!(session.handle(ehlo()).is_ok());
+        assert!(session.handle(rcpt("b@example.org")).is_err());
+        assert!(session.handle(Command::Data).is_err());
+        assert!(session.handle(mail("a@example.org")).is_ok());
+        assert!(session.handle(mail("a@example.org")).is_err());
+        assert!(session.handle(Command::Data).is_err());
+        assert!(session.handle(Command::Noop { argument: None }).is_ok());
+
+        let unknown = Command::Unknown {
+            verb: "FOO".into(),
+            arguments: None,
+        };
+        assert_eq!(
+            session.handle(unknown).unwrap_err().code(),
+            ReplyCode::SyntaxError
+        );
+        assert_eq!(session.state(), State::Mail);
+
+        let extension = Command::Extension {
+            verb: "XUSR".into(),
+            arguments: None,
+        };
+        assert_eq!(
+            session.handle(extension.clone()),
+            Ok(Event::Other(extension))
+        );
+        assert_eq!(session.state(), State::Mail);
+    }
+
+    #[test]
+    fn test_reset_and_reject() {
+        let mut session = Session::new();
+
+        assert!(session.handle(ehlo()).is_ok());
+        assert!(session.handle(mail("a@example.org")).is_ok());
+        assert!(session.handle(rcpt("b@example.org")).is_ok());
+        session.reject();
+        assert_eq!(session.state(), State::Mail);
+        assert!(session.handle(Command::Data).is_err());
+
+        assert_eq!(session.handle(Command::Rset), Ok(Event::Reset));
+        assert_eq!(session.state(), State::Ready);
+        assert!(session.transaction().is_none());
+        assert!(session.handle(rcpt("b@example.org")).is_err());
+    }
+
+    #[test]
+    fn test_aborted() {
+        let mut session = Session::new();
+
+        assert!(session.handle(ehlo()).is_ok());
+        assert_eq!(session.poll_aborted(), None);
+        assert!(session.handle(mail("a@example.org")).is_ok());
+        assert!(session.handle(rcpt("b@example.org")).is_ok());
+        assert_eq!(session.handle(Command::Rset), Ok(Event::Reset));
+        let aborted = session.poll_aborted().unwrap();
+        assert_eq!(aborted.reason, AbortReason::Reset);
+        assert_eq!(aborted.transaction.recipients.len(), 1);
+        assert_eq!(session.poll_aborted(), None);
+
+        assert!(session.handle(mail("a@example.org")).is_ok());
+        assert!(session.handle(ehlo()).is_ok());
+        assert_eq!(session.poll_aborted().unwrap().reason, AbortReason::Hello);
+
+        assert!(session.handle(mail("a@example.org")).is_ok());
+        assert!(session.handle(rcpt("b@example.org")).is_ok());
+        assert!(session
+            .handle(Command::Bdat {
+                size: 10,
+                last: false
+            })
+            .is_ok());
+        assert_eq!(session.handle(Command::Quit), Ok(Event::Quit));
+        assert_eq!(session.poll_aborted().unwrap().reason, AbortReason::Quit);
+        assert!(session.transaction().is_none());
+    }
+
+    #[test]
+    fn test_bdat() {
+        let mut session = Session::new();
+
+        assert!(session.handle(ehlo()).is_ok());
+        assert!(session.handle(mail("a@example.org")).is_ok());
+        assert!(session
+            .handle(Command::Bdat {
+                size: 10,
+                last: false
+            })
+            .is_err());
+        assert!(session.handle(rcpt("b@example.org")).is_ok());
+        assert!(session
+            .handle(Command::Bdat {
+                size: 10,
+                last: false
+            })
+            .is_ok());
+        assert_eq!(session.state(), State::Chunking);
+        assert!(session.handle(Command::Data).is_err());
+        assert!(session.handle(rcpt("c@example.org")).is_err());
+        assert!(session
+            .handle(Command::Bdat {
+                size: 0,
+                last: true
+            })
+            .is_ok());
+        assert_eq!(session.state(), State::Data);
+        assert_eq!(session.data_end().unwrap().recipients.len(), 1);
+    }
+
+    #[test]
+    fn test_binary_mime() {
+        let binary_mail = Command::Mail {
+            reverse_path: "a@example.org".into(),
+            parameters: vec![EsmtpParam::Body(Body::BinaryMime)],
+        };
+        let code = |result: Result<Event, Response>| result.unwrap_err().code();
+        let mut session = Session::new();
+
+        assert!(session.handle(ehlo()).is_ok());
+        assert!(session.handle(binary_mail.clone()).is_ok());
+        assert!(session.handle(rcpt("b@example.org")).is_ok());
+        assert_eq!(
+            session.transaction().unwrap().body(),
+            Some(Body::BinaryMime)
+        );
+        assert_eq!(code(session.handle(Command::Data)), ReplyCode::BadSequence);
+        assert_eq!(
+            code(session.handle(Command::Bdat {
+                size: 10,
+                last: true
+            })),
+            ReplyCode::TransactionFailed
+        );
+        assert_eq!(session.handle(Command::Rset), Ok(Event::Reset));
+
+        assert!(session.handle(binary_mail).is_ok());
+        assert!(session.handle(rcpt("b@example.org")).is_ok());
+        assert!(session
+            .handle(Command::Bdat {
+                size: 10,
+                last: true
+            })
+            .is_ok());
+        assert!(session.data_end().is_some());
+    }
+
+    #[test]
+    fn test_lmtp() {
+        let mut session = Session::with_protocol(Protocol::Lmtp);
+
+        assert!(session.handle(ehlo()).is_err());
+        assert!(session
+            .handle(Command::Lhlo {
+                domain_or_address: HeloIdentity::Domain("example.org".into()),
+            })
+            .is_ok());
+        assert!(session.handle(mail("a@example.org")).is_ok());
+        assert!(session.handle(rcpt("b@example.org")).is_ok());
+        assert!(session.handle(rcpt("c@example.org")).is_ok());
+        session.reject();
+        assert!(session.handle(rcpt("d@example.org")).is_ok());
+        assert!(session.handle(Command::Data).is_ok());
+
+        let (_, replies) = session
+            .data_end_with(|recipient| Response::Other {
+                code: ReplyCode::Ok,
+                lines: vec![
+                    TextString::new(&recipient.unwrap().forward_path.to_string())
+                        .unwrap()
+                        .into_owned(),
+                ],
+            })
+            .unwrap();
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[1].to_vec().unwrap(), b"250 d@example.org\r\n");
+        assert_eq!(session.state(), State::Ready);
+
+        let mut session = Session::new();
+        assert!(session
+            .handle(Command::Lhlo {
+                domain_or_address: HeloIdentity::Domain("example.org".into()),
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_starttls() {
+        let mut session = Session::new();
+
+        assert!(session.handle(ehlo()).is_ok());
+        assert_eq!(
+            session.handle(Command::StartTls),
+            Ok(Event::NeedsTlsUpgrade)
+        );
+        assert_eq!(session.state(), State::TlsHandshake);
+        // Commands that were sent before the handshake are refused.
+        assert!(session.handle(mail("a@example.org")).is_err());
+        assert!(session.handle(ehlo()).is_err());
+
+        session.tls_established();
+        assert!(session.is_tls());
+        assert_eq!(session.state(), State::Connected);
+        assert!(session.handle(mail("a@example.org")).is_err());
+        assert!(session.handle(ehlo()).is_ok());
+        assert!(session.handle(Command::StartTls).is_err());
+
+        // The server may also refuse STARTTLS.
+        let mut session = Session::new();
+        assert!(session.handle(ehlo()).is_ok());
+        assert!(session.handle(Command::StartTls).is_ok());
+        session.reject();
+        assert_eq!(session.state(), State::Ready);
+        assert!(!session.is_tls());
+    }
+
+    #[test]
+    fn test_starttls_bad_sequence() {
+        let mut session = Session::new();
+        let assert_refused = |session: &mut Session, state| {
+            assert_eq!(
+                session.handle(Command::StartTls).unwrap_err().code(),
+                ReplyCode::BadSequence
+            );
+            assert_eq!(session.state(), state);
+        };
+
+        assert_refused(&mut session, State::Connected);
+        assert!(session.handle(ehlo()).is_ok());
+        assert!(session.handle(mail("a@example.org")).is_ok());
+        assert_refused(&mut session, State::Mail);
+        assert!(session.handle(rcpt("b@example.org")).is_ok());
+        assert_refused(&mut session, State::Rcpt);
+        assert!(session
+            .handle(Command::Bdat {
+                size: 5,
+                last: false
+            })
+            .is_ok());
+        assert_refused(&mut session, State::Chunking);
+        assert_eq!(session.transaction().unwrap().recipients.len(), 1);
+
+        assert!(session.handle(Command::Rset).is_ok());
+        assert_eq!(
+            session.handle(Command::StartTls),
+            Ok(Event::NeedsTlsUpgrade)
+        );
+    }
+
+    #[test]
+    fn test_implicit_tls() {
+        let supported = [Capability::StartTls, Capability::Pipelining];
+        let mut session = Session::new();
+        assert_eq!(session.capabilities(supported.clone()), supported);
+
+        session.set_implicit_tls(true);
+        assert!(session.is_tls() && session.is_implicit_tls());
+        assert_eq!(session.capabilities(supported), [Capability::Pipelining]);
+        assert!(session.handle(ehlo()).is_ok());
+        assert_eq!(
+            session.handle(Command::StartTls).unwrap_err().code(),
+            ReplyCode::BadSequence
+        );
+        #[cfg(feature = "std")]
+        assert_eq!(
+            crate::trace::Protocol::of_session(Protocol::Smtp, true, session.is_tls(), false),
+            crate::trace::Protocol::Esmtps
+        );
+    }
+
+    #[test]
+    fn test_submission() {
+        let auth = Command::Auth {
+            mechanism: crate::AuthMechanism::Plain,
+            initial_response: None,
+        };
+        let code = |result: Result<Event, Response>| result.unwrap_err().code();
+        let mut session = Session::new();
+        session.set_profile(ServerProfile::Submission);
+
+        assert_eq!(code(session.handle(auth.clone())), ReplyCode::BadSequence);
+        assert_eq!(
+            code(session.handle(Command::Helo {
+                domain_or_address: HeloIdentity::Domain("example.org".into()),
+            })),
+            ReplyCode::CommandNotImplemented
+        );
+        assert!(session.handle(ehlo()).is_ok());
+        assert_eq!(
+            code(session.handle(mail("a@example.org"))),
+            ReplyCode::Other(530)
+        );
+
+        assert!(matches!(session.handle(auth.clone()), Ok(Event::Other(_))));
+        session.set_authenticated();
+        assert_eq!(code(session.handle(auth)), ReplyCode::BadSequence);
+        assert!(session.handle(mail("a@example.org")).is_ok());
+        assert_eq!(
+            code(session.handle(Command::Rcpt {
+                forward_path: ForwardPath::Path(Path {
+                    route: Some(vec!["relay.example.org".into()]),
+                    mailbox: "b@example.org".into(),
+                }),
+                parameters: vec![],
+            })),
+            ReplyCode::MailboxPermanentlyUnavailable
+        );
+        assert!(session.handle(rcpt("b@example.org")).is_ok());
+    }
+
+    #[test]
+    fn test_recipient_limit() {
+        let mut session = Session::new();
+        session.set_limits(Limits {
+            recipients: 2,
+            ..Limits::default()
+        });
+
+        assert!(session.handle(ehlo()).is_ok());
+        assert!(session.handle(mail("a@example.org")).is_ok());
+        assert!(session.handle(rcpt("b@example.org")).is_ok());
+        assert!(session.handle(rcpt("c@example.org")).is_ok());
+        let reply = session.handle(rcpt("d@example.org")).unwrap_err();
+        assert_eq!(reply.code(), ReplyCode::InsufficientStorage);
+        assert_eq!(
+            reply.enhanced_code(),
+            Some(crate::EnhancedStatusCode::new(4, 5, 3))
+        );
+        assert!(session.handle(rcpt("e@example.org")).is_err());
+        assert_eq!(session.recipient_count(), 2);
+        assert_eq!(session.transaction().unwrap().excess_recipients, 2);
+        assert!(session.handle(Command::Data).is_ok());
+        assert_eq!(session.data_end().unwrap().recipients.len(), 2);
+
+        assert!(session.handle(mail("a@example.org")).is_ok());
+        assert_eq!(session.recipient_count(), 0);
+        assert_eq!(session.transaction().unwrap().excess_recipients, 0);
+    }
+
+    #[test]
+    fn test_max_size() {
+        let mut session = Session::new();
+        session.set_max_size(Some(1000));
+        let mail = |size| Command::Mail {
+            reverse_path: "a@example.org".into(),
+            parameters: vec![EsmtpParam::Size(size)],
+        };
+
+        assert!(session.handle(ehlo()).is_ok());
+        assert_eq!(
+            session.handle(mail(1001)).unwrap_err().code(),
+            ReplyCode::ExceededStorageAllocation
+        );
+        assert_eq!(session.state(), State::Ready);
+        assert!(session.handle(mail(1000)).is_ok());
+    }
+
+    #[test]
+    fn test_observations() {
+        let peer: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut session = Session::new();
+
+        assert!(session.handle(ehlo()).is_ok());
+        assert_eq!(session.observations(), []);
+
+        session.set_connection(Connection {
+            peer,
+            ptr: Some("client.example.org".into()),
+            ptr_addresses: vec!["192.0.2.2".parse().unwrap()],
+        });
+        session.set_helo_addresses(vec![peer]);
+        assert_eq!(
+            session.observations(),
+            [Observation::PtrMismatch {
+                ptr: "client.example.org".into()
+            }]
+        );
+        assert_eq!(session.connection().unwrap().confirmed_name(), None);
+
+        session.set_connection(Connection {
+            peer,
+            ptr: Some("client.example.org".into()),
+            ptr_addresses: vec![peer],
+        });
+        session.set_helo_addresses(vec![]);
+        assert!(session.handle(mail("a@example.org")).is_ok());
+        assert_eq!(
+            session.transaction().unwrap().observations,
+            [Observation::HeloMismatch {
+                domain: "example.org".into()
+            }]
+        );
+        assert_eq!(
+            session.connection().unwrap().confirmed_name(),
+            Some("client.example.org")
+        );
+
+        // The addresses of the previous identity no longer apply.
+        let literal = AddressLiteral::Ip("192.0.2.3".parse().unwrap());
+        assert!(session
+            .handle(Command::Helo {
+                domain_or_address: HeloIdentity::AddressLiteral(literal.clone()),
+            })
+            .is_ok());
+        assert_eq!(
+            session.observations(),
+            [Observation::HeloLiteralMismatch { literal }]
+        );
+        assert_eq!(Observation::NoPtr.to_string(), "no PTR record");
+    }
+
+    #[test]
+    fn test_greylist() {
+        let mut session = Session::new();
+        let peer = "192.0.2.1".parse().unwrap();
+        session.set_connection(Connection {
+            peer,
+            ptr: None,
+            ptr_addresses: vec![],
+        });
+        assert!(session.handle(ehlo()).is_ok());
+        assert!(session.handle(mail("a@example.org")).is_ok());
+        assert_eq!(session.greylist_with(|_| unreachable!()), None);
+
+        assert!(session.handle(rcpt("b@example.org")).is_ok());
+        let mut fingerprints = Vec::new();
+        let reply = session.greylist_with(|fingerprint| {
+            fingerprints.push(fingerprint.clone());
+            GreylistDecision::Defer
+        });
+        assert_eq!(reply.unwrap().code(), ReplyCode::ProcessingError);
+        assert_eq!(
+            fingerprints,
+            [TransactionFingerprint {
+                client: peer,
+                reverse_path: "a@example.org".into(),
+                forward_path: "b@example.org".into(),
+            }]
+        );
+        assert_eq!(session.recipient_count(), 0);
+        assert_eq!(session.state(), State::Mail);
+
+        assert!(session.handle(rcpt("c@example.org")).is_ok());
+        assert_eq!(session.greylist_with(|_| GreylistDecision::Accept), None);
+        assert!(session.handle(rcpt("d@example.org")).is_ok());
+        assert_eq!(session.greylist_with(|_| unreachable!()), None);
+        assert_eq!(
+            session.fingerprint().unwrap().forward_path,
+            "c@example.org".into()
+        );
+    }
+
+    #[test]
+    fn test_policy() {
+        struct Deny;
+        impl Policy for Deny {
+            fn on_ehlo(&mut self, session: &Session, _: &HeloIdentity) -> Verdict {
+                match session.is_tls() {
+                    true => Verdict::Accept,
+                    false => Verdict::Defer,
+                }
+            }
+
+            fn on_data_start(&mut self, _: &Session) -> Verdict {
+                Verdict::Reject
+            }
+        }
+
+        let mut session = Session::new();
+        assert_eq!(
+            session.handle_with(ehlo(), &mut Deny).unwrap_err().code(),
+            ReplyCode::MailboxTemporarilyUnavailable
+        );
+        assert_eq!((session.state(), session.helo()), (State::Connected, None));
+
+        session.set_implicit_tls(true);
+        assert!(session.handle_with(ehlo(), &mut Deny).is_ok());
+        assert!(session
+            .handle_with(mail("a@example.org"), &mut Deny)
+            .is_ok());
+        assert!(session
+            .handle_with(rcpt("b@example.org"), &mut Deny)
+            .is_ok());
+        assert_eq!(
+            session
+                .handle_with(Command::Data, &mut Deny)
+                .unwrap_err()
+                .code(),
+            ReplyCode::TransactionFailed
+        );
+        assert_eq!(session.state(), State::Rcpt);
+
+        // Decisions made elsewhere, e.g., asynchronously
+        assert!(session.handle(Command::Data).is_ok());
+        assert_eq!(session.decision_point(), Some(DecisionPoint::DataStart));
+        assert!(session.apply(Verdict::Accept).is_ok());
+        assert!(session.data_end().is_some());
+        assert_eq!(session.decision_point(), Some(DecisionPoint::DataEnd));
+        assert_eq!(
+            session.apply(Verdict::Defer).unwrap_err().code(),
+            ReplyCode::ProcessingError
+        );
+        assert!(session.apply(Verdict::Reject).is_ok());
+    }
+
+    #[test]
+    fn test_abuse_counters() {
+        let threshold = Threshold {
+            slow_down: Some(2),
+            disconnect: Some(3),
+        };
+        let mut session = Session::new();
+        session.set_abuse_limits(AbuseLimits {
+            syntax_errors: threshold,
+            unknown_commands: threshold,
+            early_talker: None,
+        });
+        let unknown = Command::Unknown {
+            verb: "FOO".into(),
+            arguments: None,
+        };
+
+        assert!(session.handle(unknown.clone()).is_err());
+        assert_eq!(
+            session.handle(Command::Lhlo {
+                domain_or_address: HeloIdentity::Domain("example.org".into()),
+            }),
+            Err(unrecognized())
+        );
+        assert_eq!(session.recommendation(), Some(Recommendation::SlowDown));
+        assert_eq!(
+            session.record_syntax_error(),
+            Some(Recommendation::SlowDown)
+        );
+        assert!(session.handle(unknown).is_err());
+        assert_eq!(session.recommendation(), Some(Recommendation::Disconnect));
+        assert_eq!(
+            session.abuse_counters(),
+            AbuseCounters {
+                syntax_errors: 1,
+                unknown_commands: 3,
+                early_talker: false,
+            }
+        );
+
+        let mut session = Session::new();
+        assert_eq!(session.record_syntax_error(), None);
+        assert_eq!(
+            session.record_early_talker(),
+            Some(Recommendation::Disconnect)
+        );
+    }
+}
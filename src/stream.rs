@@ -0,0 +1,254 @@
+//! Reading and writing SMTP over a `futures_io` stream.
+//!
+//! [`SmtpStream`] combines an [`AsyncRead`] + [`AsyncWrite`] transport, e.g., a smol or
+//! async-std `TcpStream`, with a [`Decoder`]. It works with any executor and does not depend on
+//! tokio; see [`crate::codec`] for `tokio_util::codec::Framed`.
+
+use std::{fmt, future::poll_fn, io, pin::Pin};
+
+use futures_io::{AsyncRead, AsyncWrite};
+
+use crate::{
+    decoder::{DecodeError, Decoder},
+    Command, Response, ResponseKind,
+};
+
+/// Size of the reads from the transport
+const READ_SIZE: usize = 4096;
+
+#[derive(Debug)]
+pub struct SmtpStream<S> {
+    stream: S,
+    decoder: Decoder,
+}
+
+impl<S> SmtpStream<S> {
+    pub fn new(stream: S) -> Self {
+        Self::with_decoder(stream, Decoder::new())
+    }
+
+    /// Use a decoder with other limits or syntax options, see [`Decoder::with_options`].
+    pub fn with_decoder(stream: S, decoder: Decoder) -> Self {
+        Self { stream, decoder }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// The decoder, e.g., to take message content that was already read.
+    pub fn decoder_mut(&mut self) -> &mut Decoder {
+        &mut self.decoder
+    }
+
+    /// Return the transport, e.g., to perform the TLS handshake after STARTTLS.
+    ///
+    /// Input that was read but not decoded yet is dropped, as it was not protected by TLS.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: AsyncRead + Unpin> SmtpStream<S> {
+    /// Read the next command. Returns `None` when the client closed the connection.
+    pub async fn read_command(&mut self) -> Result<Option<Command>, StreamError> {
+        loop {
+            if let Some(command) = self.decoder.decode_command()? {
+                return Ok(Some(command));
+            }
+            if !self.fill().await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Read the next reply of the given kind. Returns `None` when the server closed the
+    /// connection.
+    pub async fn read_response(
+        &mut self,
+        kind: ResponseKind,
+    ) -> Result<Option<Response>, StreamError> {
+        loop {
+            if let Some(response) = self.decoder.decode_response(kind)? {
+                return Ok(Some(response));
+            }
+            if !self.fill().await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Read up to `length` octets without decoding them, e.g., the message content following
+    /// BDAT. Returns an empty chunk when the connection was closed.
+    pub async fn read_chunk(&mut self, length: usize) -> Result<Vec<u8>, StreamError> {
+        if self.decoder.buffered().is_empty() && length > 0 {
+            self.fill().await?;
+        }
+
+        Ok(self.decoder.take(length).to_vec())
+    }
+
+    /// Read once from the transport. Returns `false` at the end of the stream.
+    ///
+    /// Fails with [`io::ErrorKind::UnexpectedEof`] when the stream ends within a line.
+    async fn fill(&mut self) -> Result<bool, StreamError> {
+        let mut buffer = [0; READ_SIZE];
+        let stream = &mut self.stream;
+        let read = poll_fn(|cx| Pin::new(&mut *stream).poll_read(cx, &mut buffer)).await?;
+
+        if read == 0 {
+            return match self.decoder.buffered().is_empty() {
+                true => Ok(false),
+                false => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            };
+        }
+
+        self.decoder.push(&buffer[..read]);
+        Ok(true)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> SmtpStream<S> {
+    pub async fn send_command(&mut self, command: &Command) -> Result<(), StreamError> {
+        let mut serialized = Vec::new();
+        command.serialize(&mut serialized)?;
+        self.write_all(&serialized).await
+    }
+
+    pub async fn send_response(&mut self, response: &Response) -> Result<(), StreamError> {
+        let mut serialized = Vec::new();
+        response.serialize(&mut serialized)?;
+        self.write_all(&serialized).await
+    }
+
+    /// Write raw octets, e.g., dot-stuffed message content, and flush the transport.
+    pub async fn write_all(&mut self, mut data: &[u8]) -> Result<(), StreamError> {
+        let stream = &mut self.stream;
+
+        while !data.is_empty() {
+            let written = poll_fn(|cx| Pin::new(&mut *stream).poll_write(cx, data)).await?;
+            if written == 0 {
+                return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+            }
+            data = &data[written..];
+        }
+
+        poll_fn(|cx| Pin::new(&mut *stream).poll_flush(cx)).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum StreamError {
+    Io(io::Error),
+    /// Input could not be decoded. The offending line was consumed, reading may continue.
+    Decode(DecodeError),
+}
+
+impl From<io::Error> for StreamError {
+    fn from(error: io::Error) -> Self {
+        StreamError::Io(error)
+    }
+}
+
+impl From<DecodeError> for StreamError {
+    fn from(error: DecodeError) -> Self {
+        StreamError::Decode(error)
+    }
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::Io(error) => write!(f, "{}", error),
+            StreamError::Decode(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StreamError::Io(error) => Some(error),
+            StreamError::Decode(error) => Some(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        future::Future,
+        pin::pin,
+        sync::Arc,
+        task::{Context, Poll, Wake, Waker},
+    };
+
+    use super::*;
+    use crate::ReplyCode;
+
+    /// Run a future whose I/O is always ready.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct Noop;
+
+        impl Wake for Noop {
+            fn wake(self: Arc<Self>) {}
+        }
+
+        let waker = Waker::from(Arc::new(Noop));
+        let mut context = Context::from_waker(&waker);
+        let mut future = pin!(future);
+
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_command() {
+        let mut stream = SmtpStream::new(&b"NOOP\r\nBDAT 3 LAST\r\nabcQUIT\r\n"[..]);
+
+        block_on(async {
+            assert_eq!(
+                stream.read_command().await.unwrap(),
+                Some(Command::Noop { argument: None })
+            );
+            assert!(matches!(
+                stream.read_command().await.unwrap(),
+                Some(Command::Bdat { size: 3, .. })
+            ));
+            assert_eq!(stream.read_chunk(3).await.unwrap(), b"abc");
+            assert_eq!(stream.read_command().await.unwrap(), Some(Command::Quit));
+            assert_eq!(stream.read_command().await.unwrap(), None);
+        });
+
+        let mut stream = SmtpStream::new(&b"NOOP"[..]);
+        assert!(matches!(
+            block_on(stream.read_command()),
+            Err(StreamError::Io(error)) if error.kind() == io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    fn test_response() {
+        let mut stream =
+            SmtpStream::new(&b"220 example.org ESMTP\r\n250-example.org\r\n250 SIZE\r\n"[..]);
+
+        block_on(async {
+            let greeting = stream.read_response(ResponseKind::Greeting).await.unwrap();
+            assert_eq!(greeting.unwrap().code(), ReplyCode::Ready);
+            let ehlo = stream.read_response(ResponseKind::Ehlo).await.unwrap();
+            assert!(matches!(ehlo, Some(Response::Ehlo { .. })));
+        });
+
+        let mut stream = SmtpStream::new(Vec::new());
+        block_on(stream.send_command(&Command::Quit)).unwrap();
+        assert_eq!(stream.get_ref(), b"QUIT\r\n");
+    }
+}
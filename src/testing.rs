@@ -0,0 +1,240 @@
+//! [`proptest`](mod@proptest) strategies for grammar-valid commands and replies.
+//!
+//! The strategies generate values that serialize to valid SMTP, e.g., to property-test a server
+//! with sessions of the form EHLO, MAIL, RCPT, DATA, …, QUIT:
+//!
+//! ```ignore
+//! proptest! {
+//!     #[test]
+//!     fn accepts_sessions(commands in testing::session()) {
+//!         let mut server = MyServer::new();
+//!         for command in commands {
+//!             server.handle(command)?;
+//!         }
+//!     }
+//! }
+//! ```
+
+use proptest::{collection::vec, option, prelude::*};
+
+use crate::{
+    AddressLiteral, Body, ByMode, Command, DeliverBy, EsmtpParam, ForwardPath, HeloIdentity,
+    Notify, Path, Priority, ReplyCode, Response, Ret, ReversePath, TextString,
+};
+
+/// Domain = sub-domain *("." sub-domain)
+pub fn domain() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9]{1,8}(-[a-zA-Z0-9]{1,8})?(\\.[a-zA-Z0-9]{1,8}(-[a-zA-Z0-9]{1,8})?){0,3}"
+}
+
+/// address-literal with an IPv4 or IPv6 address
+pub fn address_literal() -> impl Strategy<Value = AddressLiteral> {
+    any::<std::net::IpAddr>().prop_map(AddressLiteral::Ip)
+}
+
+pub fn domain_or_address() -> impl Strategy<Value = HeloIdentity> {
+    prop_oneof![
+        3 => domain().prop_map(HeloIdentity::Domain),
+        1 => address_literal().prop_map(HeloIdentity::AddressLiteral),
+    ]
+}
+
+/// Mailbox = Local-part "@" ( Domain / address-literal )
+///
+/// The local part is a Dot-string.
+pub fn mailbox() -> impl Strategy<Value = String> {
+    let local_part =
+        "[a-zA-Z0-9!#$%&'*+/=?^_`{|}~-]{1,16}(\\.[a-zA-Z0-9!#$%&'*+/=?^_`{|}~-]{1,16}){0,2}";
+    let domain = prop_oneof![
+        3 => domain(),
+        1 => address_literal().prop_map(|address| address.to_string()),
+    ];
+
+    (local_part, domain).prop_map(|(local_part, domain)| format!("{}@{}", local_part, domain))
+}
+
+/// Path = "<" [ A-d-l ":" ] Mailbox ">"
+///
+/// Few paths have a source route.
+pub fn path() -> impl Strategy<Value = Path> {
+    let route = option::weighted(0.1, vec(domain(), 1..3));
+
+    (route, mailbox()).prop_map(|(route, mailbox)| Path { route, mailbox })
+}
+
+/// Parameters that are valid with MAIL
+pub fn mail_parameter() -> impl Strategy<Value = EsmtpParam> {
+    prop_oneof![
+        any::<u32>().prop_map(EsmtpParam::Size),
+        prop_oneof![
+            Just(Body::SevenBit),
+            Just(Body::EightBitMime),
+            Just(Body::BinaryMime)
+        ]
+        .prop_map(EsmtpParam::Body),
+        prop_oneof![Just(Ret::Full), Just(Ret::Hdrs)].prop_map(EsmtpParam::Ret),
+        "[ -~]{1,32}".prop_map(EsmtpParam::Envid),
+        Just(EsmtpParam::Auth("<>".into())),
+        deliver_by().prop_map(EsmtpParam::By),
+        (-9..=9i8).prop_map(|priority| EsmtpParam::MtPriority(Priority::new(priority).unwrap())),
+        Just(EsmtpParam::RequireTls),
+    ]
+}
+
+/// by-value = by-time ";" by-mode [ by-trace ]
+///
+/// The time is positive with the "R" mode.
+pub fn deliver_by() -> impl Strategy<Value = DeliverBy> {
+    let time_and_mode = prop_oneof![
+        (-999_999_999..=999_999_999i32, Just(ByMode::Notify)),
+        (1..=999_999_999i32, Just(ByMode::Return)),
+    ];
+
+    (time_and_mode, any::<bool>()).prop_map(|((time, mode), trace)| DeliverBy { time, mode, trace })
+}
+
+/// Parameters that are valid with RCPT
+pub fn rcpt_parameter() -> impl Strategy<Value = EsmtpParam> {
+    // Any set of conditions, or NEVER for the empty set
+    let notify = (0u8..=7).prop_map(Notify);
+
+    prop_oneof![
+        notify.prop_map(EsmtpParam::Notify),
+        mailbox().prop_map(|address| EsmtpParam::Orcpt {
+            addr_type: "rfc822".into(),
+            address,
+        }),
+    ]
+}
+
+/// ehlo = "EHLO" SP ( Domain / address-literal ) CRLF
+pub fn ehlo() -> impl Strategy<Value = Command> {
+    domain_or_address().prop_map(|domain_or_address| Command::Ehlo { domain_or_address })
+}
+
+/// mail = "MAIL FROM:" Reverse-path [SP Mail-parameters] CRLF
+pub fn mail() -> impl Strategy<Value = Command> {
+    let reverse_path = prop_oneof![
+        1 => Just(ReversePath::Null),
+        7 => path().prop_map(ReversePath::Path),
+    ];
+
+    (reverse_path, vec(mail_parameter(), 0..3)).prop_map(|(reverse_path, parameters)| {
+        Command::Mail {
+            reverse_path,
+            parameters,
+        }
+    })
+}
+
+/// ( "<Postmaster@" Domain ">" / "\<Postmaster>" / Forward-path )
+pub fn forward_path() -> impl Strategy<Value = ForwardPath> {
+    prop_oneof![
+        1 => option::of(domain()).prop_map(ForwardPath::Postmaster),
+        7 => path().prop_map(ForwardPath::Path),
+    ]
+}
+
+/// rcpt = "RCPT TO:" ( "<Postmaster@" Domain ">" / "\<Postmaster>" / Forward-path )
+///        [SP Rcpt-parameters] CRLF
+pub fn rcpt() -> impl Strategy<Value = Command> {
+    (forward_path(), vec(rcpt_parameter(), 0..2)).prop_map(|(forward_path, parameters)| {
+        Command::Rcpt {
+            forward_path,
+            parameters,
+        }
+    })
+}
+
+/// A mail transaction: MAIL, one or more RCPT, and DATA, or BDAT LAST for BINARYMIME messages
+pub fn transaction() -> impl Strategy<Value = Vec<Command>> {
+    (mail(), vec(rcpt(), 1..5), 0u64..1000).prop_map(|(mail, rcpts, size)| {
+        let binary = matches!(&mail, Command::Mail { parameters, .. }
+            if parameters.contains(&EsmtpParam::Body(Body::BinaryMime)));
+
+        let mut commands = vec![mail];
+        commands.extend(rcpts);
+        commands.push(match binary {
+            true => Command::Bdat { size, last: true },
+            false => Command::Data,
+        });
+        commands
+    })
+}
+
+/// A session: EHLO, zero or more transactions, and QUIT
+///
+/// The message content following each DATA is not part of the sequence.
+pub fn session() -> impl Strategy<Value = Vec<Command>> {
+    (ehlo(), vec(transaction(), 0..4)).prop_map(|(ehlo, transactions)| {
+        let mut commands = vec![ehlo];
+        commands.extend(transactions.into_iter().flatten());
+        commands.push(Command::Quit);
+        commands
+    })
+}
+
+/// Reply-code = %x32-35 %x30-35 %x30-39
+pub fn reply_code() -> impl Strategy<Value = ReplyCode> {
+    (2u16..=5, 0u16..=5, 0u16..=9)
+        .prop_map(|(class, category, detail)| ReplyCode::from(100 * class + 10 * category + detail))
+}
+
+/// A generic reply with up to four lines of text
+pub fn reply() -> impl Strategy<Value = Response> {
+    let line = "[\t -~]{1,64}".prop_map(|line| TextString::new(&line).unwrap().into_owned());
+
+    (reply_code(), vec(line, 0..5)).prop_map(|(code, lines)| Response::Other { code, lines })
+}
+
+/// The serialized replies of a session, with the greeting first
+pub fn reply_stream() -> impl Strategy<Value = Vec<u8>> {
+    let greeting =
+        (domain(), option::of("[ -~]{1,64}")).prop_map(|(domain, text)| Response::Greeting {
+            domain,
+            text: text.unwrap_or_default(),
+        });
+
+    (greeting, vec(reply(), 0..16)).prop_map(|(greeting, replies)| {
+        std::iter::once(greeting)
+            .chain(replies)
+            .flat_map(|reply| reply.to_vec().unwrap())
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{server::Session, ResponseKind};
+
+    proptest! {
+        #[test]
+        fn test_session(commands in session()) {
+            let mut session = Session::new();
+
+            for command in commands {
+                let mut serialized = Vec::new();
+                command.serialize(&mut serialized).unwrap();
+                let (rem, parsed) = Command::from_bytes(&serialized).unwrap();
+                prop_assert!(rem.is_empty());
+                prop_assert_eq!(&parsed, &command);
+
+                let is_data = matches!(command, Command::Data | Command::Bdat { last: true, .. });
+                prop_assert!(session.handle(command).is_ok());
+                if is_data {
+                    prop_assert!(session.data_end().is_some());
+                }
+            }
+        }
+
+        #[test]
+        fn test_reply_stream(stream in reply_stream()) {
+            let (mut rem, _) = Response::parse(ResponseKind::Greeting, &stream).unwrap();
+
+            while !rem.is_empty() {
+                rem = Response::parse(ResponseKind::Other, rem).unwrap().0;
+            }
+        }
+    }
+}
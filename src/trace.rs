@@ -0,0 +1,263 @@
+//! Trace information (RFC 5321, 4.4.)
+//!
+//! Every server that accepts a message must prepend a "Received:" header to its content. The
+//! header records the identity of the client, the receiving host, the protocol, and the time:
+//!
+//! ```text
+//! Received: from client.example.org (client.example.org [192.0.2.1])
+//!         by mx.example.org with ESMTPS id 1a2b3c for <fred@example.org>;
+//!         Wed, 14 Oct 2026 12:34:56 +0000
+//! ```
+
+use std::{
+    fmt,
+    net::IpAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{server, AddressLiteral, ForwardPath, HeloIdentity};
+
+/// Header lines should not be longer than 78 characters (RFC 5322, 2.1.1.)
+const LINE_LENGTH: usize = 78;
+
+/// Protocol = "ESMTP" / "SMTP" / Attdl-Protocol
+///
+/// With the types of RFC 3848 for sessions that used TLS ("S") or authentication ("A").
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Smtp,
+    Esmtp,
+    Esmtpa,
+    Esmtps,
+    Esmtpsa,
+    Lmtp,
+    Lmtpa,
+    Lmtps,
+    Lmtpsa,
+}
+
+impl Protocol {
+    /// The protocol of a session, e.g., ESMTPSA after EHLO, STARTTLS, and AUTH.
+    ///
+    /// `extended` is set when the client identified itself with EHLO rather than HELO. A session
+    /// that used TLS or authentication is always extended.
+    pub fn of_session(
+        protocol: server::Protocol,
+        extended: bool,
+        tls: bool,
+        authenticated: bool,
+    ) -> Self {
+        match (protocol, tls, authenticated) {
+            (server::Protocol::Smtp, false, false) if !extended => Protocol::Smtp,
+            (server::Protocol::Smtp, false, false) => Protocol::Esmtp,
+            (server::Protocol::Smtp, false, true) => Protocol::Esmtpa,
+            (server::Protocol::Smtp, true, false) => Protocol::Esmtps,
+            (server::Protocol::Smtp, true, true) => Protocol::Esmtpsa,
+            (server::Protocol::Lmtp, false, false) => Protocol::Lmtp,
+            (server::Protocol::Lmtp, false, true) => Protocol::Lmtpa,
+            (server::Protocol::Lmtp, true, false) => Protocol::Lmtps,
+            (server::Protocol::Lmtp, true, true) => Protocol::Lmtpsa,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::Smtp => "SMTP",
+            Protocol::Esmtp => "ESMTP",
+            Protocol::Esmtpa => "ESMTPA",
+            Protocol::Esmtps => "ESMTPS",
+            Protocol::Esmtpsa => "ESMTPSA",
+            Protocol::Lmtp => "LMTP",
+            Protocol::Lmtpa => "LMTPA",
+            Protocol::Lmtps => "LMTPS",
+            Protocol::Lmtpsa => "LMTPSA",
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Time-stamp-line = "Received:" FWS Stamp \<CRLF\>
+///
+/// Stamp = From-domain By-domain Opt-info \[CFWS\] ";" FWS date-time
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Received {
+    /// The identity the client sent with EHLO, HELO, or LHLO
+    pub helo: HeloIdentity,
+    /// The address the client connected from
+    pub client_address: Option<IpAddr>,
+    /// The name of the client, if the reverse lookup of its address was confirmed
+    pub client_name: Option<String>,
+    /// The name of the receiving host
+    pub by: String,
+    pub protocol: Protocol,
+    /// Identifier the receiving host assigned to the message, an Atom or a msg-id
+    pub id: Option<String>,
+    /// The recipient, which should only be set for messages with a single recipient, as the
+    /// header would disclose the other recipients (RFC 5321, 7.2.)
+    pub recipient: Option<ForwardPath>,
+    pub timestamp: SystemTime,
+}
+
+impl Received {
+    /// The header including "Received:" and the final CRLF, folded at clause boundaries
+    pub fn to_header(&self) -> String {
+        let mut clauses = Vec::with_capacity(5);
+
+        // From-domain = "FROM" FWS Extended-Domain
+        let mut from = format!("from {}", self.helo);
+        if let Some(tcp_info) = self.tcp_info() {
+            from.push_str(&format!(" ({})", tcp_info));
+        }
+        clauses.push(from);
+
+        // By-domain = CFWS "BY" FWS Extended-Domain
+        clauses.push(format!("by {}", self.by));
+        // With = CFWS "WITH" FWS Protocol
+        clauses.push(format!("with {}", self.protocol));
+        // ID = CFWS "ID" FWS ( Atom / msg-id )
+        if let Some(ref id) = self.id {
+            clauses.push(format!("id {}", id));
+        }
+        // For = CFWS "FOR" FWS ( Path / Mailbox )
+        if let Some(ref recipient) = self.recipient {
+            clauses.push(format!("for <{}>", recipient));
+        }
+
+        if let Some(last) = clauses.last_mut() {
+            last.push(';');
+        }
+        clauses.push(format_date(self.timestamp));
+
+        let mut header = String::from("Received:");
+        let mut line_length = header.len();
+        for clause in clauses {
+            if line_length + 1 + clause.len() > LINE_LENGTH && line_length > "Received:".len() {
+                header.push_str("\r\n\t");
+                line_length = 1;
+            } else {
+                header.push(' ');
+                line_length += 1;
+            }
+            header.push_str(&clause);
+            line_length += clause.len();
+        }
+        header.push_str("\r\n");
+
+        header
+    }
+
+    /// TCP-info = address-literal / ( Domain FWS address-literal )
+    fn tcp_info(&self) -> Option<String> {
+        let address = AddressLiteral::Ip(self.client_address?);
+
+        Some(match self.client_name {
+            Some(ref name) => format!("{} {}", name, address),
+            None => address.to_string(),
+        })
+    }
+}
+
+/// date-time = [ day-of-week "," ] date time (RFC 5322, 3.3.), in UTC
+///
+/// Times before 1970 are formatted as the start of 1970.
+pub fn format_date(timestamp: SystemTime) -> String {
+    const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let seconds = timestamp
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let days = seconds / 86400;
+    let time = seconds % 86400;
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{}, {} {} {} {:02}:{:02}:{:02} +0000",
+        // 1970-01-01 was a Thursday.
+        DAYS[((days + 4) % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// The proleptic Gregorian date of a day since 1970-01-01 as (year, month, day)
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // Count from 0000-03-01, so that the leap day is the last day of a year.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_format_date() {
+        let tests = [
+            (0, "Thu, 1 Jan 1970 00:00:00 +0000"),
+            (951_782_405, "Tue, 29 Feb 2000 00:00:05 +0000"),
+            (1_791_981_296, "Wed, 14 Oct 2026 12:34:56 +0000"),
+        ];
+
+        for (seconds, expected) in tests {
+            let timestamp = UNIX_EPOCH + Duration::from_secs(seconds);
+            assert_eq!(format_date(timestamp), expected);
+        }
+    }
+
+    #[test]
+    fn test_received() {
+        let mut received = Received {
+            helo: HeloIdentity::Domain("client.example.org".into()),
+            client_address: Some("192.0.2.1".parse().unwrap()),
+            client_name: Some("client.example.org".into()),
+            by: "mx.example.org".into(),
+            protocol: Protocol::of_session(server::Protocol::Smtp, true, true, false),
+            id: Some("1a2b3c".into()),
+            recipient: Some("fred@example.org".into()),
+            timestamp: UNIX_EPOCH + Duration::from_secs(1_791_981_296),
+        };
+
+        assert_eq!(
+            received.to_header(),
+            "Received: from client.example.org (client.example.org [192.0.2.1])\r\n\
+\tby mx.example.org with ESMTPS id 1a2b3c for <fred@example.org>;\r\n\
+\tWed, 14 Oct 2026 12:34:56 +0000\r\n"
+        );
+
+        received.helo =
+            HeloIdentity::AddressLiteral(AddressLiteral::Ip("2001:db8::1".parse().unwrap()));
+        received.client_address = None;
+        received.protocol = Protocol::Smtp;
+        received.id = None;
+        received.recipient = None;
+        assert_eq!(
+            received.to_header(),
+            "Received: from [IPv6:2001:db8::1] by mx.example.org with SMTP;\r\n\
+\tWed, 14 Oct 2026 12:34:56 +0000\r\n"
+        );
+    }
+}
@@ -0,0 +1,422 @@
+//! Recording and replaying sessions
+//!
+//! A [`Transcript`] records the commands, replies, and other octets of a session in the order
+//! they were exchanged, with the time since the session started. With the `serde` feature, it
+//! can be saved, e.g., as JSON, and later be replayed against a [`Session`] or a
+//! [`ClientMachine`] to reproduce an interoperability problem in a test.
+//!
+//! Credentials are not recorded: AUTH with an initial response and the responses of a SASL
+//! exchange are recorded as [`Item::Redacted`].
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::{ClientError, ClientMachine},
+    sasl::Continuation,
+    server::{Event, Session, Transaction},
+    Command, Response, SyntaxOptions,
+};
+
+/// The sender of a record
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Client,
+    Server,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Item {
+    Command(Command),
+    Response(Response),
+    /// Octets sent outside of commands, as on the wire: the dot-stuffed message content
+    /// including the final ".", or a BDAT chunk
+    Data(Vec<u8>),
+    /// A line with credentials, which was not recorded
+    Redacted,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub direction: Direction,
+    /// Time since the start of the session
+    pub elapsed: Duration,
+    pub item: Item,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transcript {
+    pub started: SystemTime,
+    pub records: Vec<Record>,
+}
+
+impl Default for Transcript {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transcript {
+    /// An empty transcript of a session that starts now
+    pub fn new() -> Self {
+        Self {
+            started: SystemTime::now(),
+            records: Vec::new(),
+        }
+    }
+
+    /// Record an item at the current time.
+    pub fn record(&mut self, direction: Direction, item: Item) {
+        let elapsed = self.started.elapsed().unwrap_or_default();
+        self.records.push(Record {
+            direction,
+            elapsed,
+            item,
+        });
+    }
+
+    /// Record a command, or [`Item::Redacted`] for AUTH with an initial response.
+    pub fn command(&mut self, command: &Command) {
+        let item = match command {
+            Command::Auth {
+                initial_response: Some(_),
+                ..
+            } => Item::Redacted,
+            command => Item::Command(command.clone()),
+        };
+        self.record(Direction::Client, item);
+    }
+
+    /// Record a line of a SASL exchange, i.e., [`Item::Redacted`] for a response to a challenge.
+    pub fn continuation(&mut self, continuation: &Continuation) {
+        let item = match continuation {
+            Continuation::Response(_) => Item::Redacted,
+            Continuation::Cancel => Item::Data(b"*\r\n".to_vec()),
+        };
+        self.record(Direction::Client, item);
+    }
+
+    pub fn response(&mut self, response: &Response) {
+        self.record(Direction::Server, Item::Response(response.clone()));
+    }
+
+    pub fn data(&mut self, direction: Direction, data: &[u8]) {
+        self.record(direction, Item::Data(data.to_vec()));
+    }
+
+    /// Pass the recorded commands to a server session, and return the completed transactions.
+    ///
+    /// Each command is handled once its reply was recorded, so that pipelined commands are
+    /// handled in order. A command the session accepts is reverted with [`Session::reject`] when
+    /// the recorded reply rejected it; STARTTLS is followed by [`Session::tls_established`], and
+    /// the reply to the message content by [`Session::data_end`]. Other replies, e.g., of a SASL
+    /// exchange, are skipped.
+    pub fn replay_server(&self, session: &mut Session) -> Result<Vec<Transaction>, ReplayError> {
+        let mut transactions = Vec::new();
+        let mut commands = VecDeque::new();
+
+        for (index, record) in self.records.iter().enumerate() {
+            let response = match &record.item {
+                Item::Command(command) => {
+                    commands.push_back(command.clone());
+                    continue;
+                }
+                Item::Response(response) => response,
+                Item::Data(_) | Item::Redacted => continue,
+            };
+
+            // The first reply to the message content ends the transaction; in LMTP, the others
+            // (one per recipient) are skipped.
+            let Some(command) = commands.pop_front() else {
+                transactions.extend(session.data_end());
+                continue;
+            };
+
+            let positive = response.is_positive_completion() || response.is_intermediate();
+            match session.handle(command) {
+                Ok(_) if !positive => session.reject(),
+                Ok(Event::NeedsTlsUpgrade) => session.tls_established(),
+                Ok(Event::Bdat { last: true, .. }) => transactions.extend(session.data_end()),
+                Ok(_) => {}
+                Err(_) if !positive => {}
+                Err(rejection) => {
+                    return Err(ReplayError::Rejected {
+                        record: index,
+                        rejection,
+                    })
+                }
+            }
+        }
+
+        Ok(transactions)
+    }
+
+    /// Pass the recorded replies to a client machine, and check that it sends the recorded
+    /// commands and data.
+    ///
+    /// The machine does not need to send the commands in the same groups, e.g., it may wait for
+    /// the reply to MAIL before it sends RCPT, but in the same order. Any line is accepted in
+    /// place of an [`Item::Redacted`].
+    ///
+    /// Returns the errors of [`ClientMachine::handle`], e.g., the rejections, as they are part of
+    /// the session. The TLS handshake is assumed to succeed.
+    pub fn replay_client(
+        &self,
+        machine: &mut ClientMachine,
+    ) -> Result<Vec<ClientError>, ReplayError> {
+        let options = SyntaxOptions {
+            smtputf8: true,
+            ..SyntaxOptions::default()
+        };
+        let mut errors = Vec::new();
+        let mut expected = Vec::new();
+        let mut sent = Vec::new();
+        // Offsets in `expected` of redacted lines that were not sent yet
+        let mut redacted = VecDeque::new();
+
+        for (index, record) in self.records.iter().enumerate() {
+            let response = match &record.item {
+                Item::Command(command) => {
+                    // Writing to a `Vec` only fails for commands that can't be encoded, which
+                    // the machine does not send.
+                    let _ = command.serialize_with(&mut expected, options);
+                    continue;
+                }
+                Item::Data(data) => {
+                    expected.extend_from_slice(data);
+                    continue;
+                }
+                Item::Redacted => {
+                    redacted.push_back(expected.len());
+                    continue;
+                }
+                Item::Response(response) => response,
+            };
+
+            // The recorded client may have pipelined commands that the machine sends one by one.
+            sent.extend(machine.poll_transmit().unwrap_or_default());
+            skip_redacted(&mut sent, &mut redacted);
+            if !expected.starts_with(&sent) {
+                return Err(ReplayError::Diverged {
+                    record: index,
+                    expected,
+                    sent,
+                });
+            }
+
+            if let Err(error) = machine.handle(response.clone()) {
+                errors.push(error);
+            }
+            if machine.needs_tls_upgrade() {
+                machine.tls_established();
+            }
+        }
+
+        sent.extend(machine.poll_transmit().unwrap_or_default());
+        skip_redacted(&mut sent, &mut redacted);
+        if sent != expected {
+            return Err(ReplayError::Diverged {
+                record: self.records.len(),
+                expected,
+                sent,
+            });
+        }
+
+        Ok(errors)
+    }
+}
+
+/// Remove the lines that were sent in place of redacted lines.
+fn skip_redacted(sent: &mut Vec<u8>, redacted: &mut VecDeque<usize>) {
+    while let Some(&offset) = redacted.front() {
+        let Some(end) = sent.get(offset..).and_then(crate::find_crlf) else {
+            break;
+        };
+
+        sent.drain(offset..offset + end + 2);
+        redacted.pop_front();
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The session rejected a command that the recorded server accepted.
+    Rejected { record: usize, rejection: Response },
+    /// Before the reply at index `record` (or at the end), the machine sent other octets than
+    /// the recorded client.
+    Diverged {
+        record: usize,
+        expected: Vec<u8>,
+        sent: Vec<u8>,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Rejected { record, rejection } => write!(
+                f,
+                "session rejected the command answered by record {} with {}",
+                record,
+                u16::from(rejection.code())
+            ),
+            ReplayError::Diverged { record, .. } => {
+                write!(f, "client diverged before record {}", record)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        client::{ClientConfig, Message, Timeouts, TlsMode},
+        HeloIdentity, Limits, ReplyCode, TextString,
+    };
+
+    fn reply(code: ReplyCode, text: &str) -> Response {
+        Response::Other {
+            code,
+            lines: vec![TextString::new(text).unwrap().into_owned()],
+        }
+    }
+
+    /// A pipelined session in which the first recipient is rejected.
+    fn transcript() -> Transcript {
+        let mut transcript = Transcript::new();
+        let commands: [&[u8]; 4] = [
+            b"EHLO client.example.org\r\n",
+            b"MAIL FROM:<a@example.org>\r\n",
+            b"RCPT TO:<b@example.org>\r\n",
+            b"RCPT TO:<c@example.org>\r\n",
+        ];
+
+        transcript.response(&Response::Greeting {
+            domain: "example.org".into(),
+            text: "ESMTP".into(),
+        });
+        transcript.command(&Command::from_bytes(commands[0]).unwrap().1);
+        transcript.response(
+            &Response::parse_complete(
+                crate::ResponseKind::Ehlo,
+                b"250-example.org\r\n250 PIPELINING\r\n",
+            )
+            .unwrap()
+            .1,
+        );
+        for command in &commands[1..] {
+            transcript.command(&Command::from_bytes(command).unwrap().1);
+        }
+        transcript.response(&reply(ReplyCode::Ok, "OK"));
+        transcript.response(&reply(
+            ReplyCode::MailboxPermanentlyUnavailable,
+            "No such user",
+        ));
+        transcript.response(&reply(ReplyCode::Ok, "OK"));
+        transcript.command(&Command::Data);
+        transcript.response(&reply(ReplyCode::StartMailInput, "Go ahead"));
+        transcript.data(Direction::Client, b"Hello\r\n.\r\n");
+        transcript.response(&reply(ReplyCode::Ok, "Queued"));
+        transcript.command(&Command::Quit);
+        transcript.response(&reply(ReplyCode::ClosingChannel, "Bye"));
+
+        transcript
+    }
+
+    #[test]
+    fn test_replay_server() {
+        let mut session = Session::new();
+        let transactions = transcript().replay_server(&mut session).unwrap();
+
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].recipients.len(), 1);
+        assert_eq!(
+            transactions[0].recipients[0].forward_path,
+            "c@example.org".into()
+        );
+        assert_eq!(session.state(), crate::server::State::Closed);
+
+        // DATA before MAIL is rejected by the session, but was accepted by the recorded server.
+        let mut transcript = Transcript::new();
+        transcript.command(&Command::Data);
+        transcript.response(&reply(ReplyCode::StartMailInput, "Go ahead"));
+        assert!(matches!(
+            transcript.replay_server(&mut Session::new()),
+            Err(ReplayError::Rejected { record: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_replay_client() {
+        let config = ClientConfig {
+            identity: HeloIdentity::Domain("client.example.org".into()),
+            tls: TlsMode::None,
+            credentials: None,
+            mechanisms: crate::sasl::password_mechanisms(),
+            limits: Limits::default(),
+            timeouts: Timeouts::default(),
+        };
+        let mut message = Message {
+            reverse_path: "a@example.org".into(),
+            mail_parameters: vec![],
+            recipients: vec!["b@example.org".into(), "c@example.org".into()],
+            rcpt_parameters: vec![],
+            content: b"Hello\r\n".to_vec(),
+        };
+
+        let mut machine = ClientMachine::new(config.clone(), message.clone());
+        let errors = transcript().replay_client(&mut machine).unwrap();
+        assert!(errors.is_empty());
+        assert!(machine.is_finished());
+
+        message.recipients.pop();
+        let mut machine = ClientMachine::new(config, message);
+        assert!(matches!(
+            transcript().replay_client(&mut machine),
+            Err(ReplayError::Diverged { record: 8, .. })
+        ));
+    }
+
+    #[test]
+    fn test_redacted() {
+        let mut transcript = Transcript::new();
+        transcript.command(
+            &Command::from_bytes(b"AUTH PLAIN AHVzZXIAcGFzcw==\r\n")
+                .unwrap()
+                .1,
+        );
+        transcript.response(&reply(ReplyCode::Other(235), "OK"));
+        transcript.command(&Command::from_bytes(b"AUTH LOGIN\r\n").unwrap().1);
+        transcript.continuation(&Continuation::Response(b"user".to_vec().into()));
+        transcript.continuation(&Continuation::Cancel);
+
+        let items: Vec<_> = transcript
+            .records
+            .iter()
+            .map(|record| &record.item)
+            .collect();
+        assert_eq!(items[0], &Item::Redacted);
+        assert!(matches!(items[2], Item::Command(Command::Auth { .. })));
+        assert_eq!(items[3], &Item::Redacted);
+        assert_eq!(items[4], &Item::Data(b"*\r\n".to_vec()));
+
+        let mut sent = b"EHLO a\r\nAUTH PLAIN AHVzZXIAcGFzcw==\r\nQUIT\r\n".to_vec();
+        let mut redacted = VecDeque::from([8]);
+        skip_redacted(&mut sent, &mut redacted);
+        assert_eq!(sent, b"EHLO a\r\nQUIT\r\n");
+        assert!(redacted.is_empty());
+    }
+}
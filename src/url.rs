@@ -0,0 +1,290 @@
+//! Connection strings of senders, e.g., "submission://fred@mail.example.org"
+//!
+//! [`EndpointConfig::parse`] accepts URLs of the form
+//! `scheme://[user[:password]@]host[:port][/][?starttls=mode]`:
+//!
+//! ```
+//! use instant_smtp::{client::TlsMode, url::EndpointConfig};
+//!
+//! let url = "smtp://fred%40example.org:s3cret@[2001:db8::1]:2525?starttls=required";
+//! let endpoint = EndpointConfig::parse(url).unwrap();
+//! assert_eq!(endpoint.host, "2001:db8::1");
+//! assert_eq!(endpoint.port, 2525);
+//! assert_eq!(endpoint.tls, TlsMode::Required);
+//! assert_eq!(endpoint.username.as_deref(), Some("fred@example.org"));
+//! assert_eq!(endpoint.credentials().unwrap().password.expose(), "s3cret");
+//! ```
+//!
+//! The scheme selects the default port and TLS mode:
+//!
+//! | Scheme                   | Port | TLS                          |
+//! |--------------------------|------|------------------------------|
+//! | `smtp`                   | 25   | [`TlsMode::Opportunistic`]   |
+//! | `submission`             | 587  | [`TlsMode::Required`]        |
+//! | `smtps`, `submissions`   | 465  | [`TlsMode::Implicit`]        |
+//!
+//! The "starttls" parameter, which is "none", "opportunistic", or "required", overrides the
+//! mode of `smtp` and `submission`. The user and password are percent-decoded.
+
+use alloc::{borrow::ToOwned, string::String, vec::Vec};
+use core::{fmt, net::Ipv6Addr};
+
+use crate::{
+    client::{Credentials, TlsMode},
+    Secret,
+};
+
+/// The server to send to, and how
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EndpointConfig {
+    /// A host name or an IP address, without the brackets of IPv6 addresses
+    pub host: String,
+    pub port: u16,
+    pub tls: TlsMode,
+    /// The identity to authenticate as
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+}
+
+impl EndpointConfig {
+    pub fn parse(url: &str) -> Result<Self, UrlError> {
+        let (scheme, rest) = url.split_once("://").ok_or(UrlError::Syntax)?;
+        let (port, mut tls) = match scheme.to_ascii_lowercase().as_str() {
+            "smtp" => (25, TlsMode::Opportunistic),
+            "submission" => (587, TlsMode::Required),
+            "smtps" | "submissions" => (465, TlsMode::Implicit),
+            _ => return Err(UrlError::UnknownScheme(scheme.to_owned())),
+        };
+
+        let (rest, query) = match rest.split_once('?') {
+            Some((rest, query)) => (rest, Some(query)),
+            None => (rest, None),
+        };
+        let authority = rest.strip_suffix('/').unwrap_or(rest);
+        if authority.contains(['/', '#']) {
+            return Err(UrlError::Syntax);
+        }
+
+        let (userinfo, host_port) = match authority.rsplit_once('@') {
+            Some((userinfo, host_port)) => (Some(userinfo), host_port),
+            None => (None, authority),
+        };
+        let (username, password) = match userinfo {
+            Some(userinfo) => match userinfo.split_once(':') {
+                Some((user, password)) => (
+                    Some(percent_decode(user)?),
+                    Some(Secret::new(percent_decode(password)?)),
+                ),
+                None => (Some(percent_decode(userinfo)?), None),
+            },
+            None => (None, None),
+        };
+        let (host, port) = host_and_port(host_port, port)?;
+
+        for parameter in query.into_iter().flat_map(|query| query.split('&')) {
+            let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            if !name.eq_ignore_ascii_case("starttls") {
+                return Err(UrlError::UnknownParameter(name.to_owned()));
+            }
+            tls = match (tls, value.to_ascii_lowercase().as_str()) {
+                (TlsMode::Implicit, _) => return Err(UrlError::InvalidTlsMode),
+                (_, "none") => TlsMode::None,
+                (_, "opportunistic") => TlsMode::Opportunistic,
+                (_, "required") => TlsMode::Required,
+                _ => return Err(UrlError::InvalidTlsMode),
+            };
+        }
+
+        Ok(Self {
+            host,
+            port,
+            tls,
+            username,
+            password,
+        })
+    }
+
+    /// The credentials to authenticate with, when both the user and the password are given
+    pub fn credentials(&self) -> Option<Credentials> {
+        Some(Credentials {
+            username: self.username.clone()?,
+            password: self.password.clone()?,
+        })
+    }
+}
+
+impl core::str::FromStr for EndpointConfig {
+    type Err = UrlError;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        Self::parse(url)
+    }
+}
+
+/// host [ ":" port ], where host is a name, an IPv4 address, or an IPv6 address in brackets
+fn host_and_port(input: &str, default_port: u16) -> Result<(String, u16), UrlError> {
+    let (host, port) = match input.strip_prefix('[') {
+        Some(rest) => {
+            let (address, port) = rest.split_once(']').ok_or(UrlError::InvalidHost)?;
+            if address.parse::<Ipv6Addr>().is_err() {
+                return Err(UrlError::InvalidHost);
+            }
+            match port {
+                "" => (address, None),
+                port => (
+                    address,
+                    Some(port.strip_prefix(':').ok_or(UrlError::Syntax)?),
+                ),
+            }
+        }
+        None => match input.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (input, None),
+        },
+    };
+
+    let is_host_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | ':');
+    if host.is_empty() || !host.chars().all(is_host_char) {
+        return Err(UrlError::InvalidHost);
+    }
+    let port = match port {
+        Some(port) => match port.parse() {
+            Ok(0) | Err(_) => return Err(UrlError::InvalidPort),
+            Ok(port) => port,
+        },
+        None => default_port,
+    };
+
+    Ok((host.to_owned(), port))
+}
+
+/// Decode "%XX" escapes, which must yield UTF-8.
+fn percent_decode(encoded: &str) -> Result<String, UrlError> {
+    let hex = |byte: Option<u8>| {
+        char::from(byte.ok_or(UrlError::InvalidUserInfo)?)
+            .to_digit(16)
+            .ok_or(UrlError::InvalidUserInfo)
+    };
+
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut bytes = encoded.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'%' => {
+                let high = hex(bytes.next())?;
+                let low = hex(bytes.next())?;
+                decoded.push((high << 4 | low) as u8);
+            }
+            _ => decoded.push(byte),
+        }
+    }
+
+    String::from_utf8(decoded).map_err(|_| UrlError::InvalidUserInfo)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UrlError {
+    /// The URL is not of the form "scheme://authority".
+    Syntax,
+    UnknownScheme(String),
+    InvalidHost,
+    /// The port is not a number from 1 to 65535.
+    InvalidPort,
+    /// The user or password contains an invalid percent-encoding, or is not UTF-8.
+    InvalidUserInfo,
+    UnknownParameter(String),
+    /// The "starttls" parameter has an unknown value, or is given for implicit TLS.
+    InvalidTlsMode,
+}
+
+impl fmt::Display for UrlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UrlError::Syntax => write!(f, "invalid URL syntax"),
+            UrlError::UnknownScheme(scheme) => write!(f, "unknown scheme \"{}\"", scheme),
+            UrlError::InvalidHost => write!(f, "invalid host"),
+            UrlError::InvalidPort => write!(f, "invalid port"),
+            UrlError::InvalidUserInfo => write!(f, "invalid user or password"),
+            UrlError::UnknownParameter(name) => write!(f, "unknown parameter \"{}\"", name),
+            UrlError::InvalidTlsMode => write!(f, "invalid \"starttls\" parameter"),
+        }
+    }
+}
+
+impl core::error::Error for UrlError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let endpoint = EndpointConfig::parse("submission://mail.example.org").unwrap();
+        assert_eq!(endpoint.host, "mail.example.org");
+        assert_eq!(endpoint.port, 587);
+        assert_eq!(endpoint.tls, TlsMode::Required);
+        assert_eq!(endpoint.username, None);
+        assert_eq!(endpoint.credentials(), None);
+
+        let tests = [
+            ("smtp://192.0.2.1/", "192.0.2.1", 25, TlsMode::Opportunistic),
+            (
+                "SMTP://mx.example.org?starttls=none",
+                "mx.example.org",
+                25,
+                TlsMode::None,
+            ),
+            ("smtps://[::1]:4650", "::1", 4650, TlsMode::Implicit),
+            (
+                "submissions://fred@example.org",
+                "example.org",
+                465,
+                TlsMode::Implicit,
+            ),
+        ];
+        for (url, host, port, tls) in tests {
+            let endpoint: EndpointConfig = url.parse().unwrap();
+            assert_eq!(
+                (endpoint.host.as_str(), endpoint.port, endpoint.tls),
+                (host, port, tls)
+            );
+        }
+
+        let endpoint = EndpointConfig::parse("smtp://fred@mx.example.org").unwrap();
+        assert_eq!(endpoint.username.as_deref(), Some("fred"));
+        assert_eq!(endpoint.credentials(), None);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        let tests = [
+            ("mail.example.org", UrlError::Syntax),
+            (
+                "imap://mail.example.org",
+                UrlError::UnknownScheme("imap".into()),
+            ),
+            ("smtp://", UrlError::InvalidHost),
+            ("smtp://mail.example.org/inbox", UrlError::Syntax),
+            ("smtp://[192.0.2.1]", UrlError::InvalidHost),
+            ("smtp://mail_example.org", UrlError::InvalidHost),
+            ("smtp://mail.example.org:0", UrlError::InvalidPort),
+            ("smtp://mail.example.org:65536", UrlError::InvalidPort),
+            ("smtp://fred%4@mail.example.org", UrlError::InvalidUserInfo),
+            (
+                "smtp://mail.example.org?helo=a",
+                UrlError::UnknownParameter("helo".into()),
+            ),
+            (
+                "smtp://mail.example.org?starttls=always",
+                UrlError::InvalidTlsMode,
+            ),
+            (
+                "smtps://mail.example.org?starttls=none",
+                UrlError::InvalidTlsMode,
+            ),
+        ];
+        for (url, expected) in tests {
+            assert_eq!(EndpointConfig::parse(url), Err(expected), "{}", url);
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! Constraints the grammar alone can't express
+//!
+//! The parsers accept everything the ABNF of RFC 5321 allows. Some rules are stated in prose
+//! instead, e.g., the length of a label (RFC 1035, 2.3.4.), or a TLD that is not all-numeric
+//! (RFC 3696, 2.). The functions of this module check parsed values against them, and return
+//! all violations, so that a policy can decide which ones to reject:
+//!
+//! ```
+//! use instant_smtp::{validate, Command};
+//!
+//! let (_, command) = Command::from_bytes(b"RCPT TO:<fred@example.123>\r\n").unwrap();
+//! assert_eq!(
+//!     validate::command(&command),
+//!     [validate::Violation::NumericTld("123".into())]
+//! );
+//! ```
+
+use alloc::{
+    borrow::ToOwned,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+
+use crate::{Command, ForwardPath, HeloIdentity, Path, ReversePath};
+
+/// Maximum length of a label (RFC 1035, 2.3.4.)
+const LABEL_LENGTH: usize = 63;
+/// Maximum length of a domain (RFC 5321, 4.5.3.1.2.)
+const DOMAIN_LENGTH: usize = 255;
+/// Maximum length of a local part (RFC 5321, 4.5.3.1.1.)
+const LOCAL_PART_LENGTH: usize = 64;
+/// Maximum length of a path, including the angle brackets (RFC 5321, 4.5.3.1.3.)
+const PATH_LENGTH: usize = 256;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// A domain has an empty label, e.g., "example..org".
+    EmptyLabel,
+    /// A label is longer than 63 octets.
+    LabelTooLong(String),
+    /// A label starts or ends with a hyphen.
+    HyphenatedLabel(String),
+    /// The top-level domain is all-numeric, and could be confused with an IPv4 address.
+    NumericTld(String),
+    /// A domain is longer than 255 octets.
+    DomainTooLong(usize),
+    /// A local part is longer than 64 octets.
+    LocalPartTooLong(usize),
+    /// A path, including the angle brackets, is longer than 256 octets.
+    PathTooLong(usize),
+    /// A mailbox has no "@".
+    MissingDomain,
+}
+
+impl Violation {
+    /// Whether the domain is invalid in the DNS, i.e., UTS #46 ToASCII with CheckHyphens and
+    /// VerifyDnsLength rejects it.
+    ///
+    /// A numeric top-level domain is merely ambiguous, and lengths of the local part and the
+    /// path are not about the domain.
+    pub fn is_invalid_domain(&self) -> bool {
+        matches!(
+            self,
+            Violation::EmptyLabel
+                | Violation::LabelTooLong(_)
+                | Violation::HyphenatedLabel(_)
+                | Violation::DomainTooLong(_)
+        )
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::EmptyLabel => write!(f, "domain has an empty label"),
+            Violation::LabelTooLong(label) => {
+                write!(f, "label {:?} exceeds {} octets", label, LABEL_LENGTH)
+            }
+            Violation::HyphenatedLabel(label) => {
+                write!(f, "label {:?} starts or ends with a hyphen", label)
+            }
+            Violation::NumericTld(tld) => write!(f, "top-level domain {:?} is numeric", tld),
+            Violation::DomainTooLong(length) => {
+                write!(f, "domain of {} octets exceeds {}", length, DOMAIN_LENGTH)
+            }
+            Violation::LocalPartTooLong(length) => write!(
+                f,
+                "local part of {} octets exceeds {}",
+                length, LOCAL_PART_LENGTH
+            ),
+            Violation::PathTooLong(length) => {
+                write!(f, "path of {} octets exceeds {}", length, PATH_LENGTH)
+            }
+            Violation::MissingDomain => write!(f, "mailbox has no domain"),
+        }
+    }
+}
+
+/// Check a domain, e.g., of EHLO or of a mailbox. Address literals are not checked.
+///
+/// Lengths are counted in octets of the given form, i.e., of the U-labels of an internationalized
+/// domain.
+pub fn domain(domain: &str) -> Vec<Violation> {
+    let mut violations = Vec::new();
+
+    if domain.starts_with('[') {
+        return violations;
+    }
+    if domain.len() > DOMAIN_LENGTH {
+        violations.push(Violation::DomainTooLong(domain.len()));
+    }
+
+    for label in domain.split('.') {
+        if label.is_empty() {
+            if !violations.contains(&Violation::EmptyLabel) {
+                violations.push(Violation::EmptyLabel);
+            }
+            continue;
+        }
+        if label.len() > LABEL_LENGTH {
+            violations.push(Violation::LabelTooLong(label.to_owned()));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            violations.push(Violation::HyphenatedLabel(label.to_owned()));
+        }
+    }
+
+    match domain.rsplit('.').next() {
+        Some(tld) if !tld.is_empty() && tld.bytes().all(|byte| byte.is_ascii_digit()) => {
+            violations.push(Violation::NumericTld(tld.to_owned()));
+        }
+        _ => {}
+    }
+
+    violations
+}
+
+/// Check a mailbox, i.e., the length of its local part and its domain.
+pub fn mailbox(mailbox: &str) -> Vec<Violation> {
+    let Some((local_part, domain)) = mailbox.rsplit_once('@') else {
+        return vec![Violation::MissingDomain];
+    };
+
+    let mut violations = Vec::new();
+    if local_part.len() > LOCAL_PART_LENGTH {
+        violations.push(Violation::LocalPartTooLong(local_part.len()));
+    }
+    violations.extend(self::domain(domain));
+
+    violations
+}
+
+/// Check the mailbox, the domains of the source route, and the total length of a path.
+pub fn path(path: &Path) -> Vec<Violation> {
+    let mut violations = Vec::new();
+
+    // "<" Path ">"
+    let length = path.to_string().len() + 2;
+    if length > PATH_LENGTH {
+        violations.push(Violation::PathTooLong(length));
+    }
+    for hop in path.route.iter().flatten() {
+        violations.extend(domain(hop));
+    }
+    violations.extend(mailbox(&path.mailbox));
+
+    violations
+}
+
+pub fn reverse_path(reverse_path: &ReversePath) -> Vec<Violation> {
+    match reverse_path {
+        ReversePath::Null => Vec::new(),
+        ReversePath::Path(path) => self::path(path),
+    }
+}
+
+pub fn forward_path(forward_path: &ForwardPath) -> Vec<Violation> {
+    match forward_path {
+        ForwardPath::Postmaster(None) => Vec::new(),
+        ForwardPath::Postmaster(Some(postmaster)) => domain(postmaster),
+        ForwardPath::Path(path) => self::path(path),
+    }
+}
+
+/// Check the identity of EHLO, HELO, and LHLO, and the paths of MAIL and RCPT.
+pub fn command(command: &Command) -> Vec<Violation> {
+    match command {
+        Command::Ehlo { domain_or_address }
+        | Command::Helo { domain_or_address }
+        | Command::Lhlo { domain_or_address } => match domain_or_address {
+            HeloIdentity::Domain(name) => domain(name),
+            HeloIdentity::AddressLiteral(_) => Vec::new(),
+        },
+        Command::Mail { reverse_path, .. } => self::reverse_path(reverse_path),
+        Command::Rcpt { forward_path, .. } => self::forward_path(forward_path),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_domain() {
+        let long = "a".repeat(64);
+        let tests = [
+            ("example.org", vec![]),
+            ("[192.0.2.1]", vec![]),
+            ("example..org", vec![Violation::EmptyLabel]),
+            (
+                "-a.b-.example.org",
+                vec![
+                    Violation::HyphenatedLabel("-a".into()),
+                    Violation::HyphenatedLabel("b-".into()),
+                ],
+            ),
+            ("192.0.2.1", vec![Violation::NumericTld("1".into())]),
+            (
+                &*format!("{}.example", long),
+                vec![Violation::LabelTooLong(long.clone())],
+            ),
+        ];
+
+        for (test, expected) in tests {
+            assert_eq!(domain(test), expected, "{}", test);
+        }
+    }
+
+    #[test]
+    fn test_path() {
+        let local_part = "a".repeat(65);
+        let route = vec!["relay-.example".to_owned()];
+        let path = Path {
+            route: Some(route),
+            mailbox: format!("{}@{}.example", local_part, "b".repeat(200)),
+        };
+
+        assert_eq!(
+            self::path(&path),
+            vec![
+                Violation::PathTooLong(292),
+                Violation::HyphenatedLabel("relay-".into()),
+                Violation::LocalPartTooLong(65),
+                Violation::LabelTooLong("b".repeat(200)),
+            ]
+        );
+        assert_eq!(mailbox("fred"), vec![Violation::MissingDomain]);
+        assert!(reverse_path(&ReversePath::Null).is_empty());
+    }
+}
@@ -0,0 +1,57 @@
+//! Parsers for JavaScript, via `wasm-bindgen`
+//!
+//! The functions parse what a browser-based tool, e.g., an analyzer of session transcripts, has
+//! at hand: a single command or reply, with or without the final CRLF. The result is the `serde`
+//! representation of [`Command`] or [`Response`] as a JavaScript object, and errors are thrown as
+//! `Error`s:
+//!
+//! ```js
+//! import { parseCommand, parseResponse } from "instant-smtp";
+//!
+//! const command = parseCommand(new TextEncoder().encode("MAIL FROM:<fred@example.org>\r\n"));
+//! const reply = parseResponse(new TextEncoder().encode("250-mx.example.org\r\n250 SIZE\r\n"), "ehlo");
+//! ```
+//!
+//! Build with `--target wasm32-unknown-unknown --features wasm`. Note that the initial response
+//! of AUTH is part of the result, see [`Secret`](crate::Secret).
+
+use alloc::string::ToString;
+
+use wasm_bindgen::prelude::*;
+
+use crate::{Command, Response, ResponseKind, SmtpParseError};
+
+/// Parse a command, e.g., "EHLO example.org".
+#[wasm_bindgen(js_name = parseCommand)]
+pub fn parse_command(bytes: &[u8]) -> Result<JsValue, JsError> {
+    match Command::from_bytes_complete(bytes) {
+        Ok(([], command)) => to_value(&command),
+        Ok(_) => Err(JsError::new("input following the command")),
+        Err(error) => Err(parse_error(error)),
+    }
+}
+
+/// Parse a reply of the given kind: "greeting", "ehlo", or "other".
+#[wasm_bindgen(js_name = parseResponse)]
+pub fn parse_response(bytes: &[u8], kind: &str) -> Result<JsValue, JsError> {
+    let kind = match kind {
+        "greeting" => ResponseKind::Greeting,
+        "ehlo" => ResponseKind::Ehlo,
+        "other" => ResponseKind::Other,
+        _ => return Err(JsError::new("unknown kind of reply")),
+    };
+
+    match Response::parse_complete(kind, bytes) {
+        Ok(([], response)) => to_value(&response),
+        Ok(_) => Err(JsError::new("input following the reply")),
+        Err(error) => Err(parse_error(error)),
+    }
+}
+
+fn to_value<T: serde::Serialize>(value: &T) -> Result<JsValue, JsError> {
+    serde_wasm_bindgen::to_value(value).map_err(|error| JsError::new(&error.to_string()))
+}
+
+fn parse_error(error: SmtpParseError) -> JsError {
+    JsError::new(&error.to_string())
+}
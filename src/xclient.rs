@@ -0,0 +1,221 @@
+//! Attributes of the XCLIENT and XFORWARD commands of Postfix
+//!
+//! A proxy in front of Postfix sends XCLIENT to make Postfix apply its access rules to the
+//! original client instead of the proxy, and XFORWARD to log the original client:
+//!
+//! ```text
+//! XCLIENT NAME=spike.porcupine.org ADDR=168.100.189.2 PROTO=ESMTP
+//! XFORWARD NAME=[UNAVAILABLE] ADDR=168.100.189.2 SOURCE=REMOTE
+//! ```
+//!
+//! The server advertises the attributes it supports in its EHLO reply, e.g.,
+//! "250-XCLIENT NAME ADDR PROTO HELO", which is kept as [`Capability::Other`].
+//!
+//! See <https://www.postfix.org/XCLIENT_README.html> and
+//! <https://www.postfix.org/XFORWARD_README.html>.
+//!
+//! [`Capability::Other`]: crate::Capability::Other
+
+use alloc::{borrow::ToOwned, string::String};
+use core::fmt;
+
+use crate::io::Write;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::parse::encode_xtext;
+
+/// attribute-name "=" attribute-value
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attribute {
+    pub name: AttributeName,
+    /// The value, xtext-encoded when serialized, or `None` for "\[UNAVAILABLE\]"
+    pub value: Option<String>,
+}
+
+impl Attribute {
+    pub fn new(name: AttributeName, value: impl Into<String>) -> Self {
+        Self {
+            name,
+            value: Some(value.into()),
+        }
+    }
+
+    /// The attribute with the value "\[UNAVAILABLE\]"
+    pub fn unavailable(name: AttributeName) -> Self {
+        Self { name, value: None }
+    }
+
+    pub fn serialize(&self, writer: &mut impl Write) -> crate::io::Result<()> {
+        match &self.value {
+            Some(value) => write!(writer, "{}={}", self.name, encode_xtext(value)),
+            None => write!(writer, "{}=[UNAVAILABLE]", self.name),
+        }
+    }
+}
+
+/// Names of the attributes, matched case-insensitively
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AttributeName {
+    /// The host name of the client, or "\[TEMPUNAVAIL\]" if the lookup failed temporarily
+    Name,
+    /// The host name as found in the DNS, before it was verified (XCLIENT only)
+    ReverseName,
+    /// The IP address of the client, with the prefix "IPV6:" for IPv6
+    Addr,
+    Port,
+    /// "SMTP" or "ESMTP"
+    Proto,
+    /// The domain of HELO or EHLO
+    Helo,
+    /// The SASL login name (XCLIENT only)
+    Login,
+    /// The IP address the client connected to (XCLIENT only)
+    DestAddr,
+    /// The port the client connected to (XCLIENT only)
+    DestPort,
+    /// The queue ID of the message at the original MTA (XFORWARD only)
+    Ident,
+    /// "LOCAL" or "REMOTE" (XFORWARD only)
+    Source,
+    Other(String),
+}
+
+impl AttributeName {
+    pub fn as_str(&self) -> &str {
+        match self {
+            AttributeName::Name => "NAME",
+            AttributeName::ReverseName => "REVERSE_NAME",
+            AttributeName::Addr => "ADDR",
+            AttributeName::Port => "PORT",
+            AttributeName::Proto => "PROTO",
+            AttributeName::Helo => "HELO",
+            AttributeName::Login => "LOGIN",
+            AttributeName::DestAddr => "DESTADDR",
+            AttributeName::DestPort => "DESTPORT",
+            AttributeName::Ident => "IDENT",
+            AttributeName::Source => "SOURCE",
+            AttributeName::Other(name) => name,
+        }
+    }
+}
+
+impl From<&str> for AttributeName {
+    fn from(name: &str) -> Self {
+        let known = [
+            AttributeName::Name,
+            AttributeName::ReverseName,
+            AttributeName::Addr,
+            AttributeName::Port,
+            AttributeName::Proto,
+            AttributeName::Helo,
+            AttributeName::Login,
+            AttributeName::DestAddr,
+            AttributeName::DestPort,
+            AttributeName::Ident,
+            AttributeName::Source,
+        ];
+
+        known
+            .into_iter()
+            .find(|known| known.as_str().eq_ignore_ascii_case(name))
+            .unwrap_or_else(|| AttributeName::Other(name.to_owned()))
+    }
+}
+
+impl fmt::Display for AttributeName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::{vec, vec::Vec};
+
+    use super::*;
+    use crate::Command;
+
+    #[test]
+    fn test_round_trip() {
+        let attributes = vec![
+            Attribute::new(AttributeName::Name, "spike.porcupine.org"),
+            Attribute::new(AttributeName::Addr, "IPV6:2001:db8::1"),
+            Attribute::new(AttributeName::Login, "fred=x+y"),
+            Attribute::unavailable(AttributeName::Helo),
+        ];
+        let command = Command::Xclient { attributes };
+
+        let mut serialized = Vec::new();
+        command.serialize(&mut serialized).unwrap();
+        assert_eq!(
+            serialized,
+            b"XCLIENT NAME=spike.porcupine.org ADDR=IPV6:2001:db8::1 LOGIN=fred+3Dx+2By \
+HELO=[UNAVAILABLE]\r\n"
+        );
+        assert_eq!(Command::from_bytes(&serialized), Ok((&b""[..], command)));
+    }
+
+    #[test]
+    fn test_unavailable() {
+        let mut serialized = Vec::new();
+        Attribute::unavailable(AttributeName::Name)
+            .serialize(&mut serialized)
+            .unwrap();
+        assert_eq!(serialized, b"NAME=[UNAVAILABLE]");
+
+        let (_, command) = Command::from_bytes(b"XFORWARD NAME=[UNAVAILABLE]\r\n").unwrap();
+        assert_eq!(
+            command,
+            Command::Xforward {
+                attributes: vec![Attribute::unavailable(AttributeName::Name)],
+            }
+        );
+        // Other placeholders are values.
+        let (_, command) = Command::from_bytes(b"XCLIENT NAME=[TEMPUNAVAIL]\r\n").unwrap();
+        assert_eq!(
+            command,
+            Command::Xclient {
+                attributes: vec![Attribute::new(AttributeName::Name, "[TEMPUNAVAIL]")],
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalid_xtext() {
+        for line in [
+            &b"XCLIENT LOGIN=a+2x\r\n"[..],
+            b"XCLIENT LOGIN=a+2\r\n",
+            b"XCLIENT LOGIN=a=b\r\n",
+        ] {
+            assert!(Command::from_bytes(line).is_err(), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn test_attribute_name() {
+        assert_eq!(
+            AttributeName::from("reverse_name"),
+            AttributeName::ReverseName
+        );
+        assert_eq!(
+            AttributeName::from("X-TRACE"),
+            AttributeName::Other("X-TRACE".into())
+        );
+        assert_eq!(AttributeName::from("X-TRACE").to_string(), "X-TRACE");
+
+        let (_, command) = Command::from_bytes(b"XCLIENT X-TRACE=1 DESTPORT=25\r\n").unwrap();
+        assert_eq!(
+            command,
+            Command::Xclient {
+                attributes: vec![
+                    Attribute::new(AttributeName::Other("X-TRACE".into()), "1"),
+                    Attribute::new(AttributeName::DestPort, "25"),
+                ],
+            }
+        );
+    }
+}
@@ -21,7 +21,7 @@ fn parse_trace(mut trace: &[u8]) {
                 println!("S: {:?}", rsp);
                 trace = rem;
             }
-            Command::Data { .. } => {
+            Command::Data => {
                 let (rem, rsp) = Response::parse_other(trace).unwrap();
                 println!("S: {:?}", rsp);
                 trace = rem;